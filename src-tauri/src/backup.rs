@@ -0,0 +1,885 @@
+//! Encrypted backups of everything in the data directory: the database,
+//! the key for sensitive items, the config, images and large text blobs.
+//!
+//! A backup is a tar.gz encrypted with a key derived from a passphrase
+//! (PBKDF2-SHA256). The stream is cut into chunks that are sealed with
+//! AES-256-GCM one by one, with the chunk number and a last-chunk flag in
+//! the nonce, so changed, reordered or missing data can't go unnoticed. The
+//! archive ends with a manifest holding the SHA-256 of every file.
+//!
+//! Restoring can't replace files the running app has open. The backup is
+//! checked and unpacked into `restore/`, and [`apply_pending_restore`] moves
+//! it into place on the next start, before anything is opened.
+
+use crate::crypto::Crypto;
+use crate::db::Database;
+use crate::models::AppConfig;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use chrono::{Duration, Local, NaiveDateTime};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"CLIPBAK\0";
+// Version of the archive layout, bumped when it changes
+const FORMAT_VERSION: u16 = 1;
+const KDF_ITERATIONS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+// The rest of the nonce is the chunk number and the last-chunk flag
+const NONCE_PREFIX_LEN: usize = 7;
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4 + SALT_LEN + NONCE_PREFIX_LEN;
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+const MANIFEST: &str = "manifest.json";
+const DATABASE: &str = "history.db";
+const KEY_FILE: &str = "secret.key";
+// Files and folders of the data directory that go into a backup
+const DATA_FILES: [&str; 4] = [DATABASE, KEY_FILE, "config.json", "paste_stack.json"];
+const DATA_DIRS: [&str; 2] = ["images", "blobs"];
+// SQLite files that belong to the database being replaced
+const DATABASE_SIDE_FILES: [&str; 2] = ["history.db-wal", "history.db-shm"];
+
+const STAGING_DIR: &str = "restore.partial";
+const PENDING_DIR: &str = "restore";
+// The data replaced by the last restore, in case it has to be brought back
+const PREVIOUS_DIR: &str = "before-restore";
+
+// Names of automatic backups, which sort by date
+const AUTO_BACKUP_NAME: &str = "clipboard-backup-%Y%m%d-%H%M%S.cbak";
+
+/// How often the app checks whether an automatic backup is due.
+pub const SCHEDULE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupReport {
+    pub path: String,
+    pub files: usize,
+    // Size of the archive in bytes
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreReport {
+    pub created_at: String,
+    pub app_version: String,
+    pub files: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u16,
+    pub created_at: String,
+    pub app_version: String,
+    // Where the backed up data lived, image paths in the database start with it
+    pub data_dir: String,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Writes a backup of `data_dir` to `path`. The database is copied from a
+/// consistent snapshot, so the app can keep running.
+pub fn create_backup(
+    data_dir: &Path,
+    db: &Database,
+    path: &Path,
+    passphrase: &str,
+) -> Result<BackupReport, String> {
+    if passphrase.is_empty() {
+        return Err("A passphrase is required".to_string());
+    }
+    let snapshot_dir = data_dir.join(format!(".backup-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&snapshot_dir).map_err(|e| e.to_string())?;
+    let snapshot = snapshot_dir.join(DATABASE);
+
+    let result = db
+        .snapshot(&snapshot)
+        .map_err(|e| format!("Can't copy the database: {}", e))
+        .and_then(|_| write_archive(data_dir, &snapshot, path, passphrase));
+    let _ = fs::remove_dir_all(&snapshot_dir);
+    result
+}
+
+fn write_archive(
+    data_dir: &Path,
+    snapshot: &Path,
+    path: &Path,
+    passphrase: &str,
+) -> Result<BackupReport, String> {
+    let mut files = vec![(DATABASE.to_string(), snapshot.to_path_buf())];
+    for name in &DATA_FILES[1..] {
+        let file = data_dir.join(name);
+        if file.is_file() {
+            files.push((name.to_string(), file));
+        }
+    }
+    for name in DATA_DIRS {
+        collect_files(&data_dir.join(name), name, &mut files)?;
+    }
+
+    // Written next to the target and renamed at the end, so a failed backup
+    // never replaces a good one
+    let mut partial_name = path.file_name().unwrap_or_default().to_os_string();
+    partial_name.push(".partial");
+    let partial = path.with_file_name(partial_name);
+    let result = write_encrypted(&partial, data_dir, &files, passphrase);
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, path).map_err(|e| e.to_string())?;
+
+    Ok(BackupReport {
+        path: path.to_string_lossy().into_owned(),
+        files: files.len(),
+        size: fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+    })
+}
+
+fn write_encrypted(
+    target: &Path,
+    data_dir: &Path,
+    files: &[(String, PathBuf)],
+    passphrase: &str,
+) -> Result<(), String> {
+    let file = File::create(target).map_err(|e| format!("Can't create the backup: {}", e))?;
+    let writer = EncryptWriter::new(BufWriter::new(file), passphrase).map_err(|e| e.to_string())?;
+    let mut archive = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+
+    let mut manifest = Manifest {
+        version: FORMAT_VERSION,
+        created_at: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        data_dir: data_dir.to_string_lossy().into_owned(),
+        files: Vec::new(),
+    };
+    for (name, path) in files {
+        let file = File::open(path).map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+        let size = file.metadata().map_err(|e| e.to_string())?.len();
+        let mut reader = HashingReader::new(file.take(size));
+        append(&mut archive, name, size, &mut reader)?;
+        manifest.files.push(ManifestFile {
+            path: name.clone(),
+            size,
+            sha256: reader.hex_digest(),
+        });
+    }
+    let json = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+    append(
+        &mut archive,
+        MANIFEST,
+        json.len() as u64,
+        &mut json.as_slice(),
+    )?;
+
+    let writer = archive
+        .into_inner()
+        .and_then(GzEncoder::finish)
+        .and_then(EncryptWriter::finish)
+        .map_err(|e| format!("Can't write the backup: {}", e))?;
+    let file = writer.into_inner().map_err(|e| e.to_string())?;
+    file.sync_all().map_err(|e| e.to_string())
+}
+
+fn append<W: Write>(
+    archive: &mut tar::Builder<W>,
+    name: &str,
+    size: u64,
+    data: &mut impl Read,
+) -> Result<(), String> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o600);
+    header.set_mtime(Local::now().timestamp().max(0) as u64);
+    archive
+        .append_data(&mut header, name, data)
+        .map_err(|e| format!("Can't add {} to the backup: {}", name, e))
+}
+
+// Files below `dir`, named with `/` separators relative to the data directory
+fn collect_files(dir: &Path, name: &str, files: &mut Vec<(String, PathBuf)>) -> Result<(), String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    let mut entries: Vec<_> = entries.flatten().collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let entry_name = format!("{}/{}", name, entry.file_name().to_string_lossy());
+        let file_type = entry.file_type().map_err(|e| e.to_string())?;
+        if file_type.is_dir() {
+            collect_files(&path, &entry_name, files)?;
+        } else if file_type.is_file() {
+            files.push((entry_name, path));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the backup at `path` and unpacks it to be applied on the next
+/// start. Nothing in the data directory changes when the backup is damaged
+/// or the passphrase is wrong.
+pub fn restore_backup(
+    data_dir: &Path,
+    path: &Path,
+    passphrase: &str,
+) -> Result<RestoreReport, String> {
+    let staging = data_dir.join(STAGING_DIR);
+    let _ = fs::remove_dir_all(&staging);
+    fs::create_dir_all(&staging).map_err(|e| e.to_string())?;
+
+    let manifest = match unpack(path, passphrase, &staging) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+    };
+
+    let pending = data_dir.join(PENDING_DIR);
+    let _ = fs::remove_dir_all(&pending);
+    fs::rename(&staging, &pending).map_err(|e| e.to_string())?;
+    log::info!(
+        "Backup from {} checked, it replaces the data on the next start",
+        manifest.created_at
+    );
+    Ok(RestoreReport {
+        created_at: manifest.created_at,
+        app_version: manifest.app_version,
+        files: manifest.files.len(),
+    })
+}
+
+fn unpack(path: &Path, passphrase: &str, staging: &Path) -> Result<Manifest, String> {
+    let file = File::open(path).map_err(|e| format!("Can't open {}: {}", path.display(), e))?;
+    let reader = DecryptReader::new(BufReader::new(file), passphrase)?;
+    let mut archive = tar::Archive::new(GzDecoder::new(reader));
+    let damaged = |e: io::Error| format!("The backup is damaged: {}", e);
+
+    let mut manifest: Option<Manifest> = None;
+    // (size, sha256) of every unpacked file
+    let mut unpacked: HashMap<String, (u64, String)> = HashMap::new();
+    for entry in archive.entries().map_err(damaged)? {
+        let mut entry = entry.map_err(damaged)?;
+        let name = entry
+            .path()
+            .map_err(damaged)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        if !entry.header().entry_type().is_file() || !is_safe_name(&name) {
+            return Err(format!("Unexpected entry {} in the backup", name));
+        }
+
+        if name == MANIFEST {
+            let mut json = String::new();
+            entry.read_to_string(&mut json).map_err(damaged)?;
+            manifest = Some(serde_json::from_str(&json).map_err(|e| e.to_string())?);
+            continue;
+        }
+        let target = staging.join(&name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut writer = HashingWriter::new(File::create(&target).map_err(|e| e.to_string())?);
+        let size = io::copy(&mut entry, &mut writer).map_err(damaged)?;
+        unpacked.insert(name, (size, writer.hex_digest()));
+    }
+
+    let manifest = manifest.ok_or("The backup has no manifest")?;
+    if manifest.version > FORMAT_VERSION {
+        return Err("The backup was made by a newer version of the app".to_string());
+    }
+    for file in &manifest.files {
+        match unpacked.remove(&file.path) {
+            Some((size, sha256)) if size == file.size && sha256 == file.sha256 => {}
+            Some(_) => return Err(format!("{} in the backup is damaged", file.path)),
+            None => return Err(format!("{} is missing from the backup", file.path)),
+        }
+    }
+    if let Some(name) = unpacked.keys().next() {
+        return Err(format!("{} in the backup isn't in its manifest", name));
+    }
+    for required in [DATABASE, KEY_FILE] {
+        if !manifest.files.iter().any(|file| file.path == required) {
+            return Err(format!("The backup has no {}", required));
+        }
+    }
+
+    let json = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+    fs::write(staging.join(MANIFEST), json).map_err(|e| e.to_string())?;
+    Ok(manifest)
+}
+
+// Relative paths without `..`, so nothing is written outside the staging folder
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Moves a restored backup into the data directory. Has to run before the
+/// database and config are opened. The replaced data is kept in
+/// `before-restore/` until the next restore. If anything can't be moved, the
+/// current data is put back and the restore is tried again on the next start.
+pub fn apply_pending_restore(data_dir: &Path) -> Option<Manifest> {
+    apply_restore_with(data_dir, |from, to| fs::rename(from, to))
+}
+
+fn apply_restore_with(
+    data_dir: &Path,
+    rename: impl Fn(&Path, &Path) -> io::Result<()>,
+) -> Option<Manifest> {
+    let pending = data_dir.join(PENDING_DIR);
+    if !pending.is_dir() {
+        return None;
+    }
+    let manifest: Manifest = match fs::read(pending.join(MANIFEST))
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_slice(&json).map_err(|e| e.to_string()))
+    {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            log::error!("Dropping unreadable restore: {}", e);
+            let _ = fs::remove_dir_all(&pending);
+            return None;
+        }
+    };
+
+    let previous = data_dir.join(PREVIOUS_DIR);
+    let _ = fs::remove_dir_all(&previous);
+    if let Err(e) = fs::create_dir_all(&previous) {
+        log::error!("Failed to restore backup: {}", e);
+        return None;
+    }
+
+    // A half-swapped data directory must never be opened: on the first
+    // failure everything moved so far goes back and the restore stays
+    // pending for the next start
+    let mut moved_aside = Vec::new();
+    let mut restored = Vec::new();
+    let swapped = (|| {
+        let replaced = DATA_FILES
+            .iter()
+            .chain(&DATA_DIRS)
+            .chain(&DATABASE_SIDE_FILES);
+        for name in replaced {
+            let current = data_dir.join(name);
+            if current.exists() {
+                rename(&current, &previous.join(name))
+                    .map_err(|e| format!("Failed to move {:?} aside: {}", current, e))?;
+                moved_aside.push(name);
+            }
+        }
+        for name in DATA_FILES.iter().chain(&DATA_DIRS) {
+            let from = pending.join(name);
+            if from.exists() {
+                rename(&from, &data_dir.join(name))
+                    .map_err(|e| format!("Failed to restore {}: {}", name, e))?;
+                restored.push(name);
+            }
+        }
+        Ok::<_, String>(())
+    })();
+    if let Err(e) = swapped {
+        log::error!("{}, keeping the current data", e);
+        for name in restored.into_iter().rev() {
+            if let Err(e) = rename(&data_dir.join(name), &pending.join(name)) {
+                log::error!("Failed to take back restored {}: {}", name, e);
+            }
+        }
+        for name in moved_aside.into_iter().rev() {
+            if let Err(e) = rename(&previous.join(name), &data_dir.join(name)) {
+                log::error!("Failed to put back {}: {}", name, e);
+            }
+        }
+        return None;
+    }
+    let _ = fs::remove_dir_all(&pending);
+    log::info!("Restored backup from {}", manifest.created_at);
+    Some(manifest)
+}
+
+/// Folder automatic backups are written to.
+pub fn backup_dir(data_dir: &Path, config: &AppConfig) -> PathBuf {
+    if config.auto_backup_dir.is_empty() {
+        data_dir.join("backups")
+    } else {
+        PathBuf::from(&config.auto_backup_dir)
+    }
+}
+
+/// Makes an automatic backup when the last one is older than the configured
+/// interval, then deletes the oldest ones beyond `auto_backup_keep`.
+pub fn run_scheduled(
+    data_dir: &Path,
+    db: &Database,
+    crypto: &Crypto,
+    config: &AppConfig,
+) -> Result<Option<BackupReport>, String> {
+    if !config.auto_backup_enabled {
+        return Ok(None);
+    }
+    if config.auto_backup_passphrase.is_empty() {
+        return Err("Automatic backups need a passphrase".to_string());
+    }
+
+    let dir = backup_dir(data_dir, config);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let interval = Duration::hours(config.auto_backup_interval_hours.max(1).into());
+    if let Some((_, last)) = auto_backups(&dir).last() {
+        if Local::now().naive_local() - *last < interval {
+            return Ok(None);
+        }
+    }
+
+    let passphrase = crypto.decrypt(&config.auto_backup_passphrase)?;
+    let path = dir.join(Local::now().format(AUTO_BACKUP_NAME).to_string());
+    let report = create_backup(data_dir, db, &path, &passphrase)?;
+    log::info!("Automatic backup written to {}", report.path);
+
+    let backups = auto_backups(&dir);
+    let keep = config.auto_backup_keep.max(1);
+    for (old, _) in backups.iter().take(backups.len().saturating_sub(keep)) {
+        match fs::remove_file(old) {
+            Ok(()) => log::info!("Deleted old backup {:?}", old),
+            Err(e) => log::error!("Failed to delete old backup {:?}: {}", old, e),
+        }
+    }
+    Ok(Some(report))
+}
+
+// Automatic backups in `dir`, oldest first
+fn auto_backups(dir: &Path) -> Vec<(PathBuf, NaiveDateTime)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut backups: Vec<_> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let date = NaiveDateTime::parse_from_str(&name, AUTO_BACKUP_NAME).ok()?;
+            Some((entry.path(), date))
+        })
+        .collect();
+    backups.sort_by_key(|(_, date)| *date);
+    backups
+}
+
+struct Header {
+    iterations: u32,
+    salt: [u8; SALT_LEN],
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+}
+
+impl Header {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.iterations.to_le_bytes());
+        bytes.extend_from_slice(&self.salt);
+        bytes.extend_from_slice(&self.nonce_prefix);
+        bytes
+    }
+
+    fn parse(bytes: &[u8; HEADER_LEN]) -> Result<Self, String> {
+        let (magic, rest) = bytes.split_at(MAGIC.len());
+        if magic != MAGIC {
+            return Err("Not a clipboard backup".to_string());
+        }
+        let version = u16::from_le_bytes([rest[0], rest[1]]);
+        if version > FORMAT_VERSION {
+            return Err("The backup was made by a newer version of the app".to_string());
+        }
+        // Taken from the file, so anything but our own setting is refused
+        // rather than handed to PBKDF2
+        let iterations = u32::from_le_bytes([rest[2], rest[3], rest[4], rest[5]]);
+        if iterations != KDF_ITERATIONS {
+            return Err("Unsupported key derivation settings".to_string());
+        }
+        let mut salt = [0; SALT_LEN];
+        salt.copy_from_slice(&rest[6..6 + SALT_LEN]);
+        let mut nonce_prefix = [0; NONCE_PREFIX_LEN];
+        nonce_prefix.copy_from_slice(&rest[6 + SALT_LEN..]);
+        Ok(Self {
+            iterations,
+            salt,
+            nonce_prefix,
+        })
+    }
+
+    fn cipher(&self, passphrase: &str) -> Aes256Gcm {
+        let mut key = Key::<Aes256Gcm>::default();
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), &self.salt, self.iterations, &mut key);
+        Aes256Gcm::new(&key)
+    }
+
+    fn nonce(&self, chunk: u32, last: bool) -> Nonce<aes_gcm::aead::consts::U12> {
+        let mut nonce = [0; 12];
+        nonce[..NONCE_PREFIX_LEN].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&chunk.to_be_bytes());
+        nonce[11] = u8::from(last);
+        nonce.into()
+    }
+}
+
+/// Encrypts everything written to it, chunk by chunk. `finish` seals the
+/// last chunk, without it the backup can't be read.
+struct EncryptWriter<W: Write> {
+    inner: W,
+    header: Header,
+    header_bytes: Vec<u8>,
+    cipher: Aes256Gcm,
+    chunk: u32,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    fn new(mut inner: W, passphrase: &str) -> io::Result<Self> {
+        let mut header = Header {
+            iterations: KDF_ITERATIONS,
+            salt: [0; SALT_LEN],
+            nonce_prefix: [0; NONCE_PREFIX_LEN],
+        };
+        OsRng.fill_bytes(&mut header.salt);
+        OsRng.fill_bytes(&mut header.nonce_prefix);
+        let header_bytes = header.to_bytes();
+        inner.write_all(&header_bytes)?;
+        Ok(Self {
+            inner,
+            cipher: header.cipher(passphrase),
+            header,
+            header_bytes,
+            chunk: 0,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        let payload = Payload {
+            msg: &self.buf,
+            aad: &self.header_bytes,
+        };
+        let sealed = self
+            .cipher
+            .encrypt(&self.header.nonce(self.chunk, last), payload)
+            .map_err(|_| io::Error::other("Encryption failed"))?;
+        self.inner.write_all(&sealed)?;
+        self.buf.clear();
+        self.chunk = self
+            .chunk
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("The backup is too large"))?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.seal(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, mut data: &[u8]) -> io::Result<usize> {
+        let written = data.len();
+        while !data.is_empty() {
+            // A full chunk is only sealed once more data follows, the last one is sealed by finish
+            if self.buf.len() == CHUNK_SIZE {
+                self.seal(false)?;
+            }
+            let take = (CHUNK_SIZE - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data[..take]);
+            data = &data[take..];
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct DecryptReader<R: BufRead> {
+    inner: R,
+    header: Header,
+    header_bytes: Vec<u8>,
+    cipher: Aes256Gcm,
+    chunk: u32,
+    plain: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: BufRead> DecryptReader<R> {
+    /// Reads the header and the first chunk, which tells a wrong passphrase
+    /// apart before anything is unpacked.
+    fn new(mut inner: R, passphrase: &str) -> Result<Self, String> {
+        let mut header_bytes = [0; HEADER_LEN];
+        inner
+            .read_exact(&mut header_bytes)
+            .map_err(|_| "Not a clipboard backup".to_string())?;
+        let header = Header::parse(&header_bytes)?;
+        let mut reader = Self {
+            inner,
+            cipher: header.cipher(passphrase),
+            header,
+            header_bytes: header_bytes.to_vec(),
+            chunk: 0,
+            plain: Vec::new(),
+            pos: 0,
+            done: false,
+        };
+        reader
+            .next_chunk()
+            .map_err(|_| "Wrong passphrase or damaged backup".to_string())?;
+        Ok(reader)
+    }
+
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let mut sealed = Vec::with_capacity(CHUNK_SIZE + TAG_LEN);
+        (&mut self.inner)
+            .take((CHUNK_SIZE + TAG_LEN) as u64)
+            .read_to_end(&mut sealed)?;
+        let last = self.inner.fill_buf()?.is_empty();
+        let payload = Payload {
+            msg: &sealed,
+            aad: &self.header_bytes,
+        };
+        self.plain = self
+            .cipher
+            .decrypt(&self.header.nonce(self.chunk, last), payload)
+            .map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "damaged or incomplete data")
+            })?;
+        self.pos = 0;
+        self.chunk += 1;
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for DecryptReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.plain.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.next_chunk()?;
+        }
+        let len = out.len().min(self.plain.len() - self.pos);
+        out[..len].copy_from_slice(&self.plain[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    fn hex_digest(self) -> String {
+        hex(&self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    fn hex_digest(self) -> String {
+        hex(&self.hasher.finalize())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ClipboardItem;
+    use std::sync::Arc;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("clipboard-backup-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn open(data_dir: &Path) -> Database {
+        let crypto = Arc::new(Crypto::new(data_dir.join(KEY_FILE)));
+        Database::new(data_dir.join(DATABASE), crypto).unwrap()
+    }
+
+    fn text(content: &str, is_sensitive: bool) -> ClipboardItem {
+        ClipboardItem {
+            content: content.to_string(),
+            kind: "text".into(),
+            timestamp: "2024-01-01 00:00:00".into(),
+            data_type: "text".into(),
+            is_sensitive,
+            store_as_blob: content.len() > 1000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = temp_dir("round-trip");
+        let data = dir.join("data");
+        fs::create_dir_all(&data).unwrap();
+        let big = "b".repeat(5000);
+        {
+            let db = open(&data);
+            db.insert_item(&text("hello", false), 100).unwrap();
+            db.insert_item(&text("secret", true), 100).unwrap();
+            db.insert_item(&text(&big, false), 100).unwrap();
+            AppConfig::default()
+                .save(&data.join("config.json"))
+                .unwrap();
+
+            let backup = dir.join("history.cbak");
+            let report = create_backup(&data, &db, &backup, "passphrase").unwrap();
+            assert!(restore_backup(&data, &backup, "wrong")
+                .unwrap_err()
+                .contains("Wrong passphrase"));
+            let restored = restore_backup(&data, &backup, "passphrase").unwrap();
+            assert_eq!(restored.files, report.files);
+
+            // Changes after the backup are undone by the restore
+            db.insert_item(&text("later", false), 100).unwrap();
+        }
+
+        assert!(apply_pending_restore(&data).is_some());
+        assert!(apply_pending_restore(&data).is_none());
+        let db = open(&data);
+        let items = db
+            .get_history(1, 20, None, false, false, None, None, None)
+            .unwrap();
+        let mut contents: Vec<String> = items
+            .iter()
+            .map(|item| db.get_item_content(item.id.unwrap()).unwrap())
+            .collect();
+        contents.sort();
+        assert_eq!(contents, [big, "hello".to_string(), "secret".to_string()]);
+        assert!(data.join(PREVIOUS_DIR).join(DATABASE).exists());
+    }
+
+    #[test]
+    fn header_only_accepts_our_kdf_settings() {
+        let header = |iterations| Header {
+            iterations,
+            salt: [1; SALT_LEN],
+            nonce_prefix: [2; NONCE_PREFIX_LEN],
+        };
+        let parse = |header: Header| {
+            let bytes: [u8; HEADER_LEN] = header.to_bytes().try_into().unwrap();
+            Header::parse(&bytes)
+        };
+        let parsed = parse(header(KDF_ITERATIONS)).unwrap();
+        assert_eq!(parsed.salt, [1; SALT_LEN]);
+        assert_eq!(parsed.nonce_prefix, [2; NONCE_PREFIX_LEN]);
+        assert!(parse(header(1)).is_err());
+        assert!(parse(header(u32::MAX)).is_err());
+    }
+
+    #[test]
+    fn failed_restore_puts_the_data_back() {
+        let data = temp_dir("rollback");
+        let pending = data.join(PENDING_DIR);
+        fs::create_dir_all(&pending).unwrap();
+        let manifest = Manifest {
+            version: FORMAT_VERSION,
+            created_at: "2024-01-01 00:00:00".into(),
+            app_version: "0.0.0".into(),
+            data_dir: data.to_string_lossy().into_owned(),
+            files: Vec::new(),
+        };
+        fs::write(
+            pending.join(MANIFEST),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+        for name in [DATABASE, KEY_FILE, "config.json"] {
+            fs::write(data.join(name), "old").unwrap();
+            fs::write(pending.join(name), "new").unwrap();
+        }
+
+        // The last file can't be moved in
+        let applied = apply_restore_with(&data, |from, to| {
+            if from.starts_with(&pending) && to.ends_with("config.json") {
+                return Err(io::Error::other("disk full"));
+            }
+            fs::rename(from, to)
+        });
+        assert!(applied.is_none());
+        for name in [DATABASE, KEY_FILE, "config.json"] {
+            assert_eq!(fs::read_to_string(data.join(name)).unwrap(), "old");
+            assert_eq!(fs::read_to_string(pending.join(name)).unwrap(), "new");
+        }
+
+        // Next start, nothing in the way
+        assert!(apply_pending_restore(&data).is_some());
+        for name in [DATABASE, KEY_FILE, "config.json"] {
+            assert_eq!(fs::read_to_string(data.join(name)).unwrap(), "new");
+        }
+        assert!(!pending.exists());
+    }
+}
@@ -0,0 +1,613 @@
+//! Command-line access to the clipboard history, e.g.
+//! `clipboard-cli list --limit 20 --type url`, `clipboard-cli add < notes.txt` or
+//! `clipboard-cli export --format markdown -o history.md` or
+//! `clipboard-cli import copyq --dry-run`.
+//!
+//! Works on the same `history.db` as the app and is safe to use while the app
+//! is running.
+
+use chrono::Local;
+use clap::{Parser, Subcommand};
+use clipboard_lib::classifier::Classifier;
+use clipboard_lib::crypto::Crypto;
+use clipboard_lib::db::Database;
+use clipboard_lib::export::{
+    export_history, export_to_string, ExportFormat, ExportOptions, ImageMode, SensitiveMode,
+};
+use clipboard_lib::image_store::ImageStore;
+use clipboard_lib::import::{self, ImportSource};
+use clipboard_lib::models::{AppConfig, ClipboardItem};
+use clipboard_lib::{app_data_dir, apply_size_limits};
+use clipboard_rs::common::RustImage;
+use clipboard_rs::{Clipboard, ClipboardContent, ClipboardContext, RustImageData};
+use std::io::{self, IsTerminal, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+// Characters of content shown per item by `list` and `search`.
+const PREVIEW_CHARS: usize = 80;
+
+#[derive(Parser)]
+#[command(
+    name = "clipboard-cli",
+    version,
+    about = "Read and script the clipboard history"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the most recent items, pinned ones first
+    List {
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// Only items of this type, e.g. url, email, code
+        #[arg(long = "type")]
+        data_type: Option<String>,
+        /// Print JSON instead of one tab-separated line per item
+        #[arg(long)]
+        json: bool,
+    },
+    /// Search content and notes
+    Search {
+        query: String,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// Treat the query as a regular expression
+        #[arg(long)]
+        regex: bool,
+        #[arg(long)]
+        case_sensitive: bool,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Put an item back on the system clipboard
+    Copy { id: i64 },
+    /// Pin an item, or unpin it with --off
+    Pin {
+        id: i64,
+        #[arg(long)]
+        off: bool,
+    },
+    /// Add text read from stdin to the history
+    Add {
+        #[arg(long)]
+        note: Option<String>,
+        /// Store the text encrypted, like items from sensitive apps
+        #[arg(long)]
+        sensitive: bool,
+    },
+    /// Export the history as JSON, CSV or Markdown
+    Export {
+        #[arg(long, default_value = "json", value_parser = parse_format)]
+        format: ExportFormat,
+        /// Write to this file instead of stdout, images go next to it
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        #[arg(long)]
+        collection: Option<i64>,
+        #[arg(long = "type")]
+        data_type: Option<String>,
+        /// Only items containing this text
+        #[arg(long)]
+        query: Option<String>,
+        /// First day (or timestamp) to include, e.g. 2024-01-31
+        #[arg(long)]
+        from: Option<String>,
+        /// Last day (or timestamp) to include
+        #[arg(long)]
+        to: Option<String>,
+        /// exclude, redact or decrypt
+        #[arg(long, default_value = "exclude", value_parser = parse_sensitive)]
+        sensitive: SensitiveMode,
+        /// Don't ask before exporting decrypted sensitive content
+        #[arg(long)]
+        yes: bool,
+        /// Embed images as base64 instead of copying the files
+        #[arg(long)]
+        embed_images: bool,
+    },
+    /// Import the history of another clipboard manager
+    Import {
+        /// copyq, maccy, clipy, ditto, gpaste, clipman or cliphist
+        #[arg(value_parser = parse_source)]
+        source: ImportSource,
+        /// File or folder to read instead of the default location
+        path: Option<PathBuf>,
+        /// Only report what would be imported
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+fn parse_format(name: &str) -> Result<ExportFormat, String> {
+    ExportFormat::from_name(name).ok_or_else(|| "expected json, csv or markdown".to_string())
+}
+
+fn parse_source(name: &str) -> Result<ImportSource, String> {
+    ImportSource::from_name(name).ok_or_else(|| {
+        "expected copyq, maccy, clipy, ditto, gpaste, clipman or cliphist".to_string()
+    })
+}
+
+fn parse_sensitive(name: &str) -> Result<SensitiveMode, String> {
+    SensitiveMode::from_name(name).ok_or_else(|| "expected exclude, redact or decrypt".to_string())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("clipboard-cli: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Command) -> Result<(), String> {
+    let data_dir = app_data_dir();
+    let config = AppConfig::load(&data_dir.join("config.json"));
+    let crypto = Arc::new(Crypto::new(data_dir.join("secret.key")));
+    let db = Database::new(data_dir.join("history.db"), crypto).map_err(|e| e.to_string())?;
+
+    match command {
+        Command::List {
+            limit,
+            data_type,
+            json,
+        } => {
+            let items = db
+                .get_history(1, limit, None, false, false, None, None, data_type)
+                .map_err(|e| e.to_string())?;
+            print_items(&items, json)
+        }
+        Command::Search {
+            query,
+            limit,
+            regex,
+            case_sensitive,
+            json,
+        } => {
+            let items = db
+                .get_history(
+                    1,
+                    limit,
+                    Some(query),
+                    regex,
+                    case_sensitive,
+                    None,
+                    None,
+                    None,
+                )
+                .map_err(|e| e.to_string())?;
+            print_items(&items, json)
+        }
+        Command::Copy { id } => copy(&db, id),
+        Command::Pin { id, off } => {
+            if !db.set_pinned(id, !off).map_err(|e| e.to_string())? {
+                return Err(format!("Item {} not found", id));
+            }
+            Ok(())
+        }
+        Command::Add { note, sensitive } => add(&db, &config, note, sensitive),
+        Command::Export {
+            format,
+            output,
+            collection,
+            data_type,
+            query,
+            from,
+            to,
+            sensitive,
+            yes,
+            embed_images,
+        } => {
+            let confirm_decrypt = sensitive == SensitiveMode::Decrypt && (yes || confirm()?);
+            let options = ExportOptions {
+                format,
+                collection_id: collection,
+                data_type,
+                query,
+                from,
+                to,
+                sensitive,
+                confirm_decrypt,
+                images: if embed_images || output.is_none() {
+                    ImageMode::Base64
+                } else {
+                    ImageMode::Files
+                },
+            };
+            export(&db, output, &options)
+        }
+        Command::Import {
+            source,
+            path,
+            dry_run,
+        } => import(&db, &config, source, path, dry_run),
+    }
+}
+
+fn print_items(items: &[ClipboardItem], json: bool) -> Result<(), String> {
+    if json {
+        let json = serde_json::to_string_pretty(items).map_err(|e| e.to_string())?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    for item in items {
+        println!(
+            "{}\t{}\t{}\t{}{}",
+            item.id.unwrap_or_default(),
+            item.timestamp,
+            item.data_type,
+            if item.is_pinned { "* " } else { "" },
+            preview(item)
+        );
+    }
+    Ok(())
+}
+
+fn preview(item: &ClipboardItem) -> String {
+    if item.is_sensitive {
+        return "••••••".to_string();
+    }
+    match item.kind.as_str() {
+        "image" => format!(
+            "[image {}x{}]",
+            item.width.unwrap_or_default(),
+            item.height.unwrap_or_default()
+        ),
+        "file" => {
+            let files: Vec<String> = serde_json::from_str(&item.content).unwrap_or_default();
+            format!("[files] {}", files.join(", "))
+        }
+        _ => {
+            let line = item
+                .content
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            if line.chars().count() > PREVIEW_CHARS {
+                let cut: String = line.chars().take(PREVIEW_CHARS - 1).collect();
+                format!("{}…", cut)
+            } else {
+                line
+            }
+        }
+    }
+}
+
+fn copy(db: &Database, id: i64) -> Result<(), String> {
+    let item = db
+        .get_item(id)
+        .map_err(|e| e.to_string())?
+        .filter(|item| item.deleted_at.is_none())
+        .ok_or_else(|| format!("Item {} not found", id))?;
+
+    let contents = match item.kind.as_str() {
+        "text" => {
+            let text = db.get_item_content(id).map_err(|e| e.to_string())?;
+            let mut contents = vec![ClipboardContent::Text(text)];
+            if let Some(html) = item.html_content {
+                contents.push(ClipboardContent::Html(html));
+            }
+            contents
+        }
+        "image" => {
+            let image = RustImageData::from_path(&item.content).map_err(|e| e.to_string())?;
+            vec![ClipboardContent::Image(image)]
+        }
+        "file" => {
+            let files: Vec<String> =
+                serde_json::from_str(&item.content).map_err(|e| e.to_string())?;
+            vec![ClipboardContent::Files(files)]
+        }
+        kind => return Err(format!("Can't copy {} items", kind)),
+    };
+
+    // On X11 the selection is owned by this process, so it only outlives the
+    // command when the app (or another clipboard manager) picks it up.
+    let ctx = ClipboardContext::new().map_err(|e| e.to_string())?;
+    ctx.set(contents).map_err(|e| e.to_string())
+}
+
+fn add(
+    db: &Database,
+    config: &AppConfig,
+    note: Option<String>,
+    sensitive: bool,
+) -> Result<(), String> {
+    let mut content = String::new();
+    io::stdin()
+        .read_to_string(&mut content)
+        .map_err(|e| e.to_string())?;
+    if content.trim().is_empty() {
+        return Err("Nothing to add, stdin was empty".to_string());
+    }
+
+    let classification = Classifier::new(&config.custom_classifiers).classify(&content);
+    let item = ClipboardItem {
+        id: None,
+        content,
+        kind: "text".to_string(),
+        timestamp: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        is_sensitive: sensitive,
+        is_pinned: false,
+        source_app: Some("clipboard-cli".to_string()),
+        data_type: classification.data_type,
+        collection_id: None,
+        note,
+        html_content: None,
+        language: classification.language,
+        ..Default::default()
+    };
+    let item = apply_size_limits(item, config)
+        .ok_or_else(|| "The text is over the size limit".to_string())?;
+
+    let (id, pruned_items) = db
+        .insert_item(&item, config.max_history_size)
+        .map_err(|e| e.to_string())?;
+    // Files of pruned images are removed by the app's storage cleanup
+    if !pruned_items.is_empty() {
+        eprintln!(
+            "Removed {} old items over the history limit",
+            pruned_items.len()
+        );
+    }
+    println!("{}", id);
+    Ok(())
+}
+
+fn confirm() -> Result<bool, String> {
+    if !io::stdin().is_terminal() {
+        return Err("Pass --yes to export decrypted sensitive content".to_string());
+    }
+    eprint!("Sensitive items will be exported in plain text. Continue? [y/N] ");
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .map_err(|e| e.to_string())?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn export(db: &Database, output: Option<PathBuf>, options: &ExportOptions) -> Result<(), String> {
+    let report = match output {
+        Some(path) => export_history(db, &path, options)?,
+        None => {
+            let (document, report) = export_to_string(db, options)?;
+            print!("{}", document);
+            report
+        }
+    };
+    eprintln!(
+        "Exported {} items ({} images, {} sensitive)",
+        report.items, report.images, report.sensitive
+    );
+    Ok(())
+}
+
+fn import(
+    db: &Database,
+    config: &AppConfig,
+    source: ImportSource,
+    path: Option<PathBuf>,
+    dry_run: bool,
+) -> Result<(), String> {
+    let path = path
+        .or_else(|| source.default_path())
+        .ok_or("This source has no default location, pass the file to import")?;
+    let image_store = ImageStore::new(app_data_dir().join("images"));
+    let report = import::run(
+        db,
+        &image_store,
+        config,
+        source,
+        &path,
+        dry_run,
+        |progress| {
+            if io::stderr().is_terminal() {
+                eprint!("\r{}/{} items", progress.processed, progress.total);
+            }
+        },
+    )?;
+    if io::stderr().is_terminal() {
+        eprintln!();
+    }
+
+    let verb = if dry_run { "Would import" } else { "Imported" };
+    println!(
+        "{} {} of {} items ({} pinned, {} images), {} duplicates, {} skipped",
+        verb,
+        report.imported,
+        report.found,
+        report.pinned,
+        report.images,
+        report.duplicates,
+        report.skipped
+    );
+    if !report.collections.is_empty() {
+        println!("Collections: {}", report.collections.join(", "));
+    }
+    if report.history_size > report.history_limit {
+        println!(
+            "The history will hold {} items, the oldest unpinned ones over the limit of {} are removed on the next copy",
+            report.history_size, report.history_limit
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn parse(args: &[&str]) -> Result<Command, clap::Error> {
+        Cli::try_parse_from(std::iter::once("clipboard-cli").chain(args.iter().copied()))
+            .map(|cli| cli.command)
+    }
+
+    #[test]
+    fn arguments_are_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn list_has_defaults() {
+        let Command::List {
+            limit,
+            data_type,
+            json,
+        } = parse(&["list"]).unwrap()
+        else {
+            panic!("expected list");
+        };
+        assert_eq!((limit, data_type, json), (20, None, false));
+
+        let Command::List {
+            limit, data_type, ..
+        } = parse(&["list", "--limit", "5", "--type", "url"]).unwrap()
+        else {
+            panic!("expected list");
+        };
+        assert_eq!((limit, data_type.as_deref()), (5, Some("url")));
+    }
+
+    #[test]
+    fn search_takes_a_query() {
+        let Command::Search {
+            query,
+            regex,
+            case_sensitive,
+            ..
+        } = parse(&["search", "^foo", "--regex", "--case-sensitive"]).unwrap()
+        else {
+            panic!("expected search");
+        };
+        assert_eq!(query, "^foo");
+        assert!(regex && case_sensitive);
+        assert!(parse(&["search"]).is_err());
+    }
+
+    #[test]
+    fn pin_and_copy_need_a_numeric_id() {
+        assert!(matches!(
+            parse(&["pin", "7", "--off"]).unwrap(),
+            Command::Pin { id: 7, off: true }
+        ));
+        assert!(matches!(
+            parse(&["copy", "3"]).unwrap(),
+            Command::Copy { id: 3 }
+        ));
+        assert!(parse(&["copy", "three"]).is_err());
+        assert!(parse(&["pin"]).is_err());
+    }
+
+    #[test]
+    fn export_options_are_parsed() {
+        let Command::Export {
+            format,
+            output,
+            sensitive,
+            yes,
+            embed_images,
+            ..
+        } = parse(&["export"]).unwrap()
+        else {
+            panic!("expected export");
+        };
+        assert_eq!(format, ExportFormat::Json);
+        assert_eq!(output, None);
+        assert_eq!(sensitive, SensitiveMode::Exclude);
+        assert!(!yes && !embed_images);
+
+        let Command::Export {
+            format,
+            output,
+            sensitive,
+            from,
+            ..
+        } = parse(&[
+            "export",
+            "--format",
+            "markdown",
+            "-o",
+            "out.md",
+            "--sensitive",
+            "redact",
+            "--from",
+            "2024-01-31",
+        ])
+        .unwrap()
+        else {
+            panic!("expected export");
+        };
+        assert_eq!(format, ExportFormat::Markdown);
+        assert_eq!(output, Some(PathBuf::from("out.md")));
+        assert_eq!(sensitive, SensitiveMode::Redact);
+        assert_eq!(from.as_deref(), Some("2024-01-31"));
+
+        assert!(parse(&["export", "--format", "xml"]).is_err());
+        assert!(parse(&["export", "--sensitive", "show"]).is_err());
+    }
+
+    #[test]
+    fn import_needs_a_known_source() {
+        let Command::Import {
+            source,
+            path,
+            dry_run,
+        } = parse(&["import", "copyq", "--dry-run"]).unwrap()
+        else {
+            panic!("expected import");
+        };
+        assert_eq!(source, ImportSource::CopyQ);
+        assert_eq!(path, None);
+        assert!(dry_run);
+        assert!(parse(&["import", "klipper"]).is_err());
+    }
+
+    #[test]
+    fn unknown_commands_are_rejected() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["frobnicate"]).is_err());
+        assert!(parse(&["list", "--limit", "many"]).is_err());
+    }
+
+    #[test]
+    fn previews_fit_on_one_line() {
+        let item = |content: &str| ClipboardItem {
+            content: content.into(),
+            kind: "text".into(),
+            ..Default::default()
+        };
+        assert_eq!(preview(&item("a\n  b\tc")), "a b c");
+        let long = preview(&item(&"x".repeat(200)));
+        assert_eq!(long.chars().count(), PREVIEW_CHARS);
+        assert!(long.ends_with('…'));
+
+        let secret = ClipboardItem {
+            is_sensitive: true,
+            ..item("hunter2")
+        };
+        assert_eq!(preview(&secret), "••••••");
+        let image = ClipboardItem {
+            kind: "image".into(),
+            width: Some(4),
+            height: Some(3),
+            ..item("/a.png")
+        };
+        assert_eq!(preview(&image), "[image 4x3]");
+        let files = ClipboardItem {
+            kind: "file".into(),
+            ..item(r#"["/a", "/b"]"#)
+        };
+        assert_eq!(preview(&files), "[files] /a, /b");
+    }
+}
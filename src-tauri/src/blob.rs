@@ -0,0 +1,84 @@
+use crate::crypto::Crypto;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Stores oversized payloads as gzip files outside the database.
+///
+/// Plain blobs are named after the SHA-256 of their content, so the same
+/// payload always maps to the same file. Sensitive blobs are compressed first
+/// and then encrypted with the same key as the database rows; they get a
+/// random name, so the name gives nothing away and no other row shares them.
+pub struct BlobStore {
+    dir: PathBuf,
+}
+
+impl BlobStore {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        let dir = dir.as_ref().to_path_buf();
+        if !dir.exists() {
+            let _ = fs::create_dir_all(&dir);
+        }
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Writes the payload and returns the blob name to keep in the row.
+    pub fn write(&self, data: &[u8], crypto: Option<&Crypto>) -> io::Result<String> {
+        let name = match crypto {
+            Some(_) => format!("{}.gz", uuid::Uuid::new_v4().simple()),
+            None => blob_name(data),
+        };
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let mut bytes = encoder.finish()?;
+
+        if let Some(crypto) = crypto {
+            bytes = crypto.encrypt_bytes(&bytes).map_err(io::Error::other)?;
+        }
+
+        fs::write(self.dir.join(&name), bytes)?;
+        Ok(name)
+    }
+
+    pub fn read(&self, name: &str, crypto: Option<&Crypto>) -> io::Result<Vec<u8>> {
+        let mut bytes = fs::read(self.dir.join(name))?;
+
+        if let Some(crypto) = crypto {
+            bytes = crypto.decrypt_bytes(&bytes).map_err(io::Error::other)?;
+        }
+
+        let mut data = Vec::new();
+        GzDecoder::new(bytes.as_slice()).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    pub fn remove(&self, name: &str) {
+        let path = self.dir.join(name);
+        if path.exists() {
+            if let Err(e) = fs::remove_file(&path) {
+                log::error!("Failed to delete blob {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+/// The name `write` gives the plain blob of `data`.
+pub fn blob_name(data: &[u8]) -> String {
+    format!("{}.gz", hex_digest(data))
+}
+
+fn hex_digest(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
@@ -0,0 +1,628 @@
+use crate::language::language_for;
+use crate::models::CustomClassifier;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use regex::Regex;
+use serde::Serialize;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::LazyLock;
+
+// Bump whenever the detectors change so existing rows get reclassified.
+pub const CLASSIFIER_VERSION: i64 = 1;
+
+// Below this confidence an item is just "text".
+const MIN_CONFIDENCE: f32 = 0.5;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Classification {
+    pub data_type: String,
+    pub confidence: f32,
+    // Programming or markup language, for code-like types
+    pub language: Option<String>,
+}
+
+/// A built-in detector: returns how confident it is that the (trimmed)
+/// content is of its type, 0.0 meaning "not at all".
+struct Detector {
+    data_type: &'static str,
+    detect: fn(&str) -> f32,
+}
+
+static DETECTORS: &[Detector] = &[
+    Detector {
+        data_type: "url",
+        detect: detect_url,
+    },
+    Detector {
+        data_type: "email",
+        detect: detect_email,
+    },
+    Detector {
+        data_type: "phone",
+        detect: detect_phone,
+    },
+    Detector {
+        data_type: "uuid",
+        detect: detect_uuid,
+    },
+    Detector {
+        data_type: "color",
+        detect: detect_color,
+    },
+    Detector {
+        data_type: "ip",
+        detect: detect_ip,
+    },
+    Detector {
+        data_type: "jwt",
+        detect: detect_jwt,
+    },
+    Detector {
+        data_type: "json",
+        detect: detect_json,
+    },
+    Detector {
+        data_type: "html",
+        detect: detect_html,
+    },
+    Detector {
+        data_type: "xml",
+        detect: detect_xml,
+    },
+    Detector {
+        data_type: "yaml",
+        detect: detect_yaml,
+    },
+    Detector {
+        data_type: "markdown",
+        detect: detect_markdown,
+    },
+    Detector {
+        data_type: "base64",
+        detect: detect_base64,
+    },
+    Detector {
+        data_type: "path",
+        detect: detect_path,
+    },
+    Detector {
+        data_type: "date",
+        detect: detect_date,
+    },
+    Detector {
+        data_type: "currency",
+        detect: detect_currency,
+    },
+    Detector {
+        data_type: "number",
+        detect: detect_number,
+    },
+    Detector {
+        data_type: "shell",
+        detect: detect_shell,
+    },
+    Detector {
+        data_type: "code",
+        detect: detect_code,
+    },
+];
+
+/// Runs the built-in detectors plus the user's regex classifiers.
+pub struct Classifier {
+    custom: Vec<(String, Regex, f32)>,
+}
+
+impl Classifier {
+    /// Compiles the user classifiers once; invalid patterns are logged and skipped.
+    pub fn new(custom: &[CustomClassifier]) -> Self {
+        let custom = custom
+            .iter()
+            .filter_map(|c| match Regex::new(&c.pattern) {
+                Ok(regex) => Some((c.name.clone(), regex, c.confidence)),
+                Err(e) => {
+                    log::error!("Invalid classifier pattern for '{}': {}", c.name, e);
+                    None
+                }
+            })
+            .collect();
+        Self { custom }
+    }
+
+    /// Returns the most likely data type of the content, and its language
+    /// when it is code.
+    pub fn classify(&self, content: &str) -> Classification {
+        let mut best = self
+            .candidates(content)
+            .into_iter()
+            .next()
+            .filter(|c| c.confidence >= MIN_CONFIDENCE)
+            .unwrap_or(Classification {
+                data_type: "text".to_string(),
+                confidence: 1.0,
+                language: None,
+            });
+        best.language = language_for(&best.data_type, content);
+        best
+    }
+
+    /// Every matching type, most confident first.
+    pub fn candidates(&self, content: &str) -> Vec<Classification> {
+        let trimmed = content.trim();
+        if trimmed.is_empty() {
+            return Vec::new();
+        }
+
+        let mut matches: Vec<Classification> = self
+            .custom
+            .iter()
+            .filter(|(_, regex, _)| regex.is_match(trimmed))
+            .map(|(name, _, confidence)| Classification {
+                data_type: name.clone(),
+                confidence: *confidence,
+                language: None,
+            })
+            .collect();
+
+        for detector in DETECTORS {
+            let confidence = (detector.detect)(trimmed);
+            if confidence > 0.0 {
+                matches.push(Classification {
+                    data_type: detector.data_type.to_string(),
+                    confidence,
+                    language: None,
+                });
+            }
+        }
+
+        // Stable sort, so user classifiers win ties over built-ins
+        matches.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        matches
+    }
+}
+
+fn regex(pattern: &str) -> Regex {
+    Regex::new(pattern).unwrap()
+}
+
+fn is_single_line(s: &str) -> bool {
+    !s.contains('\n')
+}
+
+fn detect_url(s: &str) -> f32 {
+    static RE: LazyLock<Regex> = LazyLock::new(|| regex(r"^(https?://|www\.)[^\s/$.?#].[^\s]*$"));
+    if RE.is_match(s) {
+        0.95
+    } else {
+        0.0
+    }
+}
+
+fn detect_email(s: &str) -> f32 {
+    static RE: LazyLock<Regex> =
+        LazyLock::new(|| regex(r"^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$"));
+    if RE.is_match(s) {
+        0.95
+    } else {
+        0.0
+    }
+}
+
+fn detect_phone(s: &str) -> f32 {
+    static RE: LazyLock<Regex> =
+        LazyLock::new(|| regex(r"^(\+\d{1,3}[- ]?)?\(?\d{3}\)?[- ]?\d{3}[- ]?\d{4}$"));
+    if RE.is_match(s) {
+        0.8
+    } else {
+        0.0
+    }
+}
+
+fn detect_uuid(s: &str) -> f32 {
+    static RE: LazyLock<Regex> = LazyLock::new(|| {
+        regex(
+            r"^\{?[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\}?$",
+        )
+    });
+    if RE.is_match(s) {
+        0.99
+    } else {
+        0.0
+    }
+}
+
+fn detect_color(s: &str) -> f32 {
+    static HEX: LazyLock<Regex> =
+        LazyLock::new(|| regex(r"^#([0-9a-fA-F]{3,4}|[0-9a-fA-F]{6}|[0-9a-fA-F]{8})$"));
+    static FUNC: LazyLock<Regex> = LazyLock::new(|| {
+        regex(
+            r"^(?i)(rgba?|hsla?)\(\s*[\d.]+%?\s*[,\s]\s*[\d.]+%?\s*[,\s]\s*[\d.]+%?\s*([,/]\s*[\d.]+%?\s*)?\)$",
+        )
+    });
+    if HEX.is_match(s) || FUNC.is_match(s) {
+        0.95
+    } else {
+        0.0
+    }
+}
+
+fn detect_ip(s: &str) -> f32 {
+    if s.parse::<IpAddr>().is_ok() || s.parse::<SocketAddr>().is_ok() {
+        0.95
+    } else {
+        0.0
+    }
+}
+
+fn detect_jwt(s: &str) -> f32 {
+    static RE: LazyLock<Regex> =
+        LazyLock::new(|| regex(r"^eyJ[A-Za-z0-9_-]+\.eyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]*$"));
+    if RE.is_match(s) {
+        0.97
+    } else {
+        0.0
+    }
+}
+
+fn detect_json(s: &str) -> f32 {
+    let structured =
+        (s.starts_with('{') && s.ends_with('}')) || (s.starts_with('[') && s.ends_with(']'));
+    if structured && serde_json::from_str::<serde_json::Value>(s).is_ok() {
+        0.95
+    } else {
+        0.0
+    }
+}
+
+fn looks_like_markup(s: &str) -> bool {
+    static TAG: LazyLock<Regex> = LazyLock::new(|| regex(r"</?[A-Za-z][\w:.-]*[^<>]*>"));
+    s.starts_with('<') && s.ends_with('>') && TAG.find_iter(s).take(2).count() >= 2
+}
+
+fn detect_html(s: &str) -> f32 {
+    static HTML_TAG: LazyLock<Regex> = LazyLock::new(|| {
+        regex(
+            r"(?i)<(!doctype html|html|head|body|div|span|p|a|img|table|ul|ol|li|h[1-6]|br|script|style)[\s>/]",
+        )
+    });
+    if looks_like_markup(s) && HTML_TAG.is_match(s) {
+        0.9
+    } else {
+        0.0
+    }
+}
+
+fn detect_xml(s: &str) -> f32 {
+    if s.starts_with("<?xml") {
+        0.95
+    } else if looks_like_markup(s) {
+        0.75
+    } else {
+        0.0
+    }
+}
+
+fn detect_yaml(s: &str) -> f32 {
+    static LINE: LazyLock<Regex> =
+        LazyLock::new(|| regex(r#"^\s*(- +)?([\w"'.-]+ *: *([^\s{].*)?|- +\S.*)$"#));
+    let lines: Vec<&str> = s
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#') && l.trim() != "---")
+        .collect();
+    if lines.len() < 2 || !lines.iter().any(|l| l.contains(':')) {
+        return 0.0;
+    }
+    // Code ends statements with these, YAML doesn't
+    if lines
+        .iter()
+        .any(|l| l.trim_end().ends_with(';') || l.trim_end().ends_with('{'))
+    {
+        return 0.0;
+    }
+
+    let matching = lines.iter().filter(|l| LINE.is_match(l)).count();
+    let ratio = matching as f32 / lines.len() as f32;
+    if ratio >= 0.9 {
+        if s.starts_with("---") {
+            0.85
+        } else {
+            0.7
+        }
+    } else {
+        0.0
+    }
+}
+
+fn detect_markdown(s: &str) -> f32 {
+    static BLOCK: LazyLock<Regex> =
+        LazyLock::new(|| regex(r"(?m)^(#{1,6} \S|```|> \S|[-*+] \S|\d+\. \S|\|.*\|\s*$|---+\s*$)"));
+    static INLINE: LazyLock<Regex> =
+        LazyLock::new(|| regex(r"\[[^\]\n]+\]\([^)\s]+\)|\*\*[^*\n]+\*\*|`[^`\n]+`"));
+
+    let score = BLOCK.find_iter(s).count() + INLINE.find_iter(s).count();
+    let has_heading = s.lines().any(|l| l.starts_with('#') && l.contains("# "));
+    match score {
+        0 | 1 => 0.0,
+        2 if !has_heading => 0.5,
+        _ => 0.65,
+    }
+}
+
+fn detect_base64(s: &str) -> f32 {
+    static RE: LazyLock<Regex> =
+        LazyLock::new(|| regex(r"^[A-Za-z0-9+/\r\n]+={0,2}$|^[A-Za-z0-9_\-\r\n]+={0,2}$"));
+    let compact: String = s.split_whitespace().collect();
+    if compact.len() < 20 || !compact.len().is_multiple_of(4) || !RE.is_match(s) {
+        return 0.0;
+    }
+    // Plain words and numbers are valid base64 too, so demand some mix
+    let has_digit = compact.chars().any(|c| c.is_ascii_digit());
+    let has_upper = compact.chars().any(|c| c.is_ascii_uppercase());
+    let has_lower = compact.chars().any(|c| c.is_ascii_lowercase());
+    if !(has_digit && has_upper && has_lower) {
+        return 0.0;
+    }
+
+    use base64::Engine;
+    let engine = base64::engine::general_purpose::STANDARD;
+    if engine.decode(&compact).is_ok() {
+        0.7
+    } else {
+        0.55
+    }
+}
+
+fn detect_path(s: &str) -> f32 {
+    static RE: LazyLock<Regex> =
+        LazyLock::new(|| regex(r#"^(~?/|\.{1,2}/|[A-Za-z]:[\\/]|\\\\[^\\\s]+\\)[^\n<>"|?*]*$"#));
+    if !RE.is_match(s) {
+        return 0.0;
+    }
+    // "/" followed by a sentence is more likely text than a path
+    if s.split_whitespace().count() > 3 {
+        0.5
+    } else {
+        0.85
+    }
+}
+
+fn detect_date(s: &str) -> f32 {
+    const DATE_FORMATS: &[&str] = &[
+        "%Y-%m-%d",
+        "%Y/%m/%d",
+        "%d.%m.%Y",
+        "%d/%m/%Y",
+        "%B %d, %Y",
+        "%d %B %Y",
+    ];
+    const DATE_TIME_FORMATS: &[&str] = &[
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y/%m/%d %H:%M:%S",
+    ];
+
+    if s.len() > 40 || !is_single_line(s) {
+        return 0.0;
+    }
+    if DateTime::parse_from_rfc3339(s).is_ok() || DateTime::parse_from_rfc2822(s).is_ok() {
+        return 0.95;
+    }
+    if DATE_TIME_FORMATS
+        .iter()
+        .any(|f| NaiveDateTime::parse_from_str(s, f).is_ok())
+    {
+        return 0.9;
+    }
+    if DATE_FORMATS
+        .iter()
+        .any(|f| NaiveDate::parse_from_str(s, f).is_ok())
+    {
+        return 0.85;
+    }
+    0.0
+}
+
+const NUMBER: &str = r"[-+]?(\d{1,3}([,_ ]\d{3})+|\d+)([.,]\d+)?";
+
+fn detect_currency(s: &str) -> f32 {
+    static RE: LazyLock<Regex> = LazyLock::new(|| {
+        let symbol = r"([$€£¥₹₩₽]|[A-Z]{3})";
+        regex(&format!(r"^({symbol} ?{NUMBER}|{NUMBER} ?{symbol})$"))
+    });
+    if RE.is_match(s) {
+        0.9
+    } else {
+        0.0
+    }
+}
+
+fn detect_number(s: &str) -> f32 {
+    static RE: LazyLock<Regex> = LazyLock::new(|| regex(&format!(r"^{NUMBER}([eE][-+]?\d+)?%?$")));
+    if RE.is_match(s) {
+        0.75
+    } else {
+        0.0
+    }
+}
+
+fn detect_shell(s: &str) -> f32 {
+    static RE: LazyLock<Regex> = LazyLock::new(|| {
+        regex(
+            r"^(\$ |# )?(sudo|git|cd|ls|npm|npx|pnpm|yarn|cargo|rustup|docker|kubectl|curl|wget|apt|apt-get|brew|pip|pip3|echo|export|ssh|scp|chmod|chown|mkdir|rm|cp|mv|grep|cat|tail|find|tar|make|python|python3|node|go|systemctl|journalctl)( |$)",
+        )
+    });
+    let lines: Vec<&str> = s.lines().filter(|l| !l.trim().is_empty()).collect();
+    if lines.is_empty() || lines.len() > 5 {
+        return 0.0;
+    }
+    let commands = lines.iter().filter(|l| RE.is_match(l.trim())).count();
+    if commands == 0 {
+        return 0.0;
+    }
+    if commands == lines.len() {
+        if s.starts_with("$ ") || s.contains(" | ") || s.contains(" && ") || s.contains(" --") {
+            0.85
+        } else {
+            0.7
+        }
+    } else {
+        0.0
+    }
+}
+
+fn detect_code(s: &str) -> f32 {
+    static KEYWORDS: LazyLock<Regex> = LazyLock::new(|| {
+        regex(
+            r"\b(function|class|def|import|from|const|let|var|public|private|static|return|fn|func|package|struct|impl|interface|enum|void|async|await|elif|lambda|namespace|include|SELECT|FROM|WHERE|INSERT|UPDATE|DELETE|JOIN)\b",
+        )
+    });
+    static SYNTAX: LazyLock<Regex> = LazyLock::new(|| {
+        regex(
+            r"=>|->|::|==|!=|&&|\|\||\+=|\(\)|\w\(.*\)\s*[{;:]?$|^(SELECT|INSERT|UPDATE|DELETE|CREATE|ALTER|WITH)\b",
+        )
+    });
+
+    let lines: Vec<&str> = s.lines().filter(|l| !l.trim().is_empty()).collect();
+    let keywords: std::collections::HashSet<&str> =
+        KEYWORDS.find_iter(s).map(|m| m.as_str()).collect();
+    let syntax = lines
+        .iter()
+        .filter(|l| SYNTAX.is_match(l.trim_end()))
+        .count();
+    let terminated = lines
+        .iter()
+        .filter(|l| {
+            let l = l.trim_end();
+            l.ends_with(';') || l.ends_with('{') || l.ends_with('}') || l.ends_with(':')
+        })
+        .count();
+    let indented = lines
+        .iter()
+        .filter(|l| l.starts_with("    ") || l.starts_with('\t'))
+        .count();
+
+    // Prose has keywords ("return", "class") too, so require structure as well
+    let structure = syntax + terminated + indented.min(lines.len() / 2);
+    if structure == 0 {
+        return 0.0;
+    }
+
+    let score = keywords.len() * 2 + structure;
+    if score < 4 {
+        return 0.0;
+    }
+    (0.4 + score as f32 * 0.05).min(0.85)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(content: &str) -> String {
+        Classifier::new(&[]).classify(content).data_type
+    }
+
+    fn custom(name: &str, pattern: &str, confidence: f32) -> CustomClassifier {
+        CustomClassifier {
+            name: name.into(),
+            pattern: pattern.into(),
+            confidence,
+        }
+    }
+
+    #[test]
+    fn detects_each_type() {
+        let cases = [
+            ("https://example.com/a?b=1", "url"),
+            ("www.example.com", "url"),
+            ("someone@example.com", "email"),
+            ("+1 555-123-4567", "phone"),
+            ("123e4567-e89b-12d3-a456-426614174000", "uuid"),
+            ("#ff00aa", "color"),
+            ("rgba(10, 20, 30, 0.5)", "color"),
+            ("192.168.1.1", "ip"),
+            ("::1", "ip"),
+            ("10.0.0.1:8080", "ip"),
+            ("eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiIxIn0.abc_DEF", "jwt"),
+            ("{\"a\": [1, 2]}", "json"),
+            ("<div><p>hi</p></div>", "html"),
+            ("<?xml version=\"1.0\"?><a/>", "xml"),
+            ("<note><to>x</to></note>", "xml"),
+            ("name: foo\nversion: 1\ndeps:\n  - a\n  - b", "yaml"),
+            (
+                "# Title\n\nSome **bold** and [a link](http://x).",
+                "markdown",
+            ),
+            ("SGVsbG8gV29ybGQhIFRoaXMgaXMgYjY0IQ==", "base64"),
+            ("/usr/local/bin/foo", "path"),
+            ("C:\\Users\\me\\file.txt", "path"),
+            ("~/notes.txt", "path"),
+            ("2024-01-05", "date"),
+            ("2024-01-05T10:00:00Z", "date"),
+            ("$1,234.50", "currency"),
+            ("12 EUR", "currency"),
+            ("3.14", "number"),
+            ("1,000,000", "number"),
+            ("git commit -m 'x' && git push", "shell"),
+            ("$ ls -la", "shell"),
+            ("fn main() {\n    println!(\"hi\");\n}", "code"),
+            ("def foo(x):\n    return x + 1\n", "code"),
+            ("const a = 1;\nlet b = a => a * 2;", "code"),
+        ];
+        for (content, expected) in cases {
+            assert_eq!(classify(content), expected, "{:?}", content);
+        }
+    }
+
+    #[test]
+    fn leaves_prose_alone() {
+        let cases = [
+            "Hello world, this is just prose.",
+            "I think if (we) go there { it } is fine",
+            "Use { and } around the name in the template",
+            "Please return the form by Friday.",
+            "We should return the class to the teacher.",
+            "#hashtag thoughts for the weekend",
+            "# not really a heading",
+            "Visit https://example.com for the details",
+            "Mail someone@example.com about it",
+            "Call me at 555-123-4567 tomorrow",
+            "hello",
+        ];
+        for content in cases {
+            assert_eq!(classify(content), "text", "{:?}", content);
+        }
+    }
+
+    #[test]
+    fn empty_content_has_no_candidates() {
+        assert!(Classifier::new(&[]).candidates("  \n ").is_empty());
+    }
+
+    #[test]
+    fn user_classifiers_come_first() {
+        let classifier = Classifier::new(&[
+            custom("ticket", r"^[A-Z]+-\d+$", 0.9),
+            custom("order", r"^\d+$", 0.75),
+            custom("broken", "(", 1.0),
+        ]);
+
+        assert_eq!(classifier.classify("JIRA-123").data_type, "ticket");
+        // Equally confident as the number detector, the user's type wins
+        let candidates = classifier.candidates("12345");
+        assert_eq!(candidates[0].data_type, "order");
+        assert_eq!(candidates[1].data_type, "number");
+        assert_eq!(classifier.classify("12345").data_type, "order");
+    }
+
+    #[test]
+    fn code_gets_a_language() {
+        let classifier = Classifier::new(&[]);
+        let rust = classifier.classify("fn main() {\n    println!(\"hi\");\n}");
+        assert_eq!(rust.language.as_deref(), Some("rust"));
+        assert_eq!(
+            classifier.classify("{\"a\": 1}").language.as_deref(),
+            Some("json")
+        );
+        assert_eq!(classifier.classify("just some words").language, None);
+    }
+}
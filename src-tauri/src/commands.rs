@@ -1,15 +1,31 @@
 use chrono::Local;
-use std::fs;
-use tauri::Emitter;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tauri::{Emitter, Manager};
 use tauri_plugin_global_shortcut::GlobalShortcutExt;
 
-use crate::models::{AppConfig, ClipboardItem, Collection};
+use crate::backup::{self, BackupReport, RestoreReport};
+use crate::classifier::Classifier;
+use crate::export::{self, ExportOptions, ExportReport};
+use crate::gc::{collect_garbage, GcReport};
+use crate::import::{self, ImportReport, ImportSource};
+use crate::language::language_for;
+use crate::merge::{merge, MergeOptions};
+use crate::models::{AppConfig, ClipboardItem, Collection, Revision};
 use crate::ocr::recognize_text;
+use crate::paste_mode::PasteMode;
+use crate::paste_stack::{self, PasteStackSnapshot, StackMode};
+use crate::revisions::{diff_lines, DiffLine};
+use crate::split::{part_timestamps, split, SplitBy};
 use crate::state::AppState;
+use crate::sync::{FolderSyncReport, FolderSyncStatus, Peer, SyncReport, SyncStatus};
+use crate::template::{Template, TemplateError};
+use crate::transforms::{apply_transforms, list_transforms, Content};
 use crate::tray::{update_pause_menu_item, update_tray_menu};
-use crate::utils::{classify_content, write_to_clipboard};
+use crate::utils::{apply_size_limits, write_to_clipboard};
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub fn get_history(
     state: tauri::State<AppState>,
     page: usize,
@@ -18,6 +34,8 @@ pub fn get_history(
     search_regex: Option<bool>,
     search_case_sensitive: Option<bool>,
     collection_id: Option<i64>,
+    language: Option<String>,
+    data_type: Option<String>,
 ) -> Vec<ClipboardItem> {
     log::info!(
         "get_history query: {:?}, regex: {:?}, case: {:?}",
@@ -34,26 +52,29 @@ pub fn get_history(
             search_regex.unwrap_or(false),
             search_case_sensitive.unwrap_or(false),
             collection_id,
+            language,
+            data_type,
         )
         .unwrap_or_default()
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub fn set_clipboard_item(
     app: tauri::AppHandle,
     content: String,
     kind: String,
     id: Option<i64>,
     html_content: Option<String>,
+    paste_mode: Option<String>,
+    inputs: Option<HashMap<String, String>>,
     state: tauri::State<AppState>,
-) -> Result<(), String> {
-    // Mark this content as set by the app to avoid duplication in monitor
-    // Do this BEFORE writing to clipboard to avoid race condition
-    if let Ok(mut last_change) = state.last_app_change.lock() {
-        *last_change = Some(content.clone());
-    }
-
-    let data_type = classify_content(&content);
+) -> Result<Option<usize>, String> {
+    let classification = state.classifier.lock().unwrap().classify(&content);
+    // Pasting an existing item keeps its type, so snippets get expanded
+    let stored_type = id
+        .and_then(|id| state.db.get_item(id).ok().flatten())
+        .map(|stored| stored.data_type);
 
     let item = ClipboardItem {
         id,
@@ -63,17 +84,23 @@ pub fn set_clipboard_item(
         is_sensitive: false, // Manually added items are assumed not sensitive
         is_pinned: false,
         source_app: None,
-        data_type,
+        data_type: stored_type.unwrap_or(classification.data_type),
         collection_id: None,
         note: None,
         html_content: html_content.clone(),
+        language: classification.language,
+        ..Default::default()
     };
 
     // Write to clipboard
-    if let Err(e) = write_to_clipboard(&app, &item) {
-        log::error!("Failed to write to clipboard: {}", e);
-        return Err(e);
-    }
+    let mode = PasteMode::for_paste(&state, paste_mode.as_deref());
+    let cursor_from_end = match write_to_clipboard(&app, &item, mode, inputs.as_ref()) {
+        Ok(cursor_from_end) => cursor_from_end,
+        Err(e) => {
+            log::error!("Failed to write to clipboard: {}", e);
+            return Err(e);
+        }
+    };
 
     // Update DB
     if let Some(id) = id {
@@ -82,22 +109,14 @@ pub fn set_clipboard_item(
             return Err(e.to_string());
         }
     } else {
-        let max_size = state.config.lock().unwrap().max_history_size;
-        match state.db.insert_item(&item, max_size) {
-            Ok(pruned_items) => {
+        let config = state.config.lock().unwrap().clone();
+        let Some(item) = apply_size_limits(item, &config) else {
+            return Ok(None);
+        };
+        match state.db.insert_item(&item, config.max_history_size) {
+            Ok((_, pruned_items)) => {
                 // Delete pruned images
-                for pruned in pruned_items {
-                    if pruned.kind == "image" {
-                        let path = std::path::Path::new(&pruned.content);
-                        if path.exists() {
-                            if let Err(e) = fs::remove_file(path) {
-                                log::error!("Failed to delete pruned image file: {}", e);
-                            } else {
-                                log::info!("Deleted pruned image file: {:?}", path);
-                            }
-                        }
-                    }
-                }
+                state.image_store.remove_all(&pruned_items);
             }
             Err(e) => {
                 log::error!("Failed to insert item into DB: {}", e);
@@ -109,14 +128,257 @@ pub fn set_clipboard_item(
     // Update Tray
     let history = state
         .db
-        .get_history(1, 20, None, false, false, None)
+        .get_history(1, 20, None, false, false, None, None, None)
         .unwrap_or_default();
     if let Err(e) = update_tray_menu(&app, &history) {
         log::error!("Failed to update tray menu: {}", e);
     }
 
     log::info!("Clipboard item set successfully");
-    Ok(())
+    Ok(cursor_from_end)
+}
+
+#[tauri::command]
+pub fn get_transforms() -> Vec<&'static str> {
+    list_transforms()
+}
+
+/// Writes a transformed copy of a text item to the clipboard. The stored item
+/// is left untouched; with `save_as_new` the result is added to the history.
+#[tauri::command]
+pub fn paste_transformed(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    id: i64,
+    transforms: Vec<String>,
+    save_as_new: Option<bool>,
+    paste_mode: Option<String>,
+) -> Result<String, String> {
+    let item = state
+        .db
+        .get_item(id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Item {} not found", id))?;
+    if item.kind != "text" {
+        return Err("Only text items can be transformed".to_string());
+    }
+
+    let text = state.db.get_item_content(id).map_err(|e| e.to_string())?;
+    let transformed = apply_transforms(
+        Content {
+            text,
+            html: item.html_content.clone(),
+        },
+        &transforms,
+    )?;
+
+    let classification = state.classifier.lock().unwrap().classify(&transformed.text);
+    let new_item = ClipboardItem {
+        id: None,
+        content: transformed.text.clone(),
+        kind: "text".to_string(),
+        timestamp: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        is_sensitive: item.is_sensitive,
+        is_pinned: false,
+        source_app: item.source_app.clone(),
+        data_type: classification.data_type,
+        collection_id: None,
+        note: None,
+        html_content: transformed.html,
+        language: classification.language,
+        ..Default::default()
+    };
+
+    let mode = PasteMode::for_paste(&state, paste_mode.as_deref());
+    if let Err(e) = write_to_clipboard(&app, &new_item, mode, None) {
+        log::error!("Failed to write to clipboard: {}", e);
+        return Err(e);
+    }
+
+    if save_as_new.unwrap_or(false) {
+        let config = state.config.lock().unwrap().clone();
+        if let Some(new_item) = apply_size_limits(new_item, &config) {
+            let (_, pruned_items) = state
+                .db
+                .insert_item(&new_item, config.max_history_size)
+                .map_err(|e| e.to_string())?;
+            state.image_store.remove_all(&pruned_items);
+
+            let history = state
+                .db
+                .get_history(1, 20, None, false, false, None, None, None)
+                .unwrap_or_default();
+            let _ = update_tray_menu(&app, &history);
+            let _ = app.emit("clipboard-update", ());
+        }
+    }
+
+    Ok(transformed.text)
+}
+
+/// Joins text items into a new history item, in the order of `ids`.
+/// The result is sensitive when any part is and remembers its sources.
+#[tauri::command]
+pub fn merge_items(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    ids: Vec<i64>,
+    separator: Option<String>,
+    options: Option<MergeOptions>,
+) -> Result<ClipboardItem, String> {
+    if ids.len() < 2 {
+        return Err("Select at least two items to merge".to_string());
+    }
+
+    let mut parts = Vec::with_capacity(ids.len());
+    let mut sources = Vec::with_capacity(ids.len());
+    for &id in &ids {
+        let item = state
+            .db
+            .get_item(id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Item {} not found", id))?;
+        if item.kind != "text" {
+            return Err("Only text items can be merged".to_string());
+        }
+        let text = state.db.get_item_content(id).map_err(|e| e.to_string())?;
+        parts.push(Content {
+            text,
+            html: item.html_content.clone(),
+        });
+        sources.push(item);
+    }
+
+    let merged = merge(
+        parts,
+        separator.as_deref().unwrap_or("\n"),
+        &options.unwrap_or_default(),
+    );
+    let source_app = sources[0].source_app.clone();
+    let same_app = sources.iter().all(|item| item.source_app == source_app);
+
+    let classification = state.classifier.lock().unwrap().classify(&merged.text);
+    let item = ClipboardItem {
+        id: None,
+        content: merged.text,
+        kind: "text".to_string(),
+        timestamp: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        is_sensitive: sources.iter().any(|item| item.is_sensitive),
+        is_pinned: false,
+        source_app: if same_app { source_app } else { None },
+        data_type: classification.data_type,
+        collection_id: None,
+        note: None,
+        html_content: merged.html,
+        language: classification.language,
+        source_ids: Some(ids),
+        ..Default::default()
+    };
+
+    let config = state.config.lock().unwrap().clone();
+    let item = apply_size_limits(item, &config)
+        .ok_or_else(|| "The merged text is over the size limit".to_string())?;
+    let (id, pruned_items) = state
+        .db
+        .insert_item(&item, config.max_history_size)
+        .map_err(|e| e.to_string())?;
+    state.image_store.remove_all(&pruned_items);
+
+    let history = state
+        .db
+        .get_history(1, 20, None, false, false, None, None, None)
+        .unwrap_or_default();
+    let _ = update_tray_menu(&app, &history);
+    let _ = app.emit("clipboard-update", ());
+
+    state
+        .db
+        .get_item(id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Item {} not found", id))
+}
+
+/// Turns each part of a text item into its own history entry, in order.
+/// The parts keep the item's sensitivity and source app; with
+/// `push_to_stack` they are queued on the paste stack as well.
+#[tauri::command]
+pub fn split_item(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    id: i64,
+    by: SplitBy,
+    push_to_stack: Option<bool>,
+) -> Result<Vec<ClipboardItem>, String> {
+    let item = state
+        .db
+        .get_item(id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Item {} not found", id))?;
+    if item.kind != "text" {
+        return Err("Only text items can be split".to_string());
+    }
+
+    let text = state.db.get_item_content(id).map_err(|e| e.to_string())?;
+    let parts = split(&text, &by)?;
+    if parts.len() < 2 {
+        return Err("Nothing to split".to_string());
+    }
+
+    let config = state.config.lock().unwrap().clone();
+    let timestamps = part_timestamps(Local::now(), parts.len());
+    let mut ids = Vec::with_capacity(parts.len());
+    for (part, timestamp) in parts.into_iter().zip(timestamps) {
+        let classification = state.classifier.lock().unwrap().classify(&part);
+        let child = ClipboardItem {
+            id: None,
+            content: part,
+            kind: "text".to_string(),
+            timestamp,
+            is_sensitive: item.is_sensitive,
+            is_pinned: false,
+            source_app: item.source_app.clone(),
+            data_type: classification.data_type,
+            collection_id: None,
+            note: None,
+            html_content: None,
+            language: classification.language,
+            source_ids: Some(vec![id]),
+            ..Default::default()
+        };
+        let Some(child) = apply_size_limits(child, &config) else {
+            continue;
+        };
+        let (child_id, pruned_items) = state
+            .db
+            .insert_item(&child, config.max_history_size)
+            .map_err(|e| e.to_string())?;
+        state.image_store.remove_all(&pruned_items);
+        ids.push(child_id);
+    }
+
+    if push_to_stack.unwrap_or(false) {
+        state
+            .paste_stack
+            .lock()
+            .map_err(|e| e.to_string())?
+            .push(&ids);
+        paste_stack::notify_changed(&app);
+    }
+
+    let history = state
+        .db
+        .get_history(1, 20, None, false, false, None, None, None)
+        .unwrap_or_default();
+    let _ = update_tray_menu(&app, &history);
+    let _ = app.emit("clipboard-update", ());
+
+    let mut children = Vec::with_capacity(ids.len());
+    for child_id in ids {
+        if let Some(child) = state.db.get_item(child_id).map_err(|e| e.to_string())? {
+            children.push(child);
+        }
+    }
+    Ok(children)
 }
 
 #[tauri::command]
@@ -127,16 +389,7 @@ pub fn delete_item(
 ) -> Result<(), String> {
     match state.db.delete_item(index) {
         Ok(Some(item)) => {
-            if item.kind == "image" {
-                let path = std::path::Path::new(&item.content);
-                if path.exists() {
-                    if let Err(e) = fs::remove_file(path) {
-                        log::error!("Failed to delete image file: {}", e);
-                    } else {
-                        log::info!("Deleted image file: {:?}", path);
-                    }
-                }
-            }
+            *state.last_deleted.lock().unwrap() = item.id.into_iter().collect();
         }
         Ok(None) => {
             log::warn!("Item at index {} not found", index);
@@ -150,7 +403,7 @@ pub fn delete_item(
     // Update Tray
     let history = state
         .db
-        .get_history(1, 20, None, false, false, None)
+        .get_history(1, 20, None, false, false, None, None, None)
         .unwrap_or_default();
     if let Err(e) = update_tray_menu(&app, &history) {
         log::error!("Failed to update tray menu after delete: {}", e);
@@ -199,7 +452,39 @@ pub fn update_clipboard_item_content(
     data_type: String,
     note: Option<String>,
 ) -> Result<(), String> {
-    match state.db.update_content(id, content, data_type, note) {
+    let current = state
+        .db
+        .get_item(id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Item {} not found", id))?;
+
+    // A type different from the stored one is a manual override. Otherwise
+    // the type follows the edited content, unless it was overridden before.
+    let data_type_manual = current.data_type_manual || data_type != current.data_type;
+    let data_type = if data_type_manual || current.kind != "text" {
+        data_type
+    } else {
+        state
+            .classifier
+            .lock()
+            .unwrap()
+            .classify(&content)
+            .data_type
+    };
+    if data_type == "snippet" {
+        Template::parse(&content).map_err(|e| e.to_string())?;
+    }
+    let language = language_for(&data_type, &content);
+
+    match state.db.update_content(
+        id,
+        content,
+        None,
+        data_type,
+        language,
+        data_type_manual,
+        note,
+    ) {
         Ok(_) => {
             log::info!("Updated item content for id {}", id);
             Ok(())
@@ -211,6 +496,131 @@ pub fn update_clipboard_item_content(
     }
 }
 
+/// Earlier versions of an item, newest first.
+#[tauri::command]
+pub fn get_item_revisions(state: tauri::State<AppState>, id: i64) -> Result<Vec<Revision>, String> {
+    state.db.get_revisions(id).map_err(|e| e.to_string())
+}
+
+/// Line diff between two revisions of an item. Without `to` the revision is
+/// compared with the current content.
+#[tauri::command]
+pub fn diff_revisions(
+    state: tauri::State<AppState>,
+    id: i64,
+    from: i64,
+    to: Option<i64>,
+) -> Result<Vec<DiffLine>, String> {
+    let revision_content = |revision_id: i64| {
+        state
+            .db
+            .get_revision(id, revision_id)
+            .map_err(|e| e.to_string())?
+            .map(|revision| revision.content)
+            .ok_or_else(|| format!("Revision {} not found", revision_id))
+    };
+
+    let old = revision_content(from)?;
+    let new = match to {
+        Some(to) => revision_content(to)?,
+        None => state.db.get_item_content(id).map_err(|e| e.to_string())?,
+    };
+    Ok(diff_lines(&old, &new))
+}
+
+/// Restores an earlier version. The version being replaced becomes a
+/// revision itself, so a revert can be undone the same way.
+#[tauri::command]
+pub fn revert_item(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    id: i64,
+    revision_id: i64,
+) -> Result<(), String> {
+    let current = state
+        .db
+        .get_item(id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Item {} not found", id))?;
+    let revision = state
+        .db
+        .get_revision(id, revision_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Revision {} not found", revision_id))?;
+
+    let language = language_for(&revision.data_type, &revision.content);
+    state
+        .db
+        .update_content(
+            id,
+            revision.content,
+            revision.html_content,
+            revision.data_type,
+            language,
+            current.data_type_manual,
+            revision.note,
+        )
+        .map_err(|e| e.to_string())?;
+
+    log::info!("Reverted item {} to revision {}", id, revision_id);
+    let _ = app.emit("clipboard-update", ());
+    Ok(())
+}
+
+/// Checks a snippet body and returns the names of its inputs, or the first
+/// syntax error with its position.
+#[tauri::command]
+pub fn validate_snippet(content: String) -> Result<Vec<String>, TemplateError> {
+    Template::parse(&content).map(|template| template.inputs())
+}
+
+#[tauri::command]
+pub fn create_snippet(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    content: String,
+    note: Option<String>,
+    collection_id: Option<i64>,
+) -> Result<(), String> {
+    Template::parse(&content).map_err(|e| e.to_string())?;
+
+    let item = ClipboardItem {
+        id: None,
+        content,
+        kind: "text".to_string(),
+        timestamp: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        is_sensitive: false,
+        is_pinned: false,
+        source_app: None,
+        data_type: "snippet".to_string(),
+        collection_id,
+        note: note.clone(),
+        html_content: None,
+        data_type_manual: true,
+        ..Default::default()
+    };
+
+    let max_size = state.config.lock().unwrap().max_history_size;
+    let (id, pruned_items) = state
+        .db
+        .insert_item(&item, max_size)
+        .map_err(|e| e.to_string())?;
+    state.image_store.remove_all(&pruned_items);
+    // The body may already be in the history, that item becomes the snippet
+    state
+        .db
+        .make_snippet(id, note, collection_id)
+        .map_err(|e| e.to_string())?;
+
+    let history = state
+        .db
+        .get_history(1, 20, None, false, false, None, None, None)
+        .unwrap_or_default();
+    let _ = update_tray_menu(&app, &history);
+    let _ = app.emit("clipboard-update", ());
+    Ok(())
+}
+
 #[tauri::command]
 pub fn clear_history(app: tauri::AppHandle, state: tauri::State<AppState>) -> Result<(), String> {
     let (clear_pinned, clear_collected) = {
@@ -223,16 +633,7 @@ pub fn clear_history(app: tauri::AppHandle, state: tauri::State<AppState>) -> Re
 
     match state.db.clear_history(clear_pinned, clear_collected) {
         Ok(items) => {
-            for item in items {
-                if item.kind == "image" {
-                    let path = std::path::Path::new(&item.content);
-                    if path.exists() {
-                        if let Err(e) = fs::remove_file(path) {
-                            log::error!("Failed to delete image file: {}", e);
-                        }
-                    }
-                }
-            }
+            *state.last_deleted.lock().unwrap() = items.iter().filter_map(|item| item.id).collect();
         }
         Err(e) => {
             log::error!("Failed to clear history: {}", e);
@@ -240,11 +641,346 @@ pub fn clear_history(app: tauri::AppHandle, state: tauri::State<AppState>) -> Re
         }
     }
 
-    // Update Tray
-    let _ = update_tray_menu(&app, &[]);
+    // Pinned or collected items may have been kept
+    let history = state
+        .db
+        .get_history(1, 20, None, false, false, None, None, None)
+        .unwrap_or_default();
+    let _ = update_tray_menu(&app, &history);
+    let _ = app.emit("clipboard-update", ());
     Ok(())
 }
 
+/// Restores what the last delete or clear moved to the trash. Returns the
+/// number of restored items, 0 when there is nothing to undo.
+#[tauri::command]
+pub fn undo_last_action(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+) -> Result<usize, String> {
+    let ids = std::mem::take(&mut *state.last_deleted.lock().unwrap());
+    restore(&app, &state, &ids)
+}
+
+#[tauri::command]
+pub fn get_trash(state: tauri::State<AppState>) -> Result<Vec<ClipboardItem>, String> {
+    state.db.get_trash().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn restore_from_trash(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    ids: Vec<i64>,
+) -> Result<usize, String> {
+    restore(&app, &state, &ids)
+}
+
+fn restore(app: &tauri::AppHandle, state: &AppState, ids: &[i64]) -> Result<usize, String> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    let restored = state.db.restore_items(ids).map_err(|e| e.to_string())?;
+    log::info!("Restored {} items from the trash", restored);
+
+    let history = state
+        .db
+        .get_history(1, 20, None, false, false, None, None, None)
+        .unwrap_or_default();
+    let _ = update_tray_menu(app, &history);
+    let _ = app.emit("clipboard-update", ());
+    Ok(restored)
+}
+
+/// Permanently deletes everything in the trash, including image files.
+#[tauri::command]
+pub fn empty_trash(state: tauri::State<AppState>) -> Result<usize, String> {
+    let items = state.db.purge_trash(None).map_err(|e| e.to_string())?;
+    state.image_store.remove_all(&items);
+    state.last_deleted.lock().unwrap().clear();
+    log::info!("Emptied trash, {} items removed", items.len());
+    Ok(items.len())
+}
+
+/// Writes the history, filtered by `options`, to `path`. Decrypted sensitive
+/// content needs `confirm_decrypt`, which the UI only sets after asking.
+#[tauri::command]
+pub fn export_history(
+    state: tauri::State<AppState>,
+    path: String,
+    options: ExportOptions,
+) -> Result<ExportReport, String> {
+    let report = export::export_history(&state.db, Path::new(&path), &options)?;
+    log::info!("Exported {} items to {}", report.items, path);
+    Ok(report)
+}
+
+/// Imports the history of another clipboard manager, from its default
+/// location unless `path` is given. Reports progress as "import-progress";
+/// with `dry_run` nothing is written.
+#[tauri::command]
+pub async fn import_history(
+    app: tauri::AppHandle,
+    source: ImportSource,
+    path: Option<String>,
+    dry_run: Option<bool>,
+) -> Result<ImportReport, String> {
+    let dry_run = dry_run.unwrap_or(false);
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => source
+            .default_path()
+            .ok_or("This source has no default location, choose a file")?,
+    };
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let config = state.config.lock().unwrap().clone();
+        let report = import::run(
+            &state.db,
+            &state.image_store,
+            &config,
+            source,
+            &path,
+            dry_run,
+            |progress| {
+                let _ = app.emit("import-progress", &progress);
+            },
+        )?;
+
+        if !dry_run && report.imported > 0 {
+            log::info!("Imported {} items from {}", report.imported, path.display());
+            let history = state
+                .db
+                .get_history(1, 20, None, false, false, None, None, None)
+                .unwrap_or_default();
+            let _ = update_tray_menu(&app, &history);
+            let _ = app.emit("clipboard-update", ());
+        }
+        Ok(report)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Writes an encrypted backup of the history, images, config and key to `path`.
+#[tauri::command]
+pub async fn create_backup(
+    app: tauri::AppHandle,
+    path: String,
+    passphrase: String,
+) -> Result<BackupReport, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let report = backup::create_backup(
+            &crate::app_data_dir(),
+            &state.db,
+            Path::new(&path),
+            &passphrase,
+        )?;
+        log::info!("Backup written to {}", report.path);
+        Ok(report)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Checks the backup at `path` and restarts the app to put it in place.
+/// The current data is left untouched when the backup can't be used.
+#[tauri::command]
+pub async fn restore_backup(
+    app: tauri::AppHandle,
+    path: String,
+    passphrase: String,
+) -> Result<RestoreReport, String> {
+    let report = tauri::async_runtime::spawn_blocking(move || {
+        backup::restore_backup(&crate::app_data_dir(), Path::new(&path), &passphrase)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    app.request_restart();
+    Ok(report)
+}
+
+/// Sets up automatic backups. The passphrase is kept encrypted with the
+/// app's key; it only has to be given the first time.
+#[tauri::command]
+pub fn set_auto_backup(
+    enabled: bool,
+    interval_hours: u32,
+    keep: usize,
+    dir: String,
+    passphrase: Option<String>,
+    state: tauri::State<AppState>,
+) -> Result<AppConfig, String> {
+    let mut config = state.config.lock().unwrap();
+    let mut new_config = AppConfig {
+        auto_backup_enabled: enabled,
+        auto_backup_interval_hours: interval_hours.max(1),
+        auto_backup_keep: keep.max(1),
+        auto_backup_dir: dir,
+        ..config.clone()
+    };
+    if let Some(passphrase) = passphrase.filter(|p| !p.is_empty()) {
+        new_config.auto_backup_passphrase = state.crypto.encrypt(&passphrase)?;
+    }
+    if enabled && new_config.auto_backup_passphrase.is_empty() {
+        return Err("Automatic backups need a passphrase".to_string());
+    }
+
+    new_config
+        .save(&state.config_path)
+        .map_err(|e| e.to_string())?;
+    *config = new_config.clone();
+    Ok(new_config)
+}
+
+#[tauri::command]
+pub fn get_sync_status(state: tauri::State<AppState>) -> SyncStatus {
+    state.sync.status()
+}
+
+/// Turns LAN sync on or off. An empty device name means the host name.
+#[tauri::command]
+pub fn set_sync_enabled(
+    enabled: bool,
+    device_name: Option<String>,
+    state: tauri::State<AppState>,
+) -> Result<SyncStatus, String> {
+    {
+        let mut config = state.config.lock().unwrap();
+        let new_config = AppConfig {
+            sync_enabled: enabled,
+            sync_device_name: device_name
+                .map(|name| name.trim().to_string())
+                .unwrap_or_else(|| config.sync_device_name.clone()),
+            ..config.clone()
+        };
+        new_config
+            .save(&state.config_path)
+            .map_err(|e| e.to_string())?;
+        *config = new_config;
+    }
+
+    if enabled {
+        state.sync.start()?;
+    } else {
+        state.sync.stop();
+    }
+    Ok(state.sync.status())
+}
+
+/// A one-time code to enter on the device that should pair with this one.
+#[tauri::command]
+pub fn start_sync_pairing(state: tauri::State<AppState>) -> Result<String, String> {
+    state.sync.start_pairing()
+}
+
+/// Pairs with the device at `address` ("ip:port") showing `code`.
+#[tauri::command]
+pub async fn pair_sync_device(
+    app: tauri::AppHandle,
+    address: String,
+    code: String,
+) -> Result<Peer, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let peer = state.sync.pair(address.trim(), &code)?;
+        log::info!("Paired with {} ({})", peer.name, peer.device_id);
+        Ok(peer)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub fn unpair_sync_device(device_id: String, state: tauri::State<AppState>) -> Result<(), String> {
+    state.sync.unpair(&device_id)
+}
+
+/// Syncs with every paired device that can be reached right away.
+#[tauri::command]
+pub async fn sync_now(app: tauri::AppHandle) -> Result<Vec<SyncReport>, String> {
+    if !app.state::<AppState>().sync.status().running {
+        return Err("Sync is turned off".to_string());
+    }
+    tauri::async_runtime::spawn_blocking(move || app.state::<AppState>().sync.sync_all())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Excludes an item from syncing, or includes it again.
+#[tauri::command]
+pub fn set_item_sync(
+    id: i64,
+    enabled: bool,
+    state: tauri::State<AppState>,
+) -> Result<bool, String> {
+    state
+        .db
+        .set_no_sync(id, !enabled)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_folder_sync_status(state: tauri::State<AppState>) -> FolderSyncStatus {
+    state.folder_sync.status()
+}
+
+/// Sets up syncing through a shared folder. The passphrase has to be the
+/// same on all devices; it is kept encrypted with the app's key and only
+/// has to be given the first time.
+#[tauri::command]
+pub fn set_folder_sync(
+    enabled: bool,
+    dir: String,
+    passphrase: Option<String>,
+    state: tauri::State<AppState>,
+) -> Result<FolderSyncStatus, String> {
+    {
+        let mut config = state.config.lock().unwrap();
+        let mut new_config = AppConfig {
+            folder_sync_enabled: enabled,
+            folder_sync_dir: dir,
+            ..config.clone()
+        };
+        if let Some(passphrase) = passphrase.filter(|p| !p.is_empty()) {
+            new_config.folder_sync_passphrase = state.crypto.encrypt(&passphrase)?;
+        }
+        if enabled && new_config.folder_sync_passphrase.is_empty() {
+            return Err("Folder sync needs a passphrase".to_string());
+        }
+        if enabled && !Path::new(&new_config.folder_sync_dir).is_dir() {
+            return Err("Choose an existing folder to sync through".to_string());
+        }
+
+        new_config
+            .save(&state.config_path)
+            .map_err(|e| e.to_string())?;
+        *config = new_config;
+    }
+
+    // Restarted so a new folder or passphrase is picked up
+    state.folder_sync.stop();
+    if enabled {
+        state.folder_sync.start()?;
+    }
+    Ok(state.folder_sync.status())
+}
+
+/// Writes local changes to the sync folder and applies the other devices'
+/// right away.
+#[tauri::command]
+pub async fn folder_sync_now(app: tauri::AppHandle) -> Result<FolderSyncReport, String> {
+    if !app.state::<AppState>().folder_sync.status().running {
+        return Err("Folder sync is turned off".to_string());
+    }
+    tauri::async_runtime::spawn_blocking(move || app.state::<AppState>().folder_sync.sync_now())
+        .await
+        .map_err(|e| e.to_string())?
+}
+
 #[tauri::command]
 pub fn get_config(state: tauri::State<AppState>) -> AppConfig {
     let config = state.config.lock().unwrap();
@@ -252,6 +988,7 @@ pub fn get_config(state: tauri::State<AppState>) -> AppConfig {
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub fn save_config(
     app: tauri::AppHandle,
     shortcut: String,
@@ -264,10 +1001,8 @@ pub fn save_config(
     clear_collected_on_clear: bool,
     state: tauri::State<AppState>,
 ) -> Result<(), String> {
-    let old_shortcut = {
-        let config = state.config.lock().unwrap();
-        config.shortcut.clone()
-    };
+    let old_config = state.config.lock().unwrap().clone();
+    let old_shortcut = old_config.shortcut.clone();
 
     let new_config = AppConfig {
         shortcut: shortcut.clone(),
@@ -278,18 +1013,19 @@ pub fn save_config(
         compact_mode,
         clear_pinned_on_clear,
         clear_collected_on_clear,
+        // Options not exposed in the settings dialog are kept as they are
+        ..old_config
     };
 
     // Save to file
-    if let Ok(json) = serde_json::to_string_pretty(&new_config) {
-        if let Err(e) = fs::write(&state.config_path, json) {
-            log::error!("Failed to save config file: {}", e);
-            return Err(e.to_string());
-        }
+    if let Err(e) = new_config.save(&state.config_path) {
+        log::error!("Failed to save config file: {}", e);
+        return Err(e.to_string());
     }
 
     // Update state
     {
+        *state.classifier.lock().unwrap() = Classifier::new(&new_config.custom_classifiers);
         let mut config = state.config.lock().unwrap();
         *config = new_config;
     }
@@ -364,15 +1100,127 @@ pub fn get_history_count(state: tauri::State<AppState>) -> usize {
 }
 
 #[tauri::command]
-pub fn set_paste_stack(
+pub fn get_paste_stack(state: tauri::State<AppState>) -> PasteStackSnapshot {
+    state.paste_stack.lock().unwrap().snapshot()
+}
+
+#[tauri::command]
+pub fn paste_stack_push(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    ids: Vec<i64>,
+) -> Result<(), String> {
+    state
+        .paste_stack
+        .lock()
+        .map_err(|e| e.to_string())?
+        .push(&ids);
+    paste_stack::notify_changed(&app);
+    Ok(())
+}
+
+/// Pastes the next item of the stack, like the global shortcut does.
+/// Returns `None` when the stack is empty.
+#[tauri::command]
+pub fn paste_stack_pop(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    paste_mode: Option<String>,
+) -> Result<Option<ClipboardItem>, String> {
+    let mode = PasteMode::for_paste(&state, paste_mode.as_deref());
+    paste_stack::paste_next(&app, mode)
+}
+
+/// The item the next pop would paste, without removing it.
+#[tauri::command]
+pub fn paste_stack_peek(state: tauri::State<AppState>) -> Result<Option<ClipboardItem>, String> {
+    let id = state.paste_stack.lock().map_err(|e| e.to_string())?.peek();
+    match id {
+        Some(id) => state.db.get_item(id).map_err(|e| e.to_string()),
+        None => Ok(None),
+    }
+}
+
+#[tauri::command]
+pub fn paste_stack_reorder(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    ids: Vec<i64>,
+) -> Result<(), String> {
+    state
+        .paste_stack
+        .lock()
+        .map_err(|e| e.to_string())?
+        .reorder(ids)?;
+    paste_stack::notify_changed(&app);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn paste_stack_clear(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+) -> Result<(), String> {
+    state.paste_stack.lock().map_err(|e| e.to_string())?.clear();
+    paste_stack::notify_changed(&app);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn set_paste_stack_mode(
+    app: tauri::AppHandle,
     state: tauri::State<AppState>,
-    items: Vec<ClipboardItem>,
+    mode: String,
 ) -> Result<(), String> {
-    let mut stack = state.paste_stack.lock().map_err(|e| e.to_string())?;
-    *stack = items;
+    let mode =
+        StackMode::from_name(&mode).ok_or_else(|| format!("Unknown paste stack mode: {}", mode))?;
+    state
+        .paste_stack
+        .lock()
+        .map_err(|e| e.to_string())?
+        .set_mode(mode);
+    paste_stack::notify_changed(&app);
     Ok(())
 }
 
+#[tauri::command]
+pub fn find_similar_images(
+    state: tauri::State<AppState>,
+    id: i64,
+    max_distance: Option<u32>,
+) -> Result<Vec<ClipboardItem>, String> {
+    // Looser than the dedup distance so visually related shots show up too
+    let max_distance = max_distance.unwrap_or(12);
+    state
+        .db
+        .find_similar_images(id, max_distance)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn run_storage_gc(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+) -> Result<GcReport, String> {
+    let report = collect_garbage(&state.db, &state.image_store)?;
+    if !report.missing_items.is_empty() {
+        let _ = app.emit("clipboard-update", ());
+    }
+    Ok(report)
+}
+
+#[tauri::command]
+pub fn reclassify_history(app: tauri::AppHandle, force: Option<bool>) {
+    let force = force.unwrap_or(false);
+    std::thread::spawn(
+        move || match crate::reclassify::reclassify_history(&app, force) {
+            Ok(true) => {}
+            Ok(false) => log::info!("Reclassification already running"),
+            Err(e) => log::error!("Failed to reclassify history: {}", e),
+        },
+    );
+}
+
 #[tauri::command]
 pub async fn ocr_image(image_path: String) -> Result<String, String> {
     log::info!("Starting OCR for image: {}", image_path);
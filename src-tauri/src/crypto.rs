@@ -14,7 +14,7 @@ impl Crypto {
     pub fn new<P: AsRef<Path>>(key_path: P) -> Self {
         let key = if key_path.as_ref().exists() {
             let bytes = fs::read(&key_path).expect("Failed to read key file");
-            Key::<Aes256Gcm>::from_slice(&bytes).clone()
+            *Key::<Aes256Gcm>::from_slice(&bytes)
         } else {
             let mut key = Key::<Aes256Gcm>::default();
             OsRng.fill_bytes(&mut key);
@@ -28,18 +28,24 @@ impl Crypto {
     }
 
     pub fn encrypt(&self, plaintext: &str) -> Result<String, String> {
+        let combined = self.encrypt_bytes(plaintext.as_bytes())?;
+
+        // Format: nonce + ciphertext (base64 encoded)
+        use base64::{engine::general_purpose, Engine as _};
+        Ok(general_purpose::STANDARD.encode(combined))
+    }
+
+    /// Encrypts raw bytes, returning nonce + ciphertext without any encoding.
+    pub fn encrypt_bytes(&self, plaintext: &[u8]) -> Result<Vec<u8>, String> {
         let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
         let ciphertext = self
             .cipher
-            .encrypt(&nonce, plaintext.as_bytes())
+            .encrypt(&nonce, plaintext)
             .map_err(|e| e.to_string())?;
 
-        // Format: nonce + ciphertext (base64 encoded)
         let mut combined = nonce.to_vec();
         combined.extend(ciphertext);
-
-        use base64::{engine::general_purpose, Engine as _};
-        Ok(general_purpose::STANDARD.encode(combined))
+        Ok(combined)
     }
 
     pub fn decrypt(&self, encrypted_base64: &str) -> Result<String, String> {
@@ -48,18 +54,21 @@ impl Crypto {
             .decode(encrypted_base64)
             .map_err(|e| e.to_string())?;
 
-        if decoded.len() < 12 {
+        let plaintext_bytes = self.decrypt_bytes(&decoded)?;
+        String::from_utf8(plaintext_bytes).map_err(|e| e.to_string())
+    }
+
+    /// Reverses `encrypt_bytes`.
+    pub fn decrypt_bytes(&self, encrypted: &[u8]) -> Result<Vec<u8>, String> {
+        if encrypted.len() < 12 {
             return Err("Invalid encrypted data length".to_string());
         }
 
-        let (nonce_bytes, ciphertext) = decoded.split_at(12);
+        let (nonce_bytes, ciphertext) = encrypted.split_at(12);
         let nonce = Nonce::from_slice(nonce_bytes);
 
-        let plaintext_bytes = self
-            .cipher
+        self.cipher
             .decrypt(nonce, ciphertext)
-            .map_err(|e| e.to_string())?;
-
-        String::from_utf8(plaintext_bytes).map_err(|e| e.to_string())
+            .map_err(|e| e.to_string())
     }
 }
@@ -1,19 +1,96 @@
+use crate::blob::{blob_name, BlobStore};
+use crate::classifier::CLASSIFIER_VERSION;
 use crate::crypto::Crypto;
-use crate::models::{ClipboardItem, Collection};
+use crate::image_store::StoredImage;
+use crate::models::{ClipboardItem, Collection, Revision, SyncRecord};
+use crate::phash;
 use chrono::Local;
 use regex::Regex;
-use rusqlite::{functions::FunctionFlags, params, Connection, OptionalExtension, Result};
+use rusqlite::{functions::FunctionFlags, params, Connection, OptionalExtension, Result, Row};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Columns read by `item_from_row`, in order.
+const HISTORY_COLUMNS: &str = "id, content, kind, timestamp, is_sensitive, is_pinned, source_app, data_type, collection_id, note, html_content, content_length, blob_path, thumbnail_path, image_width, image_height, byte_size, image_hash, file_missing, language, data_type_manual, source_ids, deleted_at, no_sync, image_digest";
+
+// Number of characters kept in the row when the full content lives in a blob.
+const BLOB_PREVIEW_CHARS: usize = 2000;
+
+/// The most items the external APIs return per page.
+pub const MAX_PAGE_SIZE: usize = 500;
+
+// Older edits of an item are dropped beyond this many revisions.
+const MAX_REVISIONS_PER_ITEM: usize = 50;
+
+// Current time in milliseconds, as SQL.
+const NOW_MS: &str = "CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)";
+
+// Give new rows a sync id and record local changes to the pin, note and
+// collection. Rows written by sync set change_seq themselves and are left alone.
+const SYNC_TRIGGERS: &str = "
+CREATE TRIGGER IF NOT EXISTS history_sync_insert AFTER INSERT ON history
+WHEN NEW.sync_id IS NULL
+BEGIN
+    UPDATE sync_counter SET value = value + 1;
+    UPDATE history SET sync_id = lower(hex(randomblob(16))),
+        change_seq = (SELECT value FROM sync_counter) WHERE id = NEW.id;
+END;
+CREATE TRIGGER IF NOT EXISTS history_sync_pin AFTER UPDATE OF is_pinned ON history
+WHEN NEW.is_pinned IS NOT OLD.is_pinned AND NEW.change_seq = OLD.change_seq
+BEGIN
+    UPDATE sync_counter SET value = value + 1;
+    UPDATE history SET pin_changed_at = {now},
+        change_seq = (SELECT value FROM sync_counter) WHERE id = NEW.id;
+END;
+CREATE TRIGGER IF NOT EXISTS history_sync_note AFTER UPDATE OF note ON history
+WHEN NEW.note IS NOT OLD.note AND NEW.change_seq = OLD.change_seq
+BEGIN
+    UPDATE sync_counter SET value = value + 1;
+    UPDATE history SET note_changed_at = {now},
+        change_seq = (SELECT value FROM sync_counter) WHERE id = NEW.id;
+END;
+CREATE TRIGGER IF NOT EXISTS history_sync_collection AFTER UPDATE OF collection_id ON history
+WHEN NEW.collection_id IS NOT OLD.collection_id AND NEW.change_seq = OLD.change_seq
+BEGIN
+    UPDATE sync_counter SET value = value + 1;
+    UPDATE history SET collection_changed_at = {now},
+        change_seq = (SELECT value FROM sync_counter) WHERE id = NEW.id;
+END;
+";
+
+// Items moved to the trash here are remembered by sync id, so the deletion
+// can be passed on. Deletions applied by sync set change_seq themselves.
+const SYNC_DELETE_TRIGGER: &str = "
+CREATE TRIGGER IF NOT EXISTS history_sync_delete AFTER UPDATE OF deleted_at ON history
+WHEN OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL AND NEW.change_seq = OLD.change_seq
+    AND NEW.no_sync = 0 AND NEW.is_sensitive = 0 AND NEW.kind != 'file'
+BEGIN
+    UPDATE sync_counter SET value = value + 1;
+    INSERT OR REPLACE INTO sync_deletions (sync_id, change_seq)
+        VALUES (NEW.sync_id, (SELECT value FROM sync_counter));
+END;
+";
 
 pub struct Database {
     conn: Mutex<Connection>,
     crypto: Arc<Crypto>,
+    blobs: BlobStore,
 }
 
 impl Database {
     pub fn new<P: AsRef<Path>>(path: P, crypto: Arc<Crypto>) -> Result<Self> {
+        let blobs_dir = path
+            .as_ref()
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("blobs");
         let mut conn = Connection::open(path)?;
+        // The CLI opens the same file while the app is running: WAL lets
+        // readers work during a write, and writers wait for each other
+        // instead of failing with SQLITE_BUSY.
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.busy_timeout(Duration::from_secs(5))?;
 
         let tx = conn.transaction()?;
         let version: i32 = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
@@ -85,6 +162,185 @@ impl Database {
             tx.execute("PRAGMA user_version = 6", [])?;
         }
 
+        if version < 7 {
+            let _ = tx.execute("ALTER TABLE history ADD COLUMN content_length INTEGER", []);
+            let _ = tx.execute("ALTER TABLE history ADD COLUMN blob_path TEXT", []);
+            tx.execute("PRAGMA user_version = 7", [])?;
+        }
+
+        if version < 8 {
+            let _ = tx.execute("ALTER TABLE history ADD COLUMN thumbnail_path TEXT", []);
+            let _ = tx.execute("ALTER TABLE history ADD COLUMN image_width INTEGER", []);
+            let _ = tx.execute("ALTER TABLE history ADD COLUMN image_height INTEGER", []);
+            let _ = tx.execute("ALTER TABLE history ADD COLUMN byte_size INTEGER", []);
+            tx.execute("PRAGMA user_version = 8", [])?;
+        }
+
+        if version < 9 {
+            let _ = tx.execute("ALTER TABLE history ADD COLUMN image_hash INTEGER", []);
+            tx.execute("PRAGMA user_version = 9", [])?;
+        }
+
+        if version < 10 {
+            let _ = tx.execute(
+                "ALTER TABLE history ADD COLUMN file_missing BOOLEAN NOT NULL DEFAULT 0",
+                [],
+            );
+            tx.execute("PRAGMA user_version = 10", [])?;
+        }
+
+        if version < 11 {
+            let _ = tx.execute("ALTER TABLE history ADD COLUMN language TEXT", []);
+            tx.execute(
+                "CREATE INDEX IF NOT EXISTS idx_language ON history (language)",
+                [],
+            )?;
+            tx.execute("PRAGMA user_version = 11", [])?;
+        }
+
+        if version < 12 {
+            // Existing rows keep version 0 so the reclassify job picks them up
+            let _ = tx.execute(
+                "ALTER TABLE history ADD COLUMN classifier_version INTEGER NOT NULL DEFAULT 0",
+                [],
+            );
+            let _ = tx.execute(
+                "ALTER TABLE history ADD COLUMN data_type_manual BOOLEAN NOT NULL DEFAULT 0",
+                [],
+            );
+            tx.execute("PRAGMA user_version = 12", [])?;
+        }
+
+        if version < 13 {
+            // JSON array of the ids an item was merged or split from
+            let _ = tx.execute("ALTER TABLE history ADD COLUMN source_ids TEXT", []);
+            tx.execute("PRAGMA user_version = 13", [])?;
+        }
+
+        if version < 14 {
+            // Content and HTML are encrypted when the item is sensitive
+            tx.execute(
+                "CREATE TABLE IF NOT EXISTS history_revisions (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    item_id INTEGER NOT NULL,
+                    content TEXT NOT NULL,
+                    html_content TEXT,
+                    data_type TEXT NOT NULL,
+                    note TEXT,
+                    created_at TEXT NOT NULL
+                )",
+                [],
+            )?;
+            tx.execute(
+                "CREATE INDEX IF NOT EXISTS idx_revisions_item ON history_revisions (item_id)",
+                [],
+            )?;
+            tx.execute("PRAGMA user_version = 14", [])?;
+        }
+
+        if version < 15 {
+            // Deleted rows stay in the trash until it is emptied
+            let _ = tx.execute("ALTER TABLE history ADD COLUMN deleted_at TEXT", []);
+            tx.execute(
+                "CREATE INDEX IF NOT EXISTS idx_deleted_at ON history (deleted_at)",
+                [],
+            )?;
+            tx.execute("PRAGMA user_version = 15", [])?;
+        }
+
+        if version < 16 {
+            // Sync: an id shared by all devices, the change counter other
+            // devices pull by and when pin, note and collection last changed
+            let _ = tx.execute("ALTER TABLE history ADD COLUMN sync_id TEXT", []);
+            for column in [
+                "change_seq INTEGER NOT NULL DEFAULT 0",
+                "no_sync BOOLEAN NOT NULL DEFAULT 0",
+                "pin_changed_at INTEGER NOT NULL DEFAULT 0",
+                "note_changed_at INTEGER NOT NULL DEFAULT 0",
+                "collection_changed_at INTEGER NOT NULL DEFAULT 0",
+            ] {
+                let _ = tx.execute(&format!("ALTER TABLE history ADD COLUMN {}", column), []);
+            }
+            // Only goes up, so nothing is missed when the newest rows get deleted
+            tx.execute(
+                "CREATE TABLE IF NOT EXISTS sync_counter (value INTEGER NOT NULL)",
+                [],
+            )?;
+            tx.execute(
+                "INSERT INTO sync_counter (value) SELECT COALESCE(MAX(id), 0) FROM history",
+                [],
+            )?;
+            tx.execute(
+                "UPDATE history SET sync_id = lower(hex(randomblob(16))), change_seq = id WHERE sync_id IS NULL",
+                [],
+            )?;
+            tx.execute(
+                "CREATE UNIQUE INDEX IF NOT EXISTS idx_sync_id ON history (sync_id)",
+                [],
+            )?;
+            tx.execute(
+                "CREATE INDEX IF NOT EXISTS idx_change_seq ON history (change_seq)",
+                [],
+            )?;
+            tx.execute_batch(&SYNC_TRIGGERS.replace("{now}", NOW_MS))?;
+            tx.execute("PRAGMA user_version = 16", [])?;
+        }
+
+        if version < 17 {
+            tx.execute(
+                "CREATE TABLE IF NOT EXISTS sync_deletions (
+                    sync_id TEXT PRIMARY KEY,
+                    change_seq INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            tx.execute(
+                "CREATE INDEX IF NOT EXISTS idx_sync_deletions_seq ON sync_deletions (change_seq)",
+                [],
+            )?;
+            tx.execute_batch(SYNC_DELETE_TRIGGER)?;
+            tx.execute("PRAGMA user_version = 17", [])?;
+        }
+
+        if version < 18 {
+            separate_sensitive_blobs(&tx, &BlobStore::new(&blobs_dir), &crypto)?;
+            tx.execute("PRAGMA user_version = 18", [])?;
+        }
+
+        if version < 19 {
+            // Changes applied from another device, by change_seq, so they
+            // aren't sent back to where they came from
+            tx.execute(
+                "CREATE TABLE IF NOT EXISTS sync_sources (
+                    change_seq INTEGER PRIMARY KEY,
+                    source TEXT NOT NULL
+                )",
+                [],
+            )?;
+            tx.execute("PRAGMA user_version = 19", [])?;
+        }
+
+        if version < 20 {
+            // The dHash only says two images look alike, identity needs the
+            // pixels. Existing images are hashed by the image store upgrade.
+            let _ = tx.execute("ALTER TABLE history ADD COLUMN image_digest TEXT", []);
+            tx.execute(
+                "CREATE INDEX IF NOT EXISTS idx_image_digest ON history (image_digest)",
+                [],
+            )?;
+            tx.execute("PRAGMA user_version = 20", [])?;
+        }
+
+        if version < 21 {
+            // Revisions of items stored in a blob point at the blob instead
+            // of holding another copy of it
+            let _ = tx.execute(
+                "ALTER TABLE history_revisions ADD COLUMN blob_path TEXT",
+                [],
+            );
+            tx.execute("PRAGMA user_version = 21", [])?;
+        }
+
         tx.commit()?;
 
         // Add REGEXP function
@@ -111,9 +367,205 @@ impl Database {
         Ok(Self {
             conn: Mutex::new(conn),
             crypto,
+            blobs: BlobStore::new(blobs_dir),
+        })
+    }
+
+    fn item_from_row(&self, row: &Row) -> Result<ClipboardItem> {
+        let id: i64 = row.get(0)?;
+        let content: String = row.get(1)?;
+        let kind: String = row.get(2)?;
+        let is_sensitive: bool = row.get(4)?;
+        let html_content: Option<String> = row.get(10)?;
+
+        let final_content = if is_sensitive && kind == "text" {
+            self.crypto.decrypt(&content).unwrap_or(content)
+        } else {
+            content
+        };
+
+        let final_html = if let Some(html) = html_content {
+            if is_sensitive {
+                Some(self.crypto.decrypt(&html).unwrap_or(html))
+            } else {
+                Some(html)
+            }
+        } else {
+            None
+        };
+
+        Ok(ClipboardItem {
+            id: Some(id),
+            content: final_content,
+            kind,
+            timestamp: row.get(3)?,
+            is_sensitive,
+            is_pinned: row.get(5)?,
+            source_app: row.get(6)?,
+            data_type: row.get(7)?,
+            collection_id: row.get(8)?,
+            note: row.get(9)?,
+            html_content: final_html,
+            content_length: row.get(11)?,
+            blob_path: row.get(12)?,
+            store_as_blob: false,
+            thumbnail: row.get(13)?,
+            width: row.get(14)?,
+            height: row.get(15)?,
+            byte_size: row.get(16)?,
+            image_hash: row.get(17)?,
+            file_missing: row.get(18)?,
+            language: row.get(19)?,
+            data_type_manual: row.get(20)?,
+            source_ids: row
+                .get::<_, Option<String>>(21)?
+                .and_then(|ids| serde_json::from_str(&ids).ok()),
+            deleted_at: row.get(22)?,
+            no_sync: row.get(23)?,
+            image_digest: row.get(24)?,
         })
     }
 
+    fn blob_crypto(&self, is_sensitive: bool) -> Option<&Crypto> {
+        if is_sensitive {
+            Some(self.crypto.as_ref())
+        } else {
+            None
+        }
+    }
+
+    /// Removes the blob file once no row or revision refers to it any more.
+    fn release_blob(&self, conn: &Connection, name: &str) -> Result<()> {
+        let in_use: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM history WHERE blob_path = ?1) OR EXISTS (SELECT 1 FROM history_revisions WHERE blob_path = ?1)",
+            params![name],
+            |row| row.get(0),
+        )?;
+        if !in_use {
+            self.blobs.remove(name);
+        }
+        Ok(())
+    }
+
+    fn encrypt_if(&self, is_sensitive: bool, text: String) -> String {
+        if is_sensitive {
+            self.crypto.encrypt(&text).unwrap_or(text)
+        } else {
+            text
+        }
+    }
+
+    fn decrypt_if(&self, is_sensitive: bool, text: String) -> String {
+        if is_sensitive {
+            self.crypto.decrypt(&text).unwrap_or(text)
+        } else {
+            text
+        }
+    }
+
+    // Full text of an item, read from its blob if it has one.
+    fn content_of(&self, conn: &Connection, id: i64) -> Result<String> {
+        let (content, is_sensitive, kind, blob_path): (String, bool, String, Option<String>) = conn
+            .query_row(
+                "SELECT content, is_sensitive, kind, blob_path FROM history WHERE id = ?1",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )?;
+
+        if let Some(name) = blob_path {
+            let data = self
+                .blobs
+                .read(&name, self.blob_crypto(is_sensitive))
+                .map_err(blob_error)?;
+            return Ok(String::from_utf8_lossy(&data).into_owned());
+        }
+
+        Ok(self.decrypt_if(is_sensitive && kind == "text", content))
+    }
+
+    // Saves the current version of an item before it is overwritten.
+    fn record_revision(&self, conn: &Connection, id: i64) -> Result<()> {
+        let (content, kind, is_sensitive, html_content, data_type, note, blob_path): (
+            String,
+            String,
+            bool,
+            Option<String>,
+            String,
+            Option<String>,
+            Option<String>,
+        ) = conn.query_row(
+            "SELECT content, kind, is_sensitive, html_content, data_type, note, blob_path FROM history WHERE id = ?1",
+            params![id],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                ))
+            },
+        )?;
+
+        // Blob items keep their preview and share the blob with the revision
+        let content = if blob_path.is_some() {
+            self.decrypt_if(is_sensitive && kind == "text", content)
+        } else {
+            self.content_of(conn, id)?
+        };
+
+        // The row's HTML is already encrypted for sensitive items
+        conn.execute(
+            "INSERT INTO history_revisions (item_id, content, html_content, data_type, note, created_at, blob_path) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                id,
+                self.encrypt_if(is_sensitive, content),
+                html_content,
+                data_type,
+                note,
+                Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                blob_path
+            ],
+        )?;
+        self.remove_revisions(
+            conn,
+            "item_id = ?1 AND id NOT IN (SELECT id FROM history_revisions WHERE item_id = ?1 ORDER BY id DESC LIMIT ?2)",
+            params![id, MAX_REVISIONS_PER_ITEM],
+        )
+    }
+
+    // Drops the revisions of items that no longer exist.
+    fn remove_orphan_revisions(&self, conn: &Connection) -> Result<()> {
+        self.remove_revisions(conn, "item_id NOT IN (SELECT id FROM history)", params![])
+    }
+
+    // Deletes the revisions matching `condition` and releases their blobs.
+    fn remove_revisions(
+        &self,
+        conn: &Connection,
+        condition: &str,
+        params: impl rusqlite::Params + Clone,
+    ) -> Result<()> {
+        let names = conn
+            .prepare(&format!(
+                "SELECT DISTINCT blob_path FROM history_revisions WHERE blob_path IS NOT NULL AND {}",
+                condition
+            ))?
+            .query_map(params.clone(), |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>>>()?;
+        conn.execute(
+            &format!("DELETE FROM history_revisions WHERE {}", condition),
+            params,
+        )?;
+        for name in names {
+            self.release_blob(conn, &name)?;
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn get_history(
         &self,
         page: usize,
@@ -122,11 +574,16 @@ impl Database {
         search_regex: bool,
         search_case_sensitive: bool,
         collection_id: Option<i64>,
+        language: Option<String>,
+        data_type: Option<String>,
     ) -> Result<Vec<ClipboardItem>> {
         let conn = self.conn.lock().unwrap();
-        let offset = (page - 1) * page_size;
+        let offset = page.saturating_sub(1).saturating_mul(page_size);
 
-        let mut sql = String::from("SELECT id, content, kind, timestamp, is_sensitive, is_pinned, source_app, data_type, collection_id, note, html_content FROM history WHERE 1=1");
+        let mut sql = format!(
+            "SELECT {} FROM history WHERE deleted_at IS NULL",
+            HISTORY_COLUMNS
+        );
         let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
 
         if let Some(q) = query {
@@ -142,29 +599,27 @@ impl Database {
                     };
                     params.push(Box::new(final_query.clone()));
                     params.push(Box::new(final_query));
+                } else if search_case_sensitive {
+                    // SQLite LIKE is case-insensitive by default for ASCII characters.
+                    // To make it case-sensitive, we can use GLOB which is case-sensitive (and uses * instead of %),
+                    // OR we can use the `PRAGMA case_sensitive_like = ON` command (but that is connection wide),
+                    // OR we can simply use the binary comparison for exact match or INSTR for substrings,
+                    // BUT for pattern matching usually `GLOB` is the way.
+                    // However, GLOB uses * and ?, not %. So we need to reformat the pattern or just use INSTR > 0 for simpler specific contains check.
+                    // Since `q` here is a substring (like %q%), INSTR(content, q) > 0 does the job and is case-sensitive by default (if not explicitly COLLATE NOCASE).
+                    // Wait, sqlite INSTR IS case-sensitive by default? No, it depends on collation?
+                    // Actually, GLOB is the standard way for case-sensitive pattern matching in SQLite.
+                    // wildcard: * matches any sequence, ? matches any single char.
+
+                    sql.push_str(" AND (content GLOB ? OR note GLOB ?)");
+                    let pattern = format!("*{}*", q); // Using * for GLOB
+                    params.push(Box::new(pattern.clone()));
+                    params.push(Box::new(pattern));
                 } else {
-                    if search_case_sensitive {
-                        // SQLite LIKE is case-insensitive by default for ASCII characters.
-                        // To make it case-sensitive, we can use GLOB which is case-sensitive (and uses * instead of %),
-                        // OR we can use the `PRAGMA case_sensitive_like = ON` command (but that is connection wide),
-                        // OR we can simply use the binary comparison for exact match or INSTR for substrings,
-                        // BUT for pattern matching usually `GLOB` is the way.
-                        // However, GLOB uses * and ?, not %. So we need to reformat the pattern or just use INSTR > 0 for simpler specific contains check.
-                        // Since `q` here is a substring (like %q%), INSTR(content, q) > 0 does the job and is case-sensitive by default (if not explicitly COLLATE NOCASE).
-                        // Wait, sqlite INSTR IS case-sensitive by default? No, it depends on collation?
-                        // Actually, GLOB is the standard way for case-sensitive pattern matching in SQLite.
-                        // wildcard: * matches any sequence, ? matches any single char.
-
-                        sql.push_str(" AND (content GLOB ? OR note GLOB ?)");
-                        let pattern = format!("*{}*", q); // Using * for GLOB
-                        params.push(Box::new(pattern.clone()));
-                        params.push(Box::new(pattern));
-                    } else {
-                        sql.push_str(" AND (content LIKE ? OR note LIKE ?)");
-                        let pattern = format!("%{}%", q);
-                        params.push(Box::new(pattern.clone()));
-                        params.push(Box::new(pattern));
-                    }
+                    sql.push_str(" AND (content LIKE ? OR note LIKE ?)");
+                    let pattern = format!("%{}%", q);
+                    params.push(Box::new(pattern.clone()));
+                    params.push(Box::new(pattern));
                 }
             }
         }
@@ -174,6 +629,16 @@ impl Database {
             params.push(Box::new(cid));
         }
 
+        if let Some(lang) = language {
+            sql.push_str(" AND language = ?");
+            params.push(Box::new(lang));
+        }
+
+        if let Some(data_type) = data_type {
+            sql.push_str(" AND data_type = ?");
+            params.push(Box::new(data_type));
+        }
+
         sql.push_str(" ORDER BY is_pinned DESC, timestamp DESC LIMIT ? OFFSET ?");
         params.push(Box::new(page_size));
         params.push(Box::new(offset));
@@ -183,49 +648,7 @@ impl Database {
         // Convert params to references for query_map
         let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
 
-        let rows = stmt.query_map(params_refs.as_slice(), |row| {
-            let id: i64 = row.get(0)?;
-            let content: String = row.get(1)?;
-            let kind: String = row.get(2)?;
-            let timestamp: String = row.get(3)?;
-            let is_sensitive: bool = row.get(4)?;
-            let is_pinned: bool = row.get(5)?;
-            let source_app: Option<String> = row.get(6)?;
-            let data_type: String = row.get(7)?;
-            let collection_id: Option<i64> = row.get(8)?;
-            let note: Option<String> = row.get(9)?;
-            let html_content: Option<String> = row.get(10)?;
-
-            let final_content = if is_sensitive && kind == "text" {
-                self.crypto.decrypt(&content).unwrap_or(content)
-            } else {
-                content
-            };
-
-            let final_html = if let Some(html) = html_content {
-                if is_sensitive {
-                    Some(self.crypto.decrypt(&html).unwrap_or(html))
-                } else {
-                    Some(html)
-                }
-            } else {
-                None
-            };
-
-            Ok(ClipboardItem {
-                id: Some(id),
-                content: final_content,
-                kind,
-                timestamp,
-                is_sensitive,
-                is_pinned,
-                source_app,
-                data_type,
-                collection_id,
-                note,
-                html_content: final_html,
-            })
-        })?;
+        let rows = stmt.query_map(params_refs.as_slice(), |row| self.item_from_row(row))?;
 
         let mut items = Vec::new();
         for row in rows {
@@ -234,18 +657,37 @@ impl Database {
         Ok(items)
     }
 
-    pub fn insert_item(&self, item: &ClipboardItem, max_size: usize) -> Result<Vec<ClipboardItem>> {
+    /// Inserts an item, or refreshes the existing row with the same content.
+    /// Returns the id of that row and the items pruned to stay under `max_size`.
+    pub fn insert_item(
+        &self,
+        item: &ClipboardItem,
+        max_size: usize,
+    ) -> Result<(i64, Vec<ClipboardItem>)> {
         let conn = self.conn.lock().unwrap();
         let mut pruned_items = Vec::new();
 
-        let content_to_store = if item.is_sensitive && item.kind == "text" {
-            self.crypto
-                .encrypt(&item.content)
-                .unwrap_or(item.content.clone())
+        // Oversized text keeps only a preview in the row, the rest goes to a blob
+        let mut content_length = item.content_length;
+        let mut blob_path = None;
+        let stored_text = if item.store_as_blob {
+            let name = self
+                .blobs
+                .write(item.content.as_bytes(), self.blob_crypto(item.is_sensitive))
+                .map_err(blob_error)?;
+            content_length = Some(item.content.len() as i64);
+            blob_path = Some(name);
+            preview_of(&item.content)
         } else {
             item.content.clone()
         };
 
+        let content_to_store = if item.is_sensitive && item.kind == "text" {
+            self.crypto.encrypt(&stored_text).unwrap_or(stored_text)
+        } else {
+            stored_text
+        };
+
         let html_to_store = if let Some(html) = &item.html_content {
             if item.is_sensitive {
                 Some(self.crypto.encrypt(html).unwrap_or(html.clone()))
@@ -256,16 +698,39 @@ impl Database {
             None
         };
 
-        // Deduplicate: Update timestamp, source_app and html_content if exists
-        let updated_count = conn.execute(
-            "UPDATE history SET timestamp = ?1, source_app = ?2, html_content = ?3 WHERE content = ?4 AND kind = ?5",
-            params![item.timestamp, item.source_app, html_to_store, content_to_store, item.kind],
-        )?;
+        let source_ids = item
+            .source_ids
+            .as_ref()
+            .map(|ids| serde_json::to_string(ids).unwrap_or_default());
+
+        // Deduplicate: Update timestamp, source_app and html_content if exists.
+        // Copying something that is in the trash brings it back.
+        let existing_id: Option<i64> = if let Some(name) = &blob_path {
+            conn.query_row(
+                "SELECT id FROM history WHERE blob_path = ?1 AND is_sensitive = ?2",
+                params![name, item.is_sensitive],
+                |row| row.get(0),
+            )
+            .optional()?
+        } else {
+            conn.query_row(
+                "SELECT id FROM history WHERE content = ?1 AND kind = ?2",
+                params![content_to_store, item.kind],
+                |row| row.get(0),
+            )
+            .optional()?
+        };
 
-        if updated_count == 0 {
+        let id = if let Some(id) = existing_id {
+            conn.execute(
+                "UPDATE history SET timestamp = ?1, source_app = ?2, html_content = ?3, source_ids = COALESCE(?4, source_ids), deleted_at = NULL WHERE id = ?5",
+                params![item.timestamp, item.source_app, html_to_store, source_ids, id],
+            )?;
+            id
+        } else {
             // Insert new item
             conn.execute(
-                "INSERT INTO history (content, kind, timestamp, is_sensitive, is_pinned, source_app, data_type, collection_id, note, html_content) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                "INSERT INTO history (content, kind, timestamp, is_sensitive, is_pinned, source_app, data_type, collection_id, note, html_content, content_length, blob_path, thumbnail_path, image_width, image_height, byte_size, image_hash, language, data_type_manual, classifier_version, source_ids, image_digest) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22)",
                 params![
                     content_to_store,
                     item.kind,
@@ -276,82 +741,58 @@ impl Database {
                     item.data_type,
                     item.collection_id,
                     item.note,
-                    html_to_store
+                    html_to_store,
+                    content_length,
+                    blob_path,
+                    item.thumbnail,
+                    item.width,
+                    item.height,
+                    item.byte_size,
+                    item.image_hash,
+                    item.language,
+                    item.data_type_manual,
+                    CLASSIFIER_VERSION,
+                    source_ids,
+                    item.image_digest
                 ],
             )?;
-        }
+            conn.last_insert_rowid()
+        };
 
         // Prune if exceeding max_size
-        let count: usize = conn.query_row("SELECT COUNT(*) FROM history", [], |row| row.get(0))?;
+        let count: usize = conn.query_row(
+            "SELECT COUNT(*) FROM history WHERE deleted_at IS NULL",
+            [],
+            |row| row.get(0),
+        )?;
         if count > max_size {
             let delete_count = count - max_size;
 
-            // Fetch items to be deleted first (oldest timestamp, NOT pinned)
+            // Fetch items to be deleted first (oldest timestamp, NOT pinned, NOT collected)
             let mut stmt = conn.prepare(&format!(
-                "SELECT content, kind, timestamp, is_sensitive, is_pinned, source_app, data_type, collection_id, note, html_content FROM history WHERE is_pinned = 0 ORDER BY timestamp ASC LIMIT {}",
-                delete_count
+                "SELECT {} FROM history WHERE is_pinned = 0 AND collection_id IS NULL AND deleted_at IS NULL ORDER BY timestamp ASC LIMIT {}",
+                HISTORY_COLUMNS, delete_count
             ))?;
 
-            let rows = stmt.query_map([], |row| {
-                let content: String = row.get(0)?;
-                let kind: String = row.get(1)?;
-                let timestamp: String = row.get(2)?;
-                let is_sensitive: bool = row.get(3)?;
-                let is_pinned: bool = row.get(4)?;
-                let source_app: Option<String> = row.get(5)?;
-                let data_type: String = row.get(6)?;
-                let collection_id: Option<i64> = row.get(7)?;
-                let note: Option<String> = row.get(8)?;
-                let html_content: Option<String> = row.get(9)?;
-
-                let final_content = if is_sensitive && kind == "text" {
-                    self.crypto.decrypt(&content).unwrap_or(content)
-                } else {
-                    content
-                };
-
-                let final_html = if let Some(html) = html_content {
-                    if is_sensitive {
-                        Some(self.crypto.decrypt(&html).unwrap_or(html))
-                    } else {
-                        Some(html)
-                    }
-                } else {
-                    None
-                };
-
-                Ok(ClipboardItem {
-                    id: None,
-                    content: final_content,
-                    kind,
-                    timestamp,
-                    is_sensitive,
-                    is_pinned,
-                    source_app,
-                    data_type,
-                    collection_id,
-                    note,
-                    html_content: final_html,
-                })
-            })?;
-
-            for row in rows {
-                if let Ok(item) = row {
-                    pruned_items.push(item);
-                }
+            let rows = stmt.query_map([], |row| self.item_from_row(row))?;
+            for item in rows.flatten() {
+                pruned_items.push(item);
             }
 
             // Delete them
-            conn.execute(
-                &format!(
-                    "DELETE FROM history WHERE id IN (SELECT id FROM history WHERE is_pinned = 0 AND collection_id IS NULL ORDER BY timestamp ASC LIMIT {})",
-                    delete_count
-                ),
-                [],
-            )?;
+            for item in &pruned_items {
+                conn.execute("DELETE FROM history WHERE id = ?1", params![item.id])?;
+            }
+            self.remove_orphan_revisions(&conn)?;
+            for name in pruned_items
+                .iter()
+                .filter_map(|item| item.blob_path.as_ref())
+            {
+                self.release_blob(&conn, name)?;
+            }
         }
 
-        Ok(pruned_items)
+        Ok((id, pruned_items))
     }
 
     pub fn delete_item(&self, index: usize) -> Result<Option<ClipboardItem>> {
@@ -360,62 +801,27 @@ impl Database {
         // We need to find the ID of the item at that offset.
         let conn = self.conn.lock().unwrap();
 
-        // Get the ID and details of the item at the specified offset
-        let item: Option<(i64, ClipboardItem)> = conn
+        // Get the details of the item at the specified offset
+        let item: Option<ClipboardItem> = conn
             .query_row(
-                "SELECT id, content, kind, timestamp, is_sensitive, is_pinned, source_app, data_type, collection_id, note, html_content FROM history ORDER BY is_pinned DESC, timestamp DESC LIMIT 1 OFFSET ?1",
+                &format!(
+                    "SELECT {} FROM history WHERE deleted_at IS NULL ORDER BY is_pinned DESC, timestamp DESC LIMIT 1 OFFSET ?1",
+                    HISTORY_COLUMNS
+                ),
                 params![index],
-                |row| {
-                    let id: i64 = row.get(0)?;
-                    let content: String = row.get(1)?;
-                    let kind: String = row.get(2)?;
-                    let timestamp: String = row.get(3)?;
-                    let is_sensitive: bool = row.get(4)?;
-                    let is_pinned: bool = row.get(5)?;
-                    let source_app: Option<String> = row.get(6)?;
-                    let data_type: String = row.get(7)?;
-                    let collection_id: Option<i64> = row.get(8)?;
-                    let note: Option<String> = row.get(9)?;
-                    let html_content: Option<String> = row.get(10)?;
-
-                    let final_content = if is_sensitive && kind == "text" {
-                        self.crypto.decrypt(&content).unwrap_or(content)
-                    } else {
-                        content
-                    };
-
-                     let final_html = if let Some(html) = html_content {
-                         if is_sensitive {
-                             Some(self.crypto.decrypt(&html).unwrap_or(html))
-                        } else {
-                             Some(html)
-                        }
-                    } else {
-                        None
-                    };
-
-                    Ok((
-                        id,
-                        ClipboardItem {
-                            id: Some(id),
-                            content: final_content,
-                            kind,
-                            timestamp,
-                            is_sensitive,
-                            is_pinned,
-                            source_app,
-                            data_type,
-                            collection_id,
-                            note,
-                            html_content: final_html,
-                        },
-                    ))
-                },
+                |row| self.item_from_row(row),
             )
             .optional()?;
 
-        if let Some((id, item)) = item {
-            conn.execute("DELETE FROM history WHERE id = ?1", params![id])?;
+        if let Some(item) = item {
+            // Moved to the trash, files are removed when it is emptied
+            conn.execute(
+                "UPDATE history SET deleted_at = ?1 WHERE id = ?2",
+                params![
+                    Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                    item.id
+                ],
+            )?;
             Ok(Some(item))
         } else {
             Ok(None)
@@ -426,9 +832,9 @@ impl Database {
         let conn = self.conn.lock().unwrap();
 
         // Get item at index
-        let item: Option<(i64, String, bool, String)> = conn
+        let item: Option<(i64, String, bool, String, Option<String>)> = conn
             .query_row(
-                "SELECT id, content, is_sensitive, kind FROM history ORDER BY is_pinned DESC, timestamp DESC LIMIT 1 OFFSET ?1",
+                "SELECT id, content, is_sensitive, kind, blob_path FROM history WHERE deleted_at IS NULL ORDER BY is_pinned DESC, timestamp DESC LIMIT 1 OFFSET ?1",
                 params![index],
                 |row| {
                     Ok((
@@ -436,12 +842,13 @@ impl Database {
                         row.get(1)?,
                         row.get(2)?,
                         row.get(3)?,
+                        row.get(4)?,
                     ))
                 },
             )
             .optional()?;
 
-        if let Some((id, content, is_sensitive, kind)) = item {
+        if let Some((id, content, is_sensitive, kind, blob_path)) = item {
             let new_state = !is_sensitive;
             let new_content = if kind == "text" {
                 if new_state {
@@ -455,23 +862,98 @@ impl Database {
                 content
             };
 
+            // Plain and sensitive blobs are named differently, so it moves
+            let blob_path = match blob_path {
+                Some(name) => {
+                    let data = self
+                        .blobs
+                        .read(&name, self.blob_crypto(is_sensitive))
+                        .map_err(blob_error)?;
+                    let new_name = self
+                        .blobs
+                        .write(&data, self.blob_crypto(new_state))
+                        .map_err(blob_error)?;
+                    Some((name, new_name))
+                }
+                None => None,
+            };
+
             conn.execute(
-                "UPDATE history SET is_sensitive = ?1, content = ?2 WHERE id = ?3",
-                params![new_state, new_content, id],
+                "UPDATE history SET is_sensitive = ?1, content = ?2, blob_path = COALESCE(?3, blob_path) WHERE id = ?4",
+                params![
+                    new_state,
+                    new_content,
+                    blob_path.as_ref().map(|(_, new_name)| new_name),
+                    id
+                ],
+            )?;
+            let mut released: Vec<String> = blob_path.into_iter().map(|(name, _)| name).collect();
+
+            // Revisions are encrypted exactly when the item is sensitive
+            let mut stmt = conn.prepare(
+                "SELECT id, content, html_content, blob_path FROM history_revisions WHERE item_id = ?1",
             )?;
+            let revisions = stmt
+                .query_map(params![id], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>>>()?;
+            for (revision_id, content, html, revision_blob) in revisions {
+                let content = self.encrypt_if(new_state, self.decrypt_if(is_sensitive, content));
+                let html = html
+                    .map(|html| self.encrypt_if(new_state, self.decrypt_if(is_sensitive, html)));
+                let revision_blob = match revision_blob {
+                    Some(name) => {
+                        let data = self
+                            .blobs
+                            .read(&name, self.blob_crypto(is_sensitive))
+                            .map_err(blob_error)?;
+                        let new_name = self
+                            .blobs
+                            .write(&data, self.blob_crypto(new_state))
+                            .map_err(blob_error)?;
+                        released.push(name);
+                        Some(new_name)
+                    }
+                    None => None,
+                };
+                conn.execute(
+                    "UPDATE history_revisions SET content = ?1, html_content = ?2, blob_path = ?3 WHERE id = ?4",
+                    params![content, html, revision_blob, revision_id],
+                )?;
+            }
+
+            // Only once nothing points at the old files any more
+            for name in released {
+                self.release_blob(&conn, &name)?;
+            }
             Ok(new_state)
         } else {
             Err(rusqlite::Error::QueryReturnedNoRows)
         }
     }
 
+    pub fn set_pinned(&self, id: i64, pinned: bool) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE history SET is_pinned = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+            params![pinned, id],
+        )?;
+        Ok(updated > 0)
+    }
+
     pub fn toggle_pin(&self, index: usize) -> Result<bool> {
         let conn = self.conn.lock().unwrap();
 
         // Get item at index
         let item: Option<(i64, bool)> = conn
             .query_row(
-                "SELECT id, is_pinned FROM history ORDER BY is_pinned DESC, timestamp DESC LIMIT 1 OFFSET ?1",
+                "SELECT id, is_pinned FROM history WHERE deleted_at IS NULL ORDER BY is_pinned DESC, timestamp DESC LIMIT 1 OFFSET ?1",
                 params![index],
                 |row| Ok((row.get(0)?, row.get(1)?)),
             )
@@ -489,38 +971,77 @@ impl Database {
         }
     }
 
+    /// Replaces the content of an item. The previous version is kept in
+    /// `history_revisions` so the edit can be reverted.
+    #[allow(clippy::too_many_arguments)]
     pub fn update_content(
         &self,
         id: i64,
         new_content: String,
+        new_html: Option<String>,
         new_data_type: String,
+        new_language: Option<String>,
+        data_type_manual: bool,
         new_note: Option<String>,
     ) -> Result<()> {
         let conn = self.conn.lock().unwrap();
 
         // Fetch is_sensitive and kind to encrypt if needed
-        let (is_sensitive, kind): (bool, String) = conn.query_row(
-            "SELECT is_sensitive, kind FROM history WHERE id = ?1",
+        let (is_sensitive, kind, old_blob, old_html): (
+            bool,
+            String,
+            Option<String>,
+            Option<String>,
+        ) = conn.query_row(
+            "SELECT is_sensitive, kind, blob_path, html_content FROM history WHERE id = ?1",
             params![id],
-            |row| Ok((row.get(0)?, row.get(1)?)),
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
         )?;
 
-        let final_content = if is_sensitive && kind == "text" {
-            self.crypto.encrypt(&new_content).unwrap_or(new_content)
+        // Saving without changing the text or HTML doesn't make a new version
+        let old_html = old_html.map(|html| self.decrypt_if(is_sensitive, html));
+        if self.content_of(&conn, id)? != new_content || old_html != new_html {
+            self.record_revision(&conn, id)?;
+        }
+
+        // Items that already live in a blob stay there after editing
+        let (stored_text, content_length, blob_path) = if old_blob.is_some() {
+            let name = self
+                .blobs
+                .write(new_content.as_bytes(), self.blob_crypto(is_sensitive))
+                .map_err(blob_error)?;
+            (
+                preview_of(&new_content),
+                Some(new_content.len() as i64),
+                Some(name),
+            )
         } else {
-            new_content
+            (new_content, None, None)
         };
 
+        let final_content = self.encrypt_if(is_sensitive && kind == "text", stored_text);
+        let final_html = new_html.map(|html| self.encrypt_if(is_sensitive, html));
+        let old_blob = old_blob.filter(|old_name| Some(old_name) != blob_path.as_ref());
+
         conn.execute(
-            "UPDATE history SET content = ?1, data_type = ?2, timestamp = ?3, note = ?4, html_content = NULL WHERE id = ?5",
+            "UPDATE history SET content = ?1, data_type = ?2, timestamp = ?3, note = ?4, html_content = ?5, content_length = ?6, blob_path = ?7, language = ?8, data_type_manual = ?9, classifier_version = ?10 WHERE id = ?11",
             params![
                 final_content,
                 new_data_type,
                 Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
                 new_note,
+                final_html,
+                content_length,
+                blob_path,
+                new_language,
+                data_type_manual,
+                CLASSIFIER_VERSION,
                 id
             ],
         )?;
+        if let Some(old_name) = old_blob {
+            self.release_blob(&conn, &old_name)?;
+        }
 
         Ok(())
     }
@@ -533,103 +1054,710 @@ impl Database {
         let conn = self.conn.lock().unwrap();
 
         // 构建 WHERE 条件
-        let mut conditions = Vec::new();
+        let mut conditions = vec!["deleted_at IS NULL"];
         if !clear_pinned_on_clear {
             conditions.push("is_pinned = 0");
         }
         if !clear_collected_on_clear {
             conditions.push("collection_id IS NULL");
         }
-        // 如果都为 true，则不加条件，全部清除
-        let where_clause = if conditions.is_empty() {
-            String::from("")
-        } else {
-            format!("WHERE {}", conditions.join(" AND "))
-        };
+        let where_clause = format!("WHERE {}", conditions.join(" AND "));
 
         // 查询所有将要被删除的项
-        let select_sql = format!(
-            "SELECT id, content, kind, timestamp, is_sensitive, is_pinned, source_app, data_type, collection_id, note, html_content FROM history {}",
-            where_clause
-        );
+        let select_sql = format!("SELECT {} FROM history {}", HISTORY_COLUMNS, where_clause);
         let mut stmt = conn.prepare(&select_sql)?;
-        let rows = stmt.query_map([], |row| {
-            let id: i64 = row.get(0)?;
-            let content: String = row.get(1)?;
-            let kind: String = row.get(2)?;
-            let timestamp: String = row.get(3)?;
-            let is_sensitive: bool = row.get(4)?;
-            let is_pinned: bool = row.get(5)?;
-            let source_app: Option<String> = row.get(6)?;
-            let data_type: String = row.get(7)?;
-            let collection_id: Option<i64> = row.get(8)?;
-            let note: Option<String> = row.get(9)?;
-            let html_content: Option<String> = row.get(10)?;
-
-            let final_content = if is_sensitive && kind == "text" {
-                self.crypto.decrypt(&content).unwrap_or(content)
-            } else {
-                content
-            };
+        let rows = stmt.query_map([], |row| self.item_from_row(row))?;
 
-            let final_html = if let Some(html) = html_content {
-                if is_sensitive {
-                    Some(self.crypto.decrypt(&html).unwrap_or(html))
-                } else {
-                    Some(html)
-                }
-            } else {
-                None
-            };
+        let mut items = Vec::new();
+        for row in rows {
+            items.push(row?);
+        }
 
-            Ok(ClipboardItem {
-                id: Some(id),
-                content: final_content,
-                kind,
-                timestamp,
-                is_sensitive,
-                is_pinned,
-                source_app,
-                data_type,
-                collection_id,
-                note,
-                html_content: final_html,
-            })
+        // 移入回收站，文件在清空回收站时才删除
+        conn.execute(
+            &format!("UPDATE history SET deleted_at = ?1 {}", where_clause),
+            params![Local::now().format("%Y-%m-%d %H:%M:%S").to_string()],
+        )?;
+        Ok(items)
+    }
+
+    /// Items in the trash, most recently deleted first.
+    pub fn get_trash(&self) -> Result<Vec<ClipboardItem>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM history WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC, timestamp DESC",
+            HISTORY_COLUMNS
+        ))?;
+        let rows = stmt.query_map([], |row| self.item_from_row(row))?;
+        rows.collect()
+    }
+
+    /// Moves items out of the trash. Returns how many were restored.
+    pub fn restore_items(&self, ids: &[i64]) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let mut restored = 0;
+        for id in ids {
+            restored += conn.execute(
+                "UPDATE history SET deleted_at = NULL WHERE id = ?1 AND deleted_at IS NOT NULL",
+                params![id],
+            )?;
+        }
+        Ok(restored)
+    }
+
+    /// Permanently deletes trashed items, all of them or only those deleted
+    /// before `deleted_before`. Returns them so their image files can be
+    /// removed as well.
+    pub fn purge_trash(&self, deleted_before: Option<&str>) -> Result<Vec<ClipboardItem>> {
+        let conn = self.conn.lock().unwrap();
+        let where_clause = "WHERE deleted_at IS NOT NULL AND (?1 IS NULL OR deleted_at < ?1)";
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM history {}",
+            HISTORY_COLUMNS, where_clause
+        ))?;
+        let items = stmt
+            .query_map(params![deleted_before], |row| self.item_from_row(row))?
+            .collect::<Result<Vec<_>>>()?;
+
+        conn.execute(
+            &format!("DELETE FROM history {}", where_clause),
+            params![deleted_before],
+        )?;
+        self.remove_orphan_revisions(&conn)?;
+        for name in items.iter().filter_map(|item| item.blob_path.as_ref()) {
+            self.release_blob(&conn, name)?;
+        }
+        Ok(items)
+    }
+
+    pub fn get_item_content(&self, id: i64) -> Result<String> {
+        let conn = self.conn.lock().unwrap();
+        // Items in the trash are only reachable through the trash
+        conn.query_row(
+            "SELECT id FROM history WHERE id = ?1 AND deleted_at IS NULL",
+            params![id],
+            |row| row.get::<_, i64>(0),
+        )?;
+        self.content_of(&conn, id)
+    }
+
+    /// Earlier versions of an item, newest first.
+    pub fn get_revisions(&self, item_id: i64) -> Result<Vec<Revision>> {
+        let conn = self.conn.lock().unwrap();
+        let is_sensitive: bool = conn.query_row(
+            "SELECT is_sensitive FROM history WHERE id = ?1",
+            params![item_id],
+            |row| row.get(0),
+        )?;
+        let mut stmt = conn.prepare(
+            "SELECT id, item_id, content, html_content, data_type, note, created_at, blob_path FROM history_revisions WHERE item_id = ?1 ORDER BY id DESC",
+        )?;
+        let rows = stmt.query_map(params![item_id], |row| {
+            self.revision_from_row(row, is_sensitive)
         })?;
+        rows.collect()
+    }
+
+    pub fn get_revision(&self, item_id: i64, revision_id: i64) -> Result<Option<Revision>> {
+        let conn = self.conn.lock().unwrap();
+        let is_sensitive: bool = conn.query_row(
+            "SELECT is_sensitive FROM history WHERE id = ?1",
+            params![item_id],
+            |row| row.get(0),
+        )?;
+        conn.query_row(
+            "SELECT id, item_id, content, html_content, data_type, note, created_at, blob_path FROM history_revisions WHERE id = ?1 AND item_id = ?2",
+            params![revision_id, item_id],
+            |row| self.revision_from_row(row, is_sensitive),
+        )
+        .optional()
+    }
+
+    fn revision_from_row(&self, row: &Row, is_sensitive: bool) -> Result<Revision> {
+        let content = match row.get::<_, Option<String>>(7)? {
+            Some(name) => {
+                let data = self
+                    .blobs
+                    .read(&name, self.blob_crypto(is_sensitive))
+                    .map_err(blob_error)?;
+                String::from_utf8_lossy(&data).into_owned()
+            }
+            None => self.decrypt_if(is_sensitive, row.get(2)?),
+        };
+        Ok(Revision {
+            id: row.get(0)?,
+            item_id: row.get(1)?,
+            content,
+            html_content: row
+                .get::<_, Option<String>>(3)?
+                .map(|html| self.decrypt_if(is_sensitive, html)),
+            data_type: row.get(4)?,
+            note: row.get(5)?,
+            created_at: row.get(6)?,
+        })
+    }
+
+    pub fn get_image_items(&self) -> Result<Vec<ClipboardItem>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM history WHERE kind = 'image'",
+            HISTORY_COLUMNS
+        ))?;
+        let rows = stmt.query_map([], |row| self.item_from_row(row))?;
 
         let mut items = Vec::new();
         for row in rows {
             items.push(row?);
         }
+        Ok(items)
+    }
 
-        // 删除这些项
-        let delete_sql = if where_clause.is_empty() {
-            String::from("DELETE FROM history")
-        } else {
-            format!("DELETE FROM history {}", where_clause)
+    pub fn set_image_file(
+        &self,
+        id: i64,
+        image: &StoredImage,
+        image_hash: u64,
+        image_digest: &str,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE history SET content = ?1, thumbnail_path = ?2, image_width = ?3, image_height = ?4, byte_size = ?5, image_hash = ?6, image_digest = ?7 WHERE id = ?8",
+            params![
+                image.path,
+                image.thumbnail_path,
+                image.width,
+                image.height,
+                image.byte_size,
+                image_hash as i64,
+                image_digest,
+                id
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Rewrites image paths starting with `from` to start with `to`, after
+    /// the data directory moved.
+    pub fn rebase_image_paths(&self, from: &str, to: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let from_len = from.chars().count() as i64;
+        let mut count = conn.execute(
+            "UPDATE history SET content = ?1 || substr(content, ?2 + 1)
+             WHERE kind = 'image' AND substr(content, 1, ?2) = ?3",
+            params![to, from_len, from],
+        )?;
+        count += conn.execute(
+            "UPDATE history SET thumbnail_path = ?1 || substr(thumbnail_path, ?2 + 1)
+             WHERE kind = 'image' AND substr(thumbnail_path, 1, ?2) = ?3",
+            params![to, from_len, from],
+        )?;
+        Ok(count)
+    }
+
+    /// Writes a consistent copy of the database to `path`, which must not exist.
+    pub fn snapshot(&self, path: &Path) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("VACUUM INTO ?1", params![path.to_string_lossy()])?;
+        Ok(())
+    }
+
+    pub fn blobs_dir(&self) -> &Path {
+        self.blobs.dir()
+    }
+
+    /// Blob names revisions refer to, so storage GC leaves them alone.
+    pub fn get_revision_blob_refs(&self) -> Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT blob_path FROM history_revisions WHERE blob_path IS NOT NULL",
+        )?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Returns (id, blob name, file_missing) of every row stored in a blob.
+    pub fn get_blob_refs(&self) -> Result<Vec<(i64, String, bool)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, blob_path, file_missing FROM history WHERE blob_path IS NOT NULL",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+
+        let mut refs = Vec::new();
+        for row in rows {
+            refs.push(row?);
+        }
+        Ok(refs)
+    }
+
+    pub fn set_file_missing(&self, id: i64, missing: bool) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE history SET file_missing = ?1 WHERE id = ?2",
+            params![missing, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_item(&self, id: i64) -> Result<Option<ClipboardItem>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!(
+                "SELECT {} FROM history WHERE id = ?1 AND deleted_at IS NULL",
+                HISTORY_COLUMNS
+            ),
+            params![id],
+            |row| self.item_from_row(row),
+        )
+        .optional()
+    }
+
+    /// Returns (id, distance) of stored images whose hash is within
+    /// `max_distance` bits of `hash`, closest first. When `dimensions` is set
+    /// only images of exactly that size are considered.
+    fn images_near(
+        &self,
+        conn: &Connection,
+        hash: u64,
+        max_distance: u32,
+        dimensions: Option<(u32, u32)>,
+    ) -> Result<Vec<(i64, u32)>> {
+        let mut stmt = conn.prepare(
+            "SELECT id, image_hash, image_width, image_height FROM history WHERE kind = 'image' AND image_hash IS NOT NULL AND deleted_at IS NULL",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, Option<u32>>(2)?,
+                row.get::<_, Option<u32>>(3)?,
+            ))
+        })?;
+
+        let mut matches = Vec::new();
+        for row in rows {
+            let (id, other, width, height) = row?;
+            if let Some((w, h)) = dimensions {
+                if width != Some(w) || height != Some(h) {
+                    continue;
+                }
+            }
+            let distance = phash::distance(hash, other as u64);
+            if distance <= max_distance {
+                matches.push((id, distance));
+            }
+        }
+        matches.sort_by_key(|&(_, distance)| distance);
+        Ok(matches)
+    }
+
+    /// Id of the item of this kind with exactly this content, including items
+    /// in the trash. Sensitive items are encrypted and never match.
+    pub fn find_item(&self, kind: &str, content: &str) -> Result<Option<i64>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT id FROM history WHERE kind = ?1 AND is_sensitive = 0 AND (content = ?2 OR blob_path = ?3)",
+            params![kind, content, blob_name(content.as_bytes())],
+            |row| row.get(0),
+        )
+        .optional()
+    }
+
+    /// Id of the stored image with exactly these pixels, see `phash::digest`.
+    pub fn find_image(&self, digest: &str) -> Result<Option<i64>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT id FROM history WHERE kind = 'image' AND image_digest = ?1 AND deleted_at IS NULL",
+            params![digest],
+            |row| row.get(0),
+        )
+        .optional()
+    }
+
+    /// Finds a stored image of the same size that looks close enough to count
+    /// as a duplicate. A dHash says nothing about identity, so this only
+    /// matches when near duplicates are turned on (`max_distance > 0`).
+    pub fn find_duplicate_image(
+        &self,
+        hash: u64,
+        max_distance: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<Option<i64>> {
+        if max_distance == 0 {
+            return Ok(None);
+        }
+        let conn = self.conn.lock().unwrap();
+        let matches = self.images_near(&conn, hash, max_distance, Some((width, height)))?;
+        Ok(matches.first().map(|&(id, _)| id))
+    }
+
+    /// Lists images that look like the image with the given id, closest first.
+    pub fn find_similar_images(&self, id: i64, max_distance: u32) -> Result<Vec<ClipboardItem>> {
+        let conn = self.conn.lock().unwrap();
+        let hash: Option<i64> = conn.query_row(
+            "SELECT image_hash FROM history WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        let Some(hash) = hash else {
+            return Ok(Vec::new());
         };
-        conn.execute(&delete_sql, [])?;
+
+        let mut items = Vec::new();
+        for (other_id, _) in self.images_near(&conn, hash as u64, max_distance, None)? {
+            if other_id == id {
+                continue;
+            }
+            let item = conn.query_row(
+                &format!("SELECT {} FROM history WHERE id = ?1", HISTORY_COLUMNS),
+                params![other_id],
+                |row| self.item_from_row(row),
+            )?;
+            items.push(item);
+        }
         Ok(items)
     }
 
-    pub fn get_item_content(&self, id: i64) -> Result<String> {
+    /// Text rows classified automatically by a classifier older than `version`.
+    pub fn count_stale_classifications(&self, version: i64) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT COUNT(*) FROM history WHERE kind = 'text' AND data_type_manual = 0 AND deleted_at IS NULL AND classifier_version < ?1",
+            params![version],
+            |row| row.get(0),
+        )
+    }
+
+    /// Returns (id, data_type) of up to `limit` stale rows.
+    pub fn get_stale_classifications(
+        &self,
+        version: i64,
+        limit: usize,
+    ) -> Result<Vec<(i64, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, data_type FROM history WHERE kind = 'text' AND data_type_manual = 0 AND deleted_at IS NULL AND classifier_version < ?1 ORDER BY id LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![version, limit], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+
+        let mut stale = Vec::new();
+        for row in rows {
+            stale.push(row?);
+        }
+        Ok(stale)
+    }
+
+    /// Stores an automatic classification, unless the user has set the type
+    /// by hand in the meantime.
+    pub fn set_classification(
+        &self,
+        id: i64,
+        data_type: &str,
+        language: Option<&str>,
+        version: i64,
+    ) -> Result<()> {
         let conn = self.conn.lock().unwrap();
-        let (content, is_sensitive, kind): (String, bool, String) = conn.query_row(
-            "SELECT content, is_sensitive, kind FROM history WHERE id = ?1",
+        conn.execute(
+            "UPDATE history SET data_type = ?1, language = ?2, classifier_version = ?3 WHERE id = ?4 AND data_type_manual = 0",
+            params![data_type, language, version, id],
+        )?;
+        Ok(())
+    }
+
+    /// Marks the row as classified by `version` without changing its type.
+    pub fn set_classifier_version(&self, id: i64, version: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE history SET classifier_version = ?1 WHERE id = ?2",
+            params![version, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn reset_classifier_version(&self) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE history SET classifier_version = 0 WHERE data_type_manual = 0",
+            [],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_no_sync(&self, id: i64, no_sync: bool) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE history SET no_sync = ?1 WHERE id = ?2",
+            params![no_sync, id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// The value of the change counter, it goes up with every change that syncs.
+    pub fn last_change_seq(&self) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT value FROM sync_counter", [], |row| row.get(0))
+    }
+
+    /// Items changed after `since` that may leave this device, in the order
+    /// they changed, and where to continue from. Sensitive items, file
+    /// lists, items in the trash and items marked as not to sync are left out,
+    /// and so are changes that came from `skip_source`.
+    pub fn get_sync_changes(
+        &self,
+        since: i64,
+        limit: usize,
+        skip_source: Option<&str>,
+    ) -> Result<(Vec<SyncRecord>, i64)> {
+        let conn = self.conn.lock().unwrap();
+        // Read first: changes committed later get a higher value
+        let last: i64 = conn.query_row("SELECT value FROM sync_counter", [], |row| row.get(0))?;
+        // A cursor past the counter is from before the database was replaced,
+        // by restoring a backup for instance
+        let since = if since > last { 0 } else { since };
+        let mut stmt = conn.prepare(
+            "SELECT h.id, h.sync_id, h.change_seq, h.kind, h.html_content, h.timestamp, h.source_app, h.data_type, h.language, h.is_pinned, h.pin_changed_at, h.note, h.note_changed_at, c.name, h.collection_changed_at
+             FROM history h LEFT JOIN collections c ON c.id = h.collection_id
+             WHERE h.change_seq > ?1 AND h.change_seq <= ?2 AND h.no_sync = 0 AND h.is_sensitive = 0 AND h.kind != 'file' AND h.deleted_at IS NULL
+                AND NOT EXISTS (SELECT 1 FROM sync_sources s WHERE s.change_seq = h.change_seq AND s.source IS ?4)
+             ORDER BY h.change_seq LIMIT ?3",
+        )?;
+        let rows = stmt.query_map(params![since, last, limit, skip_source], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                SyncRecord {
+                    sync_id: row.get(1)?,
+                    change_seq: row.get(2)?,
+                    kind: row.get(3)?,
+                    html_content: row.get(4)?,
+                    timestamp: row.get(5)?,
+                    source_app: row.get(6)?,
+                    data_type: row.get(7)?,
+                    language: row.get(8)?,
+                    is_pinned: row.get(9)?,
+                    pin_changed_at: row.get(10)?,
+                    note: row.get(11)?,
+                    note_changed_at: row.get(12)?,
+                    collection: row.get(13)?,
+                    collection_changed_at: row.get(14)?,
+                    ..Default::default()
+                },
+            ))
+        })?;
+
+        let mut records = Vec::new();
+        for row in rows {
+            let (id, mut record) = row?;
+            record.content = self.content_of(&conn, id)?;
+            records.push(record);
+        }
+        let next = match records.last() {
+            Some(record) if records.len() == limit => record.change_seq,
+            _ => last,
+        };
+        Ok((records, next))
+    }
+
+    /// Sync ids of items deleted after `since`, in the order they were
+    /// deleted, and where to continue from. Deletions that came from
+    /// `skip_source` are left out.
+    pub fn get_sync_deletions(
+        &self,
+        since: i64,
+        limit: usize,
+        skip_source: Option<&str>,
+    ) -> Result<(Vec<String>, i64)> {
+        let conn = self.conn.lock().unwrap();
+        let last: i64 = conn.query_row("SELECT value FROM sync_counter", [], |row| row.get(0))?;
+        let since = if since > last { 0 } else { since };
+        let mut stmt = conn.prepare(
+            "SELECT d.sync_id, d.change_seq FROM sync_deletions d
+             WHERE d.change_seq > ?1 AND d.change_seq <= ?2
+                AND NOT EXISTS (SELECT 1 FROM sync_sources s WHERE s.change_seq = d.change_seq AND s.source IS ?4)
+             ORDER BY d.change_seq LIMIT ?3",
+        )?;
+        let rows = stmt
+            .query_map(params![since, last, limit, skip_source], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        let next = match rows.last() {
+            Some((_, seq)) if rows.len() == limit => *seq,
+            _ => last,
+        };
+        Ok((rows.into_iter().map(|(sync_id, _)| sync_id).collect(), next))
+    }
+
+    /// Whether the item with this sync id was deleted and isn't in the
+    /// history anymore, so a record for it shouldn't bring it back.
+    pub fn is_sync_deleted(&self, sync_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sync_deletions WHERE sync_id = ?1)
+                AND NOT EXISTS (SELECT 1 FROM history WHERE sync_id = ?1)",
+            params![sync_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Applies a deletion from `source`, another device: moves the item to
+    /// the trash, unless this deletion was applied before. Returns whether an
+    /// item was moved.
+    pub fn delete_sync_item(&self, sync_id: &str, source: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+        let recorded = tx.execute(
+            "INSERT OR IGNORE INTO sync_deletions (sync_id, change_seq)
+             VALUES (?1, (SELECT value + 1 FROM sync_counter))",
+            params![sync_id],
+        )?;
+        if recorded == 0 {
+            // Seen already, an item restored from the trash since stays
+            return Ok(false);
+        }
+        tx.execute("UPDATE sync_counter SET value = value + 1", [])?;
+        tx.execute(
+            "INSERT OR REPLACE INTO sync_sources (change_seq, source)
+             VALUES ((SELECT value FROM sync_counter), ?1)",
+            params![source],
+        )?;
+        // Setting change_seq keeps the trigger from recording it once more
+        let deleted = tx.execute(
+            "UPDATE history SET deleted_at = ?1, change_seq = (SELECT value FROM sync_counter)
+             WHERE sync_id = ?2 AND deleted_at IS NULL",
+            params![
+                Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                sync_id
+            ],
+        )?;
+        tx.commit()?;
+        Ok(deleted > 0)
+    }
+
+    pub fn find_sync_item(&self, sync_id: &str) -> Result<Option<i64>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT id FROM history WHERE sync_id = ?1",
+            params![sync_id],
+            |row| row.get(0),
+        )
+        .optional()
+    }
+
+    /// Merges a record from another device into row `id`. Each of pin, note
+    /// and collection takes the newer side, ties go to the greater value so
+    /// every device ends up with the same result. The row takes the smaller
+    /// of both sync ids, or the record's when it was `inserted` for it.
+    /// When the row ends up just like the record, the change is marked as
+    /// coming from `source`. Returns whether the row changed.
+    pub fn merge_sync_record(
+        &self,
+        id: i64,
+        record: &SyncRecord,
+        inserted: bool,
+        source: &str,
+    ) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let (sync_id, change_seq, mut pinned, mut note, mut collection) = conn.query_row(
+            "SELECT h.sync_id, h.change_seq, h.pin_changed_at, h.is_pinned, h.note_changed_at, h.note, h.collection_changed_at, c.name
+             FROM history h LEFT JOIN collections c ON c.id = h.collection_id WHERE h.id = ?1",
             params![id],
-            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    (row.get(2)?, row.get::<_, bool>(3)?),
+                    (row.get(4)?, row.get::<_, Option<String>>(5)?),
+                    (row.get(6)?, row.get::<_, Option<String>>(7)?),
+                ))
+            },
         )?;
 
-        if is_sensitive && kind == "text" {
-            Ok(self.crypto.decrypt(&content).unwrap_or(content))
+        let theirs = (
+            (record.pin_changed_at, record.is_pinned),
+            (record.note_changed_at, record.note.clone()),
+            (record.collection_changed_at, record.collection.clone()),
+        );
+        let mut changed = take_newer(&mut pinned, theirs.0);
+        changed |= take_newer(&mut note, theirs.1.clone());
+        changed |= take_newer(&mut collection, theirs.2.clone());
+        let sync_id = if inserted || record.sync_id < sync_id {
+            changed |= record.sync_id != sync_id;
+            record.sync_id.clone()
         } else {
-            Ok(content)
+            sync_id
+        };
+        if !changed {
+            return Ok(false);
         }
+        // Otherwise the row holds something the source doesn't have yet
+        let from_source = sync_id == record.sync_id
+            && pinned == theirs.0
+            && note == theirs.1
+            && collection == theirs.2;
+
+        let collection_id: Option<i64> = match &collection.1 {
+            Some(name) => match conn
+                .query_row(
+                    "SELECT id FROM collections WHERE name = ?1",
+                    params![name],
+                    |row| row.get(0),
+                )
+                .optional()?
+            {
+                Some(id) => Some(id),
+                None => {
+                    conn.execute(
+                        "INSERT INTO collections (name, created_at) VALUES (?1, ?2)",
+                        params![name, Local::now().format("%Y-%m-%d %H:%M:%S").to_string()],
+                    )?;
+                    Some(conn.last_insert_rowid())
+                }
+            },
+            None => None,
+        };
+
+        // Setting change_seq here keeps the triggers from stamping the change as local
+        let tx = conn.unchecked_transaction()?;
+        tx.execute("UPDATE sync_counter SET value = value + 1", [])?;
+        tx.execute(
+            "UPDATE history SET sync_id = ?1, is_pinned = ?2, pin_changed_at = ?3, note = ?4, note_changed_at = ?5, collection_id = ?6, collection_changed_at = ?7, change_seq = (SELECT value FROM sync_counter) WHERE id = ?8",
+            params![
+                sync_id,
+                pinned.1,
+                pinned.0,
+                note.1,
+                note.0,
+                collection_id,
+                collection.0,
+                id
+            ],
+        )?;
+        tx.execute(
+            "DELETE FROM sync_sources WHERE change_seq = ?1",
+            params![change_seq],
+        )?;
+        if from_source {
+            tx.execute(
+                "INSERT OR REPLACE INTO sync_sources (change_seq, source)
+                 VALUES ((SELECT value FROM sync_counter), ?1)",
+                params![source],
+            )?;
+        }
+        tx.commit()?;
+        Ok(true)
     }
 
     pub fn count_history(&self) -> Result<usize> {
         let conn = self.conn.lock().unwrap();
-        let count: usize = conn.query_row("SELECT COUNT(*) FROM history", [], |row| row.get(0))?;
+        let count: usize = conn.query_row(
+            "SELECT COUNT(*) FROM history WHERE deleted_at IS NULL",
+            [],
+            |row| row.get(0),
+        )?;
         Ok(count)
     }
 
@@ -637,7 +1765,7 @@ impl Database {
         let conn = self.conn.lock().unwrap();
         let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
         conn.execute(
-            "UPDATE history SET timestamp = ?1 WHERE id = ?2",
+            "UPDATE history SET timestamp = ?1 WHERE id = ?2 AND deleted_at IS NULL",
             params![timestamp, id],
         )?;
         Ok(())
@@ -692,9 +1820,1007 @@ impl Database {
     pub fn set_item_collection(&self, item_id: i64, collection_id: Option<i64>) -> Result<()> {
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "UPDATE history SET collection_id = ?1 WHERE id = ?2",
+            "UPDATE history SET collection_id = ?1 WHERE id = ?2 AND deleted_at IS NULL",
             params![collection_id, item_id],
         )?;
         Ok(())
     }
+
+    /// Makes the item a snippet, for when a new snippet's body is already in
+    /// the history. The note and collection given replace the item's own.
+    pub fn make_snippet(
+        &self,
+        id: i64,
+        note: Option<String>,
+        collection_id: Option<i64>,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE history SET data_type = 'snippet', data_type_manual = 1, note = COALESCE(?1, note), collection_id = COALESCE(?2, collection_id) WHERE id = ?3",
+            params![note, collection_id, id],
+        )?;
+        Ok(())
+    }
+}
+
+// A synced value and when it last changed, in milliseconds.
+type Stamped<T> = (i64, T);
+
+// Replaces `local` with `remote` when that is newer, or equally new with a
+// greater value.
+fn take_newer<T: Ord>(local: &mut Stamped<T>, remote: Stamped<T>) -> bool {
+    if remote > *local {
+        *local = remote;
+        true
+    } else {
+        false
+    }
+}
+
+fn preview_of(content: &str) -> String {
+    content.chars().take(BLOB_PREVIEW_CHARS).collect()
+}
+
+/// Sensitive blobs used to be named after their plaintext like plain ones, so
+/// a sensitive and a plain copy of the same payload shared one file. Give each
+/// sensitive row a file of its own and leave the old name to the plain rows.
+fn separate_sensitive_blobs(conn: &Connection, blobs: &BlobStore, crypto: &Crypto) -> Result<()> {
+    let rows: Vec<(i64, String)> = conn
+        .prepare(
+            "SELECT id, blob_path FROM history WHERE is_sensitive = 1 AND blob_path IS NOT NULL",
+        )?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+
+    for (id, name) in rows {
+        // Whichever copy was written last is on disk
+        let data = match blobs
+            .read(&name, Some(crypto))
+            .or_else(|_| blobs.read(&name, None))
+        {
+            Ok(data) => data,
+            Err(e) => {
+                log::warn!("Failed to read blob {}: {}", name, e);
+                continue;
+            }
+        };
+        let new_name = blobs.write(&data, Some(crypto)).map_err(blob_error)?;
+        conn.execute(
+            "UPDATE history SET blob_path = ?1 WHERE id = ?2",
+            params![new_name, id],
+        )?;
+
+        let shared: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM history WHERE blob_path = ?1)",
+            params![name],
+            |row| row.get(0),
+        )?;
+        if shared {
+            blobs.write(&data, None).map_err(blob_error)?;
+        } else {
+            blobs.remove(&name);
+        }
+    }
+    Ok(())
+}
+
+fn blob_error(e: std::io::Error) -> rusqlite::Error {
+    rusqlite::Error::UserFunctionError(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn empty_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("clipboard-db-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn open(name: &str) -> (Database, PathBuf) {
+        let dir = empty_dir(name);
+        let crypto = Arc::new(Crypto::new(dir.join("key")));
+        let db = Database::new(dir.join("history.db"), crypto).unwrap();
+        (db, dir)
+    }
+
+    // A database as version 6 left it, with a pinned text in a collection,
+    // a code snippet and an image, upgraded to the current version
+    fn open_v6(name: &str) -> (Database, PathBuf) {
+        let dir = empty_dir(name);
+        Connection::open(dir.join("history.db"))
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE history (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    content TEXT NOT NULL,
+                    kind TEXT NOT NULL,
+                    timestamp TEXT NOT NULL,
+                    is_sensitive BOOLEAN NOT NULL DEFAULT 0,
+                    is_pinned BOOLEAN NOT NULL DEFAULT 0,
+                    source_app TEXT,
+                    data_type TEXT NOT NULL DEFAULT 'text',
+                    collection_id INTEGER,
+                    note TEXT,
+                    html_content TEXT
+                );
+                CREATE INDEX idx_content_kind ON history (content, kind);
+                CREATE TABLE collections (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    created_at TEXT NOT NULL
+                );
+                INSERT INTO collections (name, created_at) VALUES ('Work', '2023-01-01 00:00:00');
+                INSERT INTO history (content, kind, timestamp, is_pinned, source_app, collection_id, note)
+                    VALUES ('pinned text', 'text', '2023-01-01 00:00:01', 1, 'Editor', 1, 'a note');
+                INSERT INTO history (content, kind, timestamp, data_type)
+                    VALUES ('fn main() {}', 'text', '2023-01-01 00:00:02', 'code');
+                INSERT INTO history (content, kind, timestamp, data_type)
+                    VALUES ('/old/images/1.png', 'image', '2023-01-01 00:00:03', 'image');
+                PRAGMA user_version = 6;",
+            )
+            .unwrap();
+        let crypto = Arc::new(Crypto::new(dir.join("key")));
+        let db = Database::new(dir.join("history.db"), crypto).unwrap();
+        (db, dir)
+    }
+
+    fn find(db: &Database, content: &str) -> ClipboardItem {
+        db.get_history(1, 100, None, false, false, None, None, None)
+            .unwrap()
+            .into_iter()
+            .find(|item| item.content == content)
+            .unwrap()
+    }
+
+    fn text(content: &str) -> ClipboardItem {
+        ClipboardItem {
+            content: content.to_string(),
+            kind: "text".into(),
+            timestamp: "2024-01-01 00:00:00".into(),
+            data_type: "text".into(),
+            ..Default::default()
+        }
+    }
+
+    fn blob_item(content: &str, is_sensitive: bool) -> ClipboardItem {
+        ClipboardItem {
+            store_as_blob: true,
+            is_sensitive,
+            ..text(content)
+        }
+    }
+
+    fn blob_files(dir: &Path) -> usize {
+        std::fs::read_dir(dir.join("blobs")).unwrap().count()
+    }
+
+    #[test]
+    fn blob_items_keep_a_preview_in_the_row() {
+        let (db, dir) = open("blob-preview");
+        let big = "p".repeat(10_000);
+        let (id, _) = db.insert_item(&blob_item(&big, false), 100).unwrap();
+
+        let item = db.get_item(id).unwrap().unwrap();
+        assert_eq!(item.content.len(), BLOB_PREVIEW_CHARS);
+        assert_eq!(item.content_length, Some(10_000));
+        assert_eq!(item.blob_path, Some(blob_name(big.as_bytes())));
+        assert_eq!(db.get_item_content(id).unwrap(), big);
+        assert_eq!(blob_files(&dir), 1);
+
+        // Copying it again reuses the row and the file
+        let (again, _) = db.insert_item(&blob_item(&big, false), 100).unwrap();
+        assert_eq!(again, id);
+        assert_eq!(db.count_history().unwrap(), 1);
+        assert_eq!(blob_files(&dir), 1);
+    }
+
+    #[test]
+    fn sensitive_blobs_are_kept_apart_from_plain_ones() {
+        let (db, dir) = open("sensitive-blobs");
+        let big = "s".repeat(10_000);
+        db.insert_item(&blob_item(&big, false), 100).unwrap();
+        db.insert_item(&blob_item(&big, true), 100).unwrap();
+
+        let history = db
+            .get_history(1, 10, None, false, false, None, None, None)
+            .unwrap();
+        assert_eq!(history.len(), 2);
+        for item in &history {
+            assert_eq!(db.get_item_content(item.id.unwrap()).unwrap(), big);
+        }
+        assert_eq!(blob_files(&dir), 2);
+
+        // The sensitive file must not be named after its plaintext
+        let names: Vec<Option<String>> = {
+            let conn = db.conn.lock().unwrap();
+            let mut stmt = conn
+                .prepare("SELECT blob_path FROM history WHERE is_sensitive = 1")
+                .unwrap();
+            let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+            rows.map(|row| row.unwrap()).collect()
+        };
+        assert_eq!(names.len(), 1);
+        assert_ne!(
+            names[0].as_deref(),
+            Some(blob_name(big.as_bytes()).as_str())
+        );
+    }
+
+    #[test]
+    fn toggling_sensitivity_moves_the_blob() {
+        let (db, dir) = open("toggle-blob");
+        let big = "t".repeat(10_000);
+        db.insert_item(&blob_item(&big, false), 100).unwrap();
+        db.insert_item(&blob_item(&big, false), 100).unwrap();
+        let id = db
+            .get_history(1, 10, None, false, false, None, None, None)
+            .unwrap()[0]
+            .id
+            .unwrap();
+
+        assert!(db.toggle_sensitive(0).unwrap());
+        assert_eq!(db.get_item_content(id).unwrap(), big);
+        assert!(!dir.join("blobs").join(blob_name(big.as_bytes())).exists());
+        assert_eq!(blob_files(&dir), 1);
+
+        assert!(!db.toggle_sensitive(0).unwrap());
+        assert_eq!(db.get_item_content(id).unwrap(), big);
+        assert!(dir.join("blobs").join(blob_name(big.as_bytes())).exists());
+        assert_eq!(blob_files(&dir), 1);
+    }
+
+    #[test]
+    fn migration_separates_shared_sensitive_blobs() {
+        let (db, dir) = open("separate-blobs");
+        let big = "m".repeat(10_000);
+        db.insert_item(&blob_item(&big, false), 100).unwrap();
+        db.insert_item(&blob_item(&big, true), 100).unwrap();
+
+        // Lay the rows out the way older versions stored them: both rows
+        // point at the plaintext name and the encrypted copy won
+        let shared = blob_name(big.as_bytes());
+        let encrypted = db.blobs.write(big.as_bytes(), Some(&db.crypto)).unwrap();
+        std::fs::rename(
+            dir.join("blobs").join(&encrypted),
+            dir.join("blobs").join(&shared),
+        )
+        .unwrap();
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute("UPDATE history SET blob_path = ?1", params![shared])
+                .unwrap();
+            separate_sensitive_blobs(&conn, &db.blobs, &db.crypto).unwrap();
+        }
+
+        for item in db
+            .get_history(1, 10, None, false, false, None, None, None)
+            .unwrap()
+        {
+            assert_eq!(db.get_item_content(item.id.unwrap()).unwrap(), big);
+        }
+    }
+
+    #[test]
+    fn unchanged_edits_do_not_record_revisions() {
+        let (db, _dir) = open("revisions");
+        let item = ClipboardItem {
+            html_content: Some("<b>one</b>".into()),
+            ..text("one")
+        };
+        let (id, _) = db.insert_item(&item, 100).unwrap();
+        let save = |content: &str, html: Option<&str>, note: Option<&str>| {
+            db.update_content(
+                id,
+                content.into(),
+                html.map(String::from),
+                "text".into(),
+                None,
+                false,
+                note.map(String::from),
+            )
+            .unwrap()
+        };
+
+        save("one", Some("<b>one</b>"), Some("a note"));
+        assert!(db.get_revisions(id).unwrap().is_empty());
+
+        save("one", None, Some("a note"));
+        save("two", None, Some("a note"));
+        save("two", None, None);
+        let revisions = db.get_revisions(id).unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].content, "one");
+    }
+
+    #[test]
+    fn trashed_items_are_out_of_reach() {
+        let (db, _dir) = open("trash");
+        let (id, _) = db.insert_item(&text("gone"), 100).unwrap();
+        let collection = db.create_collection("work".into()).unwrap();
+        db.delete_item(0).unwrap();
+
+        assert!(db.get_item(id).unwrap().is_none());
+        assert!(db.get_item_content(id).is_err());
+        db.update_timestamp(id).unwrap();
+        db.set_item_collection(id, Some(collection.id)).unwrap();
+
+        db.restore_items(&[id]).unwrap();
+        let item = db.get_item(id).unwrap().unwrap();
+        assert_eq!(item.timestamp, "2024-01-01 00:00:00");
+        assert_eq!(item.collection_id, None);
+        assert_eq!(db.get_item_content(id).unwrap(), "gone");
+    }
+
+    // An item stored in a blob, edited once so its revision holds the old blob
+    fn edited_blob_item(db: &Database, old: &str, new: &str) -> i64 {
+        let (id, _) = db.insert_item(&blob_item(old, false), 100).unwrap();
+        db.update_content(id, new.into(), None, "text".into(), None, false, None)
+            .unwrap();
+        id
+    }
+
+    #[test]
+    fn revisions_refer_to_the_blob() {
+        let (db, dir) = open("revision-blobs");
+        let (old, new) = ("a".repeat(10_000), "b".repeat(10_000));
+        let id = edited_blob_item(&db, &old, &new);
+
+        assert_eq!(blob_files(&dir), 2);
+        let revisions = db.get_revisions(id).unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].content, old);
+
+        let stored: (i64, Option<String>) = db
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT length(content), blob_path FROM history_revisions",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(stored.0, BLOB_PREVIEW_CHARS as i64);
+        assert_eq!(stored.1, Some(blob_name(old.as_bytes())));
+    }
+
+    #[test]
+    fn blobs_outlive_rows_until_nothing_refers_to_them() {
+        let (db, dir) = open("shared-blobs");
+        let (old, new) = ("c".repeat(10_000), "d".repeat(10_000));
+        let id = edited_blob_item(&db, &old, &new);
+
+        // A second copy of the old text shares the file with the revision
+        db.insert_item(&blob_item(&old, false), 100).unwrap();
+        assert_eq!(blob_files(&dir), 2);
+        db.delete_item(1).unwrap();
+        assert_eq!(db.purge_trash(None).unwrap().len(), 1);
+        assert_eq!(blob_files(&dir), 2);
+        assert_eq!(db.get_revisions(id).unwrap()[0].content, old);
+
+        // Pruning the edited item drops its revisions and both files
+        let newer = ClipboardItem {
+            timestamp: "2999-01-01 00:00:00".into(),
+            ..text("newer")
+        };
+        let (_, pruned) = db.insert_item(&newer, 1).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(blob_files(&dir), 0);
+    }
+
+    #[test]
+    fn toggling_sensitivity_moves_revision_blobs() {
+        let (db, dir) = open("toggle-revision-blobs");
+        let (old, new) = ("e".repeat(10_000), "f".repeat(10_000));
+        let id = edited_blob_item(&db, &old, &new);
+
+        assert!(db.toggle_sensitive(0).unwrap());
+        assert!(!dir.join("blobs").join(blob_name(old.as_bytes())).exists());
+        assert_eq!(blob_files(&dir), 2);
+        assert_eq!(db.get_revisions(id).unwrap()[0].content, old);
+
+        assert!(!db.toggle_sensitive(0).unwrap());
+        assert!(dir.join("blobs").join(blob_name(old.as_bytes())).exists());
+        assert_eq!(blob_files(&dir), 2);
+        assert_eq!(db.get_revisions(id).unwrap()[0].content, old);
+    }
+
+    #[test]
+    fn two_handles_can_share_a_database() {
+        let (app, dir) = open("shared");
+        let app = Arc::new(app);
+        let cli = Arc::new(
+            Database::new(
+                dir.join("history.db"),
+                Arc::new(Crypto::new(dir.join("key"))),
+            )
+            .unwrap(),
+        );
+        let writers: Vec<_> = [app.clone(), cli.clone()]
+            .into_iter()
+            .enumerate()
+            .map(|(n, db)| {
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        db.insert_item(&text(&format!("{}-{}", n, i)), 1000)
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(app.count_history().unwrap(), 200);
+
+        let id = app
+            .get_history(1, 1, None, false, false, None, None, None)
+            .unwrap()[0]
+            .id
+            .unwrap();
+        assert!(cli.set_pinned(id, true).unwrap());
+        assert!(app.get_item(id).unwrap().unwrap().is_pinned);
+    }
+
+    #[test]
+    fn upgrade_keeps_old_rows() {
+        let (db, dir) = open_v6("v6-rows");
+        assert_eq!(db.count_history().unwrap(), 3);
+        let item = find(&db, "pinned text");
+        assert!(item.is_pinned);
+        assert_eq!(item.note.as_deref(), Some("a note"));
+        assert_eq!(item.collection_id, Some(1));
+        assert_eq!(item.source_app.as_deref(), Some("Editor"));
+        // Stored inline, the whole content is in the row
+        assert_eq!(item.content_length, None);
+        assert_eq!(
+            db.get_item_content(item.id.unwrap()).unwrap(),
+            "pinned text"
+        );
+        drop(db);
+
+        // Opening it again finds nothing left to do
+        let crypto = Arc::new(Crypto::new(dir.join("key")));
+        let db = Database::new(dir.join("history.db"), crypto).unwrap();
+        assert_eq!(db.count_history().unwrap(), 3);
+    }
+
+    #[test]
+    fn images_are_matched_by_their_pixels() {
+        let (db, _dir) = open("image-identity");
+        let white = image::RgbaImage::from_pixel(64, 64, image::Rgba([255; 4]));
+        let mut edited = white.clone();
+        edited.put_pixel(0, 0, image::Rgba([0, 0, 0, 255]));
+        // Too small a change for the dHash to see
+        let hash = phash::dhash(&white);
+        assert_eq!(phash::dhash(&edited), hash);
+
+        let (id, _) = db
+            .insert_item(
+                &ClipboardItem {
+                    kind: "image".into(),
+                    width: Some(64),
+                    height: Some(64),
+                    image_hash: Some(hash as i64),
+                    image_digest: Some(phash::digest(&white)),
+                    ..text("/images/white.png")
+                },
+                100,
+            )
+            .unwrap();
+        assert_eq!(db.find_image(&phash::digest(&white)).unwrap(), Some(id));
+        assert_eq!(db.find_image(&phash::digest(&edited)).unwrap(), None);
+
+        // Looking alike only counts when near duplicates are merged
+        assert_eq!(db.find_duplicate_image(hash, 0, 64, 64).unwrap(), None);
+        assert_eq!(db.find_duplicate_image(hash, 2, 64, 64).unwrap(), Some(id));
+        assert_eq!(db.find_duplicate_image(hash, 2, 32, 128).unwrap(), None);
+    }
+
+    #[test]
+    fn similar_images_are_found_closest_first() {
+        let (db, _dir) = open("similar-images");
+        let image = |name: &str, hash: u64| ClipboardItem {
+            kind: "image".into(),
+            image_hash: Some(hash as i64),
+            ..text(name)
+        };
+        let (original, _) = db.insert_item(&image("/a.png", 0), 100).unwrap();
+        let (far, _) = db.insert_item(&image("/b.png", 0b1111), 100).unwrap();
+        let (near, _) = db.insert_item(&image("/c.png", 0b1), 100).unwrap();
+        db.insert_item(&image("/d.png", u64::MAX), 100).unwrap();
+
+        let ids = |max_distance| -> Vec<Option<i64>> {
+            db.find_similar_images(original, max_distance)
+                .unwrap()
+                .iter()
+                .map(|item| item.id)
+                .collect()
+        };
+        assert_eq!(ids(4), [Some(near), Some(far)]);
+        assert_eq!(ids(1), [Some(near)]);
+        assert!(ids(0).is_empty());
+    }
+
+    #[test]
+    fn history_is_filtered_by_language() {
+        let (db, _dir) = open("language");
+        let code = ClipboardItem {
+            data_type: "code".into(),
+            language: Some("rust".into()),
+            ..text("fn main() {}")
+        };
+        db.insert_item(&code, 100).unwrap();
+        let (id, _) = db.insert_item(&text("SELECT 1"), 100).unwrap();
+        let in_language = |language: &str| -> Vec<String> {
+            db.get_history(1, 10, None, false, false, None, Some(language.into()), None)
+                .unwrap()
+                .into_iter()
+                .map(|item| item.content)
+                .collect()
+        };
+        assert_eq!(in_language("rust"), ["fn main() {}"]);
+        assert!(in_language("sql").is_empty());
+
+        db.set_classification(id, "code", Some("sql"), CLASSIFIER_VERSION)
+            .unwrap();
+        assert_eq!(in_language("sql"), ["SELECT 1"]);
+        assert_eq!(
+            db.get_item(id).unwrap().unwrap().language.as_deref(),
+            Some("sql")
+        );
+    }
+
+    #[test]
+    fn merged_items_remember_their_sources() {
+        let (db, _dir) = open("provenance");
+        let (first, _) = db.insert_item(&text("first"), 100).unwrap();
+        let (second, _) = db.insert_item(&text("second"), 100).unwrap();
+        let merged = ClipboardItem {
+            source_ids: Some(vec![first, second]),
+            ..text("first\nsecond")
+        };
+        let (id, _) = db.insert_item(&merged, 100).unwrap();
+        assert_eq!(
+            db.get_item(id).unwrap().unwrap().source_ids,
+            Some(vec![first, second])
+        );
+
+        // Copying the result again keeps where it came from
+        let (again, _) = db.insert_item(&text("first\nsecond"), 100).unwrap();
+        assert_eq!(again, id);
+        assert_eq!(
+            db.get_item(id).unwrap().unwrap().source_ids,
+            Some(vec![first, second])
+        );
+        assert_eq!(db.get_item(first).unwrap().unwrap().source_ids, None);
+    }
+
+    #[test]
+    fn reverting_brings_back_the_old_version() {
+        let (db, _dir) = open("revert");
+        let item = ClipboardItem {
+            html_content: Some("<b>one</b>".into()),
+            note: Some("a note".into()),
+            ..text("one")
+        };
+        let (id, _) = db.insert_item(&item, 100).unwrap();
+        db.update_content(id, "two".into(), None, "code".into(), None, true, None)
+            .unwrap();
+
+        let revision = db.get_revisions(id).unwrap().remove(0);
+        assert_eq!(revision.content, "one");
+        assert_eq!(revision.html_content.as_deref(), Some("<b>one</b>"));
+        assert_eq!(revision.data_type, "text");
+        assert_eq!(revision.note.as_deref(), Some("a note"));
+        assert_eq!(
+            db.get_revision(id, revision.id)
+                .unwrap()
+                .map(|found| found.content),
+            Some("one".into())
+        );
+
+        db.update_content(
+            id,
+            revision.content,
+            revision.html_content,
+            revision.data_type,
+            None,
+            false,
+            revision.note,
+        )
+        .unwrap();
+        let item = db.get_item(id).unwrap().unwrap();
+        assert_eq!(db.get_item_content(id).unwrap(), "one");
+        assert_eq!(item.html_content.as_deref(), Some("<b>one</b>"));
+        assert_eq!(item.note.as_deref(), Some("a note"));
+        // Reverting is an edit too
+        assert_eq!(db.get_revisions(id).unwrap()[0].content, "two");
+    }
+
+    #[test]
+    fn sensitive_revisions_are_encrypted() {
+        let (db, _dir) = open("revision-crypto");
+        let (id, _) = db.insert_item(&text("secret"), 100).unwrap();
+        db.update_content(id, "public".into(), None, "text".into(), None, false, None)
+            .unwrap();
+        let stored = |db: &Database| -> String {
+            db.conn
+                .lock()
+                .unwrap()
+                .query_row("SELECT content FROM history_revisions", [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+        assert_eq!(stored(&db), "secret");
+
+        db.toggle_sensitive(0).unwrap();
+        assert_ne!(stored(&db), "secret");
+        assert_eq!(db.get_revisions(id).unwrap()[0].content, "secret");
+
+        db.toggle_sensitive(0).unwrap();
+        assert_eq!(stored(&db), "secret");
+    }
+
+    #[test]
+    fn only_the_latest_revisions_are_kept() {
+        let (db, _dir) = open("revision-limit");
+        let (id, _) = db.insert_item(&text("0"), 100).unwrap();
+        for version in 1..=MAX_REVISIONS_PER_ITEM + 5 {
+            db.update_content(
+                id,
+                version.to_string(),
+                None,
+                "text".into(),
+                None,
+                false,
+                None,
+            )
+            .unwrap();
+        }
+        let revisions = db.get_revisions(id).unwrap();
+        assert_eq!(revisions.len(), MAX_REVISIONS_PER_ITEM);
+        assert_eq!(
+            revisions[0].content,
+            (MAX_REVISIONS_PER_ITEM + 4).to_string()
+        );
+        assert_eq!(revisions.last().unwrap().content, "5");
+    }
+
+    #[test]
+    fn deleted_items_can_be_restored() {
+        let (db, _dir) = open("restore");
+        db.insert_item(&text("kept"), 100).unwrap();
+        let newer = ClipboardItem {
+            timestamp: "2024-01-02 00:00:00".into(),
+            ..text("deleted")
+        };
+        db.insert_item(&newer, 100).unwrap();
+
+        let deleted = db.delete_item(0).unwrap().unwrap();
+        assert_eq!(deleted.content, "deleted");
+        assert_eq!(db.count_history().unwrap(), 1);
+        let trash = db.get_trash().unwrap();
+        assert_eq!(trash.len(), 1);
+        assert!(trash[0].deleted_at.is_some());
+
+        let id = deleted.id.unwrap();
+        assert_eq!(db.restore_items(&[id]).unwrap(), 1);
+        // Restoring twice does nothing
+        assert_eq!(db.restore_items(&[id]).unwrap(), 0);
+        assert_eq!(db.count_history().unwrap(), 2);
+        assert!(db.get_item(id).unwrap().unwrap().deleted_at.is_none());
+    }
+
+    #[test]
+    fn clearing_keeps_pinned_and_collected_items_when_asked() {
+        let (db, _dir) = open("clear");
+        let (pinned, _) = db.insert_item(&text("pinned"), 100).unwrap();
+        db.set_pinned(pinned, true).unwrap();
+        let (collected, _) = db.insert_item(&text("collected"), 100).unwrap();
+        let collection = db.create_collection("work".into()).unwrap();
+        db.set_item_collection(collected, Some(collection.id))
+            .unwrap();
+        db.insert_item(&text("plain"), 100).unwrap();
+
+        let cleared = db.clear_history(false, false).unwrap();
+        assert_eq!(cleared.len(), 1);
+        assert_eq!(cleared[0].content, "plain");
+        assert_eq!(db.count_history().unwrap(), 2);
+
+        assert_eq!(db.clear_history(false, true).unwrap().len(), 1);
+        assert_eq!(db.clear_history(true, true).unwrap().len(), 1);
+        assert_eq!(db.count_history().unwrap(), 0);
+        assert_eq!(db.get_trash().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn copying_a_trashed_item_again_brings_it_back() {
+        let (db, _dir) = open("revive");
+        let (id, _) = db.insert_item(&text("again"), 100).unwrap();
+        db.delete_item(0).unwrap();
+        let (again, _) = db.insert_item(&text("again"), 100).unwrap();
+        assert_eq!(again, id);
+        assert!(db.get_trash().unwrap().is_empty());
+        assert_eq!(db.count_history().unwrap(), 1);
+    }
+
+    #[test]
+    fn purging_only_removes_old_enough_items() {
+        let (db, _dir) = open("purge");
+        db.insert_item(&text("old"), 100).unwrap();
+        db.insert_item(&text("live"), 100).unwrap();
+        db.delete_item(0).unwrap();
+
+        assert!(db
+            .purge_trash(Some("2000-01-01 00:00:00"))
+            .unwrap()
+            .is_empty());
+        assert_eq!(db.get_trash().unwrap().len(), 1);
+        let purged = db.purge_trash(None).unwrap();
+        assert_eq!(purged.len(), 1);
+        assert!(db.get_trash().unwrap().is_empty());
+        assert_eq!(db.restore_items(&[purged[0].id.unwrap()]).unwrap(), 0);
+        assert_eq!(db.count_history().unwrap(), 1);
+    }
+
+    fn contents(records: &[SyncRecord]) -> Vec<&str> {
+        records
+            .iter()
+            .map(|record| record.content.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn changes_go_out_in_the_order_they_happened() {
+        let (db, _) = open("sync-changes");
+        let (a, _) = db.insert_item(&text("a"), 100).unwrap();
+        db.insert_item(&text("b"), 100).unwrap();
+        let secret = ClipboardItem {
+            is_sensitive: true,
+            ..text("secret")
+        };
+        db.insert_item(&secret, 100).unwrap();
+        let (local, _) = db.insert_item(&text("local"), 100).unwrap();
+        db.set_no_sync(local, true).unwrap();
+
+        let (records, next) = db.get_sync_changes(0, 10, None).unwrap();
+        assert_eq!(contents(&records), ["a", "b"]);
+        assert_ne!(records[0].sync_id, records[1].sync_id);
+        assert_eq!(next, db.last_change_seq().unwrap());
+
+        // Pinning is a change of its own and moves the item to the end
+        db.set_pinned(a, true).unwrap();
+        let (records, _) = db.get_sync_changes(0, 10, None).unwrap();
+        assert_eq!(contents(&records), ["b", "a"]);
+        assert!(records[1].is_pinned);
+
+        // A full page continues after its last record
+        let (page, next) = db.get_sync_changes(0, 1, None).unwrap();
+        assert_eq!(contents(&page), ["b"]);
+        assert_eq!(next, page[0].change_seq);
+        let (page, next) = db.get_sync_changes(next, 1, None).unwrap();
+        assert_eq!(contents(&page), ["a"]);
+        assert!(db.get_sync_changes(next, 1, None).unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn local_deletions_go_to_every_device() {
+        let (db, _) = open("sync-deletions");
+        db.insert_item(&text("a"), 100).unwrap();
+        db.insert_item(&text("b"), 100).unwrap();
+        let (records, since) = db.get_sync_changes(0, 10, None).unwrap();
+        let (a, b) = (records[0].sync_id.clone(), records[1].sync_id.clone());
+        let index = db
+            .get_history(1, 10, None, false, false, None, None, None)
+            .unwrap()
+            .iter()
+            .position(|item| item.content == "a")
+            .unwrap();
+
+        db.delete_item(index).unwrap();
+        assert_eq!(
+            db.get_sync_deletions(since, 10, None).unwrap().0,
+            std::slice::from_ref(&a)
+        );
+        // Came from here, so it goes to every other device
+        assert_eq!(
+            db.get_sync_deletions(since, 10, Some("folder")).unwrap().0,
+            std::slice::from_ref(&a)
+        );
+
+        // A deletion from another device isn't sent back to it
+        assert!(db.delete_sync_item(&b, "folder").unwrap());
+        assert!(!db.delete_sync_item(&b, "folder").unwrap());
+        assert_eq!(
+            db.get_sync_deletions(since, 10, None).unwrap().0,
+            [a.as_str(), &b]
+        );
+        assert_eq!(
+            db.get_sync_deletions(since, 10, Some("folder")).unwrap().0,
+            [a]
+        );
+        assert_eq!(db.count_history().unwrap(), 0);
+    }
+
+    // A text item whose pin, note and collection were last changed at the
+    // given times
+    fn synced_text(
+        db: &Database,
+        content: &str,
+        pin: Stamped<bool>,
+        note: Stamped<Option<&str>>,
+    ) -> SyncRecord {
+        let (id, _) = db.insert_item(&text(content), 100).unwrap();
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute(
+                "UPDATE history SET is_pinned = ?1, note = ?2 WHERE id = ?3",
+                params![pin.1, note.1, id],
+            )
+            .unwrap();
+            conn.execute(
+                "UPDATE history SET pin_changed_at = ?1, note_changed_at = ?2 WHERE id = ?3",
+                params![pin.0, note.0, id],
+            )
+            .unwrap();
+        }
+        let (records, _) = db.get_sync_changes(0, 100, None).unwrap();
+        records
+            .into_iter()
+            .find(|record| record.content == content)
+            .unwrap()
+    }
+
+    #[test]
+    fn merge_takes_the_newer_side_of_each_field() {
+        let (db, _) = open("merge-newer");
+        let local = synced_text(&db, "shared", (20, true), (10, Some("local")));
+        let id = db.find_sync_item(&local.sync_id).unwrap().unwrap();
+        let remote = SyncRecord {
+            is_pinned: false,
+            pin_changed_at: 10,
+            note: Some("remote".into()),
+            note_changed_at: 30,
+            collection: Some("Work".into()),
+            collection_changed_at: 5,
+            ..local.clone()
+        };
+
+        assert!(db.merge_sync_record(id, &remote, false, "peer").unwrap());
+        let item = db.get_item(id).unwrap().unwrap();
+        assert!(item.is_pinned);
+        assert_eq!(item.note.as_deref(), Some("remote"));
+        let collection = db.get_collections().unwrap().remove(0);
+        assert_eq!(collection.name, "Work");
+        assert_eq!(item.collection_id, Some(collection.id));
+
+        // Nothing newer the second time
+        assert!(!db.merge_sync_record(id, &remote, false, "peer").unwrap());
+    }
+
+    #[test]
+    fn merge_ties_go_to_the_greater_value() {
+        let (db, _) = open("merge-ties");
+        let local = synced_text(&db, "shared", (10, false), (10, Some("a")));
+        let id = db.find_sync_item(&local.sync_id).unwrap().unwrap();
+        let remote = SyncRecord {
+            is_pinned: true,
+            note: Some("b".into()),
+            ..local.clone()
+        };
+
+        assert!(db.merge_sync_record(id, &remote, false, "peer").unwrap());
+        let item = db.get_item(id).unwrap().unwrap();
+        assert!(item.is_pinned);
+        assert_eq!(item.note.as_deref(), Some("b"));
+        // The other device keeps its side when it gets ours
+        assert!(!db.merge_sync_record(id, &local, false, "peer").unwrap());
+    }
+
+    #[test]
+    fn merge_keeps_the_smaller_sync_id() {
+        let (db, _) = open("merge-sync-id");
+        let local = synced_text(&db, "shared", (0, false), (0, None));
+        let id = db.find_sync_item(&local.sync_id).unwrap().unwrap();
+        let with_id = |sync_id: &str| SyncRecord {
+            sync_id: sync_id.to_string(),
+            ..local.clone()
+        };
+
+        let greater = format!("{}0", local.sync_id);
+        assert!(!db
+            .merge_sync_record(id, &with_id(&greater), false, "peer")
+            .unwrap());
+        assert_eq!(db.find_sync_item(&local.sync_id).unwrap(), Some(id));
+
+        let smaller = "0".repeat(32);
+        assert!(db
+            .merge_sync_record(id, &with_id(&smaller), false, "peer")
+            .unwrap());
+        assert_eq!(db.find_sync_item(&smaller).unwrap(), Some(id));
+
+        // A row inserted for a record takes its id whatever it is
+        assert!(db
+            .merge_sync_record(id, &with_id(&greater), true, "peer")
+            .unwrap());
+        assert_eq!(db.find_sync_item(&greater).unwrap(), Some(id));
+    }
+
+    #[test]
+    fn merged_changes_are_not_sent_back() {
+        let (db, _) = open("merge-source");
+        let local = synced_text(&db, "shared", (10, false), (10, None));
+        let id = db.find_sync_item(&local.sync_id).unwrap().unwrap();
+        let changes = |since: i64, skip_source: &str| {
+            db.get_sync_changes(since, 10, Some(skip_source))
+                .unwrap()
+                .0
+                .len()
+        };
+
+        // Ends up just like the record
+        let since = db.last_change_seq().unwrap();
+        let pinned = SyncRecord {
+            is_pinned: true,
+            pin_changed_at: 20,
+            ..local.clone()
+        };
+        assert!(db.merge_sync_record(id, &pinned, false, "peer").unwrap());
+        assert_eq!(changes(since, "peer"), 0);
+        assert_eq!(changes(since, "folder"), 1);
+
+        // Changed here afterwards
+        let since = db.last_change_seq().unwrap();
+        db.set_pinned(id, false).unwrap();
+        assert_eq!(changes(since, "peer"), 1);
+
+        // Holds a newer note of ours as well
+        db.update_content(
+            id,
+            "shared".into(),
+            None,
+            "text".into(),
+            None,
+            false,
+            Some("ours".into()),
+        )
+        .unwrap();
+        let since = db.last_change_seq().unwrap();
+        let pinned_later = SyncRecord {
+            pin_changed_at: i64::MAX,
+            ..pinned
+        };
+        assert!(db
+            .merge_sync_record(id, &pinned_later, false, "peer")
+            .unwrap());
+        assert_eq!(changes(since, "peer"), 1);
+    }
+
+    #[test]
+    fn items_already_in_the_history_become_snippets() {
+        let (db, _dir) = open("snippets");
+        let (id, _) = db.insert_item(&text("Dear {{input:name}},"), 100).unwrap();
+        let work = db.create_collection("Work".into()).unwrap();
+
+        db.make_snippet(id, Some("Greeting".into()), Some(work.id))
+            .unwrap();
+        let item = db.get_item(id).unwrap().unwrap();
+        assert_eq!(item.data_type, "snippet");
+        assert!(item.data_type_manual);
+        assert_eq!(item.note.as_deref(), Some("Greeting"));
+        assert_eq!(item.collection_id, Some(work.id));
+
+        // Nothing given keeps what the item has
+        db.make_snippet(id, None, None).unwrap();
+        let item = db.get_item(id).unwrap().unwrap();
+        assert_eq!(item.note.as_deref(), Some("Greeting"));
+        assert_eq!(item.collection_id, Some(work.id));
+    }
 }
@@ -0,0 +1,92 @@
+//! D-Bus service for Linux desktop integrations like GNOME Shell extensions
+//! and KDE scripts.
+//!
+//! Owns `com.dmxn.cliboard` on the session bus and serves the
+//! `com.dmxn.cliboard.Clipboard1` interface at `/com/dmxn/cliboard`. All three
+//! are derived from the app identifier in tauri.conf.json, spelling included,
+//! since sandboxes like Flatpak only let an app own names under its own id.
+//! Set `CLIPBOARD_DBUS_ADDRESS` to register on another bus instead, e.g. a
+//! private one started with `dbus-daemon --session --print-address`.
+//!
+//! Off by default: every process on the session bus can call it.
+
+mod service;
+
+use crate::commands;
+use crate::models::ClipboardItem;
+use crate::state::AppState;
+use service::{Backend, Change};
+use tauri::{AppHandle, Listener, Manager};
+
+impl Backend for AppHandle {
+    fn history(&self, page: usize, page_size: usize, query: Option<String>) -> Vec<ClipboardItem> {
+        commands::get_history(
+            self.state(),
+            page,
+            page_size,
+            query,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+    }
+
+    fn content(&self, id: i64) -> Result<String, String> {
+        commands::get_item_content(self.state(), id)
+    }
+
+    fn is_sensitive(&self, id: i64) -> Result<bool, String> {
+        self.state::<AppState>()
+            .db
+            .get_item(id)
+            .map_err(|e| e.to_string())?
+            .map(|item| item.is_sensitive)
+            .ok_or_else(|| format!("Item {} not found", id))
+    }
+
+    fn set_content(&self, text: String) -> Result<(), String> {
+        commands::set_clipboard_item(
+            self.clone(),
+            text,
+            "text".to_string(),
+            None,
+            None,
+            None,
+            None,
+            self.state(),
+        )
+        .map(|_| ())
+    }
+
+    fn set_paused(&self, paused: bool) {
+        commands::set_paused(self.clone(), paused, self.state());
+    }
+
+    fn is_paused(&self) -> bool {
+        commands::get_paused(self.state())
+    }
+
+    fn on_change(&self, notify: Box<dyn Fn(Change) + Send + Sync>) {
+        let notify = std::sync::Arc::new(notify);
+        let changed = notify.clone();
+        self.listen_any("clipboard-update", move |_| changed(Change::History));
+        self.listen_any("pause-state-changed", move |event| {
+            let paused = serde_json::from_str(event.payload()).unwrap_or_default();
+            notify(Change::Paused(paused))
+        });
+    }
+}
+
+/// Registers the service on Tauri's async runtime.
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        match service::serve(app).await {
+            // The listeners keep the connection open for the app's lifetime
+            Ok(_) => log::info!("D-Bus service registered as {}", service::BUS_NAME),
+            Err(e) => log::error!("Failed to start D-Bus service: {}", e),
+        }
+    });
+}
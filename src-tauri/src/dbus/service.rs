@@ -0,0 +1,361 @@
+//! The `com.dmxn.cliboard.Clipboard1` interface, kept apart from Tauri so it can be
+//! served on a private bus in tests.
+
+use crate::db::MAX_PAGE_SIZE;
+use crate::models::ClipboardItem;
+use zbus::object_server::SignalEmitter;
+use zbus::{connection, fdo, interface, Connection};
+
+// The app identifier from tauri.conf.json, see the module docs
+pub const BUS_NAME: &str = "com.dmxn.cliboard";
+const OBJECT_PATH: &str = "/com/dmxn/cliboard";
+
+/// (id, kind, data_type, content, timestamp, is_pinned, is_sensitive)
+type DbusItem = (i64, String, String, String, String, bool, bool);
+
+/// Something the service has to announce with a signal.
+pub enum Change {
+    History,
+    Paused(bool),
+}
+
+/// What the service needs from the app.
+pub trait Backend: Send + Sync + 'static {
+    fn history(&self, page: usize, page_size: usize, query: Option<String>) -> Vec<ClipboardItem>;
+    fn content(&self, id: i64) -> Result<String, String>;
+    fn is_sensitive(&self, id: i64) -> Result<bool, String>;
+    fn set_content(&self, text: String) -> Result<(), String>;
+    fn set_paused(&self, paused: bool);
+    fn is_paused(&self) -> bool;
+    /// Calls `notify` whenever the history or the pause state changes.
+    fn on_change(&self, notify: Box<dyn Fn(Change) + Send + Sync>);
+}
+
+struct ClipboardService {
+    backend: Box<dyn Backend>,
+}
+
+#[interface(name = "com.dmxn.cliboard.Clipboard1")]
+impl ClipboardService {
+    /// Most recent items, pinned ones first. Long text is cut to a preview,
+    /// use GetContent for the rest. Sensitive content is left out.
+    fn get_history(&self, page: u32, page_size: u32) -> fdo::Result<Vec<DbusItem>> {
+        if page == 0 {
+            return Err(fdo::Error::InvalidArgs("page starts at 1".to_string()));
+        }
+        let page_size = (page_size as usize).clamp(1, MAX_PAGE_SIZE);
+        Ok(to_dbus_items(self.backend.history(
+            page as usize,
+            page_size,
+            None,
+        )))
+    }
+
+    /// Items whose content or note contains `query`.
+    fn search(&self, query: String, limit: u32) -> Vec<DbusItem> {
+        let limit = (limit as usize).clamp(1, MAX_PAGE_SIZE);
+        to_dbus_items(self.backend.history(1, limit, Some(query)))
+    }
+
+    /// The full content of one item. Sensitive items are refused, any
+    /// process on the session bus could ask for them.
+    fn get_content(&self, id: i64) -> fdo::Result<String> {
+        if self.backend.is_sensitive(id).map_err(fdo::Error::Failed)? {
+            return Err(fdo::Error::AccessDenied(format!(
+                "Item {} is sensitive",
+                id
+            )));
+        }
+        self.backend.content(id).map_err(fdo::Error::Failed)
+    }
+
+    /// Writes text to the clipboard, it's recorded like any other copy.
+    fn set_content(&self, text: String) -> fdo::Result<()> {
+        self.backend.set_content(text).map_err(fdo::Error::Failed)
+    }
+
+    fn pause(&self) {
+        self.backend.set_paused(true);
+    }
+
+    fn resume(&self) {
+        self.backend.set_paused(false);
+    }
+
+    fn is_paused(&self) -> bool {
+        self.backend.is_paused()
+    }
+
+    /// Sent whenever the history changes.
+    #[zbus(signal)]
+    async fn clipboard_changed(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn pause_changed(emitter: &SignalEmitter<'_>, paused: bool) -> zbus::Result<()>;
+}
+
+fn to_dbus_items(items: Vec<ClipboardItem>) -> Vec<DbusItem> {
+    items
+        .into_iter()
+        .map(|item| {
+            let content = if item.is_sensitive {
+                String::new()
+            } else {
+                item.content
+            };
+            (
+                item.id.unwrap_or_default(),
+                item.kind,
+                item.data_type,
+                content,
+                item.timestamp,
+                item.is_pinned,
+                item.is_sensitive,
+            )
+        })
+        .collect()
+}
+
+/// Registers the service and forwards the backend's changes as signals.
+/// Must be called on a Tokio runtime.
+pub async fn serve<B: Backend + Clone>(backend: B) -> zbus::Result<Connection> {
+    let builder = match std::env::var("CLIPBOARD_DBUS_ADDRESS") {
+        Ok(address) => connection::Builder::address(address.as_str())?,
+        Err(_) => connection::Builder::session()?,
+    };
+    let conn = builder
+        .name(BUS_NAME)?
+        .serve_at(
+            OBJECT_PATH,
+            ClipboardService {
+                backend: Box::new(backend.clone()),
+            },
+        )?
+        .build()
+        .await?;
+
+    // Changes are reported from any thread, the signals go out on this runtime
+    let runtime = tokio::runtime::Handle::current();
+    let emitter = SignalEmitter::new(&conn, OBJECT_PATH)?.into_owned();
+    backend.on_change(Box::new(move |change| {
+        let emitter = emitter.clone();
+        runtime.spawn(async move {
+            let sent = match change {
+                Change::History => ClipboardService::clipboard_changed(&emitter).await,
+                Change::Paused(paused) => ClipboardService::pause_changed(&emitter, paused).await,
+            };
+            if let Err(e) = sent {
+                log::error!("Failed to emit D-Bus signal: {}", e);
+            }
+        });
+    }));
+    Ok(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_lite::StreamExt;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use zbus::Proxy;
+
+    type Notify = Box<dyn Fn(Change) + Send + Sync>;
+
+    #[derive(Clone, Default)]
+    struct FakeBackend {
+        items: Arc<Mutex<Vec<ClipboardItem>>>,
+        paused: Arc<AtomicBool>,
+        notify: Arc<Mutex<Option<Notify>>>,
+    }
+
+    impl FakeBackend {
+        fn changed(&self, change: Change) {
+            if let Some(notify) = self.notify.lock().unwrap().as_ref() {
+                notify(change);
+            }
+        }
+    }
+
+    impl Backend for FakeBackend {
+        fn history(
+            &self,
+            page: usize,
+            page_size: usize,
+            query: Option<String>,
+        ) -> Vec<ClipboardItem> {
+            let items = self.items.lock().unwrap();
+            items
+                .iter()
+                .rev()
+                .filter(|item| {
+                    query
+                        .as_ref()
+                        .is_none_or(|q| item.content.contains(q.as_str()))
+                })
+                .skip((page - 1) * page_size)
+                .take(page_size)
+                .cloned()
+                .collect()
+        }
+
+        fn content(&self, id: i64) -> Result<String, String> {
+            let items = self.items.lock().unwrap();
+            items
+                .iter()
+                .find(|item| item.id == Some(id))
+                .map(|item| item.content.clone())
+                .ok_or_else(|| format!("Item {} not found", id))
+        }
+
+        fn is_sensitive(&self, id: i64) -> Result<bool, String> {
+            let items = self.items.lock().unwrap();
+            items
+                .iter()
+                .find(|item| item.id == Some(id))
+                .map(|item| item.is_sensitive)
+                .ok_or_else(|| format!("Item {} not found", id))
+        }
+
+        fn set_content(&self, text: String) -> Result<(), String> {
+            {
+                let mut items = self.items.lock().unwrap();
+                let id = items.len() as i64 + 1;
+                items.push(ClipboardItem {
+                    id: Some(id),
+                    content: text,
+                    kind: "text".into(),
+                    data_type: "text".into(),
+                    ..Default::default()
+                });
+            }
+            self.changed(Change::History);
+            Ok(())
+        }
+
+        fn set_paused(&self, paused: bool) {
+            self.paused.store(paused, Ordering::SeqCst);
+            self.changed(Change::Paused(paused));
+        }
+
+        fn is_paused(&self) -> bool {
+            self.paused.load(Ordering::SeqCst)
+        }
+
+        fn on_change(&self, notify: Notify) {
+            *self.notify.lock().unwrap() = Some(notify);
+        }
+    }
+
+    // A bus of our own, so the test doesn't depend on a desktop session
+    struct PrivateBus(std::process::Child);
+
+    impl PrivateBus {
+        fn start() -> Option<(Self, String)> {
+            let mut child = Command::new("dbus-daemon")
+                .args(["--session", "--print-address", "--nofork", "--nopidfile"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(child.stdout.take()?)
+                .read_line(&mut address)
+                .ok()?;
+            Some((Self(child), address.trim().to_string()))
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_the_interface_on_a_private_bus() {
+        let Some((_bus, address)) = PrivateBus::start() else {
+            eprintln!("dbus-daemon is not installed, skipping");
+            return;
+        };
+        std::env::set_var("CLIPBOARD_DBUS_ADDRESS", &address);
+
+        let backend = FakeBackend::default();
+        let _service = serve(backend.clone()).await.unwrap();
+
+        let client = connection::Builder::address(address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let proxy = Proxy::new(
+            &client,
+            BUS_NAME,
+            OBJECT_PATH,
+            "com.dmxn.cliboard.Clipboard1",
+        )
+        .await
+        .unwrap();
+        let mut changed = proxy.receive_signal("ClipboardChanged").await.unwrap();
+        let mut paused = proxy.receive_signal("PauseChanged").await.unwrap();
+
+        proxy
+            .call_method("SetContent", &("hello world",))
+            .await
+            .unwrap();
+        proxy.call_method("SetContent", &("bye",)).await.unwrap();
+        for _ in 0..2 {
+            tokio::time::timeout(Duration::from_secs(5), changed.next())
+                .await
+                .expect("ClipboardChanged")
+                .unwrap();
+        }
+
+        let history: Vec<DbusItem> = proxy.call("GetHistory", &(1u32, 10u32)).await.unwrap();
+        let contents: Vec<&str> = history.iter().map(|item| item.3.as_str()).collect();
+        assert_eq!(contents, ["bye", "hello world"]);
+
+        let error = proxy
+            .call::<_, _, Vec<DbusItem>>("GetHistory", &(0u32, 10u32))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, zbus::Error::MethodError(ref name, _, _)
+            if name.as_str() == "org.freedesktop.DBus.Error.InvalidArgs"));
+
+        let found: Vec<DbusItem> = proxy.call("Search", &("hello", 10u32)).await.unwrap();
+        assert_eq!(found.len(), 1);
+        let content: String = proxy.call("GetContent", &(found[0].0,)).await.unwrap();
+        assert_eq!(content, "hello world");
+
+        // Sensitive items are listed without content and can't be read
+        backend.items.lock().unwrap()[0].is_sensitive = true;
+        let history: Vec<DbusItem> = proxy.call("GetHistory", &(1u32, 10u32)).await.unwrap();
+        assert_eq!(history[1].3, "");
+        assert!(history[1].6);
+        let error = proxy
+            .call::<_, _, String>("GetContent", &(history[1].0,))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, zbus::Error::MethodError(ref name, _, _)
+            if name.as_str() == "org.freedesktop.DBus.Error.AccessDenied"));
+
+        proxy.call_method("Pause", &()).await.unwrap();
+        let signal = tokio::time::timeout(Duration::from_secs(5), paused.next())
+            .await
+            .expect("PauseChanged")
+            .unwrap();
+        assert!(signal.body().deserialize::<bool>().unwrap());
+        assert!(proxy.call::<_, _, bool>("IsPaused", &()).await.unwrap());
+
+        proxy.call_method("Resume", &()).await.unwrap();
+        let signal = tokio::time::timeout(Duration::from_secs(5), paused.next())
+            .await
+            .expect("PauseChanged")
+            .unwrap();
+        assert!(!signal.body().deserialize::<bool>().unwrap());
+        assert!(!proxy.call::<_, _, bool>("IsPaused", &()).await.unwrap());
+    }
+}
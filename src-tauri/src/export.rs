@@ -0,0 +1,611 @@
+//! Exports a filtered selection of the history to JSON, CSV or Markdown.
+//! Used by the `export_history` command and by `clipboard-cli export`.
+
+use crate::db::Database;
+use crate::models::ClipboardItem;
+use base64::{engine::general_purpose, Engine as _};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Rows fetched per query while collecting the items
+const PAGE_SIZE: usize = 500;
+// Version of the JSON document, bumped when its layout changes
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    // Lossless, can be imported again
+    #[default]
+    Json,
+    Csv,
+    Markdown,
+}
+
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Json),
+            "csv" => Some(Self::Csv),
+            "markdown" | "md" => Some(Self::Markdown),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SensitiveMode {
+    // Leave sensitive items out
+    #[default]
+    Exclude,
+    // Keep the items but blank their content
+    Redact,
+    // Export the decrypted content, only together with `confirm_decrypt`
+    Decrypt,
+}
+
+impl SensitiveMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "exclude" => Some(Self::Exclude),
+            "redact" => Some(Self::Redact),
+            "decrypt" => Some(Self::Decrypt),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageMode {
+    // Copy the images into a folder next to the export
+    #[default]
+    Files,
+    // Embed them in the document
+    Base64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub collection_id: Option<i64>,
+    pub data_type: Option<String>,
+    pub query: Option<String>,
+    // Inclusive bounds, either a date ("2024-01-31") or a full timestamp
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub sensitive: SensitiveMode,
+    // The user confirmed exporting sensitive content in plain text
+    pub confirm_decrypt: bool,
+    pub images: ImageMode,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportReport {
+    pub items: usize,
+    pub images: usize,
+    pub sensitive: usize,
+}
+
+#[derive(Serialize)]
+struct ExportedItem {
+    #[serde(flatten)]
+    item: ClipboardItem,
+    // Relative path of the copied image file or a base64 data URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonDocument<'a> {
+    version: u32,
+    exported_at: String,
+    items: &'a [ExportedItem],
+}
+
+/// Writes the export to `path`. With `ImageMode::Files` the images are copied
+/// to `<name>_images/` next to it.
+pub fn export_history(
+    db: &Database,
+    path: &Path,
+    options: &ExportOptions,
+) -> Result<ExportReport, String> {
+    let images_dir = match options.images {
+        ImageMode::Files => {
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "export".to_string());
+            Some(path.with_file_name(format!("{}_images", stem)))
+        }
+        ImageMode::Base64 => None,
+    };
+    let (document, report) = render(db, options, images_dir.as_deref())?;
+    fs::write(path, document).map_err(|e| e.to_string())?;
+    Ok(report)
+}
+
+/// Renders the export into a string, images are always embedded.
+pub fn export_to_string(
+    db: &Database,
+    options: &ExportOptions,
+) -> Result<(String, ExportReport), String> {
+    render(db, options, None)
+}
+
+fn render(
+    db: &Database,
+    options: &ExportOptions,
+    images_dir: Option<&Path>,
+) -> Result<(String, ExportReport), String> {
+    if options.sensitive == SensitiveMode::Decrypt && !options.confirm_decrypt {
+        return Err("Exporting decrypted sensitive content has to be confirmed".to_string());
+    }
+
+    let items = collect_items(db, options)?;
+    let mut report = ExportReport {
+        items: items.len(),
+        images: 0,
+        sensitive: items.iter().filter(|item| item.is_sensitive).count(),
+    };
+
+    let mut exported = Vec::with_capacity(items.len());
+    for item in items {
+        let image = if item.kind == "image" {
+            export_image(&item, images_dir)?
+        } else {
+            None
+        };
+        report.images += usize::from(image.is_some());
+        exported.push(ExportedItem { item, image });
+    }
+
+    let document = match options.format {
+        ExportFormat::Json => {
+            let document = JsonDocument {
+                version: FORMAT_VERSION,
+                exported_at: now(),
+                items: &exported,
+            };
+            serde_json::to_string_pretty(&document).map_err(|e| e.to_string())?
+        }
+        ExportFormat::Csv => to_csv(&exported),
+        ExportFormat::Markdown => to_markdown(&exported),
+    };
+    Ok((document, report))
+}
+
+fn now() -> String {
+    Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn collect_items(db: &Database, options: &ExportOptions) -> Result<Vec<ClipboardItem>, String> {
+    let mut items = Vec::new();
+    for page in 1.. {
+        let batch = db
+            .get_history(
+                page,
+                PAGE_SIZE,
+                options.query.clone(),
+                false,
+                false,
+                options.collection_id,
+                None,
+                options.data_type.clone(),
+            )
+            .map_err(|e| e.to_string())?;
+        let last = batch.len() < PAGE_SIZE;
+
+        for mut item in batch {
+            if !in_range(&item.timestamp, options) {
+                continue;
+            }
+            if item.is_sensitive {
+                match options.sensitive {
+                    SensitiveMode::Exclude => continue,
+                    SensitiveMode::Redact => {
+                        item.content.clear();
+                        item.html_content = None;
+                        item.content_length = None;
+                        items.push(item);
+                        continue;
+                    }
+                    SensitiveMode::Decrypt => {}
+                }
+            }
+            // History rows of large text only hold a preview
+            if item.kind == "text" && item.content_length.is_some() {
+                let id = item.id.unwrap_or_default();
+                item.content = db.get_item_content(id).map_err(|e| e.to_string())?;
+                item.content_length = None;
+            }
+            items.push(item);
+        }
+        if last {
+            break;
+        }
+    }
+    Ok(items)
+}
+
+fn in_range(timestamp: &str, options: &ExportOptions) -> bool {
+    if let Some(from) = &options.from {
+        if timestamp < from.as_str() {
+            return false;
+        }
+    }
+    if let Some(to) = &options.to {
+        // A bare date includes the whole day
+        let prefix = timestamp.get(..to.len()).unwrap_or(timestamp);
+        if prefix > to.as_str() {
+            return false;
+        }
+    }
+    true
+}
+
+fn export_image(item: &ClipboardItem, images_dir: Option<&Path>) -> Result<Option<String>, String> {
+    let source = PathBuf::from(&item.content);
+    let Some(name) = source.file_name() else {
+        return Ok(None);
+    };
+
+    match images_dir {
+        Some(dir) => {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            if let Err(e) = fs::copy(&source, dir.join(name)) {
+                log::warn!("Skipping image {:?}: {}", source, e);
+                return Ok(None);
+            }
+            let dir_name = dir.file_name().unwrap_or_default().to_string_lossy();
+            Ok(Some(format!("{}/{}", dir_name, name.to_string_lossy())))
+        }
+        None => match fs::read(&source) {
+            Ok(bytes) => {
+                let mime = match source.extension().and_then(|ext| ext.to_str()) {
+                    Some("webp") => "image/webp",
+                    _ => "image/png",
+                };
+                Ok(Some(format!(
+                    "data:{};base64,{}",
+                    mime,
+                    general_purpose::STANDARD.encode(bytes)
+                )))
+            }
+            Err(e) => {
+                log::warn!("Skipping image {:?}: {}", source, e);
+                Ok(None)
+            }
+        },
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn to_csv(items: &[ExportedItem]) -> String {
+    let mut csv = String::from(
+        "id,timestamp,kind,data_type,language,source_app,collection_id,is_pinned,is_sensitive,note,content\r\n",
+    );
+    for ExportedItem { item, image } in items {
+        let content = image.as_deref().unwrap_or(&item.content);
+        let fields = [
+            item.id.map(|id| id.to_string()).unwrap_or_default(),
+            item.timestamp.clone(),
+            item.kind.clone(),
+            item.data_type.clone(),
+            item.language.clone().unwrap_or_default(),
+            item.source_app.clone().unwrap_or_default(),
+            item.collection_id
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            item.is_pinned.to_string(),
+            item.is_sensitive.to_string(),
+            item.note.clone().unwrap_or_default(),
+            content.to_string(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+// A fence longer than any backtick run in the text, so it can't be closed early
+fn fence_for(text: &str) -> String {
+    let longest = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    "`".repeat(longest.max(2) + 1)
+}
+
+fn to_markdown(items: &[ExportedItem]) -> String {
+    let mut md = String::from("# Clipboard History\n\n");
+    let _ = writeln!(md, "Exported {}, {} items.", now(), items.len());
+
+    for ExportedItem { item, image } in items {
+        let _ = write!(md, "\n## {} · {}", item.timestamp, item.data_type);
+        if item.is_pinned {
+            md.push_str(" · pinned");
+        }
+        md.push_str("\n\n");
+        if let Some(note) = &item.note {
+            let _ = writeln!(md, "> {}\n", note.replace('\n', "\n> "));
+        }
+
+        if item.is_sensitive && item.content.is_empty() {
+            md.push_str("*Sensitive content not exported*\n");
+            continue;
+        }
+        match item.kind.as_str() {
+            "image" => match image {
+                Some(image) => {
+                    let _ = writeln!(md, "![image]({})", image);
+                }
+                None => md.push_str("*Image not available*\n"),
+            },
+            "file" => {
+                let files: Vec<String> = serde_json::from_str(&item.content).unwrap_or_default();
+                for file in files {
+                    let _ = writeln!(md, "- `{}`", file);
+                }
+            }
+            _ => {
+                let fence = fence_for(&item.content);
+                let language = item.language.as_deref().unwrap_or_default();
+                let _ = writeln!(md, "{}{}\n{}\n{}", fence, language, item.content, fence);
+            }
+        }
+    }
+    md
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Crypto;
+    use std::sync::Arc;
+
+    fn open(name: &str) -> (Database, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("clipboard-export-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let crypto = Arc::new(Crypto::new(dir.join("key")));
+        let db = Database::new(dir.join("history.db"), crypto).unwrap();
+        (db, dir)
+    }
+
+    fn text(content: &str, timestamp: &str) -> ClipboardItem {
+        ClipboardItem {
+            content: content.into(),
+            kind: "text".into(),
+            timestamp: timestamp.into(),
+            data_type: "text".into(),
+            ..Default::default()
+        }
+    }
+
+    fn export(db: &Database, options: ExportOptions) -> (String, ExportReport) {
+        export_to_string(db, &options).unwrap()
+    }
+
+    fn contents(db: &Database, options: ExportOptions) -> Vec<String> {
+        let (json, _) = export(db, options);
+        let document: serde_json::Value = serde_json::from_str(&json).unwrap();
+        document["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["content"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn decrypting_needs_confirmation() {
+        let (db, _dir) = open("confirm");
+        db.insert_item(
+            &ClipboardItem {
+                is_sensitive: true,
+                ..text("secret", "2024-01-01 00:00:00")
+            },
+            100,
+        )
+        .unwrap();
+        let decrypt = ExportOptions {
+            sensitive: SensitiveMode::Decrypt,
+            ..Default::default()
+        };
+        assert!(export_to_string(&db, &decrypt).is_err());
+
+        let confirmed = ExportOptions {
+            confirm_decrypt: true,
+            ..decrypt
+        };
+        assert_eq!(contents(&db, confirmed.clone()), ["secret"]);
+        assert_eq!(export(&db, confirmed).1.sensitive, 1);
+    }
+
+    #[test]
+    fn sensitive_items_are_left_out_or_redacted() {
+        let (db, _dir) = open("sensitive");
+        db.insert_item(&text("public", "2024-01-01 00:00:00"), 100)
+            .unwrap();
+        db.insert_item(
+            &ClipboardItem {
+                is_sensitive: true,
+                ..text("secret", "2024-01-02 00:00:00")
+            },
+            100,
+        )
+        .unwrap();
+
+        assert_eq!(contents(&db, ExportOptions::default()), ["public"]);
+        let redact = || ExportOptions {
+            sensitive: SensitiveMode::Redact,
+            ..Default::default()
+        };
+        assert_eq!(contents(&db, redact()), ["", "public"]);
+        let (markdown, _) = export(
+            &db,
+            ExportOptions {
+                format: ExportFormat::Markdown,
+                ..redact()
+            },
+        );
+        assert!(!markdown.contains("secret"));
+        assert!(markdown.contains("*Sensitive content not exported*"));
+    }
+
+    #[test]
+    fn filters_by_date_type_and_text() {
+        let (db, _dir) = open("filters");
+        db.insert_item(&text("first", "2024-01-01 10:00:00"), 100)
+            .unwrap();
+        db.insert_item(&text("second", "2024-01-02 10:00:00"), 100)
+            .unwrap();
+        db.insert_item(
+            &ClipboardItem {
+                data_type: "url".into(),
+                ..text("https://example.com", "2024-01-03 10:00:00")
+            },
+            100,
+        )
+        .unwrap();
+
+        let range = |from: Option<&str>, to: Option<&str>| ExportOptions {
+            from: from.map(String::from),
+            to: to.map(String::from),
+            ..Default::default()
+        };
+        // A bare date takes in the whole day
+        assert_eq!(
+            contents(&db, range(Some("2024-01-02"), Some("2024-01-02"))),
+            ["second"]
+        );
+        assert_eq!(
+            contents(&db, range(None, Some("2024-01-02 09:00:00"))),
+            ["first"]
+        );
+        assert_eq!(
+            contents(&db, range(Some("2024-01-02"), None)),
+            ["https://example.com", "second"]
+        );
+
+        let url = ExportOptions {
+            data_type: Some("url".into()),
+            ..Default::default()
+        };
+        assert_eq!(contents(&db, url), ["https://example.com"]);
+        let query = ExportOptions {
+            query: Some("sec".into()),
+            ..Default::default()
+        };
+        assert_eq!(contents(&db, query), ["second"]);
+    }
+
+    #[test]
+    fn json_has_the_full_text_and_copies_images() {
+        let (db, dir) = open("json");
+        let image = dir.join("a.png");
+        fs::write(&image, b"PNG").unwrap();
+        let big = "b".repeat(5000);
+        db.insert_item(
+            &ClipboardItem {
+                store_as_blob: true,
+                ..text(&big, "2024-01-01 00:00:00")
+            },
+            100,
+        )
+        .unwrap();
+        db.insert_item(
+            &ClipboardItem {
+                kind: "image".into(),
+                data_type: "image".into(),
+                ..text(image.to_str().unwrap(), "2024-01-02 00:00:00")
+            },
+            100,
+        )
+        .unwrap();
+
+        let path = dir.join("out.json");
+        let report = export_history(&db, &path, &ExportOptions::default()).unwrap();
+        assert_eq!((report.items, report.images), (2, 1));
+        let document: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(document["version"], FORMAT_VERSION);
+        assert_eq!(document["items"][0]["image"], "out_images/a.png");
+        assert_eq!(document["items"][1]["content"], big.as_str());
+        assert_eq!(
+            fs::read(dir.join("out_images").join("a.png")).unwrap(),
+            b"PNG"
+        );
+
+        // Without a folder to put them in, images are embedded
+        let (json, _) = export(&db, ExportOptions::default());
+        assert!(json.contains("data:image/png;base64,UE5H"));
+    }
+
+    #[test]
+    fn csv_quotes_fields_that_need_it() {
+        let (db, _dir) = open("csv");
+        db.insert_item(
+            &ClipboardItem {
+                note: Some("a, b".into()),
+                ..text("say \"hi\"\nbye", "2024-01-01 00:00:00")
+            },
+            100,
+        )
+        .unwrap();
+        let (csv, _) = export(
+            &db,
+            ExportOptions {
+                format: ExportFormat::Csv,
+                ..Default::default()
+            },
+        );
+        let mut lines = csv.split("\r\n");
+        assert!(lines.next().unwrap().starts_with("id,timestamp,kind"));
+        assert!(lines
+            .next()
+            .unwrap()
+            .ends_with(",text,text,,,,false,false,\"a, b\",\"say \"\"hi\"\"\nbye\""));
+    }
+
+    #[test]
+    fn markdown_fences_outlast_the_content() {
+        let (db, _dir) = open("markdown");
+        db.insert_item(
+            &ClipboardItem {
+                data_type: "code".into(),
+                language: Some("rust".into()),
+                ..text("let a = ```x```;", "2024-01-01 00:00:00")
+            },
+            100,
+        )
+        .unwrap();
+        let (markdown, report) = export(
+            &db,
+            ExportOptions {
+                format: ExportFormat::Markdown,
+                ..Default::default()
+            },
+        );
+        assert_eq!(report.items, 1);
+        assert!(markdown.starts_with("# Clipboard History"));
+        assert!(markdown.contains("## 2024-01-01 00:00:00 · code"));
+        assert!(markdown.contains("````rust\nlet a = ```x```;\n````"));
+    }
+}
@@ -0,0 +1,286 @@
+use crate::db::Database;
+use crate::image_store::ImageStore;
+use chrono::Local;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// Files younger than this are left alone, they may belong to a capture whose
+// row has not been inserted yet.
+const MIN_ORPHAN_AGE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct GcReport {
+    pub removed_files: usize,
+    pub bytes_reclaimed: u64,
+    pub missing_items: Vec<i64>,
+}
+
+/// Permanently deletes items that have been in the trash for longer than
+/// `retention_days`, together with their files. Returns how many were removed.
+pub fn purge_expired_trash(
+    db: &Database,
+    image_store: &ImageStore,
+    retention_days: u32,
+) -> Result<usize, String> {
+    if retention_days == 0 {
+        return Ok(0);
+    }
+    let cutoff = Local::now() - chrono::Duration::days(retention_days as i64);
+    let items = db
+        .purge_trash(Some(&cutoff.format("%Y-%m-%d %H:%M:%S").to_string()))
+        .map_err(|e| e.to_string())?;
+    image_store.remove_all(&items);
+    if !items.is_empty() {
+        log::info!("Removed {} expired items from the trash", items.len());
+    }
+    Ok(items.len())
+}
+
+/// Reconciles the image and blob directories with the history rows.
+///
+/// Files no row points at are deleted, and rows whose files are gone get
+/// their `file_missing` flag set so the UI can show them as broken.
+pub fn collect_garbage(db: &Database, image_store: &ImageStore) -> Result<GcReport, String> {
+    let mut report = GcReport::default();
+    let mut referenced = HashSet::new();
+
+    for item in db.get_image_items().map_err(|e| e.to_string())? {
+        let Some(id) = item.id else { continue };
+        let path = PathBuf::from(&item.content);
+        // Base64 content from very old versions has no file behind it
+        if !path.is_absolute() {
+            continue;
+        }
+
+        let missing = !path.exists();
+        if missing {
+            report.missing_items.push(id);
+        }
+        if missing != item.file_missing {
+            db.set_file_missing(id, missing)
+                .map_err(|e| e.to_string())?;
+        }
+
+        referenced.insert(path);
+        if let Some(thumbnail) = item.thumbnail {
+            referenced.insert(PathBuf::from(thumbnail));
+        }
+    }
+
+    for (id, name, flagged) in db.get_blob_refs().map_err(|e| e.to_string())? {
+        let path = db.blobs_dir().join(&name);
+        let missing = !path.exists();
+        if missing {
+            report.missing_items.push(id);
+        }
+        if missing != flagged {
+            db.set_file_missing(id, missing)
+                .map_err(|e| e.to_string())?;
+        }
+        referenced.insert(path);
+    }
+    for name in db.get_revision_blob_refs().map_err(|e| e.to_string())? {
+        referenced.insert(db.blobs_dir().join(name));
+    }
+
+    remove_orphans(image_store.dir(), &referenced, &mut report);
+    remove_orphans(db.blobs_dir(), &referenced, &mut report);
+
+    log::info!(
+        "Storage GC removed {} files ({} bytes), {} items have missing files",
+        report.removed_files,
+        report.bytes_reclaimed,
+        report.missing_items.len()
+    );
+    Ok(report)
+}
+
+fn remove_orphans(dir: &Path, referenced: &HashSet<PathBuf>, report: &mut GcReport) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!("Failed to read {:?}: {}", dir, e);
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if referenced.contains(&path) {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .unwrap_or_default();
+        if age < MIN_ORPHAN_AGE {
+            continue;
+        }
+
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                report.removed_files += 1;
+                report.bytes_reclaimed += metadata.len();
+            }
+            Err(e) => log::error!("Failed to delete orphaned file {:?}: {}", path, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Crypto;
+    use crate::models::ClipboardItem;
+    use std::sync::Arc;
+
+    fn open(name: &str) -> (Database, ImageStore, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("clipboard-gc-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let crypto = Arc::new(Crypto::new(dir.join("key")));
+        let db = Database::new(dir.join("history.db"), crypto).unwrap();
+        let store = ImageStore::new(dir.join("images"));
+        (db, store, dir)
+    }
+
+    fn image_item(store: &ImageStore, shade: u8) -> ClipboardItem {
+        let image = image::RgbaImage::from_pixel(10, 10, image::Rgba([shade, 0, 0, 255]));
+        let stored = store.save(&image, "png").unwrap();
+        ClipboardItem {
+            content: stored.path,
+            kind: "image".into(),
+            timestamp: "2024-01-01 00:00:00".into(),
+            data_type: "image".into(),
+            thumbnail: Some(stored.thumbnail_path),
+            ..Default::default()
+        }
+    }
+
+    fn blob_item(content: &str) -> ClipboardItem {
+        ClipboardItem {
+            content: content.into(),
+            kind: "text".into(),
+            timestamp: "2024-01-01 00:00:00".into(),
+            data_type: "text".into(),
+            store_as_blob: true,
+            ..Default::default()
+        }
+    }
+
+    // Writes a file that looks like it was left behind a while ago
+    fn old_file(path: &Path, len: usize) {
+        fs::write(path, vec![0; len]).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - MIN_ORPHAN_AGE * 2)
+            .unwrap();
+    }
+
+    #[test]
+    fn removes_old_files_no_row_points_at() {
+        let (db, store, dir) = open("orphans");
+        let item = image_item(&store, 1);
+        db.insert_item(&item, 100).unwrap();
+        db.insert_item(&blob_item(&"b".repeat(10_000)), 100)
+            .unwrap();
+
+        let orphan_image = store.dir().join("orphan.png");
+        old_file(&orphan_image, 4);
+        let orphan_blob = db.blobs_dir().join("orphan");
+        old_file(&orphan_blob, 6);
+        // May still be waiting for its row
+        let fresh = store.dir().join("fresh.png");
+        fs::write(&fresh, b"xx").unwrap();
+
+        let report = collect_garbage(&db, &store).unwrap();
+        assert_eq!(report.removed_files, 2);
+        assert_eq!(report.bytes_reclaimed, 10);
+        assert!(report.missing_items.is_empty());
+        assert!(!orphan_image.exists() && !orphan_blob.exists());
+        assert!(fresh.exists());
+        assert!(Path::new(&item.content).exists());
+        assert!(Path::new(item.thumbnail.as_deref().unwrap()).exists());
+        assert_eq!(fs::read_dir(dir.join("blobs")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn flags_rows_whose_file_is_gone() {
+        let (db, store, _dir) = open("missing");
+        let item = image_item(&store, 2);
+        let (image_id, _) = db.insert_item(&item, 100).unwrap();
+        let (blob_id, _) = db
+            .insert_item(&blob_item(&"m".repeat(10_000)), 100)
+            .unwrap();
+        let blob = db
+            .blobs_dir()
+            .join(db.get_item(blob_id).unwrap().unwrap().blob_path.unwrap());
+        let kept = fs::read(&blob).unwrap();
+        fs::remove_file(&item.content).unwrap();
+        fs::remove_file(&blob).unwrap();
+
+        let mut missing = collect_garbage(&db, &store).unwrap().missing_items;
+        missing.sort();
+        assert_eq!(missing, [image_id, blob_id]);
+        assert!(db.get_item(image_id).unwrap().unwrap().file_missing);
+        assert!(db.get_item(blob_id).unwrap().unwrap().file_missing);
+
+        // The flag goes away once the file is back
+        fs::write(&blob, kept).unwrap();
+        assert_eq!(
+            collect_garbage(&db, &store).unwrap().missing_items,
+            [image_id]
+        );
+        assert!(!db.get_item(blob_id).unwrap().unwrap().file_missing);
+    }
+
+    #[test]
+    fn keeps_blobs_only_revisions_refer_to() {
+        let (db, store, dir) = open("revisions");
+        let (id, _) = db
+            .insert_item(&blob_item(&"r".repeat(10_000)), 100)
+            .unwrap();
+        db.update_content(
+            id,
+            "s".repeat(10_000),
+            None,
+            "text".into(),
+            None,
+            false,
+            None,
+        )
+        .unwrap();
+        for entry in fs::read_dir(dir.join("blobs")).unwrap() {
+            old_file(&entry.unwrap().path(), 1);
+        }
+
+        assert_eq!(collect_garbage(&db, &store).unwrap().removed_files, 0);
+        assert_eq!(fs::read_dir(dir.join("blobs")).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn recently_trashed_items_are_kept() {
+        let (db, store, _dir) = open("trash");
+        let item = image_item(&store, 3);
+        db.insert_item(&item, 100).unwrap();
+        db.delete_item(0).unwrap();
+
+        assert_eq!(purge_expired_trash(&db, &store, 0).unwrap(), 0);
+        assert_eq!(purge_expired_trash(&db, &store, 30).unwrap(), 0);
+        assert_eq!(db.get_trash().unwrap().len(), 1);
+        assert!(Path::new(&item.content).exists());
+    }
+}
@@ -0,0 +1,285 @@
+//! Optional REST API on 127.0.0.1 for dashboards and test harnesses.
+//!
+//! Every request needs `Authorization: Bearer <http_api_token>` from the
+//! config. The routes are a thin layer over the control socket methods in
+//! [`crate::ipc`], the full description is served at `/api/openapi.json`.
+
+use crate::db::MAX_PAGE_SIZE;
+use crate::ipc::{self, RpcError};
+use crate::state::AppState;
+use base64::{engine::general_purpose, Engine as _};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::thread;
+use tauri::{AppHandle, Manager};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const OPENAPI: &str = include_str!("openapi.json");
+// Request bodies are small JSON documents, anything bigger is rejected
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+// Requests are handled by this many threads, the rest wait in line
+const WORKERS: usize = 4;
+
+type ApiResult = Result<Value, (u16, String)>;
+
+/// Starts the server in a background thread. A token is generated and saved
+/// to the config the first time the API is enabled.
+pub fn start(app: &AppHandle) -> Result<(), String> {
+    let state = app.state::<AppState>();
+    let (port, token) = {
+        let mut config = state.config.lock().unwrap();
+        if config.http_api_token.is_empty() {
+            config.http_api_token = generate_token();
+            config.save(&state.config_path).map_err(|e| e.to_string())?;
+        }
+        (config.http_api_port, config.http_api_token.clone())
+    };
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let server = Server::http(addr).map_err(|e| e.to_string())?;
+    log::info!("HTTP API listening on http://{}", addr);
+
+    let server = Arc::new(server);
+    for _ in 0..WORKERS {
+        let server = server.clone();
+        let app = app.clone();
+        let token = token.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                handle(&app, &token, request);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn generate_token() -> String {
+    use aes_gcm::aead::{rand_core::RngCore, OsRng};
+
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn handle(app: &AppHandle, token: &str, mut request: Request) {
+    let result = if !is_loopback_host(&request) {
+        // Guards against DNS rebinding from web pages
+        Err((403, "Forbidden host".to_string()))
+    } else if !is_authorized(&request, token) {
+        Err((401, "Missing or invalid bearer token".to_string()))
+    } else {
+        route(app, &mut request)
+    };
+
+    let (status, body) = match result {
+        Ok(value) => (200, value),
+        Err((status, message)) => (status, json!({ "error": message })),
+    };
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").expect("valid header"));
+    if let Err(e) = request.respond(response) {
+        log::error!("Failed to send HTTP API response: {}", e);
+    }
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+fn is_loopback_host(request: &Request) -> bool {
+    let Some(host) = header(request, "Host") else {
+        return false;
+    };
+    let host = host.rsplit_once(':').map_or(host, |(host, _)| host);
+    matches!(host, "127.0.0.1" | "localhost")
+}
+
+fn is_authorized(request: &Request, token: &str) -> bool {
+    let Some(given) = header(request, "Authorization").and_then(|v| v.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    // Constant time, so the token can't be guessed byte by byte
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn route(app: &AppHandle, request: &mut Request) -> ApiResult {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let query = parse_query(query);
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let method = request.method().clone();
+
+    match (&method, segments.as_slice()) {
+        (Method::Get, ["api", "openapi.json"]) => {
+            serde_json::from_str(OPENAPI).map_err(|e| (500, e.to_string()))
+        }
+        (Method::Get, ["api", "history"]) => call(app, "get_history", history_params(&query)?),
+        (Method::Get, ["api", "search"]) => {
+            let mut params = history_params(&query)?;
+            let q = query
+                .get("q")
+                .filter(|q| !q.is_empty())
+                .ok_or((400, "Missing query parameter q".to_string()))?;
+            params["query"] = json!(q);
+            call(app, "get_history", params)
+        }
+        (Method::Get, ["api", "items", id]) => {
+            let item = call(app, "get_item", json!({ "id": parse_id(id)? }))?;
+            if item.is_null() {
+                return Err((404, format!("Item {} not found", id)));
+            }
+            Ok(item)
+        }
+        (Method::Put, ["api", "items", id, "collection"]) => {
+            let mut params = read_body(request)?;
+            params["item_id"] = json!(parse_id(id)?);
+            call(app, "set_item_collection", params)
+        }
+        (Method::Get, ["api", "collections"]) => call(app, "get_collections", Value::Null),
+        (Method::Post, ["api", "collections"]) => {
+            call(app, "create_collection", read_body(request)?)
+        }
+        (Method::Delete, ["api", "collections", id]) => {
+            call(app, "delete_collection", json!({ "id": parse_id(id)? }))
+        }
+        (Method::Post, ["api", "clipboard"]) => call(app, "set_clipboard", read_body(request)?),
+        _ => Err((404, format!("No route for {} {}", method, path))),
+    }
+}
+
+fn call(app: &AppHandle, method: &str, params: Value) -> ApiResult {
+    ipc::dispatch(app, method, params).map_err(|RpcError { code, message }| {
+        let status = match code {
+            ipc::INVALID_PARAMS => 400,
+            ipc::METHOD_NOT_FOUND => 404,
+            _ => 500,
+        };
+        (status, message)
+    })
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .filter(|(key, _)| !key.is_empty())
+        .map(|(key, value)| {
+            let decode = |s: &str| {
+                urlencoding::decode(&s.replace('+', " "))
+                    .map(|s| s.into_owned())
+                    .unwrap_or_default()
+            };
+            (decode(key), decode(value))
+        })
+        .collect()
+}
+
+fn parse_id(id: &str) -> Result<i64, (u16, String)> {
+    id.parse().map_err(|_| (400, format!("Invalid id: {}", id)))
+}
+
+fn parse_number<T: std::str::FromStr>(
+    query: &HashMap<String, String>,
+    key: &str,
+) -> Result<Option<T>, (u16, String)> {
+    query
+        .get(key)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| (400, format!("Invalid {}: {}", key, value)))
+        })
+        .transpose()
+}
+
+fn history_params(query: &HashMap<String, String>) -> ApiResult {
+    let flag = |key: &str| query.get(key).map(|v| v == "true" || v == "1");
+    let page = parse_number::<usize>(query, "page")?.unwrap_or(1);
+    if page == 0 {
+        return Err((400, "page starts at 1".to_string()));
+    }
+    let page_size = parse_number::<usize>(query, "page_size")?
+        .unwrap_or(20)
+        .clamp(1, MAX_PAGE_SIZE);
+    Ok(json!({
+        "page": page,
+        "page_size": page_size,
+        "search_regex": flag("regex"),
+        "search_case_sensitive": flag("case_sensitive"),
+        "collection_id": parse_number::<i64>(query, "collection_id")?,
+        "language": query.get("language"),
+        "data_type": query.get("type"),
+    }))
+}
+
+fn read_body(request: &mut Request) -> ApiResult {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_SIZE + 1)
+        .read_to_string(&mut body)
+        .map_err(|e| (400, e.to_string()))?;
+    if body.len() as u64 > MAX_BODY_SIZE {
+        return Err((413, "Request body too large".to_string()));
+    }
+    if body.trim().is_empty() {
+        return Ok(json!({}));
+    }
+    serde_json::from_str(&body).map_err(|e| (400, format!("Invalid JSON body: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(s: &str) -> HashMap<String, String> {
+        parse_query(s)
+    }
+
+    #[test]
+    fn parses_query_strings() {
+        let q = query("q=hello+world&type=url&flag&=ignored&name=%C3%A9");
+        assert_eq!(q["q"], "hello world");
+        assert_eq!(q["type"], "url");
+        assert_eq!(q["flag"], "");
+        assert_eq!(q["name"], "é");
+        assert_eq!(q.len(), 4);
+    }
+
+    #[test]
+    fn checks_history_pages() {
+        let params = history_params(&query("")).unwrap();
+        assert_eq!(
+            (params["page"].clone(), params["page_size"].clone()),
+            (json!(1), json!(20))
+        );
+
+        assert_eq!(history_params(&query("page=0")).unwrap_err().0, 400);
+        assert_eq!(history_params(&query("page=x")).unwrap_err().0, 400);
+        assert_eq!(
+            history_params(&query("page_size=100000")).unwrap()["page_size"],
+            json!(MAX_PAGE_SIZE)
+        );
+    }
+
+    #[test]
+    fn only_search_takes_a_query() {
+        let params = history_params(&query("query=secret&regex=1")).unwrap();
+        assert!(params.get("query").is_none());
+        assert_eq!(params["search_regex"], json!(true));
+    }
+}
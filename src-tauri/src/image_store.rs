@@ -0,0 +1,302 @@
+use crate::db::Database;
+use crate::models::ClipboardItem;
+use crate::phash::{dhash, digest};
+use chrono::Local;
+use image::{imageops, ImageFormat, RgbaImage};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Longest side of the thumbnails shown in the history lists.
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// Where an image ended up on disk, plus the metadata kept on its row.
+pub struct StoredImage {
+    pub path: String,
+    pub thumbnail_path: String,
+    pub width: u32,
+    pub height: u32,
+    pub byte_size: i64,
+}
+
+/// Keeps captured images and their thumbnails in a single directory next to
+/// the database, so everything that belongs to the history lives in one place.
+pub struct ImageStore {
+    dir: PathBuf,
+}
+
+impl ImageStore {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        let dir = dir.as_ref().to_path_buf();
+        if !dir.exists() {
+            let _ = fs::create_dir_all(&dir);
+        }
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Encodes the image as "png" or "webp" and writes a thumbnail next to it.
+    pub fn save(&self, image: &RgbaImage, format: &str) -> Result<StoredImage, String> {
+        let (format, ext) = image_format(format);
+        let stem = Local::now().timestamp_nanos_opt().unwrap_or(0).to_string();
+        let path = self.dir.join(format!("{}.{}", stem, ext));
+
+        image
+            .save_with_format(&path, format)
+            .map_err(|e| e.to_string())?;
+        let thumbnail_path = self.write_thumbnail(image, &stem, format, ext)?;
+
+        Ok(StoredImage {
+            path: path.to_string_lossy().to_string(),
+            thumbnail_path,
+            width: image.width(),
+            height: image.height(),
+            byte_size: file_size(&path),
+        })
+    }
+
+    fn write_thumbnail(
+        &self,
+        image: &RgbaImage,
+        stem: &str,
+        format: ImageFormat,
+        ext: &str,
+    ) -> Result<String, String> {
+        let path = self.dir.join(format!("{}.thumb.{}", stem, ext));
+        let (width, height) = image.dimensions();
+        let longest = width.max(height);
+
+        let result = if longest <= THUMBNAIL_SIZE {
+            image.save_with_format(&path, format)
+        } else {
+            let scale = THUMBNAIL_SIZE as f64 / longest as f64;
+            let thumb_width = ((width as f64 * scale).round() as u32).max(1);
+            let thumb_height = ((height as f64 * scale).round() as u32).max(1);
+            imageops::thumbnail(image, thumb_width, thumb_height).save_with_format(&path, format)
+        };
+
+        result.map_err(|e| e.to_string())?;
+        Ok(path.to_string_lossy().to_string())
+    }
+
+    /// Removes the image file and thumbnail that belong to an item.
+    pub fn remove(&self, item: &ClipboardItem) {
+        if item.kind != "image" {
+            return;
+        }
+        remove_file(Path::new(&item.content));
+        if let Some(thumbnail) = &item.thumbnail {
+            remove_file(Path::new(thumbnail));
+        }
+    }
+
+    pub fn remove_all(&self, items: &[ClipboardItem]) {
+        for item in items {
+            self.remove(item);
+        }
+    }
+
+    /// Moves images written by older versions (under Tauri's app data dir)
+    /// into the store and fills in missing thumbnails and hashes.
+    pub fn migrate(&self, db: &Database, legacy_dir: &Path) {
+        let items = match db.get_image_items() {
+            Ok(items) => items,
+            Err(e) => {
+                log::error!("Failed to list images for migration: {}", e);
+                return;
+            }
+        };
+
+        for item in items {
+            let Some(id) = item.id else { continue };
+            let mut path = PathBuf::from(&item.content);
+            // Base64 content from very old versions has no file to migrate
+            if !path.is_absolute() {
+                continue;
+            }
+
+            let relocated = path.starts_with(legacy_dir);
+            if relocated {
+                let Some(file_name) = path.file_name() else {
+                    continue;
+                };
+                let target = self.dir.join(file_name);
+                if fs::rename(&path, &target).is_err() {
+                    if let Err(e) = fs::copy(&path, &target) {
+                        log::error!("Failed to move image {:?}: {}", path, e);
+                        continue;
+                    }
+                    remove_file(&path);
+                }
+                path = target;
+            }
+
+            if !relocated
+                && item.thumbnail.is_some()
+                && item.image_hash.is_some()
+                && item.image_digest.is_some()
+            {
+                continue;
+            }
+
+            let image = match image::open(&path) {
+                Ok(image) => image.to_rgba8(),
+                Err(e) => {
+                    log::error!("Failed to open image {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            let stem = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let (format, ext) =
+                image_format(path.extension().and_then(|e| e.to_str()).unwrap_or(""));
+            let thumbnail_path = match self.write_thumbnail(&image, &stem, format, ext) {
+                Ok(thumbnail_path) => thumbnail_path,
+                Err(e) => {
+                    log::error!("Failed to write thumbnail for {:?}: {}", path, e);
+                    continue;
+                }
+            };
+
+            let stored = StoredImage {
+                path: path.to_string_lossy().to_string(),
+                thumbnail_path,
+                width: image.width(),
+                height: image.height(),
+                byte_size: file_size(&path),
+            };
+            if let Err(e) = db.set_image_file(id, &stored, dhash(&image), &digest(&image)) {
+                log::error!("Failed to update image {}: {}", id, e);
+            }
+        }
+    }
+}
+
+fn image_format(name: &str) -> (ImageFormat, &'static str) {
+    match name {
+        "webp" => (ImageFormat::WebP, "webp"),
+        _ => (ImageFormat::Png, "png"),
+    }
+}
+
+fn file_size(path: &Path) -> i64 {
+    fs::metadata(path).map(|m| m.len() as i64).unwrap_or(0)
+}
+
+fn remove_file(path: &Path) {
+    if path.exists() {
+        if let Err(e) = fs::remove_file(path) {
+            log::error!("Failed to delete image file {:?}: {}", path, e);
+        } else {
+            log::info!("Deleted image file: {:?}", path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Crypto;
+    use std::sync::Arc;
+
+    fn empty_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("clipboard-images-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn gradient(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            image::Rgba([(x % 256) as u8, (y % 256) as u8, 0, 255])
+        })
+    }
+
+    fn dimensions(path: &str) -> (u32, u32) {
+        let image = image::open(path).unwrap();
+        (image.width(), image.height())
+    }
+
+    #[test]
+    fn saves_images_with_a_thumbnail() {
+        let dir = empty_dir("save");
+        let store = ImageStore::new(&dir);
+        for format in ["png", "webp"] {
+            let stored = store.save(&gradient(1000, 500), format).unwrap();
+            assert!(stored.path.ends_with(format));
+            assert_eq!((stored.width, stored.height), (1000, 500));
+            assert_eq!(stored.byte_size, file_size(Path::new(&stored.path)));
+            assert_eq!(dimensions(&stored.path), (1000, 500));
+            assert_eq!(dimensions(&stored.thumbnail_path), (256, 128));
+        }
+    }
+
+    #[test]
+    fn small_images_are_not_scaled_up() {
+        let store = ImageStore::new(empty_dir("small"));
+        let stored = store.save(&gradient(40, 300), "png").unwrap();
+        assert_eq!(dimensions(&stored.thumbnail_path), (34, 256));
+        let stored = store.save(&gradient(40, 30), "png").unwrap();
+        assert_eq!(dimensions(&stored.thumbnail_path), (40, 30));
+    }
+
+    #[test]
+    fn removing_an_item_deletes_both_files() {
+        let store = ImageStore::new(empty_dir("remove"));
+        let stored = store.save(&gradient(300, 300), "png").unwrap();
+        let item = ClipboardItem {
+            content: stored.path.clone(),
+            kind: "image".into(),
+            thumbnail: Some(stored.thumbnail_path.clone()),
+            ..Default::default()
+        };
+        store.remove(&item);
+        assert!(!Path::new(&stored.path).exists());
+        assert!(!Path::new(&stored.thumbnail_path).exists());
+    }
+
+    #[test]
+    fn migrate_moves_old_images_into_the_store() {
+        let dir = empty_dir("migrate");
+        let db = Database::new(
+            dir.join("history.db"),
+            Arc::new(Crypto::new(dir.join("key"))),
+        )
+        .unwrap();
+        let image = gradient(400, 300);
+        let legacy = dir.join("legacy").join("1.png");
+        fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+        image.save(&legacy).unwrap();
+        db.insert_item(
+            &ClipboardItem {
+                content: legacy.to_string_lossy().to_string(),
+                kind: "image".into(),
+                timestamp: "2024-01-01 00:00:00".into(),
+                data_type: "image".into(),
+                ..Default::default()
+            },
+            100,
+        )
+        .unwrap();
+
+        let store = ImageStore::new(dir.join("images"));
+        store.migrate(&db, &dir.join("legacy"));
+        let item = db.get_image_items().unwrap().remove(0);
+        assert_eq!(Path::new(&item.content), store.dir().join("1.png"));
+        assert!(!legacy.exists());
+        assert_eq!(dimensions(item.thumbnail.as_deref().unwrap()), (256, 192));
+        assert_eq!((item.width, item.height), (Some(400), Some(300)));
+        assert_eq!(item.image_hash, Some(dhash(&image) as i64));
+        assert_eq!(item.image_digest, Some(digest(&image)));
+
+        // Nothing is left to do the second time
+        let thumbnail = item.thumbnail.clone();
+        store.migrate(&db, &dir.join("legacy"));
+        assert_eq!(db.get_image_items().unwrap()[0].thumbnail, thumbnail);
+    }
+}
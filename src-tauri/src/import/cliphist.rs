@@ -0,0 +1,307 @@
+//! cliphist keeps its history in a bbolt database: bucket `b` maps big-endian
+//! ids, higher is newer, to the copied bytes. There are no dates.
+//!
+//! Only what's needed to read one bucket of a bbolt file is implemented: the
+//! meta pages, branch and leaf pages and inline buckets. Numbers in the file
+//! are little-endian.
+
+use super::{read_file, ImportedContent, ImportedItem};
+use std::path::Path;
+
+const MAGIC: u32 = 0xED0C_DAED;
+const PAGE_HEADER_SIZE: usize = 16;
+const ELEMENT_SIZE: usize = 16;
+// Header of a bucket value, followed by the page of an inline bucket
+const BUCKET_HEADER_SIZE: usize = 16;
+const BRANCH_PAGE: u16 = 0x01;
+const LEAF_PAGE: u16 = 0x02;
+const BUCKET_LEAF: u32 = 0x01;
+// Deeper trees only come from corrupt files
+const MAX_DEPTH: usize = 64;
+const HISTORY_BUCKET: &[u8] = b"b";
+
+pub fn read(path: &Path) -> Result<Vec<ImportedItem>, String> {
+    let data = read_file(path)?;
+    let db = Bolt::open(&data)?;
+
+    let bucket = db
+        .find(db.page(db.root)?, HISTORY_BUCKET)?
+        .ok_or_else(|| "No cliphist history in this database".to_string())?;
+    let mut entries = Vec::new();
+    db.bucket_entries(bucket, &mut entries)?;
+
+    entries.sort_by_key(|(id, _)| std::cmp::Reverse(*id));
+    Ok(entries
+        .into_iter()
+        .map(|(_, value)| match String::from_utf8(value) {
+            Ok(text) => ImportedItem::text(text),
+            Err(e) => ImportedItem {
+                content: ImportedContent::Image(e.into_bytes()),
+                ..Default::default()
+            },
+        })
+        .collect())
+}
+
+struct Bolt<'a> {
+    data: &'a [u8],
+    page_size: usize,
+    root: u64,
+}
+
+impl<'a> Bolt<'a> {
+    fn open(data: &'a [u8]) -> Result<Self, String> {
+        let invalid = || "Not a cliphist database".to_string();
+        let meta = |offset: usize| -> Option<(u32, u64, u64)> {
+            let meta = data.get(offset + PAGE_HEADER_SIZE..)?;
+            if u32_at(meta, 0)? != MAGIC {
+                return None;
+            }
+            // (page size, root bucket page, transaction id)
+            Some((u32_at(meta, 8)?, u64_at(meta, 16)?, u64_at(meta, 48)?))
+        };
+
+        // Two meta pages are written in turns, the later transaction wins
+        let first = meta(0).ok_or_else(invalid)?;
+        let second = meta(first.0 as usize).filter(|second| second.2 > first.2);
+        let (page_size, root, _) = second.unwrap_or(first);
+        Ok(Self {
+            data,
+            page_size: page_size as usize,
+            root,
+        })
+    }
+
+    // Overflow pages follow their page, so the slice runs to the end of the file
+    fn page(&self, id: u64) -> Result<&'a [u8], String> {
+        (id as usize)
+            .checked_mul(self.page_size)
+            .and_then(|offset| self.data.get(offset..))
+            .ok_or_else(|| format!("Page {} is out of the file", id))
+    }
+
+    /// The value of the bucket named `name` under the bucket page `page`.
+    fn find(&self, page: &'a [u8], name: &[u8]) -> Result<Option<&'a [u8]>, String> {
+        let mut found = None;
+        self.walk(page, 0, &mut |flags, key, value| {
+            if flags & BUCKET_LEAF != 0 && key == name {
+                found = Some(value);
+            }
+        })?;
+        Ok(found)
+    }
+
+    fn bucket_entries(
+        &self,
+        bucket: &'a [u8],
+        entries: &mut Vec<(u64, Vec<u8>)>,
+    ) -> Result<(), String> {
+        let root = u64_at(bucket, 0).ok_or("Truncated bucket")?;
+        let page = if root == 0 {
+            bucket.get(BUCKET_HEADER_SIZE..).ok_or("Truncated bucket")?
+        } else {
+            self.page(root)?
+        };
+        self.walk(page, 0, &mut |flags, key, value| {
+            if flags & BUCKET_LEAF == 0 {
+                let id = key.try_into().map(u64::from_be_bytes).unwrap_or_default();
+                entries.push((id, value.to_vec()));
+            }
+        })
+    }
+
+    /// Calls `visit` with (flags, key, value) for every element under `page`.
+    fn walk(
+        &self,
+        page: &'a [u8],
+        depth: usize,
+        visit: &mut dyn FnMut(u32, &'a [u8], &'a [u8]),
+    ) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err("The database is corrupt".to_string());
+        }
+        let truncated = || "Truncated page".to_string();
+        let flags = u16_at(page, 8).ok_or_else(truncated)?;
+        let count = u16_at(page, 10).ok_or_else(truncated)? as usize;
+
+        for index in 0..count {
+            let offset = PAGE_HEADER_SIZE + index * ELEMENT_SIZE;
+            let element = page.get(offset..).ok_or_else(truncated)?;
+            if flags & BRANCH_PAGE != 0 {
+                let child = u64_at(element, 8).ok_or_else(truncated)?;
+                self.walk(self.page(child)?, depth + 1, visit)?;
+            } else if flags & LEAF_PAGE != 0 {
+                let field = |at| {
+                    u32_at(element, at)
+                        .map(|n| n as usize)
+                        .ok_or_else(truncated)
+                };
+                let (position, key_size, value_size) = (field(4)?, field(8)?, field(12)?);
+                let key_start = offset + position;
+                let value_start = key_start + key_size;
+                let key = page.get(key_start..value_start).ok_or_else(truncated)?;
+                let value = page
+                    .get(value_start..value_start + value_size)
+                    .ok_or_else(truncated)?;
+                visit(u32_at(element, 0).unwrap_or_default(), key, value);
+            } else {
+                return Err(format!("Unexpected page type {:#x}", flags));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u64_at(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::tests::{empty_dir, png, text_of};
+
+    const PAGE_SIZE: usize = 4096;
+
+    fn meta_page(txid: u64, root: u64) -> Vec<u8> {
+        let mut page = vec![0; PAGE_HEADER_SIZE];
+        page[8..10].copy_from_slice(&4u16.to_le_bytes());
+        page.extend(MAGIC.to_le_bytes());
+        page.extend(2u32.to_le_bytes());
+        page.extend((PAGE_SIZE as u32).to_le_bytes());
+        page.extend([0; 4]);
+        page.extend(root.to_le_bytes());
+        page.extend([0; 24]);
+        page.extend(txid.to_le_bytes());
+        page
+    }
+
+    /// A leaf page holding (flags, key, value) elements.
+    fn leaf_page(elements: &[(u32, Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+        let mut page = vec![0; PAGE_HEADER_SIZE];
+        page[8..10].copy_from_slice(&LEAF_PAGE.to_le_bytes());
+        page[10..12].copy_from_slice(&(elements.len() as u16).to_le_bytes());
+        let mut data: Vec<u8> = Vec::new();
+        for (index, (flags, key, value)) in elements.iter().enumerate() {
+            let position = (elements.len() - index) * ELEMENT_SIZE + data.len();
+            page.extend(flags.to_le_bytes());
+            page.extend((position as u32).to_le_bytes());
+            page.extend((key.len() as u32).to_le_bytes());
+            page.extend((value.len() as u32).to_le_bytes());
+            data.extend(key);
+            data.extend(value);
+        }
+        page.extend(data);
+        page
+    }
+
+    fn branch_page(children: &[u64]) -> Vec<u8> {
+        let mut page = vec![0; PAGE_HEADER_SIZE];
+        page[8..10].copy_from_slice(&BRANCH_PAGE.to_le_bytes());
+        page[10..12].copy_from_slice(&(children.len() as u16).to_le_bytes());
+        for child in children {
+            page.extend([0; 8]);
+            page.extend(child.to_le_bytes());
+        }
+        page
+    }
+
+    /// The value of a bucket, inline when `root` is 0.
+    fn bucket(root: u64, inline: Vec<u8>) -> Vec<u8> {
+        let mut value = root.to_le_bytes().to_vec();
+        value.extend(0u64.to_le_bytes());
+        value.extend(inline);
+        value
+    }
+
+    fn write_db(path: &Path, pages: Vec<Vec<u8>>) {
+        let mut data = Vec::new();
+        for mut page in pages {
+            page.resize(PAGE_SIZE, 0);
+            data.extend(page);
+        }
+        std::fs::write(path, data).unwrap();
+    }
+
+    fn entry(id: u64, value: &[u8]) -> (u32, Vec<u8>, Vec<u8>) {
+        (0, id.to_be_bytes().to_vec(), value.to_vec())
+    }
+
+    #[test]
+    fn reads_the_history_bucket() {
+        let path = empty_dir("cliphist").join("db");
+        write_db(
+            &path,
+            vec![
+                // The first meta page is stale
+                meta_page(7, 99),
+                meta_page(8, 2),
+                leaf_page(&[
+                    (BUCKET_LEAF, b"a".to_vec(), bucket(0, Vec::new())),
+                    (BUCKET_LEAF, HISTORY_BUCKET.to_vec(), bucket(3, Vec::new())),
+                ]),
+                branch_page(&[4, 5]),
+                leaf_page(&[entry(1, b"first"), entry(2, &png(60))]),
+                leaf_page(&[entry(3, b"third")]),
+            ],
+        );
+
+        let items = read(&path).unwrap();
+        assert_eq!(items.len(), 3);
+        // Newest first
+        assert_eq!(text_of(&items[0]), "third");
+        assert!(matches!(&items[1].content, ImportedContent::Image(data) if *data == png(60)));
+        assert_eq!(text_of(&items[2]), "first");
+    }
+
+    #[test]
+    fn reads_inline_buckets() {
+        let path = empty_dir("cliphist-inline").join("db");
+        let inline = leaf_page(&[entry(9, b"inline")]);
+        write_db(
+            &path,
+            vec![
+                meta_page(1, 2),
+                meta_page(0, 0),
+                leaf_page(&[(BUCKET_LEAF, HISTORY_BUCKET.to_vec(), bucket(0, inline))]),
+            ],
+        );
+
+        let items = read(&path).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(text_of(&items[0]), "inline");
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let dir = empty_dir("cliphist-invalid");
+        let path = dir.join("db");
+        std::fs::write(&path, b"[\"clipman\"]").unwrap();
+        assert!(read(&path).is_err());
+
+        write_db(
+            &path,
+            vec![
+                meta_page(1, 2),
+                meta_page(0, 0),
+                leaf_page(&[entry(1, b"no bucket")]),
+            ],
+        );
+        assert!(read(&path).unwrap_err().contains("No cliphist history"));
+
+        // A branch pointing at itself
+        write_db(
+            &path,
+            vec![meta_page(1, 2), meta_page(0, 0), branch_page(&[2])],
+        );
+        assert!(read(&path).is_err());
+    }
+}
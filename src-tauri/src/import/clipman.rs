@@ -0,0 +1,10 @@
+//! clipman stores its history as a JSON array of strings, newest last.
+
+use super::{read_file, ImportedItem};
+use std::path::Path;
+
+pub fn read(path: &Path) -> Result<Vec<ImportedItem>, String> {
+    let history: Vec<String> = serde_json::from_slice(&read_file(path)?)
+        .map_err(|e| format!("Not a clipman history file: {}", e))?;
+    Ok(history.into_iter().rev().map(ImportedItem::text).collect())
+}
@@ -0,0 +1,36 @@
+//! Clipy keeps its history in a Realm database, which can't be read here.
+//! What can be imported are its snippets, exported from the snippet editor
+//! as XML: folders of snippets, each with a title and content.
+
+use super::xml;
+use super::{read_file, ImportedItem};
+use std::path::Path;
+
+pub fn read(path: &Path) -> Result<Vec<ImportedItem>, String> {
+    if path.extension().is_some_and(|ext| ext == "realm") {
+        return Err(
+            "Clipy's history database can't be imported, export the snippets to XML instead"
+                .to_string(),
+        );
+    }
+
+    let folders = xml::parse(&read_file(path)?)?;
+    if folders.name != "folders" {
+        return Err("Not a Clipy snippets export".to_string());
+    }
+
+    let mut items = Vec::new();
+    for folder in folders.children_named("folder") {
+        let group = folder.child("title").map(|title| title.text.clone());
+        let snippets = folder.child("snippets").into_iter();
+        for snippet in snippets.flat_map(|snippets| snippets.children_named("snippet")) {
+            let content = snippet.child("content").map(|content| content.text.clone());
+            items.push(ImportedItem {
+                note: snippet.child("title").map(|title| title.text.clone()),
+                group: group.clone(),
+                ..ImportedItem::text(content.unwrap_or_default())
+            });
+        }
+    }
+    Ok(items)
+}
@@ -0,0 +1,371 @@
+//! CopyQ keeps every tab in `copyq_tab_<name>.dat` in its config directory,
+//! the name being the base64 of the tab name with `/` written as `-`.
+//!
+//! The files are written with QDataStream (see CopyQ's `serialize.cpp`): the
+//! number of items, then per item the marker `-2`, the number of formats and
+//! for each a MIME type, a compression flag and the data. The MIME type starts
+//! with a digit that stands for a common prefix. Older files store each item
+//! as a count followed by MIME types and qCompress'ed data.
+//!
+//! Files made with CopyQ's "Export" wrap the same tab data in a QVariantMap.
+//! Encrypted tabs and tabs synced to a folder can't be read.
+
+use super::qdatastream::{uncompress, Reader, Variant};
+use super::{read_file, ImportedContent, ImportedItem};
+use base64::{engine::general_purpose, Engine as _};
+use std::fs;
+use std::path::Path;
+
+// The tab CopyQ stores the clipboard history in
+const MAIN_TAB: &str = "&clipboard";
+// Marks the current item format, older files have a format count instead
+const FORMAT_V2: i32 = -2;
+// Expanded form of the one-digit prefixes of the MIME types
+const MIME_PREFIXES: [&str; 5] = [
+    "",
+    "application/x-copyq-",
+    "text/",
+    "application/",
+    "image/",
+];
+
+pub fn read(path: &Path) -> Result<Vec<ImportedItem>, String> {
+    if path.is_dir() {
+        return read_config_dir(path);
+    }
+
+    let data = read_file(path)?;
+    if let Some(tabs) = read_export(&data) {
+        let mut items = Vec::new();
+        for (name, tab) in tabs {
+            items.extend(read_tab(&tab, group_for(&name))?);
+        }
+        return Ok(items);
+    }
+    let name = tab_name(path).unwrap_or_else(|| MAIN_TAB.to_string());
+    read_tab(&data, group_for(&name))
+}
+
+fn read_config_dir(dir: &Path) -> Result<Vec<ImportedItem>, String> {
+    let mut tab_files: Vec<_> = fs::read_dir(dir)
+        .map_err(|e| format!("Can't read {}: {}", dir.display(), e))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| tab_name(path).is_some())
+        .collect();
+    if tab_files.is_empty() {
+        return Err(format!("No CopyQ tabs found in {}", dir.display()));
+    }
+    tab_files.sort();
+
+    let mut items = Vec::new();
+    for path in tab_files {
+        let name = tab_name(&path).unwrap_or_default();
+        match read_tab(&read_file(&path)?, group_for(&name)) {
+            Ok(tab) => items.extend(tab),
+            Err(e) => log::warn!("Skipping CopyQ tab {:?}: {}", name, e),
+        }
+    }
+    Ok(items)
+}
+
+/// The tab a `copyq_tab_*.dat` file belongs to.
+fn tab_name(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?;
+    let encoded = file_name
+        .strip_prefix("copyq_tab_")?
+        .strip_suffix(".dat")?
+        .replace('-', "/");
+    let name = general_purpose::STANDARD.decode(encoded).ok()?;
+    String::from_utf8(name).ok()
+}
+
+/// Items of the clipboard tab go to the history, other tabs to collections.
+fn group_for(tab: &str) -> Option<String> {
+    // "&" marks the keyboard accelerator in tab names
+    let name = tab.replace('&', "");
+    (tab != MAIN_TAB && !name.is_empty()).then_some(name)
+}
+
+fn read_tab(data: &[u8], group: Option<String>) -> Result<Vec<ImportedItem>, String> {
+    let mut reader = Reader::new(data);
+    let count = reader.i32()?;
+    if count < 0 {
+        return Err("Not a CopyQ tab, it may be encrypted or synced".to_string());
+    }
+
+    let mut items = Vec::new();
+    for _ in 0..count {
+        let mut item = item_from_formats(read_formats(&mut reader)?);
+        item.group = group.clone();
+        items.push(item);
+    }
+    Ok(items)
+}
+
+fn read_formats(reader: &mut Reader) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut formats = Vec::new();
+    let length = reader.i32()?;
+    if length == FORMAT_V2 {
+        let count = reader.i32()?;
+        for _ in 0..count {
+            let mime = expand_mime(&reader.string()?.unwrap_or_default());
+            let compressed = reader.bool()?;
+            let mut data = reader.bytes()?.unwrap_or_default();
+            if compressed {
+                data = uncompress(&data)?;
+            }
+            formats.push((mime, data));
+        }
+    } else if length >= 0 {
+        for _ in 0..length {
+            let mime = reader.string()?.unwrap_or_default();
+            let data = reader.bytes()?.unwrap_or_default();
+            let data = if data.is_empty() {
+                data
+            } else {
+                uncompress(&data)?
+            };
+            formats.push((mime, data));
+        }
+    } else {
+        return Err(format!("Unknown item format {}", length));
+    }
+    Ok(formats)
+}
+
+fn expand_mime(mime: &str) -> String {
+    let mut chars = mime.chars();
+    match chars.next().and_then(|c| c.to_digit(10)) {
+        Some(digit) if (digit as usize) < MIME_PREFIXES.len() => {
+            format!("{}{}", MIME_PREFIXES[digit as usize], chars.as_str())
+        }
+        _ => mime.to_string(),
+    }
+}
+
+fn item_from_formats(formats: Vec<(String, Vec<u8>)>) -> ImportedItem {
+    let mut item = ImportedItem::default();
+    let mut text = None;
+    let mut html = None;
+    let mut files = None;
+    let mut image: Option<(bool, Vec<u8>)> = None;
+
+    for (mime, data) in formats {
+        let base = mime.split(';').next().unwrap_or_default();
+        match base {
+            "text/plain" => text = Some(String::from_utf8_lossy(&data).into_owned()),
+            "text/html" => html = Some(String::from_utf8_lossy(&data).into_owned()),
+            "text/uri-list" => {
+                let uris: Vec<String> = String::from_utf8_lossy(&data)
+                    .lines()
+                    .filter(|line| line.starts_with("file://"))
+                    .map(super::file_url_to_path)
+                    .collect();
+                files = Some(uris);
+            }
+            "application/x-copyq-item-notes" => {
+                item.note = Some(String::from_utf8_lossy(&data).into_owned())
+            }
+            "application/x-copyq-item-pinned" => item.pinned = true,
+            // PNG is preferred when an image comes in several formats
+            _ if base.starts_with("image/") => {
+                let is_png = base == "image/png";
+                if image.as_ref().is_none_or(|(png, _)| !png && is_png) {
+                    image = Some((is_png, data));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    item.content = match (text, files, image) {
+        (Some(text), _, _) if !text.is_empty() => ImportedContent::Text { text, html },
+        (_, Some(files), _) if !files.is_empty() => ImportedContent::Files(files),
+        (_, _, Some((_, image))) => ImportedContent::Image(image),
+        _ => ImportedContent::default(),
+    };
+    item
+}
+
+/// The tabs of a file made with CopyQ's "Export", as (name, tab data).
+fn read_export(data: &[u8]) -> Option<Vec<(String, Vec<u8>)>> {
+    let mut reader = Reader::new(data);
+    // The header is a QByteArray in newer versions and a QString in older ones
+    let header_is_bytes = reader
+        .bytes()
+        .ok()
+        .flatten()
+        .is_some_and(|header| header.starts_with(b"CopyQ v"));
+    if !header_is_bytes {
+        reader.seek(0);
+        let header = reader.string().ok()??;
+        if !header.starts_with("CopyQ v") {
+            return None;
+        }
+    }
+
+    let mut tabs = Vec::new();
+    while !reader.is_at_end() {
+        let start = reader.position();
+        match reader.variant() {
+            Ok(variant) => collect_tabs(&variant, &mut tabs),
+            Err(e) => {
+                log::warn!("Stopped reading CopyQ export at byte {}: {}", start, e);
+                break;
+            }
+        }
+    }
+    Some(tabs)
+}
+
+// Tabs are maps with a "name" and their items serialized into a byte array
+fn collect_tabs(variant: &Variant, tabs: &mut Vec<(String, Vec<u8>)>) {
+    match variant {
+        Variant::Map(map) => {
+            if let Some(Variant::String(name)) = map.get("name") {
+                let data = map.values().find_map(|value| match value {
+                    Variant::Bytes(bytes) if read_tab(bytes, None).is_ok() => Some(bytes),
+                    _ => None,
+                });
+                if let Some(data) = data {
+                    tabs.push((name.clone(), data.clone()));
+                    return;
+                }
+            }
+            map.values().for_each(|value| collect_tabs(value, tabs));
+        }
+        Variant::List(list) => list.iter().for_each(|value| collect_tabs(value, tabs)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::tests::{empty_dir, png, text_of};
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    fn write_string(out: &mut Vec<u8>, text: &str) {
+        let units: Vec<u16> = text.encode_utf16().collect();
+        out.extend((units.len() as u32 * 2).to_be_bytes());
+        for unit in units {
+            out.extend(unit.to_be_bytes());
+        }
+    }
+
+    fn write_bytes(out: &mut Vec<u8>, data: &[u8]) {
+        out.extend((data.len() as u32).to_be_bytes());
+        out.extend(data);
+    }
+
+    /// Data as written by qCompress: the length, then the zlib stream.
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        let mut out = (data.len() as u32).to_be_bytes().to_vec();
+        out.extend(encoder.finish().unwrap());
+        out
+    }
+
+    /// A tab with items given as (short MIME type, data, compressed).
+    fn tab(items: &[Vec<(&str, Vec<u8>, bool)>]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend((items.len() as i32).to_be_bytes());
+        for formats in items {
+            out.extend(FORMAT_V2.to_be_bytes());
+            out.extend((formats.len() as i32).to_be_bytes());
+            for (mime, data, compressed) in formats {
+                write_string(&mut out, mime);
+                out.push(*compressed as u8);
+                if *compressed {
+                    write_bytes(&mut out, &compress(data));
+                } else {
+                    write_bytes(&mut out, data);
+                }
+            }
+        }
+        out
+    }
+
+    fn tab_file(name: &str) -> String {
+        format!(
+            "copyq_tab_{}.dat",
+            general_purpose::STANDARD.encode(name).replace('/', "-")
+        )
+    }
+
+    #[test]
+    fn reads_the_config_dir() {
+        let dir = empty_dir("copyq");
+        let history = tab(&[
+            vec![
+                ("0text/plain", b"newest".to_vec(), false),
+                ("1item-notes", b"a note".to_vec(), false),
+                ("1item-pinned", Vec::new(), false),
+            ],
+            vec![
+                ("2plain", b"second".to_vec(), true),
+                ("2html", b"<b>second</b>".to_vec(), true),
+            ],
+            vec![("4png", png(10), true)],
+            vec![(
+                "2uri-list",
+                b"file:///tmp/a%20b.txt\r\nfile:///tmp/c".to_vec(),
+                false,
+            )],
+        ]);
+        std::fs::write(dir.join(tab_file(MAIN_TAB)), history).unwrap();
+        std::fs::write(
+            dir.join(tab_file("&Work/Notes")),
+            tab(&[vec![("0text/plain", b"work item".to_vec(), false)]]),
+        )
+        .unwrap();
+        std::fs::write(dir.join("copyq.conf"), b"[General]").unwrap();
+
+        let items = read(&dir).unwrap();
+        assert_eq!(items.len(), 5);
+        let work = items.iter().find(|item| item.group.is_some()).unwrap();
+        assert_eq!(text_of(work), "work item");
+        assert_eq!(work.group.as_deref(), Some("Work/Notes"));
+
+        let history: Vec<_> = items.iter().filter(|item| item.group.is_none()).collect();
+        assert_eq!(text_of(history[0]), "newest");
+        assert!(history[0].pinned);
+        assert_eq!(history[0].note.as_deref(), Some("a note"));
+        match &history[1].content {
+            ImportedContent::Text { text, html } => {
+                assert_eq!(text, "second");
+                assert_eq!(html.as_deref(), Some("<b>second</b>"));
+            }
+            other => panic!("Not a text: {:?}", other),
+        }
+        assert!(matches!(&history[2].content, ImportedContent::Image(data) if *data == png(10)));
+        assert!(matches!(
+            &history[3].content,
+            ImportedContent::Files(files) if *files == ["/tmp/a b.txt", "/tmp/c"]
+        ));
+
+        // A single tab can be read too
+        let items = read(&dir.join(tab_file("&Work/Notes"))).unwrap();
+        assert_eq!(items.len(), 1);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let dir = empty_dir("copyq-invalid");
+        assert!(read(&dir).is_err());
+
+        let encrypted = dir.join(tab_file(MAIN_TAB));
+        std::fs::write(&encrypted, (-1i32).to_be_bytes()).unwrap();
+        assert!(read(&encrypted).is_err());
+        // Unreadable tabs are skipped in a config dir
+        std::fs::write(
+            dir.join(tab_file("Other")),
+            tab(&[vec![("0text/plain", b"kept".to_vec(), false)]]),
+        )
+        .unwrap();
+        assert_eq!(read(&dir).unwrap().len(), 1);
+    }
+}
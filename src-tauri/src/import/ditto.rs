@@ -0,0 +1,319 @@
+//! Ditto keeps its history in a SQLite database. `Main` has a row per clip
+//! and per group, `Data` the clipboard formats of every clip as Windows put
+//! them on the clipboard.
+
+use super::{open_sqlite, ImportedContent, ImportedItem};
+use image::codecs::bmp::BmpDecoder;
+use image::{DynamicImage, ImageFormat};
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::Path;
+
+// stickyClipOrder of clips that aren't sticky
+const NOT_STICKY: f64 = -2_147_483_647.0;
+// Size of the DROPFILES struct that starts CF_HDROP data
+const DROPFILES_SIZE: usize = 20;
+
+struct Clip {
+    id: i64,
+    date: i64,
+    parent: i64,
+    pinned: bool,
+}
+
+pub fn read(path: &Path) -> Result<Vec<ImportedItem>, String> {
+    let conn = open_sqlite(path)?;
+    // Sticky clips came with Ditto 3.22, older databases lack the column
+    let sticky = if conn
+        .prepare("SELECT stickyClipOrder FROM Main LIMIT 0")
+        .is_ok()
+    {
+        "stickyClipOrder"
+    } else {
+        "NULL"
+    };
+
+    let groups: HashMap<i64, String> = conn
+        .prepare("SELECT lID, mText FROM Main WHERE bIsGroup = 1")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect()
+        })
+        .map_err(|e| format!("Not a Ditto database: {}", e))?;
+
+    let clips = conn
+        .prepare(&format!(
+            "SELECT lID, lDate, lParentID, lDontAutoDelete, {} FROM Main
+             WHERE bIsGroup = 0 ORDER BY lDate DESC",
+            sticky
+        ))
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| {
+                let never_delete: Option<i64> = row.get(3)?;
+                let sticky_order: Option<f64> = row.get(4)?;
+                Ok(Clip {
+                    id: row.get(0)?,
+                    date: row.get(1)?,
+                    parent: row.get::<_, Option<i64>>(2)?.unwrap_or(-1),
+                    // Both sticky clips and clips kept from auto delete are favourites
+                    pinned: never_delete.unwrap_or(0) > 0
+                        || sticky_order.is_some_and(|order| order > NOT_STICKY),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut items = Vec::new();
+    for clip in clips {
+        items.push(ImportedItem {
+            content: read_content(&conn, clip.id)?,
+            timestamp: Some(clip.date),
+            pinned: clip.pinned,
+            group: groups.get(&clip.parent).cloned(),
+            ..Default::default()
+        });
+    }
+    Ok(items)
+}
+
+fn read_content(conn: &Connection, id: i64) -> Result<ImportedContent, String> {
+    let formats = conn
+        .prepare_cached("SELECT strClipBoardFormat, ooData FROM Data WHERE lParentID = ?1")
+        .and_then(|mut stmt| {
+            stmt.query_map(params![id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<Vec<u8>>>(1)?.unwrap_or_default(),
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut text = None;
+    let mut html = None;
+    let mut files = None;
+    let mut png = None;
+    let mut dib = None;
+    for (format, data) in formats {
+        match format.as_str() {
+            "CF_UNICODETEXT" => text = Some(utf16_until_nul(&data)),
+            "CF_TEXT" if text.is_none() => text = Some(ansi_until_nul(&data)),
+            "HTML Format" => html = html_fragment(&data),
+            "CF_HDROP" => files = Some(drop_files(&data)),
+            "PNG" => png = Some(data),
+            "CF_DIB" => dib = Some(data),
+            _ => {}
+        }
+    }
+
+    if let Some(files) = files.filter(|files| !files.is_empty()) {
+        return Ok(ImportedContent::Files(files));
+    }
+    if let Some(text) = text.filter(|text| !text.is_empty()) {
+        return Ok(ImportedContent::Text { text, html });
+    }
+    if let Some(png) = png {
+        return Ok(ImportedContent::Image(png));
+    }
+    if let Some(dib) = dib {
+        match dib_to_png(&dib) {
+            Ok(png) => return Ok(ImportedContent::Image(png)),
+            Err(e) => log::warn!("Skipping Ditto image {}: {}", id, e),
+        }
+    }
+    Ok(ImportedContent::default())
+}
+
+fn utf16_until_nul(data: &[u8]) -> String {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn ansi_until_nul(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// The fragment of CF_HTML data, whose header gives its byte offsets.
+fn html_fragment(data: &[u8]) -> Option<String> {
+    let header = String::from_utf8_lossy(&data[..data.len().min(512)]).into_owned();
+    let offset = |key: &str| -> Option<usize> {
+        let line = header.lines().find_map(|line| line.strip_prefix(key))?;
+        line.trim().parse().ok()
+    };
+    let start = offset("StartFragment:")?;
+    let end = offset("EndFragment:")?.min(data.len());
+    let fragment = data.get(start..end)?;
+    Some(String::from_utf8_lossy(fragment).into_owned())
+}
+
+/// The paths of CF_HDROP data: a DROPFILES struct, then a NUL separated list.
+fn drop_files(data: &[u8]) -> Vec<String> {
+    if data.len() < DROPFILES_SIZE {
+        return Vec::new();
+    }
+    let files_offset = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let wide = u32::from_le_bytes([data[16], data[17], data[18], data[19]]) != 0;
+    let Some(list) = data.get(files_offset..) else {
+        return Vec::new();
+    };
+
+    let names = if wide {
+        let units: Vec<u16> = list
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        String::from_utf8_lossy(list).into_owned()
+    };
+    // The list ends with an empty name
+    names
+        .split('\0')
+        .take_while(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// CF_DIB is a bitmap without the BMP file header.
+fn dib_to_png(data: &[u8]) -> Result<Vec<u8>, String> {
+    let decoder =
+        BmpDecoder::new_without_file_header(Cursor::new(data)).map_err(|e| e.to_string())?;
+    let image = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::tests::{empty_dir, text_of};
+
+    fn utf16(text: &str) -> Vec<u8> {
+        let mut data: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        data.extend([0, 0]);
+        data
+    }
+
+    /// CF_HTML with `fragment` as the only thing between the markers.
+    fn cf_html(fragment: &str) -> Vec<u8> {
+        let header_len = "Version:0.9\r\nStartHTML:000000000\r\nEndHTML:000000000\r\nStartFragment:000000000\r\nEndFragment:000000000\r\n".len();
+        let body = format!(
+            "<html><body><!--StartFragment-->{}<!--EndFragment--></body></html>",
+            fragment
+        );
+        let start = header_len + body.find("-->").unwrap() + 3;
+        format!(
+            "Version:0.9\r\nStartHTML:{:09}\r\nEndHTML:{:09}\r\nStartFragment:{:09}\r\nEndFragment:{:09}\r\n{}",
+            header_len,
+            header_len + body.len(),
+            start,
+            start + fragment.len(),
+            body
+        )
+        .into_bytes()
+    }
+
+    /// A DROPFILES struct with wide names.
+    fn drop_files(files: &[&str]) -> Vec<u8> {
+        let mut data = 20u32.to_le_bytes().to_vec();
+        data.extend([0; 12]);
+        data.extend(1u32.to_le_bytes());
+        for file in files {
+            data.extend(utf16(file));
+        }
+        data.extend([0, 0]);
+        data
+    }
+
+    /// A 2x2 24-bit bottom-up DIB.
+    fn dib() -> Vec<u8> {
+        let mut data = 40u32.to_le_bytes().to_vec();
+        data.extend(2i32.to_le_bytes());
+        data.extend(2i32.to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend(24u16.to_le_bytes());
+        data.extend([0; 24]);
+        for _ in 0..2 {
+            // Two pixels, padded to four bytes
+            data.extend([0, 0, 255, 0, 255, 0, 0, 0]);
+        }
+        data
+    }
+
+    #[test]
+    fn reads_clips_and_groups() {
+        let path = empty_dir("ditto").join("Ditto.db");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE Main (lID INTEGER PRIMARY KEY, lDate INTEGER, mText TEXT, lShortCut INTEGER, lDontAutoDelete INTEGER, CRC INTEGER, bIsGroup INTEGER, lParentID INTEGER, stickyClipOrder REAL);
+             CREATE TABLE Data (lID INTEGER PRIMARY KEY, lParentID INTEGER, strClipBoardFormat TEXT, ooData BLOB);
+             INSERT INTO Main VALUES (1, 1600000000, 'Snippets', 0, 0, 0, 1, -1, -2147483647);
+             INSERT INTO Main VALUES (2, 1600000100, 'héllo', 0, 0, 0, 0, 1, -2147483647);
+             INSERT INTO Main VALUES (3, 1600000200, 'sticky', 0, 0, 0, 0, -1, 3);
+             INSERT INTO Main VALUES (4, 1600000300, 'files', 0, 1600000300, 0, 0, -1, -2147483647);
+             INSERT INTO Main VALUES (5, 1600000400, 'bmp', 0, 0, 0, 0, -1, -2147483647);",
+        )
+        .unwrap();
+        let mut insert = conn
+            .prepare("INSERT INTO Data (lParentID, strClipBoardFormat, ooData) VALUES (?1, ?2, ?3)")
+            .unwrap();
+        insert
+            .execute(params![2, "CF_UNICODETEXT", utf16("héllo")])
+            .unwrap();
+        insert
+            .execute(params![2, "HTML Format", cf_html("<b>héllo</b>")])
+            .unwrap();
+        insert
+            .execute(params![3, "CF_TEXT", b"sticky\0".to_vec()])
+            .unwrap();
+        insert
+            .execute(params![
+                4,
+                "CF_HDROP",
+                drop_files(&["C:\\a.txt", "C:\\b c.txt"])
+            ])
+            .unwrap();
+        insert.execute(params![5, "CF_DIB", dib()]).unwrap();
+        drop(insert);
+        drop(conn);
+
+        let items = read(&path).unwrap();
+        assert_eq!(items.len(), 4);
+        match &items[0].content {
+            ImportedContent::Image(png) => {
+                let image = image::load_from_memory(png).unwrap();
+                assert_eq!((image.width(), image.height()), (2, 2));
+            }
+            other => panic!("Not an image: {:?}", other),
+        }
+        assert!(matches!(
+            &items[1].content,
+            ImportedContent::Files(files) if *files == ["C:\\a.txt", "C:\\b c.txt"]
+        ));
+        // Kept from auto delete or sticky, both are favourites
+        assert!(items[1].pinned);
+        assert_eq!(text_of(&items[2]), "sticky");
+        assert!(items[2].pinned);
+        match &items[3].content {
+            ImportedContent::Text { text, html } => {
+                assert_eq!(text, "héllo");
+                assert_eq!(html.as_deref(), Some("<b>héllo</b>"));
+            }
+            other => panic!("Not a text: {:?}", other),
+        }
+        assert!(!items[3].pinned);
+        assert_eq!(items[3].group.as_deref(), Some("Snippets"));
+        assert_eq!(items[3].timestamp, Some(1600000100));
+    }
+}
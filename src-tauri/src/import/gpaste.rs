@@ -0,0 +1,53 @@
+//! GPaste writes its history to `history.xml`, newest item first. Version 2
+//! files keep each value in a `<value>` element, version 1 files directly in
+//! the `<item>`. Images are files in GPaste's data directory.
+
+use super::xml::{self, Element};
+use super::{file_url_to_path, read_file, ImportedContent, ImportedItem};
+use std::fs;
+use std::path::Path;
+
+pub fn read(path: &Path) -> Result<Vec<ImportedItem>, String> {
+    let history = xml::parse(&read_file(path)?)?;
+    if history.name != "history" {
+        return Err("Not a GPaste history file".to_string());
+    }
+    Ok(history.children_named("item").map(read_item).collect())
+}
+
+fn read_item(element: &Element) -> ImportedItem {
+    let value = element
+        .child("value")
+        .map_or(element.text.as_str(), |value| value.text.as_str());
+    let mut item = match element.attribute("kind").unwrap_or("Text") {
+        "Uris" => ImportedItem {
+            content: ImportedContent::Files(
+                value
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(file_url_to_path)
+                    .collect(),
+            ),
+            ..Default::default()
+        },
+        "Image" => match fs::read(value) {
+            Ok(bytes) => ImportedItem {
+                content: ImportedContent::Image(bytes),
+                ..Default::default()
+            },
+            Err(e) => {
+                log::warn!("Skipping GPaste image {}: {}", value, e);
+                ImportedItem::default()
+            }
+        },
+        // Passwords are named so GPaste can list them without the value
+        "Password" => ImportedItem {
+            sensitive: true,
+            note: element.attribute("name").map(str::to_string),
+            ..ImportedItem::text(value.to_string())
+        },
+        _ => ImportedItem::text(value.to_string()),
+    };
+    item.timestamp = element.attribute("date").and_then(|date| date.parse().ok());
+    item
+}
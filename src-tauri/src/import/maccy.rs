@@ -0,0 +1,141 @@
+//! Maccy keeps its history in a Core Data SQLite store: one `ZHISTORYITEM`
+//! row per entry and its pasteboard types in `ZHISTORYITEMCONTENT`.
+
+use super::{file_url_to_path, open_sqlite, ImportedContent, ImportedItem};
+use rusqlite::params;
+use std::path::Path;
+
+// Core Data dates count seconds from 2001-01-01
+const CORE_DATA_EPOCH: i64 = 978_307_200;
+
+pub fn read(path: &Path) -> Result<Vec<ImportedItem>, String> {
+    let conn = open_sqlite(path)?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT Z_PK, ZLASTCOPIEDAT, ZPIN, ZAPPLICATION FROM ZHISTORYITEM
+             ORDER BY ZLASTCOPIEDAT DESC",
+        )
+        .map_err(|e| format!("Not a Maccy database: {}", e))?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Option<f64>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })
+        .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| e.to_string())?;
+
+    let mut contents = conn
+        .prepare("SELECT ZTYPE, ZVALUE FROM ZHISTORYITEMCONTENT WHERE ZITEM = ?1")
+        .map_err(|e| e.to_string())?;
+    let mut items = Vec::new();
+    for (id, copied_at, pin, application) in rows {
+        let formats = contents
+            .query_map(params![id], |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+                    row.get::<_, Option<Vec<u8>>>(1)?.unwrap_or_default(),
+                ))
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| e.to_string())?;
+
+        items.push(ImportedItem {
+            content: content_from_types(formats),
+            timestamp: copied_at.map(|seconds| seconds as i64 + CORE_DATA_EPOCH),
+            // The pin is the shortcut key the item is pinned to
+            pinned: pin.is_some(),
+            source_app: application,
+            ..Default::default()
+        });
+    }
+    Ok(items)
+}
+
+fn content_from_types(formats: Vec<(String, Vec<u8>)>) -> ImportedContent {
+    let mut text = None;
+    let mut html = None;
+    let mut files = Vec::new();
+    let mut image = None;
+
+    for (kind, value) in formats {
+        match kind.as_str() {
+            "public.utf8-plain-text" | "NSStringPboardType" => {
+                text = Some(String::from_utf8_lossy(&value).into_owned())
+            }
+            "public.html" => html = Some(String::from_utf8_lossy(&value).into_owned()),
+            "public.file-url" => files.push(file_url_to_path(&String::from_utf8_lossy(&value))),
+            "public.png" => image = Some(value),
+            "public.tiff" if image.is_none() => image = Some(value),
+            _ => {}
+        }
+    }
+
+    if !files.is_empty() {
+        return ImportedContent::Files(files);
+    }
+    match (text, image) {
+        (Some(text), _) if !text.is_empty() => ImportedContent::Text { text, html },
+        (_, Some(image)) => ImportedContent::Image(image),
+        _ => ImportedContent::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::tests::{empty_dir, text_of};
+
+    #[test]
+    fn reads_the_history() {
+        let path = empty_dir("maccy").join("Storage.sqlite");
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE ZHISTORYITEM (Z_PK INTEGER PRIMARY KEY, ZAPPLICATION VARCHAR, ZFIRSTCOPIEDAT TIMESTAMP, ZLASTCOPIEDAT TIMESTAMP, ZNUMBEROFCOPIES INTEGER, ZPIN VARCHAR, ZTITLE VARCHAR);
+                 CREATE TABLE ZHISTORYITEMCONTENT (Z_PK INTEGER PRIMARY KEY, ZITEM INTEGER, ZTYPE VARCHAR, ZVALUE BLOB);
+                 INSERT INTO ZHISTORYITEM VALUES (1, 'com.apple.Safari', 700000000.5, 700000000.5, 1, NULL, 'hi');
+                 INSERT INTO ZHISTORYITEM VALUES (2, 'com.apple.Terminal', 700000100, 700000100, 1, 'b', 'ls');
+                 INSERT INTO ZHISTORYITEM VALUES (3, NULL, 700000200, 700000200, 1, NULL, 'x');
+                 INSERT INTO ZHISTORYITEMCONTENT VALUES (1, 1, 'public.utf8-plain-text', CAST('hello' AS BLOB));
+                 INSERT INTO ZHISTORYITEMCONTENT VALUES (2, 1, 'public.html', CAST('<p>hello</p>' AS BLOB));
+                 INSERT INTO ZHISTORYITEMCONTENT VALUES (3, 2, 'public.utf8-plain-text', CAST('ls -la' AS BLOB));
+                 INSERT INTO ZHISTORYITEMCONTENT VALUES (4, 3, 'public.file-url', CAST('file:///Users/me/x.txt' AS BLOB));
+                 INSERT INTO ZHISTORYITEMCONTENT VALUES (5, 3, 'public.utf8-plain-text', CAST('x.txt' AS BLOB));",
+            )
+            .unwrap();
+
+        let items = read(&path).unwrap();
+        assert_eq!(items.len(), 3);
+        // Newest first, files win over their name
+        assert!(matches!(
+            &items[0].content,
+            ImportedContent::Files(files) if *files == ["/Users/me/x.txt"]
+        ));
+        assert_eq!(text_of(&items[1]), "ls -la");
+        assert!(items[1].pinned);
+        assert_eq!(items[1].source_app.as_deref(), Some("com.apple.Terminal"));
+        match &items[2].content {
+            ImportedContent::Text { text, html } => {
+                assert_eq!(text, "hello");
+                assert_eq!(html.as_deref(), Some("<p>hello</p>"));
+            }
+            other => panic!("Not a text: {:?}", other),
+        }
+        assert!(!items[2].pinned);
+        assert_eq!(items[2].timestamp, Some(700000000 + CORE_DATA_EPOCH));
+    }
+
+    #[test]
+    fn rejects_other_databases() {
+        let path = empty_dir("maccy-invalid").join("other.sqlite");
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch("CREATE TABLE notes (text TEXT)")
+            .unwrap();
+        assert!(read(&path).unwrap_err().contains("Maccy"));
+    }
+}
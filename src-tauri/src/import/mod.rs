@@ -0,0 +1,713 @@
+//! Imports the history of other clipboard managers.
+//!
+//! Each source has a reader that turns its files into [`ImportedItem`]s,
+//! newest first. [`run`] then drops what is already in the history and adds
+//! the rest through `insert_item`, so imported rows look like captured ones.
+//! Groups of the source (CopyQ tabs, Ditto groups, Clipy folders) become
+//! collections.
+
+mod cliphist;
+mod clipman;
+mod clipy;
+mod copyq;
+mod ditto;
+mod gpaste;
+mod maccy;
+mod qdatastream;
+mod xml;
+
+use crate::classifier::Classifier;
+use crate::db::Database;
+use crate::image_store::ImageStore;
+use crate::models::{AppConfig, ClipboardItem};
+use crate::phash::{dhash, digest};
+use crate::utils::apply_size_limits;
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// How often progress is reported while importing
+const PROGRESS_EVERY: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportSource {
+    CopyQ,
+    Maccy,
+    Clipy,
+    Ditto,
+    GPaste,
+    Clipman,
+    Cliphist,
+}
+
+impl ImportSource {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "copyq" => Some(Self::CopyQ),
+            "maccy" => Some(Self::Maccy),
+            "clipy" => Some(Self::Clipy),
+            "ditto" => Some(Self::Ditto),
+            "gpaste" => Some(Self::GPaste),
+            "clipman" => Some(Self::Clipman),
+            "cliphist" => Some(Self::Cliphist),
+            _ => None,
+        }
+    }
+
+    /// Where the source keeps its history by default. Clipy has no default,
+    /// its snippets have to be exported to a file first.
+    pub fn default_path(self) -> Option<PathBuf> {
+        let home = home::home_dir()?;
+        let xdg = |var: &str, fallback: &str| {
+            std::env::var_os(var)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| home.join(fallback))
+        };
+        match self {
+            Self::CopyQ if cfg!(windows) => {
+                std::env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("copyq"))
+            }
+            Self::CopyQ => Some(xdg("XDG_CONFIG_HOME", ".config").join("copyq")),
+            Self::Maccy => Some(home.join(
+                "Library/Containers/org.p0deje.Maccy/Data/Library/Application Support/Maccy/Storage.sqlite",
+            )),
+            Self::Clipy => None,
+            Self::Ditto => {
+                std::env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("Ditto/Ditto.db"))
+            }
+            Self::GPaste => Some(xdg("XDG_DATA_HOME", ".local/share").join("gpaste/history.xml")),
+            Self::Clipman => Some(xdg("XDG_DATA_HOME", ".local/share").join("clipman.json")),
+            Self::Cliphist => Some(xdg("XDG_CACHE_HOME", ".cache").join("cliphist/db")),
+        }
+    }
+
+    fn read(self, path: &Path) -> Result<Vec<ImportedItem>, String> {
+        match self {
+            Self::CopyQ => copyq::read(path),
+            Self::Maccy => maccy::read(path),
+            Self::Clipy => clipy::read(path),
+            Self::Ditto => ditto::read(path),
+            Self::GPaste => gpaste::read(path),
+            Self::Clipman => clipman::read(path),
+            Self::Cliphist => cliphist::read(path),
+        }
+    }
+}
+
+/// One entry read from another clipboard manager.
+#[derive(Debug, Default)]
+pub struct ImportedItem {
+    pub content: ImportedContent,
+    // Seconds since the Unix epoch, sources without dates keep their order
+    pub timestamp: Option<i64>,
+    pub pinned: bool,
+    pub sensitive: bool,
+    pub note: Option<String>,
+    // Tab, group or folder, imported as a collection
+    pub group: Option<String>,
+    pub source_app: Option<String>,
+}
+
+impl ImportedItem {
+    fn text(text: String) -> Self {
+        Self {
+            content: ImportedContent::Text { text, html: None },
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportedContent {
+    Text { text: String, html: Option<String> },
+    // Encoded image in any format the image crate reads
+    Image(Vec<u8>),
+    Files(Vec<String>),
+}
+
+impl Default for ImportedContent {
+    fn default() -> Self {
+        Self::Text {
+            text: String::new(),
+            html: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub found: usize,
+    // Items added, or that would be added on a dry run
+    pub imported: usize,
+    pub duplicates: usize,
+    // Empty, unreadable or over the size limits
+    pub skipped: usize,
+    pub pinned: usize,
+    pub images: usize,
+    // Collections the items were put in, new ones included
+    pub collections: Vec<String>,
+    // The oldest unpinned items over the limit are pruned on the next copy
+    pub history_size: usize,
+    pub history_limit: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportProgress {
+    pub processed: usize,
+    pub total: usize,
+}
+
+/// Imports everything `source` has at `path`. With `dry_run` nothing is
+/// written, the report tells what an import would do.
+pub fn run(
+    db: &Database,
+    image_store: &ImageStore,
+    config: &AppConfig,
+    source: ImportSource,
+    path: &Path,
+    dry_run: bool,
+    mut progress: impl FnMut(ImportProgress),
+) -> Result<ImportReport, String> {
+    let items = source.read(path)?;
+    let total = items.len();
+    let mut importer = Importer {
+        db,
+        image_store,
+        config,
+        classifier: Classifier::new(&config.custom_classifiers),
+        dry_run,
+        collections: db
+            .get_collections()
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|collection| (collection.name, collection.id))
+            .collect(),
+        seen: HashSet::new(),
+        report: ImportReport {
+            dry_run,
+            found: total,
+            history_limit: config.max_history_size,
+            ..Default::default()
+        },
+    };
+
+    // Dateless sources list the newest item first, one second apart keeps that order
+    let now = Local::now().timestamp();
+    for (index, item) in items.into_iter().enumerate() {
+        if index % PROGRESS_EVERY == 0 {
+            progress(ImportProgress {
+                processed: index,
+                total,
+            });
+        }
+        let timestamp = item.timestamp.unwrap_or(now - index as i64);
+        importer.import(item, timestamp)?;
+    }
+    progress(ImportProgress {
+        processed: total,
+        total,
+    });
+
+    let mut report = importer.report;
+    report.history_size = db.count_history().map_err(|e| e.to_string())?;
+    if dry_run {
+        report.history_size += report.imported;
+    }
+    Ok(report)
+}
+
+struct Importer<'a> {
+    db: &'a Database,
+    image_store: &'a ImageStore,
+    config: &'a AppConfig,
+    classifier: Classifier,
+    dry_run: bool,
+    collections: HashMap<String, i64>,
+    // Content already handled in this import
+    seen: HashSet<String>,
+    report: ImportReport,
+}
+
+impl Importer<'_> {
+    fn import(&mut self, imported: ImportedItem, timestamp: i64) -> Result<(), String> {
+        let timestamp = Local
+            .timestamp_opt(timestamp, 0)
+            .single()
+            .unwrap_or_else(Local::now)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+
+        let prepared = match imported.content {
+            ImportedContent::Text { text, html } => {
+                self.prepare_text(text, html, imported.sensitive)?
+            }
+            ImportedContent::Files(files) => self.prepare_files(files)?,
+            ImportedContent::Image(bytes) => self.prepare_image(&bytes)?,
+        };
+        let mut item = match prepared {
+            Prepared::New(item) => *item,
+            Prepared::Duplicate(existing) => {
+                self.report.duplicates += 1;
+                // Keep the pin of the source on the copy we already have
+                if let (Some(id), true, false) = (existing, imported.pinned, self.dry_run) {
+                    self.db.set_pinned(id, true).map_err(|e| e.to_string())?;
+                }
+                return Ok(());
+            }
+            Prepared::Skipped => {
+                self.report.skipped += 1;
+                return Ok(());
+            }
+        };
+
+        item.timestamp = timestamp;
+        item.is_pinned = imported.pinned;
+        item.note = imported.note.filter(|note| !note.is_empty());
+        item.source_app = imported.source_app;
+        if let Some(group) = imported.group {
+            item.collection_id = self.collection(group)?;
+        }
+
+        self.report.imported += 1;
+        self.report.pinned += usize::from(item.is_pinned);
+        self.report.images += usize::from(item.kind == "image");
+        if self.dry_run {
+            return Ok(());
+        }
+        // Pruning is left to the next capture, so nothing imported is dropped right away
+        self.db
+            .insert_item(&item, usize::MAX)
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn prepare_text(
+        &mut self,
+        text: String,
+        html: Option<String>,
+        sensitive: bool,
+    ) -> Result<Prepared, String> {
+        if text.trim().is_empty() {
+            return Ok(Prepared::Skipped);
+        }
+        if !self.seen.insert(format!("text:{}", text)) {
+            return Ok(Prepared::Duplicate(None));
+        }
+        if let Some(id) = self
+            .db
+            .find_item("text", &text)
+            .map_err(|e| e.to_string())?
+        {
+            return Ok(Prepared::Duplicate(Some(id)));
+        }
+
+        let classification = self.classifier.classify(&text);
+        let item = ClipboardItem {
+            content: text,
+            kind: "text".to_string(),
+            is_sensitive: sensitive,
+            data_type: classification.data_type,
+            html_content: html,
+            language: classification.language,
+            ..Default::default()
+        };
+        Ok(apply_size_limits(item, self.config)
+            .map_or(Prepared::Skipped, |item| Prepared::New(Box::new(item))))
+    }
+
+    fn prepare_files(&mut self, files: Vec<String>) -> Result<Prepared, String> {
+        if files.is_empty() {
+            return Ok(Prepared::Skipped);
+        }
+        let content = serde_json::to_string(&files).map_err(|e| e.to_string())?;
+        if !self.seen.insert(format!("file:{}", content)) {
+            return Ok(Prepared::Duplicate(None));
+        }
+        if let Some(id) = self
+            .db
+            .find_item("file", &content)
+            .map_err(|e| e.to_string())?
+        {
+            return Ok(Prepared::Duplicate(Some(id)));
+        }
+        Ok(Prepared::New(Box::new(ClipboardItem {
+            content,
+            kind: "file".to_string(),
+            data_type: "file-list".to_string(),
+            ..Default::default()
+        })))
+    }
+
+    fn prepare_image(&mut self, bytes: &[u8]) -> Result<Prepared, String> {
+        let image = match image::load_from_memory(bytes) {
+            Ok(image) => image.to_rgba8(),
+            Err(e) => {
+                log::warn!("Skipping unreadable image: {}", e);
+                return Ok(Prepared::Skipped);
+            }
+        };
+        let max_image_size = self.config.max_image_size;
+        if max_image_size > 0 && image.len() > max_image_size {
+            return Ok(Prepared::Skipped);
+        }
+
+        let image_digest = digest(&image);
+        if !self.seen.insert(format!("image:{}", image_digest)) {
+            return Ok(Prepared::Duplicate(None));
+        }
+        let hash = dhash(&image);
+        let (width, height) = image.dimensions();
+        let existing = match self.db.find_image(&image_digest) {
+            Ok(None) => {
+                self.db
+                    .find_duplicate_image(hash, self.config.image_dedup_distance, width, height)
+            }
+            found => found,
+        }
+        .map_err(|e| e.to_string())?;
+        if existing.is_some() {
+            return Ok(Prepared::Duplicate(existing));
+        }
+
+        let mut item = ClipboardItem {
+            kind: "image".to_string(),
+            data_type: "image".to_string(),
+            width: Some(width),
+            height: Some(height),
+            image_hash: Some(hash as i64),
+            image_digest: Some(image_digest),
+            ..Default::default()
+        };
+        if !self.dry_run {
+            let stored = self.image_store.save(&image, &self.config.image_format)?;
+            item.content = stored.path;
+            item.thumbnail = Some(stored.thumbnail_path);
+            item.byte_size = Some(stored.byte_size);
+        }
+        Ok(Prepared::New(Box::new(item)))
+    }
+
+    /// The id of the collection with this name, created if needed.
+    fn collection(&mut self, name: String) -> Result<Option<i64>, String> {
+        if !self.report.collections.contains(&name) {
+            self.report.collections.push(name.clone());
+        }
+        if let Some(&id) = self.collections.get(&name) {
+            return Ok(Some(id));
+        }
+        if self.dry_run {
+            return Ok(None);
+        }
+        let collection = self
+            .db
+            .create_collection(name.clone())
+            .map_err(|e| e.to_string())?;
+        self.collections.insert(name, collection.id);
+        Ok(Some(collection.id))
+    }
+}
+
+enum Prepared {
+    New(Box<ClipboardItem>),
+    // The id of the matching item, None for a repeat within the import
+    Duplicate(Option<i64>),
+    Skipped,
+}
+
+/// Reads a whole file, with the path in the error.
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Can't read {}: {}", path.display(), e))
+}
+
+/// Opens another app's SQLite database without changing it.
+fn open_sqlite(path: &Path) -> Result<rusqlite::Connection, String> {
+    rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Can't open {}: {}", path.display(), e))
+}
+
+/// Turns `file://` URLs into paths, other URLs are kept as they are.
+fn file_url_to_path(url: &str) -> String {
+    match url.strip_prefix("file://") {
+        Some(path) => urlencoding::decode(path)
+            .map(|path| path.into_owned())
+            .unwrap_or_else(|_| path.to_string()),
+        None => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Crypto;
+    use std::sync::Arc;
+
+    pub(super) fn empty_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("clipboard-import-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A small PNG, different for every `shade`.
+    pub(super) fn png(shade: u8) -> Vec<u8> {
+        let image = image::RgbaImage::from_fn(32, 32, |x, y| {
+            let stripe = if (x / 4 + y / 8) % 2 == 0 {
+                shade
+            } else {
+                255 - shade
+            };
+            image::Rgba([stripe, (x * 8) as u8, (y * 8) as u8, 255])
+        });
+        let mut bytes = Vec::new();
+        image
+            .write_to(
+                &mut std::io::Cursor::new(&mut bytes),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+        bytes
+    }
+
+    /// The text of an imported item.
+    pub(super) fn text_of(item: &ImportedItem) -> &str {
+        match &item.content {
+            ImportedContent::Text { text, .. } => text,
+            other => panic!("Not a text: {:?}", other),
+        }
+    }
+
+    struct Target {
+        db: Database,
+        image_store: ImageStore,
+        config: AppConfig,
+    }
+
+    impl Target {
+        fn new(dir: &Path) -> Self {
+            let crypto = Arc::new(Crypto::new(dir.join("key")));
+            Self {
+                db: Database::new(dir.join("history.db"), crypto).unwrap(),
+                image_store: ImageStore::new(dir.join("images")),
+                config: AppConfig::default(),
+            }
+        }
+
+        fn import(
+            &self,
+            source: ImportSource,
+            path: &Path,
+            dry_run: bool,
+        ) -> Result<ImportReport, String> {
+            let mut reports = 0;
+            let report = run(
+                &self.db,
+                &self.image_store,
+                &self.config,
+                source,
+                path,
+                dry_run,
+                |_| reports += 1,
+            )?;
+            // At the start and at the end
+            assert!(reports >= 2);
+            Ok(report)
+        }
+
+        fn history(&self) -> Vec<ClipboardItem> {
+            self.db
+                .get_history(1, 100, None, false, false, None, None, None)
+                .unwrap()
+        }
+
+        fn find(&self, content: &str) -> ClipboardItem {
+            self.history()
+                .into_iter()
+                .find(|item| item.content == content)
+                .unwrap()
+        }
+    }
+
+    #[test]
+    fn dry_runs_write_nothing() {
+        let dir = empty_dir("dry-run");
+        let target = Target::new(&dir);
+        let path = dir.join("clipman.json");
+        std::fs::write(&path, r#"["oldest", "  ", "newest"]"#).unwrap();
+
+        let report = target.import(ImportSource::Clipman, &path, true).unwrap();
+        assert!(report.dry_run);
+        assert_eq!((report.found, report.imported, report.skipped), (3, 2, 1));
+        assert_eq!(report.history_size, 2);
+        assert!(target.history().is_empty());
+
+        let report = target.import(ImportSource::Clipman, &path, false).unwrap();
+        assert_eq!((report.imported, report.history_size), (2, 2));
+        // Without dates the order of the source is kept
+        assert!(target.find("oldest").timestamp < target.find("newest").timestamp);
+    }
+
+    #[test]
+    fn skips_what_is_already_there() {
+        let dir = empty_dir("duplicates");
+        let target = Target::new(&dir);
+        target
+            .db
+            .insert_item(
+                &ClipboardItem {
+                    content: "known".into(),
+                    kind: "text".into(),
+                    timestamp: "2024-01-01 00:00:00".into(),
+                    data_type: "text".into(),
+                    ..Default::default()
+                },
+                100,
+            )
+            .unwrap();
+        let path = dir.join("history.xml");
+        std::fs::write(
+            &path,
+            format!(
+                r#"<history version="2.0">
+                  <item kind="Text"><value>new</value></item>
+                  <item kind="Text"><value>new</value></item>
+                  <item kind="Text"><value>known</value></item>
+                  <item kind="Image"><value>{0}</value></item>
+                  <item kind="Image"><value>{0}</value></item>
+                </history>"#,
+                dir.join("image.png").display()
+            ),
+        )
+        .unwrap();
+        std::fs::write(dir.join("image.png"), png(10)).unwrap();
+
+        let report = target.import(ImportSource::GPaste, &path, false).unwrap();
+        assert_eq!(
+            (report.imported, report.duplicates, report.images),
+            (2, 3, 1)
+        );
+        let image = target
+            .history()
+            .into_iter()
+            .find(|item| item.kind == "image")
+            .unwrap();
+        assert!(Path::new(&image.content).starts_with(target.image_store.dir()));
+        assert_eq!((image.width, image.height), (Some(32), Some(32)));
+
+        let report = target.import(ImportSource::GPaste, &path, false).unwrap();
+        assert_eq!((report.imported, report.duplicates), (0, 5));
+        assert_eq!(target.history().len(), 3);
+    }
+
+    #[test]
+    fn imports_passwords_as_sensitive() {
+        let dir = empty_dir("sensitive");
+        let target = Target::new(&dir);
+        let path = dir.join("history.xml");
+        std::fs::write(
+            &path,
+            r#"<history version="2.0">
+              <item kind="Password" name="bank" date="1700000000"><value><![CDATA[hunter2]]></value></item>
+              <item kind="Uris"><value>file:///home/me/a%20b.txt
+file:///home/me/c.txt</value></item>
+            </history>"#,
+        )
+        .unwrap();
+
+        target.import(ImportSource::GPaste, &path, false).unwrap();
+        let history = target.history();
+        let password = history.iter().find(|item| item.is_sensitive).unwrap();
+        assert_eq!(password.content, "hunter2");
+        assert_eq!(password.note.as_deref(), Some("bank"));
+        let files = target.find(r#"["/home/me/a b.txt","/home/me/c.txt"]"#);
+        assert_eq!(files.kind, "file");
+    }
+
+    #[test]
+    fn puts_groups_into_collections() {
+        let dir = empty_dir("collections");
+        let target = Target::new(&dir);
+        target.db.create_collection("Mail".into()).unwrap();
+        let path = dir.join("snippets.xml");
+        std::fs::write(
+            &path,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <folders>
+              <folder><title>Mail</title><snippets>
+                <snippet><title>Greeting</title><content>Dear &lt;name&gt;,</content></snippet>
+              </snippets></folder>
+              <folder><title>Code</title><snippets>
+                <snippet><title>Main</title><content>fn main() {}</content></snippet>
+              </snippets></folder>
+            </folders>"#,
+        )
+        .unwrap();
+
+        let report = target.import(ImportSource::Clipy, &path, true).unwrap();
+        assert_eq!(report.collections, ["Mail", "Code"]);
+        assert_eq!(target.db.get_collections().unwrap().len(), 1);
+
+        target.import(ImportSource::Clipy, &path, false).unwrap();
+        let collections = target.db.get_collections().unwrap();
+        assert_eq!(collections.len(), 2);
+        let greeting = target.find("Dear <name>,");
+        assert_eq!(greeting.note.as_deref(), Some("Greeting"));
+        assert_eq!(greeting.collection_id, Some(collections[0].id));
+    }
+
+    #[test]
+    fn rejects_files_of_other_sources() {
+        let dir = empty_dir("wrong-source");
+        let target = Target::new(&dir);
+        let path = dir.join("clipman.json");
+        std::fs::write(&path, r#"["a"]"#).unwrap();
+
+        for source in [
+            ImportSource::GPaste,
+            ImportSource::Clipy,
+            ImportSource::Maccy,
+            ImportSource::Ditto,
+            ImportSource::Cliphist,
+        ] {
+            assert!(target.import(source, &path, true).is_err(), "{:?}", source);
+        }
+        let error = target
+            .import(ImportSource::Clipy, &dir.join("default.realm"), true)
+            .unwrap_err();
+        assert!(error.contains("history database"));
+        assert!(target
+            .import(ImportSource::Clipman, &dir.join("missing.json"), true)
+            .is_err());
+    }
+
+    #[test]
+    fn keeps_images_that_only_look_alike() {
+        let dir = empty_dir("look-alike");
+        let target = Target::new(&dir);
+        let white = image::RgbaImage::from_pixel(64, 64, image::Rgba([255; 4]));
+        let mut edited = white.clone();
+        edited.put_pixel(0, 0, image::Rgba([0, 0, 0, 255]));
+        assert_eq!(dhash(&white), dhash(&edited));
+        white.save(dir.join("white.png")).unwrap();
+        edited.save(dir.join("edited.png")).unwrap();
+        let path = dir.join("history.xml");
+        std::fs::write(
+            &path,
+            format!(
+                r#"<history version="2.0">
+                  <item kind="Image"><value>{}</value></item>
+                  <item kind="Image"><value>{}</value></item>
+                </history>"#,
+                dir.join("white.png").display(),
+                dir.join("edited.png").display()
+            ),
+        )
+        .unwrap();
+
+        let report = target.import(ImportSource::GPaste, &path, false).unwrap();
+        assert_eq!((report.images, report.duplicates), (2, 0));
+    }
+}
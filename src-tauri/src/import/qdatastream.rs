@@ -0,0 +1,164 @@
+//! Just enough of Qt's QDataStream format (version Qt 4.7 and later) to read
+//! CopyQ's files. Everything is big-endian, strings are UTF-16.
+
+use flate2::read::ZlibDecoder;
+use std::collections::HashMap;
+use std::io::Read;
+
+// Marks a null QString or QByteArray instead of a length
+const NULL_LENGTH: u32 = u32::MAX;
+
+// Numbers are read to get past them, no importer needs their value yet
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum Variant {
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    List(Vec<Variant>),
+    Map(HashMap<String, Variant>),
+    // Types that aren't needed are skipped when their size is known
+    Other,
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn seek(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| "Unexpected end of data".to_string())?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("length checked"))
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub fn i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_be_bytes(self.array()?))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_be_bytes(self.array()?))
+    }
+
+    /// A QByteArray, `None` when it was null.
+    pub fn bytes(&mut self) -> Result<Option<Vec<u8>>, String> {
+        match self.u32()? {
+            NULL_LENGTH => Ok(None),
+            len => Ok(Some(self.take(len as usize)?.to_vec())),
+        }
+    }
+
+    /// A QString, `None` when it was null.
+    pub fn string(&mut self) -> Result<Option<String>, String> {
+        match self.u32()? {
+            NULL_LENGTH => Ok(None),
+            len => {
+                let units: Vec<u16> = self
+                    .take(len as usize)?
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect();
+                Ok(Some(String::from_utf16_lossy(&units)))
+            }
+        }
+    }
+
+    /// A QVariant as written by Qt 5, limited to the common types.
+    pub fn variant(&mut self) -> Result<Variant, String> {
+        let type_id = self.u32()?;
+        let _is_null = self.u8()?;
+        let value = match type_id {
+            1 => Variant::Bool(self.bool()?),
+            2 => Variant::Int(self.i32()?.into()),
+            3 => Variant::Int(self.u32()?.into()),
+            4 | 5 => Variant::Int(self.i64()?),
+            6 => Variant::Double(self.f64()?),
+            8 => {
+                let len = self.u32()?;
+                let mut map = HashMap::new();
+                for _ in 0..len {
+                    let key = self.string()?.unwrap_or_default();
+                    map.insert(key, self.variant()?);
+                }
+                Variant::Map(map)
+            }
+            9 => {
+                let len = self.u32()?;
+                let mut list = Vec::new();
+                for _ in 0..len {
+                    list.push(self.variant()?);
+                }
+                Variant::List(list)
+            }
+            10 => Variant::String(self.string()?.unwrap_or_default()),
+            11 => {
+                let len = self.u32()?;
+                let mut list = Vec::new();
+                for _ in 0..len {
+                    list.push(Variant::String(self.string()?.unwrap_or_default()));
+                }
+                Variant::List(list)
+            }
+            12 => Variant::Bytes(self.bytes()?.unwrap_or_default()),
+            0 => Variant::Other,
+            other => return Err(format!("Unsupported QVariant type {}", other)),
+        };
+        Ok(value)
+    }
+}
+
+/// Undoes qCompress: the uncompressed size as a 32-bit number, then zlib data.
+pub fn uncompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    let Some(zlib) = data.get(4..) else {
+        return Ok(Vec::new());
+    };
+    let mut out = Vec::new();
+    ZlibDecoder::new(zlib)
+        .read_to_end(&mut out)
+        .map_err(|e| format!("Can't uncompress item data: {}", e))?;
+    Ok(out)
+}
@@ -0,0 +1,101 @@
+//! A small element tree for the sources that keep their data in XML.
+
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub struct Element {
+    pub name: String,
+    pub attributes: HashMap<String, String>,
+    pub children: Vec<Element>,
+    // Text and CDATA directly inside the element
+    pub text: String,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+}
+
+/// Parses a document and returns its root element.
+pub fn parse(data: &[u8]) -> Result<Element, String> {
+    let mut reader = Reader::from_reader(data);
+    let mut buf = Vec::new();
+    // The bottom entry collects the root element
+    let mut stack = vec![Element::default()];
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| format!("Invalid XML at byte {}: {}", reader.error_position(), e))?;
+        match event {
+            Event::Start(start) => stack.push(element(&start)?),
+            Event::Empty(start) => {
+                let element = element(&start)?;
+                stack.last_mut().expect("root").children.push(element);
+            }
+            Event::End(_) if stack.len() > 1 => {
+                let element = stack.pop().expect("checked");
+                stack.last_mut().expect("root").children.push(element);
+            }
+            Event::Text(text) => {
+                let text = text.decode().map_err(|e| e.to_string())?;
+                stack.last_mut().expect("root").text.push_str(&text);
+            }
+            Event::CData(data) => {
+                let text = data.decode().map_err(|e| e.to_string())?;
+                stack.last_mut().expect("root").text.push_str(&text);
+            }
+            Event::GeneralRef(reference) => {
+                let resolved = match reference.resolve_char_ref().map_err(|e| e.to_string())? {
+                    Some(c) => c.to_string(),
+                    None => {
+                        let name = reference.decode().map_err(|e| e.to_string())?;
+                        resolve_predefined_entity(&name)
+                            .map(str::to_string)
+                            .unwrap_or_else(|| format!("&{};", name))
+                    }
+                };
+                stack.last_mut().expect("root").text.push_str(&resolved);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    stack
+        .swap_remove(0)
+        .children
+        .into_iter()
+        .next()
+        .ok_or_else(|| "The XML document is empty".to_string())
+}
+
+fn element(start: &BytesStart) -> Result<Element, String> {
+    let mut attributes = HashMap::new();
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+        let value = attribute.unescape_value().map_err(|e| e.to_string())?;
+        attributes.insert(
+            String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+            value.into_owned(),
+        );
+    }
+    Ok(Element {
+        name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+        attributes,
+        ..Default::default()
+    })
+}
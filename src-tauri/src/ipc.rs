@@ -0,0 +1,544 @@
+//! Local control socket for editors, scripts and launchers.
+//!
+//! Speaks newline-delimited JSON-RPC 2.0 on a Unix domain socket
+//! (`~/.clipboard-manager/run/ipc.sock`, in a directory only the user can
+//! enter) or on the named pipe `\\.\pipe\clipboard-manager-<user>` on
+//! Windows. Off unless `enable_ipc` is set. After calling
+//! `subscribe` a client also receives app events as JSON-RPC notifications,
+//! e.g. `{"jsonrpc":"2.0","method":"clipboard-update","params":null}`.
+
+use crate::commands;
+use crate::db::MAX_PAGE_SIZE;
+use crate::state::AppState;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tauri::{AppHandle, Listener, Manager};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::sync::broadcast;
+
+// App events forwarded to subscribed clients.
+const FORWARDED_EVENTS: &[&str] = &[
+    "clipboard-update",
+    "pause-state-changed",
+    "paste-stack-changed",
+];
+
+// Longer requests close the connection. They are small JSON objects; the
+// largest is `set_clipboard` with new text.
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const APP_ERROR: i64 = -32000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<String> for RpcError {
+    fn from(message: String) -> Self {
+        Self::new(APP_ERROR, message)
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    // Requests without an id are notifications and get no response
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct HistoryParams {
+    page: usize,
+    page_size: usize,
+    query: Option<String>,
+    search_regex: Option<bool>,
+    search_case_sensitive: Option<bool>,
+    collection_id: Option<i64>,
+    language: Option<String>,
+    data_type: Option<String>,
+}
+
+impl HistoryParams {
+    // Pages start at 1; oversized pages are cut down rather than refused
+    fn validate(mut self) -> Result<Self, RpcError> {
+        if self.page == 0 {
+            return Err(RpcError::new(INVALID_PARAMS, "page starts at 1"));
+        }
+        self.page_size = self.page_size.clamp(1, MAX_PAGE_SIZE);
+        Ok(self)
+    }
+}
+
+impl Default for HistoryParams {
+    fn default() -> Self {
+        Self {
+            page: 1,
+            page_size: 20,
+            query: None,
+            search_regex: None,
+            search_case_sensitive: None,
+            collection_id: None,
+            language: None,
+            data_type: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IdParams {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct SetClipboardParams {
+    // Either an existing item or new text
+    id: Option<i64>,
+    text: Option<String>,
+    paste_mode: Option<String>,
+    inputs: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize)]
+struct NameParams {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ItemCollectionParams {
+    item_id: i64,
+    collection_id: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct PausedParams {
+    paused: bool,
+}
+
+#[derive(Deserialize)]
+struct IdsParams {
+    ids: Vec<i64>,
+}
+
+/// Starts the control socket on Tauri's async runtime.
+pub fn start(app: &AppHandle) {
+    let (tx, _) = broadcast::channel::<Value>(64);
+    for &event in FORWARDED_EVENTS {
+        let tx = tx.clone();
+        app.listen_any(event, move |e| {
+            let params = serde_json::from_str::<Value>(e.payload()).unwrap_or(Value::Null);
+            // Fails only when no client is connected
+            let _ = tx.send(json!({ "jsonrpc": "2.0", "method": event, "params": params }));
+        });
+    }
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = listen(app, tx).await {
+            log::error!("IPC server stopped: {}", e);
+        }
+    });
+}
+
+#[cfg(unix)]
+async fn listen(app: AppHandle, tx: broadcast::Sender<Value>) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::net::UnixListener;
+
+    // The socket is reachable as soon as it is bound, so it goes into a
+    // directory nobody else can enter instead of being locked down afterwards
+    let dir = crate::app_data_dir().join("run");
+    std::fs::create_dir_all(&dir)?;
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+    let path = dir.join("ipc.sock");
+    // Left over when the app wasn't shut down cleanly
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    log::info!("IPC server listening on {:?}", path);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tauri::async_runtime::spawn(serve(app.clone(), stream, tx.subscribe()));
+    }
+}
+
+#[cfg(windows)]
+async fn listen(app: AppHandle, tx: broadcast::Sender<Value>) -> std::io::Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let user = std::env::var("USERNAME").unwrap_or_default();
+    let name = format!(r"\\.\pipe\clipboard-manager-{}", user);
+    // Remote clients are rejected by default
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&name)?;
+    log::info!("IPC server listening on {}", name);
+
+    loop {
+        server.connect().await?;
+        let stream = server;
+        server = ServerOptions::new().create(&name)?;
+        tauri::async_runtime::spawn(serve(app.clone(), stream, tx.subscribe()));
+    }
+}
+
+async fn serve<S>(app: AppHandle, stream: S, mut notifications: broadcast::Receiver<Value>)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    let mut subscribed = false;
+
+    loop {
+        tokio::select! {
+            line = read_line(&mut reader, &mut buf) => {
+                let line = match line {
+                    Line::Complete(line) => line,
+                    Line::TooLarge => {
+                        let error = RpcError::new(INVALID_REQUEST, "Request too large");
+                        let _ = write_message(&mut writer, &error_response(Value::Null, error)).await;
+                        break;
+                    }
+                    Line::Closed => break,
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let app = app.clone();
+                let handled = tauri::async_runtime::spawn_blocking(move || {
+                    handle_line(&line, |method, params| dispatch(&app, method, params))
+                })
+                .await;
+                let Ok((response, subscribe)) = handled else { break };
+                subscribed |= subscribe;
+                if let Some(response) = response {
+                    if write_message(&mut writer, &response).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            notification = notifications.recv() => match notification {
+                Ok(message) if subscribed => {
+                    if write_message(&mut writer, &message).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+}
+
+enum Line {
+    Complete(String),
+    TooLarge,
+    Closed,
+}
+
+// Reads the next request line, at most MAX_REQUEST_SIZE bytes. Safe to cancel:
+// what was read so far stays in `buf` for the next call.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R, buf: &mut Vec<u8>) -> Line {
+    let limit = (MAX_REQUEST_SIZE + 1).saturating_sub(buf.len()) as u64;
+    match (&mut *reader).take(limit).read_until(b'\n', buf).await {
+        Ok(0) if buf.is_empty() => return Line::Closed,
+        Err(_) => return Line::Closed,
+        Ok(_) => {}
+    }
+    if buf.last() != Some(&b'\n') && buf.len() > MAX_REQUEST_SIZE {
+        return Line::TooLarge;
+    }
+    let line = String::from_utf8_lossy(buf).into_owned();
+    buf.clear();
+    Line::Complete(line)
+}
+
+async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Value,
+) -> std::io::Result<()> {
+    let mut line = message.to_string();
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    writer.flush().await
+}
+
+// Returns the response to send, if any, and whether the client subscribed.
+fn handle_line(
+    line: &str,
+    dispatch: impl FnOnce(&str, Value) -> Result<Value, RpcError>,
+) -> (Option<Value>, bool) {
+    let request: Request = match serde_json::from_str::<Value>(line) {
+        Err(e) => {
+            return (
+                Some(error_response(
+                    Value::Null,
+                    RpcError::new(PARSE_ERROR, e.to_string()),
+                )),
+                false,
+            )
+        }
+        Ok(value) => match serde_json::from_value(value) {
+            Ok(request) => request,
+            Err(e) => {
+                return (
+                    Some(error_response(
+                        Value::Null,
+                        RpcError::new(INVALID_REQUEST, e.to_string()),
+                    )),
+                    false,
+                )
+            }
+        },
+    };
+
+    let subscribe = request.method == "subscribe";
+    let result = if subscribe {
+        Ok(Value::Bool(true))
+    } else {
+        dispatch(&request.method, request.params)
+    };
+
+    let response = request.id.map(|id| match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => error_response(id, error),
+    });
+    (response, subscribe)
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| RpcError::from(e.to_string()))
+}
+
+/// Runs one method against the app. The methods mirror the Tauri commands of
+/// the same name, so scripts see the same behaviour as the UI.
+pub fn dispatch(app: &AppHandle, method: &str, raw: Value) -> Result<Value, RpcError> {
+    let state = app.state::<AppState>();
+    match method {
+        "get_history" => {
+            let p = params::<HistoryParams>(raw)?.validate()?;
+            to_value(commands::get_history(
+                state,
+                p.page,
+                p.page_size,
+                p.query,
+                p.search_regex,
+                p.search_case_sensitive,
+                p.collection_id,
+                p.language,
+                p.data_type,
+            ))
+        }
+        // The item with its full content, or null when it doesn't exist
+        "get_item" => {
+            let p: IdParams = params(raw)?;
+            let item = state.db.get_item(p.id).map_err(|e| e.to_string())?;
+            match item.filter(|item| item.deleted_at.is_none()) {
+                Some(mut item) => {
+                    if item.kind == "text" {
+                        item.content = commands::get_item_content(state, p.id)?;
+                    }
+                    to_value(item)
+                }
+                None => Ok(Value::Null),
+            }
+        }
+        "get_item_content" => {
+            let p: IdParams = params(raw)?;
+            to_value(commands::get_item_content(state, p.id)?)
+        }
+        "set_clipboard" => {
+            let p: SetClipboardParams = params(raw)?;
+            let (content, kind, html_content) = match (p.id, p.text) {
+                (Some(id), _) => {
+                    let item = state
+                        .db
+                        .get_item(id)
+                        .map_err(|e| e.to_string())?
+                        .filter(|item| item.deleted_at.is_none())
+                        .ok_or_else(|| format!("Item {} not found", id))?;
+                    let content = if item.kind == "text" {
+                        state.db.get_item_content(id).map_err(|e| e.to_string())?
+                    } else {
+                        item.content
+                    };
+                    (content, item.kind, item.html_content)
+                }
+                (None, Some(text)) => (text, "text".to_string(), None),
+                (None, None) => {
+                    return Err(RpcError::new(
+                        INVALID_PARAMS,
+                        "Either id or text is required",
+                    ))
+                }
+            };
+            to_value(commands::set_clipboard_item(
+                app.clone(),
+                content,
+                kind,
+                p.id,
+                html_content,
+                p.paste_mode,
+                p.inputs,
+                state,
+            )?)
+        }
+        "get_collections" => to_value(commands::get_collections(state)?),
+        "create_collection" => {
+            let p: NameParams = params(raw)?;
+            to_value(commands::create_collection(state, p.name)?)
+        }
+        "delete_collection" => {
+            let p: IdParams = params(raw)?;
+            commands::delete_collection(state, p.id)?;
+            Ok(Value::Null)
+        }
+        "set_item_collection" => {
+            let p: ItemCollectionParams = params(raw)?;
+            commands::set_item_collection(state, p.item_id, p.collection_id)?;
+            Ok(Value::Null)
+        }
+        "get_paused" => to_value(commands::get_paused(state)),
+        "set_paused" => {
+            let p: PausedParams = params(raw)?;
+            commands::set_paused(app.clone(), p.paused, state);
+            Ok(Value::Null)
+        }
+        "pause" => {
+            commands::set_paused(app.clone(), true, state);
+            Ok(Value::Null)
+        }
+        "resume" => {
+            commands::set_paused(app.clone(), false, state);
+            Ok(Value::Null)
+        }
+        "get_paste_stack" => to_value(commands::get_paste_stack(state)),
+        "paste_stack_push" => {
+            let p: IdsParams = params(raw)?;
+            commands::paste_stack_push(app.clone(), state, p.ids)?;
+            Ok(Value::Null)
+        }
+        "paste_stack_clear" => {
+            commands::paste_stack_clear(app.clone(), state)?;
+            Ok(Value::Null)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method: {}", method),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo(method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "echo" => Ok(params),
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, "Unknown method")),
+        }
+    }
+
+    #[test]
+    fn answers_requests_and_skips_notifications() {
+        let (response, subscribe) = handle_line(
+            r#"{"jsonrpc":"2.0","id":7,"method":"echo","params":[1]}"#,
+            echo,
+        );
+        assert_eq!(
+            response,
+            Some(json!({ "jsonrpc": "2.0", "id": 7, "result": [1] }))
+        );
+        assert!(!subscribe);
+
+        let (response, _) = handle_line(r#"{"jsonrpc":"2.0","method":"echo"}"#, echo);
+        assert_eq!(response, None);
+
+        let (response, subscribe) =
+            handle_line(r#"{"jsonrpc":"2.0","id":1,"method":"subscribe"}"#, echo);
+        assert_eq!(response.unwrap()["result"], json!(true));
+        assert!(subscribe);
+    }
+
+    #[test]
+    fn reports_protocol_errors() {
+        let code = |line: &str| handle_line(line, echo).0.unwrap()["error"]["code"].clone();
+        assert_eq!(code("{not json"), json!(PARSE_ERROR));
+        assert_eq!(code(r#"{"id":1}"#), json!(INVALID_REQUEST));
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","id":1,"method":"nope"}"#),
+            json!(METHOD_NOT_FOUND)
+        );
+    }
+
+    #[test]
+    fn validates_history_pages() {
+        let p = |raw: Value| params::<HistoryParams>(raw).and_then(HistoryParams::validate);
+        let defaults = p(Value::Null).unwrap();
+        assert_eq!((defaults.page, defaults.page_size), (1, 20));
+        assert_eq!(p(json!({ "page": 0 })).err().unwrap().code, INVALID_PARAMS);
+        assert_eq!(
+            p(json!({ "page_size": 1_000_000 })).unwrap().page_size,
+            MAX_PAGE_SIZE
+        );
+        assert_eq!(p(json!({ "page_size": 0 })).unwrap().page_size, 1);
+    }
+
+    #[tokio::test]
+    async fn reads_bounded_lines() {
+        let input = b"{\"a\":1}\n\nlast".to_vec();
+        let mut reader = &input[..];
+        let mut buf = Vec::new();
+        assert!(
+            matches!(read_line(&mut reader, &mut buf).await, Line::Complete(l) if l == "{\"a\":1}\n")
+        );
+        assert!(matches!(read_line(&mut reader, &mut buf).await, Line::Complete(l) if l == "\n"));
+        assert!(matches!(read_line(&mut reader, &mut buf).await, Line::Complete(l) if l == "last"));
+        assert!(matches!(
+            read_line(&mut reader, &mut buf).await,
+            Line::Closed
+        ));
+
+        let input = vec![b'x'; MAX_REQUEST_SIZE + 10];
+        let mut reader = &input[..];
+        assert!(matches!(
+            read_line(&mut reader, &mut buf).await,
+            Line::TooLarge
+        ));
+    }
+}
@@ -0,0 +1,276 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+// A guess needs at least this much evidence to be reported.
+const MIN_SCORE: usize = 4;
+// A single feature can't dominate the score no matter how often it repeats.
+const MAX_HITS_PER_FEATURE: usize = 3;
+
+type Features = &'static [(&'static str, usize)];
+
+const RUST: Features = &[
+    (r"\bfn\s+\w+", 3),
+    (r"\blet\s+mut\b", 4),
+    (r"\bimpl\b", 3),
+    (r"\bpub\s+(fn|struct|enum|mod|trait)\b", 4),
+    (r"&mut\s", 3),
+    (r"\b\w+!\(", 2),
+    (r"^\s*use\s+\w+::", 4),
+    (r"\b(Option|Result|Vec|Box)<", 3),
+    (r"#\[derive", 5),
+    (r"\bmatch\s+.+\{", 2),
+];
+
+const PYTHON: Features = &[
+    (r"^\s*def\s+\w+\(.*\)\s*(->.*)?:\s*$", 5),
+    (
+        r"^\s*(if|elif|else|for|while|with|try|except|class)\b.*:\s*$",
+        2,
+    ),
+    (r"^\s*elif\b", 4),
+    (r"\bself\.", 2),
+    (r"\b(None|True|False)\b", 1),
+    (r"__\w+__", 3),
+    (r"^\s*from\s+[\w.]+\s+import\b", 4),
+    (r"\bprint\(", 1),
+    (r"\blambda\b", 2),
+];
+
+const JAVASCRIPT: Features = &[
+    (r"\bconst\s+\w+\s*=", 2),
+    (r"\blet\s+\w+\s*=", 1),
+    (r"=>", 2),
+    (r"\bfunction\b", 2),
+    (r"\bconsole\.\w+\(", 4),
+    (r"\b(document|window)\.", 3),
+    (r"\brequire\(", 3),
+    (r"\bexport\s+(default|const|function|class)\b", 2),
+    (r"===|!==", 3),
+    (r"\bundefined\b", 2),
+    (r#"^\s*import\s+.+\s+from\s+['"]"#, 3),
+];
+
+// Checked on top of the JavaScript features.
+const TYPESCRIPT: Features = &[
+    (r":\s*(string|number|boolean|any|void|unknown|never)\b", 4),
+    (r"\binterface\s+\w+\s*\{", 4),
+    (r"^\s*(export\s+)?type\s+\w+\s*=", 3),
+    (r"\bas\s+(string|number|const|any)\b", 3),
+    (r"\b(private|public|readonly)\s+\w+\s*:", 3),
+];
+
+const SQL: Features = &[
+    (r"(?i)^\s*select\b", 3),
+    (r"(?i)\bfrom\s+\w+", 1),
+    (r"(?i)\bwhere\b", 2),
+    (
+        r"(?i)\b(insert\s+into|update\s+\w+\s+set|delete\s+from|create\s+(table|index|view)|alter\s+table|drop\s+table)\b",
+        5,
+    ),
+    (
+        r"(?i)\b((inner|left|right|outer)\s+join|group\s+by|order\s+by|having)\b",
+        3,
+    ),
+];
+
+const SHELL: Features = &[
+    (r"^#!\s*/(usr/)?bin/(env\s+)?(ba|z)?sh", 8),
+    (r"^\s*\$ ", 2),
+    (r"\$\{\w+\}|\$\w+", 1),
+    (r"^\s*(if \[|fi$|then$|do$|done$|esac$)", 4),
+    (r"\becho\b", 2),
+    (r"^\s*export\s+\w+=", 3),
+    (r"\|\s*(grep|awk|sed|xargs|sort|head|tail)\b", 3),
+    (r"^\s*(sudo|apt|brew|git|cd|ls|mkdir|rm|curl|wget)\s", 2),
+];
+
+const GO: Features = &[
+    (r"^\s*package\s+\w+\s*$", 5),
+    (r"\bfunc\s+(\(\w+\s+\*?\w+\)\s*)?\w+\(", 5),
+    (r":=", 3),
+    (r"\bfmt\.\w+", 4),
+    (r"\berr\s*!=\s*nil\b", 5),
+    (r"\bchan\b|\bgo\s+func\b", 3),
+    (r"^\s*import\s*\($", 3),
+];
+
+const JAVA: Features = &[
+    (r"\bpublic\s+(static\s+)?(class|void|final|interface)\b", 4),
+    (r"\bSystem\.out\.print", 5),
+    (r"\b(private|protected)\s+\w+(<.*>)?\s+\w+\s*[;=]", 3),
+    (r"@Override", 4),
+    (r"\bString\[\]", 3),
+    (r"^\s*import\s+java\.", 5),
+    (r"\b(extends|implements)\b", 2),
+    (r"\bnew\s+\w+(<.*>)?\(", 1),
+];
+
+const C: Features = &[
+    (r"^\s*#include\s*[<\x22]\w+\.h[>\x22]", 5),
+    (r"\bprintf\(", 3),
+    (r"\b(int|void|char|unsigned)\s+\*?\w+\s*\(", 2),
+    (r"\b(malloc|free|sizeof)\(", 3),
+    (r"\bstruct\s+\w+", 1),
+    (r"\bNULL\b", 2),
+    (r"->", 1),
+];
+
+// Checked on top of the C features.
+const CPP: Features = &[
+    (r"\bstd::", 5),
+    (r"^\s*#include\s*<\w+>", 5),
+    (r"\b(cout|cin|endl)\b", 4),
+    (r"\btemplate\s*<", 4),
+    (r"\bnamespace\b", 3),
+    (r"\bnullptr\b", 3),
+    (r"\b(public|private|protected):", 3),
+];
+
+// Languages in order of preference when scores tie.
+const LANGUAGES: &[(&str, &[Features])] = &[
+    ("rust", &[RUST]),
+    ("python", &[PYTHON]),
+    ("javascript", &[JAVASCRIPT]),
+    ("typescript", &[JAVASCRIPT, TYPESCRIPT]),
+    ("sql", &[SQL]),
+    ("shell", &[SHELL]),
+    ("go", &[GO]),
+    ("java", &[JAVA]),
+    ("c", &[C]),
+    ("cpp", &[C, CPP]),
+];
+
+type Model = Vec<(&'static str, Vec<(Regex, usize)>)>;
+
+static MODEL: LazyLock<Model> = LazyLock::new(|| {
+    LANGUAGES
+        .iter()
+        .map(|(name, feature_sets)| {
+            let features = feature_sets
+                .iter()
+                .flat_map(|set| set.iter())
+                .map(|(pattern, weight)| {
+                    (Regex::new(&format!("(?m){}", pattern)).unwrap(), *weight)
+                })
+                .collect();
+            (*name, features)
+        })
+        .collect()
+});
+
+/// Guesses the programming language of a code snippet by weighing how often
+/// each language's characteristic tokens show up. Returns `None` when
+/// nothing stands out.
+pub fn detect_language(code: &str) -> Option<&'static str> {
+    let trimmed = code.trim();
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+    {
+        return Some("json");
+    }
+
+    let mut best: Option<(&'static str, usize)> = None;
+    for (name, features) in MODEL.iter() {
+        let score: usize = features
+            .iter()
+            .map(|(regex, weight)| {
+                regex.find_iter(trimmed).take(MAX_HITS_PER_FEATURE).count() * weight
+            })
+            .sum();
+        if score >= MIN_SCORE && best.is_none_or(|(_, best_score)| score > best_score) {
+            best = Some((name, score));
+        }
+    }
+    best.map(|(name, _)| name)
+}
+
+/// The language to store for an item of the given data type, if any.
+pub fn language_for(data_type: &str, content: &str) -> Option<String> {
+    match data_type {
+        "code" => detect_language(content).map(str::to_string),
+        "json" | "yaml" | "html" | "xml" | "shell" | "markdown" => Some(data_type.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_each_language() {
+        let cases = [
+            (
+                "fn main() {\n    let mut x = Vec::new();\n    println!(\"{}\", x.len());\n}",
+                "rust",
+            ),
+            (
+                "def foo(self, x):\n    if x is None:\n        return True\n    return False\n",
+                "python",
+            ),
+            (
+                "const a = require('x');\nconsole.log(a === undefined);",
+                "javascript",
+            ),
+            (
+                "interface Foo {\n  name: string;\n}\nconst f = (x: number): void => {};",
+                "typescript",
+            ),
+            (
+                "SELECT id, name FROM users u LEFT JOIN orders o ON o.uid = u.id WHERE u.id = 1 ORDER BY name;",
+                "sql",
+            ),
+            (
+                "#!/bin/bash\nfor f in *.txt; do\n  echo $f | grep foo\ndone",
+                "shell",
+            ),
+            (
+                "package main\n\nimport \"fmt\"\n\nfunc main() {\n\tx := 1\n\tfmt.Println(x)\n}",
+                "go",
+            ),
+            (
+                "public class Foo {\n    @Override\n    public String toString() { return \"x\"; }\n    public static void main(String[] args) { System.out.println(1); }\n}",
+                "java",
+            ),
+            (
+                "#include <stdio.h>\nint main(void) {\n    printf(\"hi\");\n    return 0;\n}",
+                "c",
+            ),
+            (
+                "#include <iostream>\nint main() {\n    std::cout << \"hi\" << std::endl;\n}",
+                "cpp",
+            ),
+            ("{\"a\": [1, 2]}", "json"),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(detect_language(code), Some(expected), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn needs_enough_evidence() {
+        assert_eq!(detect_language("hello world"), None);
+        assert_eq!(detect_language("x = 1"), None);
+        // One keyword on its own isn't enough
+        assert_eq!(detect_language("let x = 1"), None);
+    }
+
+    #[test]
+    fn repeated_tokens_only_count_a_few_times() {
+        let echoes = "echo a\n".repeat(10);
+        let rust = format!("{}fn main() {{\n    let mut x = 1;\n}}", echoes);
+        assert_eq!(detect_language(&rust), Some("rust"));
+    }
+
+    #[test]
+    fn structured_types_are_their_own_language() {
+        assert_eq!(language_for("yaml", "a: 1"), Some("yaml".into()));
+        assert_eq!(language_for("json", "{}"), Some("json".into()));
+        assert_eq!(
+            language_for("code", "SELECT * FROM users WHERE id = 1;"),
+            Some("sql".into())
+        );
+        assert_eq!(language_for("url", "https://example.com"), None);
+        assert_eq!(language_for("text", "fn main() { let mut x = 1; }"), None);
+    }
+}
@@ -1,16 +1,39 @@
+mod backup;
+mod blob;
+pub mod classifier;
 mod commands;
-mod crypto;
-mod db;
-mod models;
+pub mod crypto;
+pub mod db;
+#[cfg(target_os = "linux")]
+mod dbus;
+pub mod export;
+mod gc;
+mod http_api;
+pub mod image_store;
+pub mod import;
+mod ipc;
+mod language;
+mod merge;
+pub mod models;
 mod monitor;
 mod ocr;
+mod paste_mode;
+mod paste_stack;
+mod phash;
+mod reclassify;
+mod revisions;
+mod split;
 mod state;
+mod sync;
+mod template;
+mod transforms;
 mod tray;
 mod utils;
 
+use active_win_pos_rs::get_active_window;
 use clipboard_master::Master;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use tauri::tray::TrayIconBuilder;
@@ -18,37 +41,71 @@ use tauri::{Emitter, Manager};
 #[cfg(target_os = "macos")]
 use window_vibrancy::{apply_vibrancy, NSVisualEffectMaterial};
 
+use crate::classifier::Classifier;
 use crate::commands::*;
 use crate::crypto::Crypto;
 use crate::db::Database;
-use crate::models::{AppConfig, ClipboardItem};
+use crate::image_store::ImageStore;
+use crate::models::AppConfig;
 use crate::monitor::ClipboardMonitor;
+use crate::paste_mode::PasteMode;
+use crate::paste_stack::PasteStack;
 use crate::state::AppState;
-use crate::utils::write_to_clipboard;
+pub use crate::utils::apply_size_limits;
 use tauri_plugin_updater::UpdaterExt;
 
+/// Directory holding the history database, config and images. The CLI
+/// reads the same one.
+pub fn app_data_dir() -> PathBuf {
+    std::env::var("HOME")
+        .map(|h| PathBuf::from(h).join(".clipboard-manager"))
+        .unwrap_or_else(|_| PathBuf::from(".clipboard-manager"))
+}
+
+// Items arrived from another device: update the tray and the open windows
+fn refresh_history(app: &tauri::AppHandle) {
+    let history = app
+        .state::<AppState>()
+        .db
+        .get_history(1, 20, None, false, false, None, None, None)
+        .unwrap_or_default();
+    let _ = crate::tray::update_tray_menu(app, &history);
+    let _ = app.emit("clipboard-update", ());
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     // Load config first
-    let app_data_dir = std::env::var("HOME")
-        .map(|h| PathBuf::from(h).join(".clipboard-manager"))
-        .unwrap_or_else(|_| PathBuf::from(".clipboard-manager"));
+    let app_data_dir = app_data_dir();
 
     if !app_data_dir.exists() {
         let _ = fs::create_dir_all(&app_data_dir);
     }
 
+    // 上次恢复的备份要在打开数据库之前放回数据目录
+    let restored = backup::apply_pending_restore(&app_data_dir);
+
     let config_path = app_data_dir.join("config.json");
-    let config = if let Ok(content) = fs::read_to_string(&config_path) {
-        serde_json::from_str::<AppConfig>(&content).unwrap_or_default()
-    } else {
-        AppConfig::default()
-    };
+    let config = AppConfig::load(&config_path);
 
     let db_path = app_data_dir.join("history.db");
     let key_path = app_data_dir.join("secret.key");
     let crypto = Arc::new(Crypto::new(&key_path));
-    let db = Arc::new(Database::new(&db_path, crypto).expect("Failed to initialize database"));
+    let db =
+        Arc::new(Database::new(&db_path, crypto.clone()).expect("Failed to initialize database"));
+    // 备份来自其他目录时，图片记录里的路径要跟着改
+    if let Some(manifest) = restored {
+        let from = Path::new(&manifest.data_dir).join("");
+        let to = app_data_dir.join("");
+        if from != to {
+            if let Err(e) = db.rebase_image_paths(&from.to_string_lossy(), &to.to_string_lossy()) {
+                log::error!("Failed to update restored image paths: {}", e);
+            }
+        }
+    }
+    let image_store = Arc::new(ImageStore::new(app_data_dir.join("images")));
+
+    let classifier = Arc::new(Mutex::new(Classifier::new(&config.custom_classifiers)));
 
     let shortcut_key = config.shortcut.clone();
     let config_arc = Arc::new(Mutex::new(config));
@@ -61,7 +118,9 @@ pub fn run() {
     let last_app_image_change_state = last_app_image_change.clone();
     let last_app_file_change = Arc::new(Mutex::new(None));
     let last_app_file_change_state = last_app_file_change.clone();
-    let paste_stack = Arc::new(Mutex::new(Vec::<ClipboardItem>::new()));
+    let paste_stack = Arc::new(Mutex::new(PasteStack::load(
+        app_data_dir.join("paste_stack.json"),
+    )));
     let paste_stack_state = paste_stack.clone();
 
     tauri::Builder::default()
@@ -73,11 +132,22 @@ pub fn run() {
                     if event.state == tauri_plugin_global_shortcut::ShortcutState::Pressed {
                         // Check Paste Stack
                         let state = app.state::<AppState>();
-                        if let Ok(mut stack) = state.paste_stack.lock() {
-                            if !stack.is_empty() {
-                                let item = stack.remove(0);
-                                let _ = write_to_clipboard(app, &item);
-                                return;
+                        // The shortcut is pressed in the app the user wants to paste into
+                        let target_app = get_active_window().ok().map(|w| w.app_name);
+                        if !state.paste_stack.lock().unwrap().is_empty() {
+                            let mode = PasteMode::resolve(
+                                &state.config.lock().unwrap(),
+                                None,
+                                target_app.as_deref(),
+                            );
+                            match paste_stack::paste_next(app, mode) {
+                                Ok(Some(_)) => return,
+                                // Only deleted items were left, fall through to the popup
+                                Ok(None) => {}
+                                Err(e) => {
+                                    log::error!("Failed to paste from stack: {}", e);
+                                    return;
+                                }
                             }
                         }
 
@@ -138,6 +208,7 @@ pub fn run() {
                                     let _ = window.center();
                                 }
 
+                                *state.paste_target_app.lock().unwrap() = target_app;
                                 let _ = window.show();
                                 let _ = window.set_focus();
                             }
@@ -171,27 +242,64 @@ pub fn run() {
 
             let handle = app.handle().clone();
 
-            // 初始化数据路径
-            let app_data_dir = app.path().app_data_dir()?;
-            if !app_data_dir.exists() {
-                let _ = fs::create_dir_all(&app_data_dir);
-            }
-            let images_dir = app_data_dir.join("images");
-            if !images_dir.exists() {
-                let _ = fs::create_dir_all(&images_dir);
-            }
+            // 旧版本把图片保存在 Tauri 的数据目录下，迁移到图片库并补全缩略图，
+            // 之后清理过期的回收站记录、没有记录引用的文件，并标记文件丢失的记录
+            let legacy_images_dir = app.path().app_data_dir()?.join("images");
+            let migrate_db = db.clone();
+            let trash_retention_days = config_arc.lock().unwrap().trash_retention_days;
+            let migrate_store = image_store.clone();
+            thread::spawn(move || {
+                migrate_store.migrate(&migrate_db, &legacy_images_dir);
+                if let Err(e) =
+                    gc::purge_expired_trash(&migrate_db, &migrate_store, trash_retention_days)
+                {
+                    log::error!("Failed to purge trash: {}", e);
+                }
+                if let Err(e) = gc::collect_garbage(&migrate_db, &migrate_store) {
+                    log::error!("Storage GC failed: {}", e);
+                }
+            });
+
+            // 其他设备同步过来的记录写入后刷新界面和托盘
+            let sync_handle = handle.clone();
+            let sync_engine = sync::SyncEngine::new(
+                &app_data_dir,
+                db.clone(),
+                image_store.clone(),
+                &crypto,
+                config_arc.clone(),
+                move || refresh_history(&sync_handle),
+            )?;
+            let folder_sync_handle = handle.clone();
+            let folder_sync = sync::FolderSync::new(
+                &app_data_dir,
+                db.clone(),
+                image_store.clone(),
+                crypto.clone(),
+                config_arc.clone(),
+                move || refresh_history(&folder_sync_handle),
+            )?;
 
             // 将状态交给 Tauri 管理
             app.manage(AppState {
                 db: db.clone(),
+                crypto: crypto.clone(),
+                image_store: image_store.clone(),
                 config_path: config_path.clone(),
                 config: config_arc.clone(),
+                classifier: classifier.clone(),
+                reclassify_running: Arc::new(Mutex::new(false)),
                 is_paused: is_paused_state.clone(),
                 last_app_change: last_app_change_state.clone(),
                 last_app_image_change: last_app_image_change_state.clone(),
                 last_app_file_change: last_app_file_change_state.clone(),
                 paste_stack: paste_stack_state.clone(),
+                sync: sync_engine.clone(),
+                folder_sync: folder_sync.clone(),
+                last_deleted: Arc::new(Mutex::new(Vec::new())),
+                paste_target_app: Arc::new(Mutex::new(None)),
                 pause_item: Arc::new(Mutex::new(None)),
+                paste_stack_item: Arc::new(Mutex::new(None)),
             });
 
             // 托盘设置
@@ -211,6 +319,21 @@ pub fn run() {
                 }
             }
 
+            // Store paste stack item in state and show the restored count
+            if let Ok(items) = menu.items() {
+                if let Some(item) = items
+                    .iter()
+                    .find(|i| i.id() == "paste_stack")
+                    .and_then(|i| i.as_menuitem())
+                {
+                    let state = app.state::<AppState>();
+                    if let Ok(mut paste_stack_item) = state.paste_stack_item.lock() {
+                        *paste_stack_item = Some(item.clone());
+                    };
+                }
+            }
+            paste_stack::notify_changed(app.handle());
+
             let _tray = TrayIconBuilder::with_id("tray")
                 .icon(
                     app.default_window_icon()
@@ -238,17 +361,32 @@ pub fn run() {
                             let _ = crate::tray::update_pause_menu_item(app, *paused);
                         };
                     }
+                    "paste_stack" => {
+                        let state = app.state::<AppState>();
+                        state.paste_stack.lock().unwrap().clear();
+                        paste_stack::notify_changed(app);
+                    }
                     "clear" => {
                         let state = app.state::<AppState>();
                         let clear_pinned_on_clear =
                             state.config.lock().unwrap().clear_pinned_on_clear;
                         let clear_collected_on_clear =
                             state.config.lock().unwrap().clear_collected_on_clear;
-                        if let Ok(_) = state
+                        // Cleared items go to the trash and can be brought back with undo
+                        if let Ok(items) = state
                             .db
                             .clear_history(clear_pinned_on_clear, clear_collected_on_clear)
                         {
-                            log::info!("History cleared from tray");
+                            *state.last_deleted.lock().unwrap() =
+                                items.iter().filter_map(|item| item.id).collect();
+                            // Pinned or collected items may have been kept
+                            let history = state
+                                .db
+                                .get_history(1, 20, None, false, false, None, None, None)
+                                .unwrap_or_default();
+                            let _ = crate::tray::update_tray_menu(app, &history);
+                            let _ = app.emit("clipboard-update", ());
+                            log::info!("History moved to trash from tray");
                         }
                     }
                     "settings" => {
@@ -284,6 +422,79 @@ pub fn run() {
                 })
                 .build(app)?;
 
+            // 分类器更新后，在后台重新分类旧记录
+            let reclassify_handle = handle.clone();
+            thread::spawn(move || {
+                if let Err(e) = reclassify::reclassify_history(&reclassify_handle, false) {
+                    log::error!("Failed to reclassify history: {}", e);
+                }
+            });
+
+            // 供外部工具使用的本地控制接口
+            if handle.state::<AppState>().config.lock().unwrap().enable_ipc {
+                ipc::start(&handle);
+            }
+            #[cfg(target_os = "linux")]
+            if handle
+                .state::<AppState>()
+                .config
+                .lock()
+                .unwrap()
+                .enable_dbus
+            {
+                dbus::start(&handle);
+            }
+            if handle
+                .state::<AppState>()
+                .config
+                .lock()
+                .unwrap()
+                .http_api_enabled
+            {
+                if let Err(e) = http_api::start(&handle) {
+                    log::error!("Failed to start HTTP API: {}", e);
+                }
+            }
+
+            // 局域网同步
+            if handle
+                .state::<AppState>()
+                .config
+                .lock()
+                .unwrap()
+                .sync_enabled
+            {
+                if let Err(e) = sync_engine.start() {
+                    log::error!("Failed to start sync: {}", e);
+                }
+            }
+            // 共享文件夹同步
+            if handle
+                .state::<AppState>()
+                .config
+                .lock()
+                .unwrap()
+                .folder_sync_enabled
+            {
+                if let Err(e) = folder_sync.start() {
+                    log::error!("Failed to start folder sync: {}", e);
+                }
+            }
+
+            // 定时自动备份
+            let backup_handle = handle.clone();
+            let backup_dir = app_data_dir.clone();
+            thread::spawn(move || loop {
+                let state = backup_handle.state::<AppState>();
+                let config = state.config.lock().unwrap().clone();
+                if let Err(e) =
+                    backup::run_scheduled(&backup_dir, &state.db, &state.crypto, &config)
+                {
+                    log::error!("Automatic backup failed: {}", e);
+                }
+                thread::sleep(backup::SCHEDULE_CHECK_INTERVAL);
+            });
+
             // 剪切板监听线程
             let monitor_handle = handle.clone();
             thread::spawn(move || {
@@ -312,7 +523,29 @@ pub fn run() {
             toggle_sensitive,
             toggle_pin,
             update_clipboard_item_content,
+            get_item_revisions,
+            diff_revisions,
+            revert_item,
             clear_history,
+            undo_last_action,
+            get_trash,
+            restore_from_trash,
+            empty_trash,
+            export_history,
+            import_history,
+            create_backup,
+            restore_backup,
+            set_auto_backup,
+            get_sync_status,
+            set_sync_enabled,
+            start_sync_pairing,
+            pair_sync_device,
+            unpair_sync_device,
+            sync_now,
+            set_item_sync,
+            get_folder_sync_status,
+            set_folder_sync,
+            folder_sync_now,
             get_config,
             save_config,
             set_paused,
@@ -323,7 +556,22 @@ pub fn run() {
             get_collections,
             delete_collection,
             set_item_collection,
-            set_paste_stack,
+            get_paste_stack,
+            paste_stack_push,
+            paste_stack_pop,
+            paste_stack_peek,
+            paste_stack_reorder,
+            paste_stack_clear,
+            set_paste_stack_mode,
+            find_similar_images,
+            run_storage_gc,
+            reclassify_history,
+            get_transforms,
+            paste_transformed,
+            merge_items,
+            split_item,
+            validate_snippet,
+            create_snippet,
             ocr_image
         ])
         .on_window_event(|window, event| match event {
@@ -0,0 +1,168 @@
+use crate::transforms::Content;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MergeOptions {
+    // Also join the HTML versions, using the escaped text for parts without one
+    pub merge_html: bool,
+    // Drop lines that already appeared in an earlier part or line
+    pub dedupe_lines: bool,
+    // Prefix every part with "1. ", "2. ", ...
+    pub numbered: bool,
+}
+
+/// Joins the parts in the given order. Parts left empty by `dedupe_lines`
+/// are dropped. HTML is only produced with `merge_html` and not together
+/// with `dedupe_lines`, since markup can't be deduplicated line by line.
+pub fn merge(parts: Vec<Content>, separator: &str, options: &MergeOptions) -> Content {
+    let mut seen = HashSet::new();
+    let parts: Vec<Content> = parts
+        .into_iter()
+        .filter_map(|part| {
+            if !options.dedupe_lines {
+                return Some(part);
+            }
+            let lines: Vec<&str> = part
+                .text
+                .lines()
+                .filter(|line| seen.insert(line.to_string()))
+                .collect();
+            if lines.is_empty() {
+                return None;
+            }
+            Some(Content {
+                text: lines.join("\n"),
+                html: None,
+            })
+        })
+        .collect();
+
+    let texts: Vec<String> = parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            if options.numbered {
+                number(i + 1, &part.text)
+            } else {
+                part.text.clone()
+            }
+        })
+        .collect();
+
+    let wants_html =
+        options.merge_html && !options.dedupe_lines && parts.iter().any(|part| part.html.is_some());
+    let html = wants_html.then(|| {
+        let fragments: Vec<String> = parts
+            .iter()
+            .map(|part| {
+                part.html
+                    .clone()
+                    .unwrap_or_else(|| escape_html(&part.text).replace('\n', "<br>"))
+            })
+            .collect();
+        if options.numbered {
+            let items: String = fragments
+                .iter()
+                .map(|fragment| format!("<li>{}</li>", fragment))
+                .collect();
+            format!("<ol>{}</ol>", items)
+        } else {
+            fragments.join(&escape_html(separator).replace('\n', "<br>"))
+        }
+    });
+
+    Content {
+        text: texts.join(separator),
+        html,
+    }
+}
+
+// Numbers a part, indenting its following lines under the first one.
+fn number(n: usize, text: &str) -> String {
+    let prefix = format!("{}. ", n);
+    let indent = " ".repeat(prefix.len());
+    let mut result = prefix;
+    for (i, line) in text.lines().enumerate() {
+        if i > 0 {
+            result.push('\n');
+            result.push_str(&indent);
+        }
+        result.push_str(line);
+    }
+    result
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(text: &str, html: Option<&str>) -> Content {
+        Content {
+            text: text.into(),
+            html: html.map(String::from),
+        }
+    }
+
+    fn options(merge_html: bool, dedupe_lines: bool, numbered: bool) -> MergeOptions {
+        MergeOptions {
+            merge_html,
+            dedupe_lines,
+            numbered,
+        }
+    }
+
+    #[test]
+    fn joins_with_the_separator() {
+        let parts = || vec![part("a", None), part("b", None), part("c", None)];
+        let plain = MergeOptions::default();
+        assert_eq!(merge(parts(), "\n", &plain).text, "a\nb\nc");
+        assert_eq!(merge(parts(), ", ", &plain).text, "a, b, c");
+        assert_eq!(merge(parts(), "", &plain).text, "abc");
+        assert_eq!(merge(parts(), "\n", &plain).html, None);
+    }
+
+    #[test]
+    fn dedupes_lines_across_parts() {
+        let merged = merge(
+            vec![part("a\nb", None), part("b\nc", None), part("a", None)],
+            "\n---\n",
+            &options(false, true, false),
+        );
+        // The last part has nothing new and is dropped
+        assert_eq!(merged.text, "a\nb\n---\nc");
+    }
+
+    #[test]
+    fn numbers_parts_and_indents_their_lines() {
+        let parts = vec![part("x\ny", None), part("z", None)];
+        let merged = merge(parts, "\n", &options(false, false, true));
+        assert_eq!(merged.text, "1. x\n   y\n2. z");
+    }
+
+    #[test]
+    fn merges_html_when_asked() {
+        let parts = || vec![part("a<b", None), part("bold", Some("<b>bold</b>"))];
+        let merged = merge(parts(), "\n", &options(true, false, false));
+        assert_eq!(merged.html.as_deref(), Some("a&lt;b<br><b>bold</b>"));
+
+        let numbered = merge(parts(), "\n", &options(true, false, true));
+        assert_eq!(
+            numbered.html.as_deref(),
+            Some("<ol><li>a&lt;b</li><li><b>bold</b></li></ol>")
+        );
+
+        // Markup can't be deduplicated, and plain parts need no HTML
+        assert_eq!(merge(parts(), "\n", &options(true, true, false)).html, None);
+        let plain = vec![part("a", None), part("b", None)];
+        assert_eq!(merge(plain, "\n", &options(true, false, false)).html, None);
+    }
+}
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ClipboardItem {
     pub id: Option<i64>,
     pub content: String, // 文字内容或图片的Base64
@@ -20,12 +23,91 @@ pub struct ClipboardItem {
     pub note: Option<String>,
     #[serde(default)]
     pub html_content: Option<String>,
+    // 内容过大时 content 只是预览，这里记录完整内容的字节数
+    #[serde(default)]
+    pub content_length: Option<i64>,
+    // 完整内容所在的 blob 文件名，仅在后端使用
+    #[serde(skip)]
+    pub blob_path: Option<String>,
+    // 插入时是否把完整内容写入 blob
+    #[serde(skip)]
+    pub store_as_blob: bool,
+    // 图片的缩略图路径及尺寸、文件大小
+    #[serde(default)]
+    pub thumbnail: Option<String>,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub byte_size: Option<i64>,
+    // 图片的感知哈希 (dHash)，仅在后端使用
+    #[serde(skip)]
+    pub image_hash: Option<i64>,
+    // 图片像素的 SHA-256，用于判断是否完全相同，仅在后端使用
+    #[serde(skip)]
+    pub image_digest: Option<String>,
+    // 图片或 blob 文件已丢失 (由存储清理检查时标记)
+    #[serde(default)]
+    pub file_missing: bool,
+    // 代码片段的编程语言，如 "rust"、"sql"
+    #[serde(default)]
+    pub language: Option<String>,
+    // 用户手动指定了类型，重新分类时保留
+    #[serde(default)]
+    pub data_type_manual: bool,
+    // 合并或拆分而来的记录，指向原始记录的 id
+    #[serde(default)]
+    pub source_ids: Option<Vec<i64>>,
+    // 移入回收站的时间，未删除时为空
+    #[serde(default)]
+    pub deleted_at: Option<String>,
+    // 不同步到其他设备
+    #[serde(default)]
+    pub no_sync: bool,
 }
 
 fn default_data_type() -> String {
     "text".to_string()
 }
 
+// 记录被编辑前的版本
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    pub id: i64,
+    pub item_id: i64,
+    pub content: String,
+    pub html_content: Option<String>,
+    pub data_type: String,
+    pub note: Option<String>,
+    pub created_at: String,
+}
+
+// 在设备之间同步的一条记录。置顶、备注和收藏夹各自记录最后修改的时间 (毫秒)，
+// 冲突时以较新的为准
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncRecord {
+    pub sync_id: String,
+    pub change_seq: i64,
+    pub kind: String,
+    // 文字内容；图片在本机是文件路径，发送时改用 data
+    pub content: String,
+    #[serde(default)]
+    pub data: Option<String>,
+    pub html_content: Option<String>,
+    pub timestamp: String,
+    pub source_app: Option<String>,
+    pub data_type: String,
+    pub language: Option<String>,
+    pub is_pinned: bool,
+    pub pin_changed_at: i64,
+    pub note: Option<String>,
+    pub note_changed_at: i64,
+    // 收藏夹按名称同步
+    pub collection: Option<String>,
+    pub collection_changed_at: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Collection {
     pub id: i64,
@@ -51,6 +133,95 @@ pub struct AppConfig {
     // 清空历史时是否删除收藏的内容
     #[serde(default)]
     pub clear_collected_on_clear: bool,
+    // 剪切板连续变化时等待其稳定的时间（毫秒），0 表示立即记录
+    #[serde(default = "default_capture_settle_ms")]
+    pub capture_settle_ms: u64,
+    // 各类内容的大小上限（字节），0 表示不限制
+    #[serde(default = "default_max_text_size")]
+    pub max_text_size: usize,
+    #[serde(default = "default_max_html_size")]
+    pub max_html_size: usize,
+    #[serde(default = "default_max_image_size")]
+    pub max_image_size: usize,
+    // 超出上限时的处理方式: "skip", "truncate" 或 "blob"
+    #[serde(default = "default_oversize_action")]
+    pub oversize_action: String,
+    // 图片保存格式: "png" 或 "webp"
+    #[serde(default = "default_image_format")]
+    pub image_format: String,
+    // 尺寸相同且感知哈希相差不超过该位数的图片视为同一张，为 0 时只合并完全相同的图片
+    #[serde(default = "default_image_dedup_distance")]
+    pub image_dedup_distance: u32,
+    // 用户自定义的正则分类器，命中时使用其名称作为内容类型
+    #[serde(default)]
+    pub custom_classifiers: Vec<CustomClassifier>,
+    // 默认粘贴方式: "rich", "plain", "html" 或 "markdown"
+    #[serde(default = "default_paste_mode")]
+    pub default_paste_mode: String,
+    // 按目标应用指定粘贴方式 (应用名 -> 粘贴方式)，终端和代码编辑器默认纯文本
+    #[serde(default = "default_paste_mode_by_app")]
+    pub paste_mode_by_app: HashMap<String, String>,
+    // 回收站中的记录保留天数，0 表示不自动清理
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u32,
+    // 是否开启本地控制接口 (Unix 套接字或 Windows 命名管道)，默认关闭
+    #[serde(default)]
+    pub enable_ipc: bool,
+    // Linux 下是否注册 D-Bus 服务，默认关闭
+    #[serde(default)]
+    pub enable_dbus: bool,
+    // 是否开启仅监听 127.0.0.1 的 HTTP API，默认关闭
+    #[serde(default)]
+    pub http_api_enabled: bool,
+    #[serde(default = "default_http_api_port")]
+    pub http_api_port: u16,
+    // HTTP API 的访问令牌，首次开启时自动生成
+    #[serde(default)]
+    pub http_api_token: String,
+    // 是否定时自动备份
+    #[serde(default)]
+    pub auto_backup_enabled: bool,
+    // 自动备份的间隔 (小时)
+    #[serde(default = "default_auto_backup_interval_hours")]
+    pub auto_backup_interval_hours: u32,
+    // 保留的自动备份数量，更早的会被删除
+    #[serde(default = "default_auto_backup_keep")]
+    pub auto_backup_keep: usize,
+    // 自动备份的保存目录，为空时使用数据目录下的 backups
+    #[serde(default)]
+    pub auto_backup_dir: String,
+    // 自动备份的密码，用 secret.key 加密后保存
+    #[serde(default)]
+    pub auto_backup_passphrase: String,
+    // 是否与局域网内配对的设备同步，默认关闭
+    #[serde(default)]
+    pub sync_enabled: bool,
+    #[serde(default = "default_sync_port")]
+    pub sync_port: u16,
+    // 向其他设备显示的名称，为空时使用主机名
+    #[serde(default)]
+    pub sync_device_name: String,
+    // 通过共享文件夹（Syncthing、Nextcloud 等）同步，默认关闭
+    #[serde(default)]
+    pub folder_sync_enabled: bool,
+    #[serde(default)]
+    pub folder_sync_dir: String,
+    // 加密变更日志的密码，所有设备相同，用 secret.key 加密后保存
+    #[serde(default)]
+    pub folder_sync_passphrase: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomClassifier {
+    pub name: String,
+    pub pattern: String,
+    // 置信度高于内置分类器时优先使用
+    #[serde(default = "default_classifier_confidence")]
+    pub confidence: f32,
+}
+
+fn default_classifier_confidence() -> f32 {
+    1.0
 }
 
 fn default_language() -> String {
@@ -61,6 +232,98 @@ fn default_theme() -> String {
     "auto".to_string()
 }
 
+fn default_capture_settle_ms() -> u64 {
+    150
+}
+
+fn default_max_text_size() -> usize {
+    1024 * 1024
+}
+
+fn default_max_html_size() -> usize {
+    1024 * 1024
+}
+
+fn default_max_image_size() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_oversize_action() -> String {
+    "blob".to_string()
+}
+
+fn default_image_format() -> String {
+    "png".to_string()
+}
+
+fn default_image_dedup_distance() -> u32 {
+    0
+}
+
+fn default_paste_mode() -> String {
+    "rich".to_string()
+}
+
+fn default_paste_mode_by_app() -> HashMap<String, String> {
+    [
+        // Terminals
+        "Terminal",
+        "iTerm2",
+        "Alacritty",
+        "kitty",
+        "WezTerm",
+        "Warp",
+        "Ghostty",
+        "Windows Terminal",
+        "WindowsTerminal",
+        "Command Prompt",
+        "PowerShell",
+        "Konsole",
+        "gnome-terminal",
+        "Tilix",
+        "xterm",
+        // Code editors
+        "Code",
+        "Cursor",
+        "Zed",
+        "Sublime Text",
+        "Xcode",
+        "IntelliJ IDEA",
+        "PyCharm",
+        "WebStorm",
+        "CLion",
+        "GoLand",
+        "RustRover",
+        "Android Studio",
+        "Neovide",
+        "Emacs",
+        "Notepad++",
+    ]
+    .into_iter()
+    .map(|app| (app.to_string(), "plain".to_string()))
+    .collect()
+}
+
+fn default_trash_retention_days() -> u32 {
+    30
+}
+
+fn default_http_api_port() -> u16 {
+    27489
+}
+
+fn default_sync_port() -> u16 {
+    27490
+}
+
+fn default_auto_backup_interval_hours() -> u32 {
+    24
+}
+
+fn default_auto_backup_keep() -> usize {
+    7
+}
+
 fn default_sensitive_apps() -> Vec<String> {
     vec![
         "1Password".to_string(),
@@ -73,6 +336,22 @@ fn default_sensitive_apps() -> Vec<String> {
     ]
 }
 
+impl AppConfig {
+    /// Reads the config file, falling back to the defaults when it is missing
+    /// or can't be parsed.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)
+    }
+}
+
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
@@ -84,6 +363,33 @@ impl Default for AppConfig {
             compact_mode: false,
             clear_pinned_on_clear: false,
             clear_collected_on_clear: false,
+            capture_settle_ms: default_capture_settle_ms(),
+            max_text_size: default_max_text_size(),
+            max_html_size: default_max_html_size(),
+            max_image_size: default_max_image_size(),
+            oversize_action: default_oversize_action(),
+            image_format: default_image_format(),
+            image_dedup_distance: default_image_dedup_distance(),
+            custom_classifiers: Vec::new(),
+            default_paste_mode: default_paste_mode(),
+            paste_mode_by_app: default_paste_mode_by_app(),
+            trash_retention_days: default_trash_retention_days(),
+            enable_ipc: false,
+            enable_dbus: false,
+            http_api_enabled: false,
+            http_api_port: default_http_api_port(),
+            http_api_token: String::new(),
+            auto_backup_enabled: false,
+            auto_backup_interval_hours: default_auto_backup_interval_hours(),
+            auto_backup_keep: default_auto_backup_keep(),
+            auto_backup_dir: String::new(),
+            auto_backup_passphrase: String::new(),
+            sync_enabled: false,
+            sync_port: default_sync_port(),
+            sync_device_name: String::new(),
+            folder_sync_enabled: false,
+            folder_sync_dir: String::new(),
+            folder_sync_passphrase: String::new(),
         }
     }
 }
@@ -2,19 +2,56 @@ use active_win_pos_rs::get_active_window;
 use chrono::Local;
 use clipboard_master::{CallbackResult, ClipboardHandler};
 use clipboard_rs::{Clipboard, ClipboardContext};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
 use tauri::{Emitter, Manager};
 use tauri_plugin_clipboard_manager::ClipboardExt;
 
 use crate::models::ClipboardItem;
+use crate::phash::{dhash, digest};
 use crate::state::AppState;
 use crate::tray::update_tray_menu;
-use crate::utils::classify_content;
+use crate::utils::apply_size_limits;
 
+// Upper bound for a single burst, so a program rewriting the clipboard in a
+// tight loop still gets captured periodically instead of never settling.
+const MAX_BURST_DURATION: Duration = Duration::from_secs(2);
+
+/// The clipboard contents observed when the OS reported a change.
+enum Snapshot {
+    Files(Vec<String>),
+    Text {
+        text: String,
+        html: Option<String>,
+    },
+    Image {
+        width: u32,
+        height: u32,
+        rgba: Vec<u8>,
+    },
+}
+
+impl Snapshot {
+    fn kind(&self) -> &'static str {
+        match self {
+            Snapshot::Files(_) => "file",
+            Snapshot::Text { .. } => "text",
+            Snapshot::Image { .. } => "image",
+        }
+    }
+}
+
+struct PendingCapture {
+    snapshot: Snapshot,
+    source_app: Option<String>,
+}
+
+/// Receives change notifications from `clipboard_master` and forwards what
+/// was on the clipboard to the capture worker, which coalesces bursts.
 pub struct ClipboardMonitor {
     pub app_handle: tauri::AppHandle,
-    pub last_text: String,
-    pub last_image_hash: Vec<u8>,
-    pub last_files: Vec<String>,
+    sender: Sender<PendingCapture>,
 }
 
 impl ClipboardMonitor {
@@ -23,12 +60,17 @@ impl ClipboardMonitor {
         if let Ok(text) = app_handle.clipboard().read_text() {
             last_text = text;
         }
-        Self {
-            app_handle,
+
+        let (sender, receiver) = mpsc::channel();
+        let worker = CaptureWorker {
+            app_handle: app_handle.clone(),
             last_text,
-            last_image_hash: Vec::new(),
+            last_image_digest: None,
             last_files: Vec::new(),
-        }
+        };
+        thread::spawn(move || worker.run(receiver));
+
+        Self { app_handle, sender }
     }
 
     fn is_password_manager(&self, app_name: &str) -> bool {
@@ -39,6 +81,42 @@ impl ClipboardMonitor {
             .iter()
             .any(|app| app_name.contains(app) || app_name.eq_ignore_ascii_case(app))
     }
+
+    fn read_snapshots(&self) -> Vec<Snapshot> {
+        let mut snapshots = Vec::new();
+
+        if let Ok(ctx) = ClipboardContext::new() {
+            if let Ok(files) = ctx.get_files() {
+                if !files.is_empty() {
+                    snapshots.push(Snapshot::Files(files));
+                    return snapshots;
+                }
+            }
+        }
+
+        if let Ok(text) = self.app_handle.clipboard().read_text() {
+            if !text.is_empty() {
+                let html = if let Ok(ctx) = ClipboardContext::new() {
+                    ctx.get_html().ok()
+                } else {
+                    None
+                };
+                snapshots.push(Snapshot::Text { text, html });
+            }
+        }
+
+        if let Ok(img) = self.app_handle.clipboard().read_image() {
+            if !img.rgba().is_empty() {
+                snapshots.push(Snapshot::Image {
+                    width: img.width(),
+                    height: img.height(),
+                    rgba: img.rgba().to_vec(),
+                });
+            }
+        }
+
+        snapshots
+    }
 }
 
 impl ClipboardHandler for ClipboardMonitor {
@@ -68,213 +146,90 @@ impl ClipboardHandler for ClipboardMonitor {
             log::warn!("Failed to get active window");
         }
 
-        let mut updated = false;
-        let max_size = state.config.lock().unwrap().max_history_size;
+        // The clipboard is read right away so intermediate states of a burst
+        // are seen before the next write replaces them.
+        for snapshot in self.read_snapshots() {
+            let pending = PendingCapture {
+                snapshot,
+                source_app: source_app.clone(),
+            };
+            if self.sender.send(pending).is_err() {
+                log::error!("Capture worker is gone, dropping clipboard change");
+            }
+        }
+
+        CallbackResult::Next
+    }
 
-        let mut captured_something = false;
+    fn on_clipboard_error(&mut self, error: std::io::Error) -> CallbackResult {
+        log::error!("Clipboard listener error: {}", error);
+        CallbackResult::Next
+    }
+}
 
-        // Check files
-        if let Ok(ctx) = ClipboardContext::new() {
-            if let Ok(files) = ctx.get_files() {
-                if !files.is_empty() {
-                    // Check if this change was initiated by the app itself
-                    if let Ok(mut last_app_file_change) = state.last_app_file_change.lock() {
-                        if let Some(last_files) = last_app_file_change.as_ref() {
-                            if last_files == &files {
-                                log::info!("Ignoring clipboard file change initiated by app");
-                                self.last_files = files;
-                                *last_app_file_change = None;
-                                return CallbackResult::Next;
-                            }
-                        }
-                    }
+/// Waits for the clipboard to settle and stores the final state of a burst.
+struct CaptureWorker {
+    app_handle: tauri::AppHandle,
+    last_text: String,
+    last_image_digest: Option<String>,
+    last_files: Vec<String>,
+}
 
-                    if files != self.last_files {
-                        self.last_files = files.clone();
-                        self.last_text = String::new();
-                        self.last_image_hash = Vec::new();
-
-                        let content = serde_json::to_string(&files).unwrap_or_default();
-
-                        let item = ClipboardItem {
-                            id: None,
-                            content,
-                            kind: "file".to_string(),
-                            timestamp: Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
-                            is_sensitive: false,
-                            is_pinned: false,
-                            source_app: source_app.clone(),
-                            data_type: "file-list".to_string(),
-                            collection_id: None,
-                            note: None,
-                            html_content: None,
-                        };
-
-                        match state.db.insert_item(&item, max_size) {
-                            Ok(pruned_items) => {
-                                for pruned in pruned_items {
-                                    if pruned.kind == "image" {
-                                        let path = std::path::Path::new(&pruned.content);
-                                        if path.exists() {
-                                            let _ = std::fs::remove_file(path);
-                                        }
-                                    }
-                                }
-                                updated = true;
-                                log::info!("New files captured");
-                            }
-                            Err(e) => {
-                                log::error!("Failed to insert file item: {}", e);
-                            }
-                        }
-                    }
-                    captured_something = true;
-                }
-            }
-        }
+impl CaptureWorker {
+    fn run(mut self, receiver: Receiver<PendingCapture>) {
+        while let Ok(first) = receiver.recv() {
+            let settle = {
+                let state = self.app_handle.state::<AppState>();
+                let settle_ms = state.config.lock().unwrap().capture_settle_ms;
+                Duration::from_millis(settle_ms)
+            };
 
-        // Check text
-        if !captured_something {
-            if let Ok(text) = self.app_handle.clipboard().read_text() {
-                // Check if this change was initiated by the app itself
-                if let Ok(mut last_app_change) = state.last_app_change.lock() {
-                    if let Some(last_content) = last_app_change.as_ref() {
-                        if last_content == &text {
-                            log::info!("Ignoring clipboard change initiated by app");
-                            self.last_text = text;
-                            *last_app_change = None;
-                            return CallbackResult::Next;
-                        }
-                    }
-                }
+            let started = Instant::now();
+            let mut burst = Vec::new();
+            coalesce(&mut burst, first);
 
-                if text != self.last_text && !text.is_empty() {
-                    self.last_text = text.clone();
-                    let is_sensitive = false;
-                    let data_type = classify_content(&text);
-
-                    let html_content = if let Ok(ctx) = ClipboardContext::new() {
-                        ctx.get_html().ok()
-                    } else {
-                        None
-                    };
-
-                    let item = ClipboardItem {
-                        id: None,
-                        content: text,
-                        kind: "text".to_string(),
-                        timestamp: Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
-                        is_sensitive,
-                        is_pinned: false,
-                        source_app: source_app.clone(),
-                        data_type,
-                        collection_id: None,
-                        note: None,
-                        html_content,
-                    };
-
-                    match state.db.insert_item(&item, max_size) {
-                        Ok(pruned_items) => {
-                            // Delete pruned images
-                            for pruned in pruned_items {
-                                if pruned.kind == "image" {
-                                    let path = std::path::Path::new(&pruned.content);
-                                    if path.exists() {
-                                        let _ = std::fs::remove_file(path);
-                                    }
-                                }
-                            }
-                            updated = true;
-                            if is_sensitive {
-                                log::info!("New sensitive text captured");
-                            } else {
-                                log::info!("New text captured");
-                            }
-                        }
-                        Err(e) => {
-                            log::error!("Failed to insert text item: {}", e);
-                        }
+            let mut disconnected = false;
+            while started.elapsed() < MAX_BURST_DURATION {
+                match receiver.recv_timeout(settle) {
+                    Ok(next) => coalesce(&mut burst, next),
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        disconnected = true;
+                        break;
                     }
                 }
             }
-        }
 
-        // Check image
-        if !captured_something {
-            if let Ok(img) = self.app_handle.clipboard().read_image() {
-                let rgba = img.rgba();
-
-                // Check if this change was initiated by the app itself
-                if let Ok(mut last_app_image_change) = state.last_app_image_change.lock() {
-                    if let Some(last_content) = last_app_image_change.as_ref() {
-                        if last_content == rgba {
-                            log::info!("Ignoring clipboard image change initiated by app");
-                            self.last_image_hash = rgba.to_vec();
-                            *last_app_image_change = None;
-                            return CallbackResult::Next;
-                        }
-                    }
-                }
+            if burst.len() > 1 {
+                log::info!("Coalesced clipboard burst into {} captures", burst.len());
+            }
+            self.commit(burst);
 
-                if !rgba.is_empty()
-                    && (rgba.len() != self.last_image_hash.len()
-                        || rgba != self.last_image_hash.as_slice())
-                {
-                    self.last_image_hash = rgba.to_vec();
-
-                    let width = img.width();
-                    let height = img.height();
-                    if let Some(buffer) = image::RgbaImage::from_raw(width, height, rgba.to_vec()) {
-                        let timestamp = Local::now().timestamp_nanos_opt().unwrap_or(0);
-                        let filename = format!("{}.png", timestamp);
-                        let app_data_dir = self.app_handle.path().app_data_dir().unwrap();
-                        let image_path = app_data_dir.join("images").join(&filename);
-
-                        if let Err(e) = buffer.save(&image_path) {
-                            log::error!("Failed to save image to disk: {}", e);
-                        } else {
-                            let item = ClipboardItem {
-                                id: None,
-                                content: image_path.to_string_lossy().to_string(),
-                                kind: "image".to_string(),
-                                timestamp: Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
-                                is_sensitive: false,
-                                is_pinned: false,
-                                source_app: source_app.clone(),
-                                data_type: "image".to_string(),
-                                collection_id: None,
-                                note: None,
-                                html_content: None,
-                            };
-
-                            match state.db.insert_item(&item, max_size) {
-                                Ok(pruned_items) => {
-                                    // Delete pruned images
-                                    for pruned in pruned_items {
-                                        if pruned.kind == "image" {
-                                            let path = std::path::Path::new(&pruned.content);
-                                            if path.exists() {
-                                                let _ = std::fs::remove_file(path);
-                                            }
-                                        }
-                                    }
-                                    updated = true;
-                                    log::info!("New image captured and saved to {:?}", image_path);
-                                }
-                                Err(e) => {
-                                    log::error!("Failed to insert image item: {}", e);
-                                }
-                            }
-                        }
-                    }
-                }
+            if disconnected {
+                break;
             }
         }
+    }
+
+    fn commit(&mut self, burst: Vec<PendingCapture>) {
+        let mut updated = false;
+        for pending in burst {
+            updated |= match pending.snapshot {
+                Snapshot::Files(files) => self.capture_files(files, pending.source_app),
+                Snapshot::Text { text, html } => self.capture_text(text, html, pending.source_app),
+                Snapshot::Image {
+                    width,
+                    height,
+                    rgba,
+                } => self.capture_image(width, height, rgba, pending.source_app),
+            };
+        }
 
         if updated {
+            let state = self.app_handle.state::<AppState>();
             let history = state
                 .db
-                .get_history(1, 20, None, false, false, None)
+                .get_history(1, 20, None, false, false, None, None, None)
                 .unwrap_or_default();
             if let Err(e) = update_tray_menu(&self.app_handle, &history) {
                 log::error!("Failed to update tray: {}", e);
@@ -284,12 +239,314 @@ impl ClipboardHandler for ClipboardMonitor {
                 log::error!("Failed to emit clipboard-update event: {}", e);
             }
         }
+    }
 
-        CallbackResult::Next
+    fn insert(&self, item: &ClipboardItem) -> bool {
+        let state = self.app_handle.state::<AppState>();
+        let max_size = state.config.lock().unwrap().max_history_size;
+        match state.db.insert_item(item, max_size) {
+            Ok((_, pruned_items)) => {
+                // Delete pruned images
+                state.image_store.remove_all(&pruned_items);
+                true
+            }
+            Err(e) => {
+                log::error!("Failed to insert {} item: {}", item.kind, e);
+                false
+            }
+        }
     }
 
-    fn on_clipboard_error(&mut self, error: std::io::Error) -> CallbackResult {
-        log::error!("Clipboard listener error: {}", error);
-        CallbackResult::Next
+    fn capture_files(&mut self, files: Vec<String>, source_app: Option<String>) -> bool {
+        let state = self.app_handle.state::<AppState>();
+
+        // Check if this change was initiated by the app itself
+        if let Ok(mut last_app_file_change) = state.last_app_file_change.lock() {
+            if let Some(last_files) = last_app_file_change.as_ref() {
+                if last_files == &files {
+                    log::info!("Ignoring clipboard file change initiated by app");
+                    self.last_files = files;
+                    *last_app_file_change = None;
+                    return false;
+                }
+            }
+        }
+
+        if files == self.last_files {
+            return false;
+        }
+        self.last_files = files.clone();
+        self.last_text = String::new();
+        self.last_image_digest = None;
+
+        let item = ClipboardItem {
+            id: None,
+            content: serde_json::to_string(&files).unwrap_or_default(),
+            kind: "file".to_string(),
+            timestamp: Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+            is_sensitive: false,
+            is_pinned: false,
+            source_app,
+            data_type: "file-list".to_string(),
+            collection_id: None,
+            note: None,
+            html_content: None,
+            ..Default::default()
+        };
+
+        let inserted = self.insert(&item);
+        if inserted {
+            log::info!("New files captured");
+        }
+        inserted
+    }
+
+    fn capture_text(
+        &mut self,
+        text: String,
+        html_content: Option<String>,
+        source_app: Option<String>,
+    ) -> bool {
+        let state = self.app_handle.state::<AppState>();
+
+        // Check if this change was initiated by the app itself
+        if let Ok(mut last_app_change) = state.last_app_change.lock() {
+            if let Some(last_content) = last_app_change.as_ref() {
+                if last_content == &text {
+                    log::info!("Ignoring clipboard change initiated by app");
+                    self.last_text = text;
+                    *last_app_change = None;
+                    return false;
+                }
+            }
+        }
+
+        if text == self.last_text {
+            return false;
+        }
+        self.last_text = text.clone();
+
+        let classification = state.classifier.lock().unwrap().classify(&text);
+        let item = ClipboardItem {
+            id: None,
+            data_type: classification.data_type,
+            language: classification.language,
+            content: text,
+            kind: "text".to_string(),
+            timestamp: Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+            is_sensitive: false,
+            is_pinned: false,
+            source_app,
+            collection_id: None,
+            note: None,
+            html_content,
+            ..Default::default()
+        };
+
+        let config = state.config.lock().unwrap().clone();
+        let Some(item) = apply_size_limits(item, &config) else {
+            return false;
+        };
+
+        let inserted = self.insert(&item);
+        if inserted {
+            log::info!("New text captured");
+        }
+        inserted
+    }
+
+    fn capture_image(
+        &mut self,
+        width: u32,
+        height: u32,
+        rgba: Vec<u8>,
+        source_app: Option<String>,
+    ) -> bool {
+        let state = self.app_handle.state::<AppState>();
+
+        let max_image_size = state.config.lock().unwrap().max_image_size;
+        if max_image_size > 0 && rgba.len() > max_image_size {
+            log::info!("Skipping image of {} bytes over the size limit", rgba.len());
+            return false;
+        }
+
+        let Some(buffer) = image::RgbaImage::from_raw(width, height, rgba) else {
+            return false;
+        };
+        let image_digest = digest(&buffer);
+
+        // Check if this change was initiated by the app itself
+        if let Ok(mut last_app_image_change) = state.last_app_image_change.lock() {
+            if last_app_image_change.as_ref() == Some(&image_digest) {
+                log::info!("Ignoring clipboard image change initiated by app");
+                self.last_image_digest = Some(image_digest);
+                *last_app_image_change = None;
+                return false;
+            }
+        }
+
+        if self.last_image_digest.as_ref() == Some(&image_digest) {
+            return false;
+        }
+        self.last_image_digest = Some(image_digest.clone());
+
+        // Copying an image that is already stored just moves it to the top,
+        // as does one that looks alike when near duplicates are merged
+        let hash = dhash(&buffer);
+        let dedup_distance = state.config.lock().unwrap().image_dedup_distance;
+        let duplicate = match state.db.find_image(&image_digest) {
+            Ok(None) => state
+                .db
+                .find_duplicate_image(hash, dedup_distance, width, height),
+            found => found,
+        };
+        match duplicate {
+            Ok(Some(id)) => {
+                if let Err(e) = state.db.update_timestamp(id) {
+                    log::error!("Failed to update timestamp of image {}: {}", id, e);
+                    return false;
+                }
+                log::info!("Image matches stored item {}, moved it to the top", id);
+                return true;
+            }
+            Ok(None) => {}
+            Err(e) => log::error!("Failed to look up similar images: {}", e),
+        }
+
+        let image_format = state.config.lock().unwrap().image_format.clone();
+        let stored = match state.image_store.save(&buffer, &image_format) {
+            Ok(stored) => stored,
+            Err(e) => {
+                log::error!("Failed to save image to disk: {}", e);
+                return false;
+            }
+        };
+        let image_path = stored.path.clone();
+
+        let item = ClipboardItem {
+            id: None,
+            content: stored.path,
+            kind: "image".to_string(),
+            timestamp: Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+            is_sensitive: false,
+            is_pinned: false,
+            source_app,
+            data_type: "image".to_string(),
+            collection_id: None,
+            note: None,
+            html_content: None,
+            thumbnail: Some(stored.thumbnail_path),
+            width: Some(stored.width),
+            height: Some(stored.height),
+            byte_size: Some(stored.byte_size),
+            image_hash: Some(hash as i64),
+            image_digest: Some(image_digest),
+            ..Default::default()
+        };
+
+        let inserted = self.insert(&item);
+        if inserted {
+            log::info!("New image captured and saved to {:?}", image_path);
+        }
+        inserted
+    }
+}
+
+/// Adds a change to the current burst. Only the latest state of each kind is
+/// kept, so text rewritten with HTML a moment later is stored once, while a
+/// text copy followed by an image keeps both.
+fn coalesce(burst: &mut Vec<PendingCapture>, next: PendingCapture) {
+    burst.retain(|pending| pending.snapshot.kind() != next.snapshot.kind());
+    burst.push(next);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> PendingCapture {
+        PendingCapture {
+            snapshot: Snapshot::Text {
+                text: text.into(),
+                html: None,
+            },
+            source_app: None,
+        }
+    }
+
+    fn image() -> PendingCapture {
+        PendingCapture {
+            snapshot: Snapshot::Image {
+                width: 1,
+                height: 1,
+                rgba: vec![0; 4],
+            },
+            source_app: None,
+        }
+    }
+
+    fn texts(burst: &[PendingCapture]) -> Vec<&str> {
+        burst
+            .iter()
+            .filter_map(|pending| match &pending.snapshot {
+                Snapshot::Text { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_latest_text() {
+        let mut burst = Vec::new();
+        coalesce(&mut burst, text("one"));
+        coalesce(&mut burst, text("two"));
+        coalesce(&mut burst, text("three"));
+        assert_eq!(burst.len(), 1);
+        assert_eq!(texts(&burst), ["three"]);
+    }
+
+    #[test]
+    fn keeps_one_capture_per_kind() {
+        let mut burst = Vec::new();
+        coalesce(&mut burst, text("one"));
+        coalesce(&mut burst, image());
+        coalesce(&mut burst, text("two"));
+        coalesce(&mut burst, image());
+        coalesce(
+            &mut burst,
+            PendingCapture {
+                snapshot: Snapshot::Files(vec!["/tmp/a".into()]),
+                source_app: Some("Files".into()),
+            },
+        );
+
+        let kinds: Vec<&str> = burst
+            .iter()
+            .map(|pending| pending.snapshot.kind())
+            .collect();
+        // In the order each kind was last seen
+        assert_eq!(kinds, ["text", "image", "file"]);
+        assert_eq!(texts(&burst), ["two"]);
+    }
+
+    #[test]
+    fn html_rewrite_replaces_the_plain_text() {
+        let mut burst = Vec::new();
+        coalesce(&mut burst, text("bold"));
+        coalesce(
+            &mut burst,
+            PendingCapture {
+                snapshot: Snapshot::Text {
+                    text: "bold".into(),
+                    html: Some("<b>bold</b>".into()),
+                },
+                source_app: None,
+            },
+        );
+        assert_eq!(burst.len(), 1);
+        assert!(matches!(
+            &burst[0].snapshot,
+            Snapshot::Text { html: Some(html), .. } if html == "<b>bold</b>"
+        ));
     }
 }
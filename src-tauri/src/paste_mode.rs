@@ -0,0 +1,162 @@
+use crate::models::AppConfig;
+use crate::state::AppState;
+
+/// Which clipboard formats a paste puts on the clipboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasteMode {
+    // Text plus the original HTML
+    Rich,
+    // Text only
+    Plain,
+    // HTML only, falling back to text for items without HTML
+    Html,
+    // Text converted from the HTML, for editors that understand Markdown
+    Markdown,
+}
+
+impl PasteMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rich" => Some(Self::Rich),
+            "plain" => Some(Self::Plain),
+            "html" => Some(Self::Html),
+            "markdown" => Some(Self::Markdown),
+            _ => None,
+        }
+    }
+
+    /// Picks the mode for a paste: an explicit request wins, then the
+    /// default for the app being pasted into, then the global default.
+    pub fn resolve(config: &AppConfig, requested: Option<&str>, target_app: Option<&str>) -> Self {
+        if let Some(mode) = requested.and_then(Self::from_name) {
+            return mode;
+        }
+
+        if let Some(app_name) = target_app {
+            let by_app = &config.paste_mode_by_app;
+            let mode = by_app
+                .iter()
+                .find(|(app, _)| app_name.eq_ignore_ascii_case(app))
+                .or_else(|| {
+                    by_app
+                        .iter()
+                        .find(|(app, _)| app_name.contains(app.as_str()))
+                })
+                .and_then(|(_, mode)| Self::from_name(mode));
+            if let Some(mode) = mode {
+                return mode;
+            }
+        }
+
+        Self::from_name(&config.default_paste_mode).unwrap_or(Self::Rich)
+    }
+
+    /// Resolves the mode for a paste into the app that was active when the
+    /// popup was opened.
+    pub fn for_paste(state: &AppState, requested: Option<&str>) -> Self {
+        let target_app = state.paste_target_app.lock().unwrap().clone();
+        let config = state.config.lock().unwrap();
+        Self::resolve(&config, requested, target_app.as_deref())
+    }
+
+    /// Returns the (text, html) to put on the clipboard for a text item.
+    pub fn apply(self, text: &str, html: Option<&str>) -> (Option<String>, Option<String>) {
+        match (self, html) {
+            (Self::Rich, html) => (Some(text.to_string()), html.map(str::to_string)),
+            (Self::Html, Some(html)) => (None, Some(html.to_string())),
+            (Self::Markdown, Some(html)) => {
+                (Some(html2md::parse_html(html).trim().to_string()), None)
+            }
+            _ => (Some(text.to_string()), None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(default: &str, by_app: &[(&str, &str)]) -> AppConfig {
+        AppConfig {
+            default_paste_mode: default.into(),
+            paste_mode_by_app: by_app
+                .iter()
+                .map(|(app, mode)| (app.to_string(), mode.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn requested_mode_wins() {
+        let config = config("plain", &[("Terminal", "plain")]);
+        assert_eq!(
+            PasteMode::resolve(&config, Some("markdown"), Some("Terminal")),
+            PasteMode::Markdown
+        );
+        // Unknown names are ignored
+        assert_eq!(
+            PasteMode::resolve(&config, Some("fancy"), None),
+            PasteMode::Plain
+        );
+    }
+
+    #[test]
+    fn app_default_comes_next() {
+        let config = config(
+            "rich",
+            &[
+                ("Code", "html"),
+                ("Visual Studio Code", "plain"),
+                ("Notes", "bad"),
+            ],
+        );
+        let resolve = |app| PasteMode::resolve(&config, None, Some(app));
+        // An exact name beats a partial one, in any case
+        assert_eq!(resolve("visual studio code"), PasteMode::Plain);
+        assert_eq!(resolve("Code - Insiders"), PasteMode::Html);
+        assert_eq!(resolve("Notes"), PasteMode::Rich);
+        assert_eq!(resolve("Mail"), PasteMode::Rich);
+    }
+
+    #[test]
+    fn falls_back_to_rich() {
+        assert_eq!(
+            PasteMode::resolve(&config("nonsense", &[]), None, None),
+            PasteMode::Rich
+        );
+    }
+
+    #[test]
+    fn apply_picks_the_formats() {
+        let html = Some("<p>Hello <b>world</b></p>");
+        assert_eq!(
+            PasteMode::Rich.apply("Hello world", html),
+            (Some("Hello world".into()), html.map(String::from))
+        );
+        assert_eq!(
+            PasteMode::Plain.apply("Hello world", html),
+            (Some("Hello world".into()), None)
+        );
+        assert_eq!(
+            PasteMode::Html.apply("Hello world", html),
+            (None, html.map(String::from))
+        );
+        assert_eq!(
+            PasteMode::Markdown.apply("Hello world", html),
+            (Some("Hello **world**".into()), None)
+        );
+    }
+
+    #[test]
+    fn items_without_html_paste_their_text() {
+        for mode in [
+            PasteMode::Rich,
+            PasteMode::Plain,
+            PasteMode::Html,
+            PasteMode::Markdown,
+        ] {
+            assert_eq!(mode.apply("text", None), (Some("text".into()), None));
+        }
+    }
+}
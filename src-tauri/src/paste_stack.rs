@@ -0,0 +1,276 @@
+use crate::models::ClipboardItem;
+use crate::paste_mode::PasteMode;
+use crate::state::AppState;
+use crate::tray::update_paste_stack_menu_item;
+use crate::utils::write_to_clipboard;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Emitter, Manager};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StackMode {
+    // Paste in the order the items were pushed
+    #[default]
+    Fifo,
+    // Paste the most recently pushed item first
+    Lifo,
+    // Like FIFO, but pasted items go back to the end instead of being removed
+    Cycle,
+}
+
+impl StackMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fifo" => Some(Self::Fifo),
+            "lifo" => Some(Self::Lifo),
+            "cycle" => Some(Self::Cycle),
+            _ => None,
+        }
+    }
+}
+
+/// Items queued for pasting with the global shortcut. Only history ids are
+/// kept (and saved to disk), the content is read from the database when an
+/// item is pasted so sensitive items are never written out in plain text.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PasteStack {
+    mode: StackMode,
+    ids: VecDeque<i64>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+/// What the `paste-stack-changed` event carries.
+#[derive(Debug, Clone, Serialize)]
+pub struct PasteStackSnapshot {
+    pub mode: StackMode,
+    pub ids: Vec<i64>,
+}
+
+impl PasteStack {
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let mut stack = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<PasteStack>(&content).ok())
+            .unwrap_or_default();
+        stack.path = path;
+        stack
+    }
+
+    fn save(&self) {
+        match serde_json::to_string(self) {
+            Ok(json) => {
+                if let Err(e) = fs::write(&self.path, json) {
+                    log::error!("Failed to save paste stack: {}", e);
+                }
+            }
+            Err(e) => log::error!("Failed to serialize paste stack: {}", e),
+        }
+    }
+
+    pub fn snapshot(&self) -> PasteStackSnapshot {
+        PasteStackSnapshot {
+            mode: self.mode,
+            ids: self.ids.iter().copied().collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn push(&mut self, ids: &[i64]) {
+        self.ids.extend(ids);
+        self.save();
+    }
+
+    /// The id that `pop` would return next.
+    pub fn peek(&self) -> Option<i64> {
+        match self.mode {
+            StackMode::Fifo | StackMode::Cycle => self.ids.front().copied(),
+            StackMode::Lifo => self.ids.back().copied(),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<i64> {
+        let id = match self.mode {
+            StackMode::Fifo => self.ids.pop_front(),
+            StackMode::Lifo => self.ids.pop_back(),
+            StackMode::Cycle => {
+                let id = self.ids.pop_front();
+                self.ids.extend(id);
+                id
+            }
+        };
+        self.save();
+        id
+    }
+
+    /// Drops an id wherever it is, e.g. when its item no longer exists.
+    pub fn remove(&mut self, id: i64) {
+        self.ids.retain(|&other| other != id);
+        self.save();
+    }
+
+    /// Replaces the order. `ids` has to contain exactly the queued ids.
+    pub fn reorder(&mut self, ids: Vec<i64>) -> Result<(), String> {
+        let mut current: Vec<i64> = self.ids.iter().copied().collect();
+        let mut requested = ids.clone();
+        current.sort_unstable();
+        requested.sort_unstable();
+        if current != requested {
+            return Err("The new order must contain exactly the queued items".to_string());
+        }
+        self.ids = ids.into();
+        self.save();
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.save();
+    }
+
+    pub fn set_mode(&mut self, mode: StackMode) {
+        self.mode = mode;
+        self.save();
+    }
+}
+
+/// Tells the frontend and the tray that the stack changed.
+pub fn notify_changed(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    let (snapshot, count) = {
+        let stack = state.paste_stack.lock().unwrap();
+        (stack.snapshot(), stack.len())
+    };
+    let _ = app.emit("paste-stack-changed", &snapshot);
+    if let Err(e) = update_paste_stack_menu_item(app, count) {
+        log::error!("Failed to update paste stack menu item: {}", e);
+    }
+}
+
+/// Takes the next item off the stack and writes it to the clipboard.
+/// Items deleted from the history in the meantime are skipped. Returns
+/// `None` when the stack is empty.
+pub fn paste_next(app: &AppHandle, mode: PasteMode) -> Result<Option<ClipboardItem>, String> {
+    let state = app.state::<AppState>();
+    loop {
+        let Some(id) = state.paste_stack.lock().unwrap().pop() else {
+            notify_changed(app);
+            return Ok(None);
+        };
+
+        let item = state.db.get_item(id).map_err(|e| e.to_string())?;
+        let Some(mut item) = item.filter(|item| item.deleted_at.is_none()) else {
+            state.paste_stack.lock().unwrap().remove(id);
+            continue;
+        };
+        if item.kind == "text" {
+            item.content = state.db.get_item_content(id).map_err(|e| e.to_string())?;
+        }
+
+        let result = write_to_clipboard(app, &item, mode, None);
+        notify_changed(app);
+        return result.map(|_| Some(item));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack(name: &str, mode: StackMode) -> PasteStack {
+        let path = std::env::temp_dir().join(format!(
+            "clipboard-paste-stack-{}-{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let mut stack = PasteStack::load(path);
+        stack.set_mode(mode);
+        stack.push(&[1, 2, 3]);
+        stack
+    }
+
+    fn pop_all(stack: &mut PasteStack, count: usize) -> Vec<Option<i64>> {
+        (0..count).map(|_| stack.pop()).collect()
+    }
+
+    #[test]
+    fn fifo_pastes_in_push_order() {
+        let mut stack = stack("fifo", StackMode::Fifo);
+        assert_eq!(stack.peek(), Some(1));
+        assert_eq!(pop_all(&mut stack, 4), [Some(1), Some(2), Some(3), None]);
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn lifo_pastes_the_latest_first() {
+        let mut stack = stack("lifo", StackMode::Lifo);
+        assert_eq!(stack.peek(), Some(3));
+        assert_eq!(pop_all(&mut stack, 4), [Some(3), Some(2), Some(1), None]);
+    }
+
+    #[test]
+    fn cycle_keeps_going_round() {
+        let mut stack = stack("cycle", StackMode::Cycle);
+        assert_eq!(
+            pop_all(&mut stack, 5),
+            [Some(1), Some(2), Some(3), Some(1), Some(2)]
+        );
+        assert_eq!(stack.len(), 3);
+        assert_eq!(stack.peek(), Some(3));
+    }
+
+    #[test]
+    fn reorder_needs_exactly_the_queued_ids() {
+        let mut stack = stack("reorder", StackMode::Fifo);
+        assert!(stack.reorder(vec![3, 1]).is_err());
+        assert!(stack.reorder(vec![3, 1, 2, 4]).is_err());
+        assert!(stack.reorder(vec![3, 1, 1]).is_err());
+        assert_eq!(stack.snapshot().ids, [1, 2, 3]);
+
+        stack.reorder(vec![3, 1, 2]).unwrap();
+        assert_eq!(pop_all(&mut stack, 3), [Some(3), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn remove_drops_every_copy() {
+        let mut stack = stack("remove", StackMode::Fifo);
+        stack.push(&[2]);
+        stack.remove(2);
+        assert_eq!(stack.snapshot().ids, [1, 3]);
+        stack.clear();
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn survives_a_restart() {
+        let mut stack = stack("persist", StackMode::Lifo);
+        stack.pop();
+        let loaded = PasteStack::load(&stack.path);
+        assert_eq!(loaded.snapshot().mode, StackMode::Lifo);
+        assert_eq!(loaded.snapshot().ids, [1, 2]);
+    }
+
+    #[test]
+    fn unreadable_files_give_an_empty_stack() {
+        let path = std::env::temp_dir().join(format!(
+            "clipboard-paste-stack-broken-{}.json",
+            std::process::id()
+        ));
+        fs::write(&path, "not json").unwrap();
+        let stack = PasteStack::load(&path);
+        assert!(stack.is_empty());
+        assert_eq!(stack.snapshot().mode, StackMode::Fifo);
+    }
+}
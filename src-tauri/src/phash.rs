@@ -0,0 +1,96 @@
+use image::{imageops, imageops::FilterType, RgbaImage};
+use sha2::{Digest, Sha256};
+
+/// Computes a 64-bit difference hash (dHash) of an image.
+///
+/// The image is shrunk to 9x8 grayscale pixels and each bit records whether a
+/// pixel is brighter than its right neighbour. Re-encoded, rescaled or slightly
+/// edited copies of the same picture end up within a few bits of each other.
+pub fn dhash(image: &RgbaImage) -> u64 {
+    let small = imageops::resize(image, 9, 8, FilterType::Triangle);
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = luma(small.get_pixel(x, y).0);
+            let right = luma(small.get_pixel(x + 1, y).0);
+            hash = (hash << 1) | u64::from(left > right);
+        }
+    }
+    hash
+}
+
+/// SHA-256 of the size and pixels of an image, as hex. Unlike the dHash it is
+/// only equal for identical images, so it decides whether a copy is new.
+pub fn digest(image: &RgbaImage) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(image.width().to_le_bytes());
+    hasher.update(image.height().to_le_bytes());
+    hasher.update(image.as_raw());
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Number of differing bits between two hashes.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+fn luma([r, g, b, _]: [u8; 4]) -> u32 {
+    // ITU-R BT.601 weights, scaled to integers
+    (299 * u32::from(r) + 587 * u32::from(g) + 114 * u32::from(b)) / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn pattern() -> RgbaImage {
+        RgbaImage::from_fn(400, 300, |x, y| {
+            Rgba([(x % 256) as u8, (y % 256) as u8, ((x * y) % 256) as u8, 255])
+        })
+    }
+
+    #[test]
+    fn distance_counts_differing_bits() {
+        assert_eq!(distance(0, 0), 0);
+        assert_eq!(distance(0b1011, 0b0001), 2);
+        assert_eq!(distance(0, u64::MAX), 64);
+    }
+
+    #[test]
+    fn rescaled_copies_hash_alike() {
+        let image = pattern();
+        let smaller = imageops::resize(&image, 200, 150, FilterType::Lanczos3);
+        assert!(distance(dhash(&image), dhash(&smaller)) <= 4);
+    }
+
+    #[test]
+    fn different_pictures_hash_apart() {
+        let falling = RgbaImage::from_fn(64, 64, |x, _| Rgba([255 - x as u8 * 4, 0, 0, 255]));
+        let rising = RgbaImage::from_fn(64, 64, |x, _| Rgba([x as u8 * 4, 0, 0, 255]));
+        assert_eq!(dhash(&falling), u64::MAX);
+        assert_eq!(dhash(&rising), 0);
+        assert!(distance(dhash(&pattern()), dhash(&rising)) > 10);
+    }
+
+    #[test]
+    fn digest_tells_identical_images_apart_from_similar_ones() {
+        let image = pattern();
+        assert_eq!(digest(&image), digest(&image.clone()));
+        assert_eq!(digest(&image).len(), 64);
+
+        let mut edited = image.clone();
+        edited.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        assert_ne!(digest(&image), digest(&edited));
+
+        // Same pixels laid out in another shape
+        let wide = RgbaImage::from_pixel(4, 1, Rgba([1; 4]));
+        let tall = RgbaImage::from_pixel(1, 4, Rgba([1; 4]));
+        assert_ne!(digest(&wide), digest(&tall));
+    }
+}
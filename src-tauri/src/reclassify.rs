@@ -0,0 +1,225 @@
+use crate::classifier::{Classifier, CLASSIFIER_VERSION};
+use crate::db::Database;
+use crate::state::AppState;
+use serde::Serialize;
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager};
+
+// Rows handled per round trip; progress is reported after each batch.
+const BATCH_SIZE: usize = 200;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReclassifyProgress {
+    pub processed: usize,
+    pub total: usize,
+    pub changed: usize,
+    pub done: bool,
+}
+
+/// Re-runs the classifier on text rows classified by an older version.
+/// With `force` every automatically classified row is redone, e.g. after the
+/// user changed their custom classifiers. Manual type overrides are skipped.
+///
+/// Emits `reclassify-progress` while running. Returns false without doing
+/// anything when a run is already in progress.
+pub fn reclassify_history(app: &AppHandle, force: bool) -> Result<bool, String> {
+    let state = app.state::<AppState>();
+    {
+        let mut running = state.reclassify_running.lock().unwrap();
+        if *running {
+            return Ok(false);
+        }
+        *running = true;
+    }
+
+    let result = run(app, &state, force);
+    *state.reclassify_running.lock().unwrap() = false;
+    result.map(|_| true)
+}
+
+fn run(app: &AppHandle, state: &AppState, force: bool) -> Result<(), String> {
+    if force {
+        state
+            .db
+            .reset_classifier_version()
+            .map_err(|e| e.to_string())?;
+    }
+
+    let total = state
+        .db
+        .count_stale_classifications(CLASSIFIER_VERSION)
+        .map_err(|e| e.to_string())?;
+    let mut progress = ReclassifyProgress {
+        processed: 0,
+        total,
+        changed: 0,
+        done: false,
+    };
+    if total == 0 {
+        progress.done = true;
+        let _ = app.emit("reclassify-progress", &progress);
+        return Ok(());
+    }
+    log::info!("Reclassifying {} history items", total);
+
+    while reclassify_batch(&state.db, &state.classifier, &mut progress)? {
+        let _ = app.emit("reclassify-progress", &progress);
+    }
+
+    progress.done = true;
+    let _ = app.emit("reclassify-progress", &progress);
+    if progress.changed > 0 {
+        let _ = app.emit("clipboard-update", ());
+    }
+    log::info!(
+        "Reclassified {} items, {} changed type",
+        progress.processed,
+        progress.changed
+    );
+    Ok(())
+}
+
+/// Reclassifies the next batch of stale rows, returns false when none are
+/// left. A row whose content can't be read, e.g. because its blob is gone,
+/// keeps its type but is marked as done, or every run would stop on it.
+fn reclassify_batch(
+    db: &Database,
+    classifier: &Mutex<Classifier>,
+    progress: &mut ReclassifyProgress,
+) -> Result<bool, String> {
+    let ids = db
+        .get_stale_classifications(CLASSIFIER_VERSION, BATCH_SIZE)
+        .map_err(|e| e.to_string())?;
+    if ids.is_empty() {
+        return Ok(false);
+    }
+
+    for (id, old_data_type) in ids {
+        progress.processed += 1;
+        let content = match db.get_item_content(id) {
+            Ok(content) => content,
+            Err(e) => {
+                log::warn!("Skipping reclassification of item {}: {}", id, e);
+                db.set_classifier_version(id, CLASSIFIER_VERSION)
+                    .map_err(|e| e.to_string())?;
+                continue;
+            }
+        };
+        let classification = classifier.lock().unwrap().classify(&content);
+        if classification.data_type != old_data_type {
+            progress.changed += 1;
+        }
+        db.set_classification(
+            id,
+            &classification.data_type,
+            classification.language.as_deref(),
+            CLASSIFIER_VERSION,
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob::blob_name;
+    use crate::crypto::Crypto;
+    use crate::models::ClipboardItem;
+    use std::sync::Arc;
+
+    fn text(content: &str, store_as_blob: bool) -> ClipboardItem {
+        ClipboardItem {
+            content: content.to_string(),
+            kind: "text".into(),
+            timestamp: "2024-01-01 00:00:00".into(),
+            data_type: "text".into(),
+            store_as_blob,
+            ..Default::default()
+        }
+    }
+
+    fn open(name: &str) -> (Database, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!(
+            "clipboard-reclassify-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Database::new(
+            dir.join("history.db"),
+            Arc::new(Crypto::new(dir.join("key"))),
+        )
+        .unwrap();
+        (db, dir)
+    }
+
+    fn progress(total: usize) -> ReclassifyProgress {
+        ReclassifyProgress {
+            processed: 0,
+            total,
+            changed: 0,
+            done: false,
+        }
+    }
+
+    #[test]
+    fn skips_rows_it_cannot_read() {
+        let (db, dir) = open("unreadable");
+        let lost = "x".repeat(10_000);
+        let (lost_id, _) = db.insert_item(&text(&lost, true), 100).unwrap();
+        let (url_id, _) = db
+            .insert_item(&text("https://example.com", false), 100)
+            .unwrap();
+        std::fs::remove_file(dir.join("blobs").join(blob_name(lost.as_bytes()))).unwrap();
+        // Both stored as plain text by an older classifier
+        db.reset_classifier_version().unwrap();
+
+        let classifier = Mutex::new(Classifier::new(&[]));
+        let mut progress = progress(2);
+        assert!(reclassify_batch(&db, &classifier, &mut progress).unwrap());
+        assert!(!reclassify_batch(&db, &classifier, &mut progress).unwrap());
+        assert_eq!((progress.processed, progress.changed), (2, 1));
+        assert_eq!(
+            db.count_stale_classifications(CLASSIFIER_VERSION).unwrap(),
+            0
+        );
+        assert_eq!(db.get_item(url_id).unwrap().unwrap().data_type, "url");
+        assert_eq!(db.get_item(lost_id).unwrap().unwrap().data_type, "text");
+    }
+
+    #[test]
+    fn leaves_manual_types_and_images_alone() {
+        let (db, _dir) = open("manual");
+        let manual = ClipboardItem {
+            data_type: "note".into(),
+            data_type_manual: true,
+            ..text("https://example.com/manual", false)
+        };
+        let (manual_id, _) = db.insert_item(&manual, 100).unwrap();
+        let image = ClipboardItem {
+            kind: "image".into(),
+            data_type: "image".into(),
+            ..text("/images/1.png", false)
+        };
+        db.insert_item(&image, 100).unwrap();
+        let (code_id, _) = db
+            .insert_item(&text("fn main() {\n    let mut x = 1;\n}", false), 100)
+            .unwrap();
+        db.reset_classifier_version().unwrap();
+        assert_eq!(
+            db.count_stale_classifications(CLASSIFIER_VERSION).unwrap(),
+            1
+        );
+
+        let classifier = Mutex::new(Classifier::new(&[]));
+        let mut progress = progress(1);
+        while reclassify_batch(&db, &classifier, &mut progress).unwrap() {}
+        assert_eq!((progress.processed, progress.changed), (1, 1));
+        let code = db.get_item(code_id).unwrap().unwrap();
+        assert_eq!(code.data_type, "code");
+        assert_eq!(code.language.as_deref(), Some("rust"));
+        assert_eq!(db.get_item(manual_id).unwrap().unwrap().data_type, "note");
+    }
+}
@@ -0,0 +1,66 @@
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+
+/// One line of a diff between two versions of an item.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffLine {
+    // "equal", "insert" or "delete"
+    pub tag: &'static str,
+    pub text: String,
+    // 1-based line numbers in the old and new version
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+}
+
+/// Line diff from `old` to `new`.
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    TextDiff::from_lines(old, new)
+        .iter_all_changes()
+        .map(|change| DiffLine {
+            tag: match change.tag() {
+                ChangeTag::Equal => "equal",
+                ChangeTag::Insert => "insert",
+                ChangeTag::Delete => "delete",
+            },
+            text: change.value().trim_end_matches(['\r', '\n']).to_string(),
+            old_line: change.old_index().map(|i| i + 1),
+            new_line: change.new_index().map(|i| i + 1),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(tag: &'static str, text: &str, old: Option<usize>, new: Option<usize>) -> DiffLine {
+        DiffLine {
+            tag,
+            text: text.into(),
+            old_line: old,
+            new_line: new,
+        }
+    }
+
+    #[test]
+    fn diffs_line_by_line() {
+        assert_eq!(
+            diff_lines("one\ntwo\nthree\n", "one\n2\nthree\nfour\n"),
+            [
+                line("equal", "one", Some(1), Some(1)),
+                line("delete", "two", Some(2), None),
+                line("insert", "2", None, Some(2)),
+                line("equal", "three", Some(3), Some(3)),
+                line("insert", "four", None, Some(4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn identical_texts_are_all_equal() {
+        let diff = diff_lines("a\r\nb\r\n", "a\r\nb\r\n");
+        assert!(diff.iter().all(|line| line.tag == "equal"));
+        assert_eq!(diff[1].text, "b");
+        assert!(diff_lines("", "").is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+use chrono::{DateTime, Duration, Local};
+use regex::Regex;
+use serde::Deserialize;
+
+// Upper bound on the entries one split may create.
+const MAX_PARTS: usize = 1000;
+
+/// How `split_item` cuts the text, e.g. `"lines"`, `{ "delimiter": "," }`
+/// or `{ "regex": "\\s*;\\s*" }`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitBy {
+    Lines,
+    Delimiter(String),
+    Regex(String),
+}
+
+/// Splits the text into parts, keeping their order. Parts are trimmed and
+/// empty ones dropped, so trailing newlines or "a, b," don't produce blanks.
+pub fn split(text: &str, by: &SplitBy) -> Result<Vec<String>, String> {
+    let parts: Vec<&str> = match by {
+        SplitBy::Lines => text.lines().collect(),
+        SplitBy::Delimiter(delimiter) => {
+            if delimiter.is_empty() {
+                return Err("The delimiter can't be empty".to_string());
+            }
+            text.split(delimiter.as_str()).collect()
+        }
+        SplitBy::Regex(pattern) => {
+            let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+            regex.split(text).collect()
+        }
+    };
+
+    let parts: Vec<String> = parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(str::to_string)
+        .collect();
+    if parts.len() > MAX_PARTS {
+        return Err(format!(
+            "The text splits into {} parts, at most {} are allowed",
+            parts.len(),
+            MAX_PARTS
+        ));
+    }
+    Ok(parts)
+}
+
+/// Timestamps for `count` parts, a millisecond apart from `start`, so the
+/// parts sort as if they had been copied one after another.
+pub fn part_timestamps(start: DateTime<Local>, count: usize) -> Vec<String> {
+    (0..count)
+        .map(|index| {
+            (start + Duration::milliseconds(index as i64))
+                .format("%Y-%m-%d %H:%M:%S%.3f")
+                .to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delimiter(delimiter: &str) -> SplitBy {
+        SplitBy::Delimiter(delimiter.to_string())
+    }
+
+    #[test]
+    fn splits_lines() {
+        assert_eq!(
+            split("first\r\n  second \n\n\tthird\n", &SplitBy::Lines).unwrap(),
+            ["first", "second", "third"]
+        );
+    }
+
+    #[test]
+    fn splits_on_a_delimiter() {
+        assert_eq!(
+            split("a, b,, c,", &delimiter(",")).unwrap(),
+            ["a", "b", "c"]
+        );
+        assert_eq!(
+            split("1 -- 2--3", &delimiter("--")).unwrap(),
+            ["1", "2", "3"]
+        );
+        assert_eq!(
+            split("no delimiter", &delimiter(";")).unwrap(),
+            ["no delimiter"]
+        );
+        assert!(split("a,b", &delimiter("")).is_err());
+    }
+
+    #[test]
+    fn splits_on_a_regex() {
+        let by = SplitBy::Regex(r"\s*[;|]\s*".to_string());
+        assert_eq!(split("x ; y|z", &by).unwrap(), ["x", "y", "z"]);
+        assert!(split("x", &SplitBy::Regex("(".to_string())).is_err());
+    }
+
+    #[test]
+    fn limits_the_number_of_parts() {
+        let text = "x\n".repeat(MAX_PARTS);
+        assert_eq!(split(&text, &SplitBy::Lines).unwrap().len(), MAX_PARTS);
+        let text = "x\n".repeat(MAX_PARTS + 1);
+        assert!(split(&text, &SplitBy::Lines).is_err());
+    }
+
+    #[test]
+    fn parts_get_increasing_timestamps() {
+        let start = Local::now();
+        let timestamps = part_timestamps(start, 1500);
+        assert_eq!(timestamps.len(), 1500);
+        assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(
+            timestamps[0],
+            start.format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+        );
+    }
+}
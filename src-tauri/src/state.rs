@@ -1,5 +1,10 @@
+use crate::classifier::Classifier;
+use crate::crypto::Crypto;
 use crate::db::Database;
-use crate::models::{AppConfig, ClipboardItem};
+use crate::image_store::ImageStore;
+use crate::models::AppConfig;
+use crate::paste_stack::PasteStack;
+use crate::sync::{FolderSync, SyncEngine};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use tauri::menu::MenuItem;
@@ -7,12 +12,23 @@ use tauri::Wry;
 
 pub struct AppState {
     pub db: Arc<Database>,
+    pub crypto: Arc<Crypto>,
+    pub image_store: Arc<ImageStore>,
     pub config_path: PathBuf,
     pub config: Arc<Mutex<AppConfig>>,
+    pub classifier: Arc<Mutex<Classifier>>,
+    pub reclassify_running: Arc<Mutex<bool>>,
     pub is_paused: Arc<Mutex<bool>>,
     pub last_app_change: Arc<Mutex<Option<String>>>,
-    pub last_app_image_change: Arc<Mutex<Option<Vec<u8>>>>,
+    pub last_app_image_change: Arc<Mutex<Option<String>>>,
     pub last_app_file_change: Arc<Mutex<Option<Vec<String>>>>,
-    pub paste_stack: Arc<Mutex<Vec<ClipboardItem>>>,
+    pub paste_stack: Arc<Mutex<PasteStack>>,
+    pub sync: Arc<SyncEngine>,
+    pub folder_sync: Arc<FolderSync>,
+    // Ids moved to the trash by the last delete or clear, for undo
+    pub last_deleted: Arc<Mutex<Vec<i64>>>,
+    // App that was in front when the popup opened, i.e. where pastes go
+    pub paste_target_app: Arc<Mutex<Option<String>>>,
     pub pause_item: Arc<Mutex<Option<MenuItem<Wry>>>>,
+    pub paste_stack_item: Arc<Mutex<Option<MenuItem<Wry>>>>,
 }
@@ -0,0 +1,111 @@
+//! Announces this device on the local network with mDNS and keeps track of
+//! the other devices that do.
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const SERVICE_TYPE: &str = "_clipboard-sync._tcp.local.";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscoveredDevice {
+    pub device_id: String,
+    pub name: String,
+    pub address: String,
+}
+
+pub struct Discovery {
+    daemon: ServiceDaemon,
+    fullname: String,
+    devices: Arc<Mutex<HashMap<String, DiscoveredDevice>>>,
+}
+
+impl Discovery {
+    /// Announces the service and starts browsing. `on_found` is called with
+    /// every device that shows up or moves to another address.
+    pub fn start(
+        device_id: &str,
+        name: &str,
+        port: u16,
+        on_found: impl Fn(&DiscoveredDevice) + Send + 'static,
+    ) -> Result<Self, String> {
+        let daemon = ServiceDaemon::new().map_err(|e| e.to_string())?;
+        let properties = [("id", device_id), ("name", name)];
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            device_id,
+            &format!("{}.local.", device_id),
+            "",
+            port,
+            &properties[..],
+        )
+        .map_err(|e| e.to_string())?
+        .enable_addr_auto();
+        let fullname = service.get_fullname().to_string();
+        daemon.register(service).map_err(|e| e.to_string())?;
+
+        let events = daemon.browse(SERVICE_TYPE).map_err(|e| e.to_string())?;
+        let devices = Arc::new(Mutex::new(HashMap::new()));
+        let own_id = device_id.to_string();
+        let found = devices.clone();
+        thread::spawn(move || {
+            while let Ok(event) = events.recv() {
+                match event {
+                    ServiceEvent::ServiceResolved(info) => {
+                        let Some(device) = device_from(&info).filter(|d| d.device_id != own_id)
+                        else {
+                            continue;
+                        };
+                        let previous = found
+                            .lock()
+                            .unwrap()
+                            .insert(info.get_fullname().to_string(), device.clone());
+                        if previous.is_none_or(|p| p.address != device.address) {
+                            on_found(&device);
+                        }
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => {
+                        found.lock().unwrap().remove(&fullname);
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        Ok(Self {
+            daemon,
+            fullname,
+            devices,
+        })
+    }
+
+    pub fn devices(&self) -> Vec<DiscoveredDevice> {
+        self.devices.lock().unwrap().values().cloned().collect()
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        let _ = self.daemon.unregister(&self.fullname);
+        let _ = self.daemon.shutdown();
+    }
+}
+
+fn device_from(info: &ServiceInfo) -> Option<DiscoveredDevice> {
+    // IPv4 first, link-local IPv6 addresses would need a scope
+    let ip = info
+        .get_addresses_v4()
+        .into_iter()
+        .next()
+        .map(|ip| ip.to_string())?;
+    Some(DiscoveredDevice {
+        device_id: info.get_property_val_str("id")?.to_string(),
+        name: info
+            .get_property_val_str("name")
+            .unwrap_or_default()
+            .to_string(),
+        address: format!("{}:{}", ip, info.get_port()),
+    })
+}
@@ -0,0 +1,793 @@
+//! Syncs the history through a folder that something else keeps in sync
+//! between devices, like Syncthing or Nextcloud.
+//!
+//! Every device appends its own changes to its own log, `<device id>.cliplog`,
+//! and replays the logs of the others. What it applied from them doesn't go
+//! to its log again. Once a log has grown well past the history it
+//! describes, it is written again from the start. A log starts with a plain JSON
+//! [`Header`] line; each further line is one [`Entry`], sealed with
+//! AES-256-GCM under a key derived from the shared passphrase and the salt
+//! in the header, and base64 encoded. Only whole lines are read, so a log
+//! that is still being copied is picked up where it stopped next time.
+//!
+//! Entries are numbered per device and the last number applied is kept, so
+//! restarts and copies of a log (conflict copies, for instance) don't apply
+//! anything twice. Applying an entry again is harmless anyway: records are
+//! matched by sync id and merged like with LAN sync, and a deletion is only
+//! applied once.
+
+use super::{apply_record, attach_image, device_name, BATCH_BYTES, BATCH_SIZE, POLL_INTERVAL};
+use crate::crypto::Crypto;
+use crate::db::Database;
+use crate::image_store::ImageStore;
+use crate::models::{AppConfig, SyncRecord};
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const STATE_FILE: &str = "folder-sync.json";
+const LOG_EXTENSION: &str = "cliplog";
+const LOG_FORMAT: &str = "clipboard-sync-log";
+const LOG_VERSION: u32 = 1;
+const KDF_ITERATIONS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+// Changes applied from the logs are marked with this, so they stay out of ours
+const SOURCE: &str = "folder";
+// Our log is written again once it is larger than this and twice as large as
+// right after it was last written from the start
+const MIN_REWRITE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The first line of a log.
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+    device_id: String,
+    name: String,
+    salt: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    // Counts up per device, starting at 1
+    number: u64,
+    #[serde(flatten)]
+    operation: Operation,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Operation {
+    // A new item, or a changed pin, note or collection
+    Upsert { record: Box<SyncRecord> },
+    Delete { sync_id: String },
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FolderDevice {
+    pub name: String,
+    // Entries of the device up to here have been applied
+    pub applied: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct FolderState {
+    device_id: String,
+    // Salt of our own log
+    salt: String,
+    // Its length right after it was last written from the start
+    #[serde(default)]
+    rewritten_len: u64,
+    next_entry: u64,
+    // The folder the fields below refer to
+    dir: String,
+    // Changes and deletions up to these have been written to our log
+    exported_changes: i64,
+    exported_deletions: i64,
+    // How far each log was read, by file name
+    logs: HashMap<String, LogPosition>,
+    devices: HashMap<String, FolderDevice>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct LogPosition {
+    // A log with another salt was written again from the start
+    salt: String,
+    offset: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FolderSyncStatus {
+    pub running: bool,
+    pub device_id: String,
+    pub dir: String,
+    pub devices: HashMap<String, FolderDevice>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FolderSyncReport {
+    // Entries appended to our log and entries of other devices applied
+    pub written: usize,
+    pub applied: usize,
+}
+
+pub struct FolderSync {
+    db: Arc<Database>,
+    image_store: Arc<ImageStore>,
+    crypto: Arc<Crypto>,
+    config: Arc<Mutex<AppConfig>>,
+    state_path: PathBuf,
+    state: Mutex<FolderState>,
+    // Held for a whole run, so runs don't overlap
+    run: Mutex<()>,
+    // Keys by salt, deriving one takes a while
+    keys: Mutex<HashMap<String, Aes256Gcm>>,
+    running: Mutex<Option<Arc<AtomicBool>>>,
+    // Called after entries from another device were applied
+    on_change: Box<dyn Fn() + Send + Sync>,
+}
+
+impl FolderSync {
+    /// Loads what was written and read so far from `folder-sync.json`,
+    /// giving the device an id the first time.
+    pub fn new(
+        data_dir: &Path,
+        db: Arc<Database>,
+        image_store: Arc<ImageStore>,
+        crypto: Arc<Crypto>,
+        config: Arc<Mutex<AppConfig>>,
+        on_change: impl Fn() + Send + Sync + 'static,
+    ) -> Result<Arc<Self>, String> {
+        let state_path = data_dir.join(STATE_FILE);
+        let mut state: FolderState = fs::read_to_string(&state_path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        if state.device_id.is_empty() {
+            state.device_id = uuid::Uuid::new_v4().simple().to_string();
+            state.next_entry = 1;
+            save_state(&state_path, &state)?;
+        }
+
+        Ok(Arc::new(Self {
+            db,
+            image_store,
+            crypto,
+            config,
+            state_path,
+            state: Mutex::new(state),
+            run: Mutex::new(()),
+            keys: Mutex::new(HashMap::new()),
+            running: Mutex::new(None),
+            on_change: Box::new(on_change),
+        }))
+    }
+
+    /// Keeps writing local changes to the folder and reading the logs of
+    /// the other devices in the background.
+    pub fn start(self: &Arc<Self>) -> Result<(), String> {
+        {
+            let config = self.config.lock().unwrap();
+            if config.folder_sync_dir.is_empty() || config.folder_sync_passphrase.is_empty() {
+                return Err("Folder sync needs a folder and a passphrase".to_string());
+            }
+        }
+        let mut running = self.running.lock().unwrap();
+        if running.is_some() {
+            return Ok(());
+        }
+        // The passphrase may have changed
+        self.keys.lock().unwrap().clear();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let engine = self.clone();
+        let stopped = stop.clone();
+        thread::spawn(move || {
+            // Polled rather than watched, watching doesn't work on every
+            // network or FUSE file system
+            while !stopped.load(Ordering::SeqCst) {
+                if let Err(e) = engine.sync_now() {
+                    log::warn!("Folder sync failed: {}", e);
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+        *running = Some(stop);
+        log::info!("Folder sync started");
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        if let Some(stop) = self.running.lock().unwrap().take() {
+            stop.store(true, Ordering::SeqCst);
+            log::info!("Folder sync stopped");
+        }
+    }
+
+    pub fn status(&self) -> FolderSyncStatus {
+        let running = self.running.lock().unwrap().is_some();
+        let state = self.state.lock().unwrap();
+        FolderSyncStatus {
+            running,
+            device_id: state.device_id.clone(),
+            dir: self.config.lock().unwrap().folder_sync_dir.clone(),
+            devices: state.devices.clone(),
+        }
+    }
+
+    /// Appends local changes to our log, then applies what is new in the
+    /// logs of the other devices.
+    pub fn sync_now(&self) -> Result<FolderSyncReport, String> {
+        let config = self.config.lock().unwrap().clone();
+        if config.folder_sync_dir.is_empty() {
+            return Err("No sync folder is set".to_string());
+        }
+        if config.folder_sync_passphrase.is_empty() {
+            return Err("Folder sync needs a passphrase".to_string());
+        }
+        let passphrase = self.crypto.decrypt(&config.folder_sync_passphrase)?;
+        let dir = PathBuf::from(&config.folder_sync_dir);
+        if !dir.is_dir() {
+            return Err(format!("The sync folder {} doesn't exist", dir.display()));
+        }
+
+        // A run works on a copy of the state, so the status can be read while
+        // it reads and writes files
+        let _run = self.run.lock().unwrap();
+        let mut state = self.state.lock().unwrap().clone();
+        if state.dir != config.folder_sync_dir {
+            // Another folder, start over there
+            state = FolderState {
+                device_id: state.device_id.clone(),
+                next_entry: state.next_entry,
+                dir: config.folder_sync_dir.clone(),
+                ..Default::default()
+            };
+        }
+
+        // Saved even when writing failed half way, entry numbers that were
+        // used may not be used again
+        let written = self.export(&mut state, &dir, &config, &passphrase);
+        self.save(&state)?;
+        let mut report = FolderSyncReport {
+            written: written?,
+            ..Default::default()
+        };
+
+        let entries = fs::read_dir(&dir).map_err(|e| e.to_string())?;
+        let mut logs: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some(LOG_EXTENSION))
+            .collect();
+        logs.sort();
+        for path in logs {
+            match self.replay(&mut state, &path, &config, &passphrase) {
+                Ok(applied) => report.applied += applied,
+                Err(e) => log::warn!("Can't read {}: {}", path.display(), e),
+            }
+        }
+        self.save(&state)?;
+
+        if report.applied > 0 {
+            log::info!("Applied {} changes from the sync folder", report.applied);
+            (self.on_change)();
+        }
+        Ok(report)
+    }
+
+    /// Writes the changes and deletions made here since the last run to our
+    /// log. A new log, one written with another passphrase or one that has
+    /// grown too large is written again from the start, with everything in it.
+    fn export(
+        &self,
+        state: &mut FolderState,
+        dir: &Path,
+        config: &AppConfig,
+        passphrase: &str,
+    ) -> Result<usize, String> {
+        let path = dir.join(format!("{}.{}", state.device_id, LOG_EXTENSION));
+        let key_matches = read_header(&path)
+            .ok()
+            .filter(|(header, _)| header.salt == state.salt)
+            .is_some_and(|_| {
+                let cipher = self.cipher(&state.salt, passphrase);
+                match read_line(&path, 1) {
+                    Some(line) => open(&cipher, &state.device_id, &line).is_ok(),
+                    // Nothing written yet
+                    None => true,
+                }
+            });
+        let len = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+        let outgrown = len > MIN_REWRITE_SIZE.max(state.rewritten_len.saturating_mul(2));
+        let rewrite = !key_matches || outgrown;
+        // Written next to it and moved over it when complete, so other
+        // devices never see a log that was cut short
+        let target = if rewrite {
+            dir.join(format!("{}.{}.tmp", state.device_id, LOG_EXTENSION))
+        } else {
+            path.clone()
+        };
+        if rewrite {
+            if outgrown {
+                log::info!("Writing the sync log again from the start");
+            }
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            state.salt = general_purpose::STANDARD.encode(salt);
+            state.exported_changes = 0;
+            state.exported_deletions = 0;
+            let header = Header {
+                format: LOG_FORMAT.to_string(),
+                version: LOG_VERSION,
+                device_id: state.device_id.clone(),
+                name: device_name(config),
+                salt: state.salt.clone(),
+            };
+            let mut line = serde_json::to_string(&header).map_err(|e| e.to_string())?;
+            line.push('\n');
+            fs::write(&target, line).map_err(|e| e.to_string())?;
+        }
+
+        let cipher = self.cipher(&state.salt, passphrase);
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&target)
+            .map_err(|e| e.to_string())?;
+        // A log written from the start also takes what came from other logs,
+        // so nothing is lost when those go away
+        let skip_source = if rewrite { None } else { Some(SOURCE) };
+        let mut written = 0;
+        loop {
+            let (records, next) = self
+                .db
+                .get_sync_changes(state.exported_changes, BATCH_SIZE, skip_source)
+                .map_err(|e| e.to_string())?;
+            let done = records.len() < BATCH_SIZE;
+            let mut lines = String::new();
+            for mut record in records {
+                if let Err(e) = attach_image(&mut record) {
+                    log::warn!("Not syncing image {}: {}", record.content, e);
+                    continue;
+                }
+                lines.push_str(&seal(
+                    &cipher,
+                    state,
+                    Operation::Upsert {
+                        record: Box::new(record),
+                    },
+                )?);
+                written += 1;
+                if lines.len() >= BATCH_BYTES {
+                    write_lines(&mut file, &lines)?;
+                    lines.clear();
+                }
+            }
+            write_lines(&mut file, &lines)?;
+            state.exported_changes = next;
+            if done {
+                break;
+            }
+        }
+        loop {
+            let (deletions, next) = self
+                .db
+                .get_sync_deletions(state.exported_deletions, BATCH_SIZE, skip_source)
+                .map_err(|e| e.to_string())?;
+            let done = deletions.len() < BATCH_SIZE;
+            let mut lines = String::new();
+            for sync_id in deletions {
+                lines.push_str(&seal(&cipher, state, Operation::Delete { sync_id })?);
+                written += 1;
+            }
+            write_lines(&mut file, &lines)?;
+            state.exported_deletions = next;
+            if done {
+                break;
+            }
+        }
+        file.sync_all().map_err(|e| e.to_string())?;
+        if rewrite {
+            fs::rename(&target, &path).map_err(|e| e.to_string())?;
+            state.rewritten_len = fs::metadata(&path).map_err(|e| e.to_string())?.len();
+        }
+        Ok(written)
+    }
+
+    /// Applies the entries of another device's log that weren't applied
+    /// yet. Returns how many changed something.
+    fn replay(
+        &self,
+        state: &mut FolderState,
+        path: &Path,
+        config: &AppConfig,
+        passphrase: &str,
+    ) -> Result<usize, String> {
+        let file_name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let len = fs::metadata(path).map_err(|e| e.to_string())?.len();
+        let position = state.logs.get(&file_name).cloned().unwrap_or_default();
+        if len == position.offset {
+            return Ok(0);
+        }
+
+        let (header, header_len) = read_header(path)?;
+        if header.device_id == state.device_id {
+            // Ours, or a copy of it
+            return Ok(0);
+        }
+        if header.format != LOG_FORMAT || header.version != LOG_VERSION {
+            return Err(format!("Unsupported log version {}", header.version));
+        }
+        let cipher = self.cipher(&header.salt, passphrase);
+
+        let mut file = File::open(path).map_err(|e| e.to_string())?;
+        let offset = if header.salt == position.salt && position.offset <= len {
+            position.offset.max(header_len)
+        } else {
+            header_len
+        };
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|e| e.to_string())?;
+        let mut reader = BufReader::new(file);
+
+        let device = state.devices.entry(header.device_id.clone()).or_default();
+        device.name = header.name.clone();
+        let mut applied = 0;
+        let mut read = 0;
+        let mut line = Vec::new();
+        let result = loop {
+            line.clear();
+            if let Err(e) = reader.read_until(b'\n', &mut line) {
+                break Err(e.to_string());
+            }
+            // Only whole lines, the rest may still be on its way
+            if !line.ends_with(b"\n") {
+                break Ok(());
+            }
+            let entry = match open(&cipher, &header.device_id, &line) {
+                Ok(entry) => entry,
+                // Stop here, a wrong passphrase would otherwise skip the whole log
+                Err(e) => break Err(e),
+            };
+            if entry.number > device.applied {
+                match self.apply(entry.operation, config) {
+                    Ok(true) => applied += 1,
+                    Ok(false) => {}
+                    Err(e) => log::warn!("Failed to apply synced change: {}", e),
+                }
+                device.applied = entry.number;
+            }
+            read += line.len() as u64;
+        };
+        state.logs.insert(
+            file_name,
+            LogPosition {
+                salt: header.salt,
+                offset: offset + read,
+            },
+        );
+        result.map(|_| applied)
+    }
+
+    fn apply(&self, operation: Operation, config: &AppConfig) -> Result<bool, String> {
+        match operation {
+            Operation::Upsert { record } => {
+                apply_record(&self.db, &self.image_store, config, *record, SOURCE)
+            }
+            Operation::Delete { sync_id } => self
+                .db
+                .delete_sync_item(&sync_id, SOURCE)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    fn save(&self, state: &FolderState) -> Result<(), String> {
+        save_state(&self.state_path, state)?;
+        *self.state.lock().unwrap() = state.clone();
+        Ok(())
+    }
+
+    fn cipher(&self, salt: &str, passphrase: &str) -> Aes256Gcm {
+        let mut keys = self.keys.lock().unwrap();
+        keys.entry(salt.to_string())
+            .or_insert_with(|| {
+                let salt = general_purpose::STANDARD.decode(salt).unwrap_or_default();
+                let mut key = Key::<Aes256Gcm>::default();
+                pbkdf2::pbkdf2_hmac::<Sha256>(
+                    passphrase.as_bytes(),
+                    &salt,
+                    KDF_ITERATIONS,
+                    &mut key,
+                );
+                Aes256Gcm::new(&key)
+            })
+            .clone()
+    }
+}
+
+// Entries are bound to the device whose log they are in
+fn seal(
+    cipher: &Aes256Gcm,
+    state: &mut FolderState,
+    operation: Operation,
+) -> Result<String, String> {
+    let entry = Entry {
+        number: state.next_entry,
+        operation,
+    };
+    let json = serde_json::to_vec(&entry).map_err(|e| e.to_string())?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let sealed = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: &json,
+                aad: state.device_id.as_bytes(),
+            },
+        )
+        .map_err(|e| e.to_string())?;
+    state.next_entry += 1;
+
+    let mut combined = nonce.to_vec();
+    combined.extend(sealed);
+    let mut line = general_purpose::STANDARD.encode(combined);
+    line.push('\n');
+    Ok(line)
+}
+
+fn open(cipher: &Aes256Gcm, device_id: &str, line: impl AsRef<[u8]>) -> Result<Entry, String> {
+    let combined = general_purpose::STANDARD
+        .decode(line.as_ref().trim_ascii())
+        .map_err(|e| e.to_string())?;
+    if combined.len() < 12 {
+        return Err("Damaged entry".to_string());
+    }
+    let (nonce, sealed) = combined.split_at(12);
+    let json = cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: sealed,
+                aad: device_id.as_bytes(),
+            },
+        )
+        .map_err(|_| "Can't decrypt the log, is the passphrase the same on all devices?")?;
+    serde_json::from_slice(&json).map_err(|e| e.to_string())
+}
+
+/// The header of a log and its length in bytes.
+fn read_header(path: &Path) -> Result<(Header, u64), String> {
+    let mut line = String::new();
+    BufReader::new(File::open(path).map_err(|e| e.to_string())?)
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+    let header = serde_json::from_str(&line).map_err(|_| "Not a clipboard sync log")?;
+    Ok((header, line.len() as u64))
+}
+
+// Line `index` of a file, if it is complete
+fn read_line(path: &Path, index: usize) -> Option<String> {
+    let reader = BufReader::new(File::open(path).ok()?);
+    reader
+        .split(b'\n')
+        .nth(index)?
+        .ok()
+        .map(|line| String::from_utf8_lossy(&line).into_owned())
+        .filter(|line| !line.is_empty())
+}
+
+fn write_lines(file: &mut File, lines: &str) -> Result<(), String> {
+    file.write_all(lines.as_bytes()).map_err(|e| e.to_string())
+}
+
+fn save_state(path: &Path, state: &FolderState) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(state).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ClipboardItem;
+
+    struct Node {
+        db: Arc<Database>,
+        sync: Arc<FolderSync>,
+    }
+
+    fn node(name: &str, folder: &Path) -> Node {
+        let dir =
+            std::env::temp_dir().join(format!("clipboard-folder-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let crypto = Arc::new(Crypto::new(dir.join("secret.key")));
+        let db = Arc::new(Database::new(dir.join("history.db"), crypto.clone()).unwrap());
+        let image_store = Arc::new(ImageStore::new(dir.join("images")));
+        let config = AppConfig {
+            folder_sync_dir: folder.to_string_lossy().into_owned(),
+            folder_sync_passphrase: crypto.encrypt("passphrase").unwrap(),
+            sync_device_name: name.to_string(),
+            ..Default::default()
+        };
+        let sync = FolderSync::new(
+            &dir,
+            db.clone(),
+            image_store,
+            crypto,
+            Arc::new(Mutex::new(config)),
+            || {},
+        )
+        .unwrap();
+        Node { db, sync }
+    }
+
+    fn folder(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("clipboard-folder-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn add_text(db: &Database, content: &str) -> i64 {
+        let item = ClipboardItem {
+            content: content.to_string(),
+            kind: "text".into(),
+            timestamp: "2024-01-01 00:00:00".into(),
+            data_type: "text".into(),
+            ..Default::default()
+        };
+        db.insert_item(&item, 100).unwrap().0
+    }
+
+    fn contents(db: &Database) -> Vec<String> {
+        let mut contents: Vec<String> = db
+            .get_history(1, 100, None, false, false, None, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|item| item.content)
+            .collect();
+        contents.sort();
+        contents
+    }
+
+    // Entries in the log of `node`
+    fn log_entries(folder: &Path, node: &Node) -> usize {
+        let path = folder.join(format!(
+            "{}.{}",
+            node.sync.status().device_id,
+            LOG_EXTENSION
+        ));
+        fs::read_to_string(path).unwrap().lines().count() - 1
+    }
+
+    #[test]
+    fn applied_changes_are_not_written_again() {
+        let shared = folder("shared-echo");
+        let a = node("echo-a", &shared);
+        let b = node("echo-b", &shared);
+
+        let id = add_text(&a.db, "from a");
+        assert_eq!(a.sync.sync_now().unwrap().written, 1);
+        assert_eq!(b.sync.sync_now().unwrap().applied, 1);
+        assert_eq!(contents(&b.db), ["from a"]);
+        assert_eq!(b.sync.sync_now().unwrap().written, 0);
+
+        // Changed on a, merged on b, and only a's log has it
+        a.db.set_pinned(id, true).unwrap();
+        a.sync.sync_now().unwrap();
+        assert_eq!(b.sync.sync_now().unwrap().applied, 1);
+        assert!(
+            b.db.get_history(1, 1, None, false, false, None, None, None)
+                .unwrap()[0]
+                .is_pinned
+        );
+        assert_eq!(b.sync.sync_now().unwrap().written, 0);
+        assert_eq!(a.sync.sync_now().unwrap().applied, 0);
+
+        // Changed on b goes the other way
+        let on_b = b.db.find_item("text", "from a").unwrap().unwrap();
+        b.db.set_pinned(on_b, false).unwrap();
+        assert_eq!(b.sync.sync_now().unwrap().written, 1);
+        assert_eq!(a.sync.sync_now().unwrap().applied, 1);
+        assert_eq!(a.sync.sync_now().unwrap().written, 0);
+
+        // Deleted on a
+        a.db.delete_item(0).unwrap();
+        assert_eq!(a.sync.sync_now().unwrap().written, 1);
+        assert_eq!(b.sync.sync_now().unwrap().applied, 1);
+        assert!(contents(&b.db).is_empty());
+        assert_eq!(b.sync.sync_now().unwrap().written, 0);
+
+        assert_eq!(log_entries(&shared, &a), 3);
+        assert_eq!(log_entries(&shared, &b), 1);
+    }
+
+    #[test]
+    fn rewritten_logs_hold_everything() {
+        let shared = folder("shared-rewrite");
+        let a = node("rewrite-a", &shared);
+        let b = node("rewrite-b", &shared);
+
+        add_text(&a.db, "from a");
+        add_text(&b.db, "from b");
+        for _ in 0..2 {
+            a.sync.sync_now().unwrap();
+            b.sync.sync_now().unwrap();
+        }
+        let id = a.db.find_item("text", "from a").unwrap().unwrap();
+        a.db.set_pinned(id, true).unwrap();
+        a.sync.sync_now().unwrap();
+        a.db.set_pinned(id, false).unwrap();
+        a.sync.sync_now().unwrap();
+        b.sync.sync_now().unwrap();
+        assert_eq!(log_entries(&shared, &a), 3);
+
+        // Written again from the start, like when it has grown too large
+        a.sync.state.lock().unwrap().salt.clear();
+        assert_eq!(a.sync.sync_now().unwrap().written, 2);
+        assert_eq!(log_entries(&shared, &a), 2);
+        assert!(!shared
+            .join(format!(
+                "{}.{}.tmp",
+                a.sync.status().device_id,
+                LOG_EXTENSION
+            ))
+            .exists());
+
+        // Read again from the start, nothing new in it
+        assert_eq!(b.sync.sync_now().unwrap().applied, 0);
+        add_text(&a.db, "after");
+        a.sync.sync_now().unwrap();
+        assert_eq!(b.sync.sync_now().unwrap().applied, 1);
+        assert_eq!(contents(&b.db), ["after", "from a", "from b"]);
+
+        // A device joining now finds it all, even without b's log
+        fs::remove_file(shared.join(format!("{}.{}", b.sync.status().device_id, LOG_EXTENSION)))
+            .unwrap();
+        let c = node("rewrite-c", &shared);
+        c.sync.sync_now().unwrap();
+        assert_eq!(contents(&c.db), ["after", "from a", "from b"]);
+    }
+
+    #[test]
+    fn incomplete_lines_are_read_later() {
+        let shared = folder("shared-partial");
+        let a = node("partial-a", &shared);
+        let b = node("partial-b", &shared);
+
+        add_text(&a.db, "one");
+        a.sync.sync_now().unwrap();
+        let path = shared.join(format!("{}.{}", a.sync.status().device_id, LOG_EXTENSION));
+        let complete = fs::read(&path).unwrap();
+        add_text(&a.db, "two");
+        a.sync.sync_now().unwrap();
+        let full = fs::read(&path).unwrap();
+
+        // The second entry is still on its way
+        fs::write(&path, &full[..full.len() - 10]).unwrap();
+        assert_eq!(b.sync.sync_now().unwrap().applied, 1);
+        assert_eq!(
+            b.sync.state.lock().unwrap().logs
+                [&format!("{}.{}", a.sync.status().device_id, LOG_EXTENSION)]
+                .offset,
+            complete.len() as u64
+        );
+        fs::write(&path, &full).unwrap();
+        assert_eq!(b.sync.sync_now().unwrap().applied, 1);
+        assert_eq!(contents(&b.db), ["one", "two"]);
+    }
+}
@@ -0,0 +1,1090 @@
+//! Syncs the history with paired devices on the local network.
+//!
+//! Devices find each other with mDNS ([`discovery`]) and pair once with a
+//! one-time code shown on one of them. After that either side can connect,
+//! and both pull what changed on the other since their last session (see
+//! [`protocol`] for the wire format). New items are added; pins, notes and
+//! collections are merged by [`Database::merge_sync_record`], so it doesn't
+//! matter in which order devices talk to each other.
+//!
+//! Deleting an item moves it to the trash on the other devices as well.
+//! Sensitive items, file lists and items marked as not to sync never leave
+//! the device.
+//!
+//! [`FolderSync`] syncs through a shared folder instead, see [`folder`].
+
+mod discovery;
+mod folder;
+mod protocol;
+
+pub use discovery::DiscoveredDevice;
+pub use folder::{FolderSync, FolderSyncReport, FolderSyncStatus};
+
+use crate::crypto::Crypto;
+use crate::db::Database;
+use crate::image_store::ImageStore;
+use crate::models::{AppConfig, ClipboardItem, SyncRecord};
+use crate::phash::{dhash, digest};
+use crate::utils::apply_size_limits;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use base64::{engine::general_purpose, Engine as _};
+use chrono::Local;
+use discovery::Discovery;
+use protocol::{Channel, Message, Purpose, Secret};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const STATE_FILE: &str = "sync.json";
+// Crockford's base32, without letters that are easily mixed up
+const CODE_ALPHABET: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const CODE_LEN: usize = 10;
+const PAIRING_TIMEOUT: Duration = Duration::from_secs(120);
+// Wrong codes a pairing survives, so a typo doesn't need a new code
+const PAIRING_ATTEMPTS: u32 = 5;
+// Connections handled at once, more are turned away
+const MAX_CONNECTIONS: usize = 8;
+// Records asked for at once, and the size at which a batch stops growing
+const BATCH_SIZE: usize = 100;
+const BATCH_BYTES: usize = 8 * 1024 * 1024;
+// Peers are synced when something changed here, and every few minutes to
+// catch up with changes made while they were away
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const FULL_SYNC_INTERVAL: Duration = Duration::from_secs(300);
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Peer {
+    pub device_id: String,
+    pub name: String,
+    pub public_key: String,
+    // Last address the device was seen at, as "ip:port"
+    pub address: Option<String>,
+    // Changes of the peer up to here have been applied
+    pub cursor: i64,
+    pub last_sync: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SyncState {
+    device_id: String,
+    // The long-term X25519 key, encrypted with secret.key
+    secret_key: String,
+    peers: Vec<Peer>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncStatus {
+    pub running: bool,
+    pub device_id: String,
+    pub device_name: String,
+    pub port: Option<u16>,
+    pub peers: Vec<Peer>,
+    // Devices announcing themselves on the network, paired or not
+    pub discovered: Vec<DiscoveredDevice>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncReport {
+    pub device_id: String,
+    pub name: String,
+    // Records applied here and records sent to the peer
+    pub received: usize,
+    pub sent: usize,
+}
+
+// A code shown for pairing
+struct Pairing {
+    code: String,
+    expires: Instant,
+    attempts_left: u32,
+}
+
+struct Running {
+    port: u16,
+    stop: Arc<AtomicBool>,
+    discovery: Option<Discovery>,
+}
+
+pub struct SyncEngine {
+    db: Arc<Database>,
+    image_store: Arc<ImageStore>,
+    config: Arc<Mutex<AppConfig>>,
+    state_path: PathBuf,
+    state: Mutex<SyncState>,
+    secret: StaticSecret,
+    pairing: Mutex<Option<Pairing>>,
+    running: Mutex<Option<Running>>,
+    // Called after records from another device were applied
+    on_change: Box<dyn Fn() + Send + Sync>,
+}
+
+impl SyncEngine {
+    /// Loads the device identity and the paired devices from `sync.json`,
+    /// creating them the first time.
+    pub fn new(
+        data_dir: &Path,
+        db: Arc<Database>,
+        image_store: Arc<ImageStore>,
+        crypto: &Crypto,
+        config: Arc<Mutex<AppConfig>>,
+        on_change: impl Fn() + Send + Sync + 'static,
+    ) -> Result<Arc<Self>, String> {
+        let state_path = data_dir.join(STATE_FILE);
+        let mut state: SyncState = fs::read_to_string(&state_path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+
+        let secret = crypto
+            .decrypt(&state.secret_key)
+            .ok()
+            .and_then(|key| protocol::decode_key(&key))
+            .map(StaticSecret::from);
+        let secret = match secret {
+            Some(secret) => secret,
+            None => {
+                // A new identity, devices paired with the old one have to pair again
+                let secret = StaticSecret::random_from_rng(OsRng);
+                state = SyncState {
+                    device_id: uuid::Uuid::new_v4().simple().to_string(),
+                    secret_key: crypto.encrypt(&protocol::encode_key(secret.as_bytes()))?,
+                    peers: Vec::new(),
+                };
+                save_state(&state_path, &state)?;
+                secret
+            }
+        };
+
+        Ok(Arc::new(Self {
+            db,
+            image_store,
+            config,
+            state_path,
+            state: Mutex::new(state),
+            secret,
+            pairing: Mutex::new(None),
+            running: Mutex::new(None),
+            on_change: Box::new(on_change),
+        }))
+    }
+
+    /// Accepts connections on the configured port (0 picks a free one),
+    /// announces the device and starts syncing with paired devices in the
+    /// background. Returns the port.
+    pub fn start(self: &Arc<Self>) -> Result<u16, String> {
+        let mut running = self.running.lock().unwrap();
+        if let Some(running) = running.as_ref() {
+            return Ok(running.port);
+        }
+
+        let port = self.config.lock().unwrap().sync_port;
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+            .map_err(|e| format!("Can't listen on port {}: {}", port, e))?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        let stop = Arc::new(AtomicBool::new(false));
+        log::info!("Sync listening on port {}", port);
+
+        let engine = self.clone();
+        let stopped = stop.clone();
+        thread::spawn(move || {
+            let connections = Arc::new(AtomicUsize::new(0));
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let address = stream.peer_addr().ok();
+                if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                    log::warn!("Too many sync connections, refusing {:?}", address);
+                    continue;
+                }
+                let engine = engine.clone();
+                let connections = connections.clone();
+                thread::spawn(move || {
+                    if let Err(e) = engine.accept(stream) {
+                        log::warn!("Sync connection from {:?} failed: {}", address, e);
+                    }
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+
+        let engine = self.clone();
+        let stopped = stop.clone();
+        thread::spawn(move || engine.sync_loop(&stopped));
+
+        let found = Arc::downgrade(self);
+        let discovery = Discovery::start(&self.device_id(), &self.device_name(), port, {
+            move |device| {
+                if let Some(engine) = found.upgrade() {
+                    engine.device_found(device);
+                }
+            }
+        })
+        .map_err(|e| log::error!("mDNS discovery unavailable: {}", e))
+        .ok();
+
+        *running = Some(Running {
+            port,
+            stop,
+            discovery,
+        });
+        Ok(port)
+    }
+
+    pub fn stop(&self) {
+        if let Some(running) = self.running.lock().unwrap().take() {
+            running.stop.store(true, Ordering::SeqCst);
+            // Wakes up the accept loop so it sees the flag
+            let _ = TcpStream::connect((Ipv4Addr::LOCALHOST, running.port));
+            log::info!("Sync stopped");
+        }
+    }
+
+    pub fn status(&self) -> SyncStatus {
+        let running = self.running.lock().unwrap();
+        SyncStatus {
+            running: running.is_some(),
+            device_id: self.device_id(),
+            device_name: self.device_name(),
+            port: running.as_ref().map(|running| running.port),
+            peers: self.state.lock().unwrap().peers.clone(),
+            discovered: running
+                .as_ref()
+                .and_then(|running| running.discovery.as_ref())
+                .map(Discovery::devices)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// A new pairing code, to be entered on the other device. It pairs one
+    /// device within a few minutes, and survives a few wrong attempts.
+    pub fn start_pairing(&self) -> Result<String, String> {
+        if self.running.lock().unwrap().is_none() {
+            return Err("Sync is turned off".to_string());
+        }
+        let mut code = String::with_capacity(CODE_LEN);
+        while code.len() < CODE_LEN {
+            let byte = (OsRng.next_u32() & 0xff) as u8;
+            // 256 is a multiple of 32, so every character is equally likely
+            code.push(CODE_ALPHABET[byte as usize % CODE_ALPHABET.len()] as char);
+        }
+        *self.pairing.lock().unwrap() = Some(Pairing {
+            code: code.clone(),
+            expires: Instant::now() + PAIRING_TIMEOUT,
+            attempts_left: PAIRING_ATTEMPTS,
+        });
+        Ok(format!(
+            "{}-{}",
+            &code[..CODE_LEN / 2],
+            &code[CODE_LEN / 2..]
+        ))
+    }
+
+    /// Pairs with the device at `address` that shows `code`.
+    pub fn pair(&self, address: &str, code: &str) -> Result<Peer, String> {
+        if self.running.lock().unwrap().is_none() {
+            return Err("Sync is turned off".to_string());
+        }
+        let code = normalize_code(code);
+        if code.len() != CODE_LEN {
+            return Err("The pairing code has 10 characters".to_string());
+        }
+        let (mut channel, hello) = self
+            .connect(address, Purpose::Pair, |_| Some(Secret::Code(&code)))
+            .map_err(|e| e.to_string())?;
+        channel
+            .send(&Message::Identity {
+                public_key: self.public_key(),
+            })
+            .map_err(|e| e.to_string())?;
+        match channel.recv() {
+            Ok(Message::Identity { public_key }) => {
+                self.add_peer(&hello, address_of(address, hello.port), public_key)
+            }
+            Ok(_) => Err("Unexpected reply from the other device".to_string()),
+            Err(_) => Err("Wrong pairing code, or the code has expired".to_string()),
+        }
+    }
+
+    pub fn unpair(&self, device_id: &str) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        state.peers.retain(|peer| peer.device_id != device_id);
+        save_state(&self.state_path, &state)
+    }
+
+    /// Exchanges changes with one paired device.
+    pub fn sync_with(&self, device_id: &str) -> Result<SyncReport, String> {
+        let address = self
+            .peer(device_id)
+            .ok_or("This device isn't paired")?
+            .address
+            .ok_or("The device hasn't been seen on the network yet")?;
+        let (mut channel, hello) = self
+            .connect(&address, Purpose::Sync, |hello| {
+                self.secret_for(&hello.device_id)
+            })
+            .map_err(|e| e.to_string())?;
+        if hello.device_id != device_id {
+            return Err("Another device answered at this address".to_string());
+        }
+
+        let mut report = SyncReport {
+            device_id: hello.device_id.clone(),
+            name: hello.name.clone(),
+            ..Default::default()
+        };
+        report.received = self.pull(&mut channel, device_id)?;
+        channel.send(&Message::Done).map_err(|e| e.to_string())?;
+        report.sent = self.serve(&mut channel, device_id)?;
+        self.finish_session(&report);
+        Ok(report)
+    }
+
+    /// Syncs with every paired device that has an address, skipping the ones
+    /// that can't be reached.
+    pub fn sync_all(&self) -> Vec<SyncReport> {
+        let peers = self.state.lock().unwrap().peers.clone();
+        peers
+            .iter()
+            .filter(|peer| peer.address.is_some())
+            .filter_map(|peer| match self.sync_with(&peer.device_id) {
+                Ok(report) => Some(report),
+                Err(e) => {
+                    log::warn!("Failed to sync with {}: {}", peer.name, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn sync_loop(&self, stop: &AtomicBool) {
+        let mut synced_seq = -1;
+        let mut last_full_sync = Instant::now();
+        while !stop.load(Ordering::SeqCst) {
+            thread::sleep(POLL_INTERVAL);
+            let seq = self.db.last_change_seq().unwrap_or(synced_seq);
+            if seq != synced_seq || last_full_sync.elapsed() >= FULL_SYNC_INTERVAL {
+                self.sync_all();
+                synced_seq = seq;
+                last_full_sync = Instant::now();
+            }
+        }
+    }
+
+    // Paired devices are synced as soon as they show up
+    fn device_found(&self, device: &DiscoveredDevice) {
+        if self.peer(&device.device_id).is_none() {
+            return;
+        }
+        self.set_peer_address(&device.device_id, device.address.clone());
+        if let Err(e) = self.sync_with(&device.device_id) {
+            log::warn!("Failed to sync with {}: {}", device.name, e);
+        }
+    }
+
+    /// Handles a connection from another device.
+    fn accept(&self, mut stream: TcpStream) -> Result<(), String> {
+        stream.set_read_timeout(Some(IO_TIMEOUT)).ok();
+        stream.set_write_timeout(Some(IO_TIMEOUT)).ok();
+        let ip = stream.peer_addr().map_err(|e| e.to_string())?.ip();
+        let (hello, their_bytes) = protocol::read_hello(&mut stream).map_err(|e| e.to_string())?;
+
+        let mut code = String::new();
+        let secret = match hello.purpose {
+            Purpose::Pair => {
+                code = self
+                    .pairing
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .filter(|pairing| Instant::now() < pairing.expires)
+                    .map(|pairing| pairing.code.clone())
+                    .ok_or("Pairing wasn't started on this device")?;
+                Secret::Code(&code)
+            }
+            Purpose::Sync => self
+                .secret_for(&hello.device_id)
+                .ok_or_else(|| format!("{} isn't paired", hello.name))?,
+        };
+
+        let (ephemeral, our_bytes) = protocol::send_hello(
+            &mut stream,
+            hello.purpose,
+            &self.device_id(),
+            &self.device_name(),
+            self.port(),
+        )
+        .map_err(|e| e.to_string())?;
+        let mut channel = Channel::new(
+            stream,
+            ephemeral,
+            &their_bytes,
+            &our_bytes,
+            &hello,
+            secret,
+            false,
+        )
+        .map_err(|e| e.to_string())?;
+        let address = SocketAddr::new(ip, hello.port).to_string();
+
+        match hello.purpose {
+            Purpose::Pair => {
+                // Used up only once the other side has shown it knows the code
+                let Ok(Message::Identity { public_key }) = channel.recv() else {
+                    self.pairing_failed(&code);
+                    return Err(format!("Wrong pairing code from {}", hello.name));
+                };
+                if !self.take_pairing(&code) {
+                    return Err("Another device paired with this code".to_string());
+                }
+                channel
+                    .send(&Message::Identity {
+                        public_key: self.public_key(),
+                    })
+                    .map_err(|e| e.to_string())?;
+                self.add_peer(&hello, address, public_key)?;
+            }
+            Purpose::Sync => {
+                self.set_peer_address(&hello.device_id, address);
+                let mut report = SyncReport {
+                    device_id: hello.device_id.clone(),
+                    name: hello.name.clone(),
+                    ..Default::default()
+                };
+                report.sent = self.serve(&mut channel, &hello.device_id)?;
+                report.received = self.pull(&mut channel, &hello.device_id)?;
+                channel.send(&Message::Done).map_err(|e| e.to_string())?;
+                self.finish_session(&report);
+            }
+        }
+        Ok(())
+    }
+
+    fn take_pairing(&self, code: &str) -> bool {
+        let mut pairing = self.pairing.lock().unwrap();
+        if pairing.as_ref().is_some_and(|pairing| pairing.code == code) {
+            *pairing = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn pairing_failed(&self, code: &str) {
+        let mut pairing = self.pairing.lock().unwrap();
+        if let Some(current) = pairing.as_mut().filter(|pairing| pairing.code == code) {
+            current.attempts_left -= 1;
+            if current.attempts_left == 0 {
+                *pairing = None;
+            }
+        }
+    }
+
+    /// Connects and shakes hands. `secret` picks the session secret once
+    /// the other side's hello is in.
+    fn connect<'a>(
+        &'a self,
+        address: &str,
+        purpose: Purpose,
+        secret: impl FnOnce(&protocol::Hello) -> Option<Secret<'a>>,
+    ) -> io::Result<(Channel, protocol::Hello)> {
+        let addr = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other("Invalid address"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, IO_TIMEOUT)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        let (ephemeral, our_bytes) = protocol::send_hello(
+            &mut stream,
+            purpose,
+            &self.device_id(),
+            &self.device_name(),
+            self.port(),
+        )?;
+        let (hello, their_bytes) = protocol::read_hello(&mut stream)?;
+        let secret = secret(&hello).ok_or_else(|| io::Error::other("Unknown device"))?;
+        let channel = Channel::new(
+            stream,
+            ephemeral,
+            &our_bytes,
+            &their_bytes,
+            &hello,
+            secret,
+            true,
+        )?;
+        Ok((channel, hello))
+    }
+
+    /// Asks the other side for its changes until it has no more.
+    fn pull(&self, channel: &mut Channel, device_id: &str) -> Result<usize, String> {
+        let mut applied = 0;
+        loop {
+            let since = self.peer(device_id).map_or(0, |peer| peer.cursor);
+            channel
+                .send(&Message::Pull { since })
+                .map_err(|e| e.to_string())?;
+            let Message::Changes {
+                records,
+                deletions,
+                next,
+                more,
+            } = channel.recv().map_err(|e| e.to_string())?
+            else {
+                return Err("Unexpected message from the other device".to_string());
+            };
+            for record in records {
+                match self.apply(record, device_id) {
+                    Ok(true) => applied += 1,
+                    Ok(false) => {}
+                    Err(e) => log::warn!("Failed to apply synced item: {}", e),
+                }
+            }
+            for sync_id in deletions {
+                match self.db.delete_sync_item(&sync_id, device_id) {
+                    Ok(true) => applied += 1,
+                    Ok(false) => {}
+                    Err(e) => log::warn!("Failed to apply synced deletion: {}", e),
+                }
+            }
+            self.update_peer(device_id, |peer| peer.cursor = next)?;
+            if !more {
+                return Ok(applied);
+            }
+        }
+    }
+
+    /// Answers the other side's requests until it is done. What came from
+    /// that device isn't sent back.
+    fn serve(&self, channel: &mut Channel, device_id: &str) -> Result<usize, String> {
+        let mut sent = 0;
+        loop {
+            match channel.recv().map_err(|e| e.to_string())? {
+                Message::Pull { since } => {
+                    let (records, deletions, next, more) = self.changes(since, device_id)?;
+                    sent += records.len() + deletions.len();
+                    channel
+                        .send(&Message::Changes {
+                            records,
+                            deletions,
+                            next,
+                            more,
+                        })
+                        .map_err(|e| e.to_string())?;
+                }
+                Message::Done => return Ok(sent),
+                _ => return Err("Unexpected message from the other device".to_string()),
+            }
+        }
+    }
+
+    /// The next batch of changes and deletions after `since` for `device_id`,
+    /// with image files attached.
+    #[allow(clippy::type_complexity)]
+    fn changes(
+        &self,
+        since: i64,
+        device_id: &str,
+    ) -> Result<(Vec<SyncRecord>, Vec<String>, i64, bool), String> {
+        let (mut records, mut next) = self
+            .db
+            .get_sync_changes(since, BATCH_SIZE, Some(device_id))
+            .map_err(|e| e.to_string())?;
+        let mut more = records.len() == BATCH_SIZE;
+
+        let mut size = 0;
+        let mut batch = Vec::with_capacity(records.len());
+        for mut record in records.drain(..) {
+            if size >= BATCH_BYTES {
+                // The rest goes with the next request
+                more = true;
+                next = batch
+                    .last()
+                    .map_or(since, |last: &SyncRecord| last.change_seq);
+                break;
+            }
+            match attach_image(&mut record) {
+                Ok(len) => size += len,
+                Err(e) => {
+                    log::warn!("Not syncing image {}: {}", record.content, e);
+                    continue;
+                }
+            }
+            batch.push(record);
+        }
+
+        // Both count on the change counter. What lies past the end of the
+        // shorter one comes again with the next batch, which is harmless.
+        let (deletions, deleted_next) = self
+            .db
+            .get_sync_deletions(since, BATCH_SIZE, Some(device_id))
+            .map_err(|e| e.to_string())?;
+        more |= deletions.len() == BATCH_SIZE;
+        Ok((batch, deletions, next.min(deleted_next), more))
+    }
+
+    fn apply(&self, record: SyncRecord, device_id: &str) -> Result<bool, String> {
+        let config = self.config.lock().unwrap().clone();
+        apply_record(&self.db, &self.image_store, &config, record, device_id)
+    }
+
+    fn finish_session(&self, report: &SyncReport) {
+        let _ = self.update_peer(&report.device_id, |peer| {
+            peer.last_sync = Some(Local::now().format("%Y-%m-%d %H:%M:%S").to_string());
+        });
+        if report.received > 0 {
+            log::info!("Received {} items from {}", report.received, report.name);
+            (self.on_change)();
+        }
+    }
+
+    fn add_peer(
+        &self,
+        hello: &protocol::Hello,
+        address: String,
+        public_key: String,
+    ) -> Result<Peer, String> {
+        protocol::decode_key(&public_key).ok_or("Invalid key from the other device")?;
+        let peer = Peer {
+            device_id: hello.device_id.clone(),
+            name: hello.name.clone(),
+            public_key,
+            address: Some(address),
+            cursor: 0,
+            last_sync: None,
+        };
+        let mut state = self.state.lock().unwrap();
+        state
+            .peers
+            .retain(|existing| existing.device_id != peer.device_id);
+        state.peers.push(peer.clone());
+        save_state(&self.state_path, &state)?;
+        log::info!("Paired with {}", peer.name);
+        Ok(peer)
+    }
+
+    fn set_peer_address(&self, device_id: &str, address: String) {
+        let _ = self.update_peer(device_id, |peer| peer.address = Some(address));
+    }
+
+    fn update_peer(&self, device_id: &str, update: impl FnOnce(&mut Peer)) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(peer) = state
+            .peers
+            .iter_mut()
+            .find(|peer| peer.device_id == device_id)
+        {
+            update(peer);
+        }
+        save_state(&self.state_path, &state)
+    }
+
+    fn peer(&self, device_id: &str) -> Option<Peer> {
+        let state = self.state.lock().unwrap();
+        state
+            .peers
+            .iter()
+            .find(|peer| peer.device_id == device_id)
+            .cloned()
+    }
+
+    fn secret_for(&self, device_id: &str) -> Option<Secret<'_>> {
+        let key = protocol::decode_key(&self.peer(device_id)?.public_key)?;
+        Some(Secret::Static {
+            ours: &self.secret,
+            theirs: PublicKey::from(key),
+        })
+    }
+
+    fn device_id(&self) -> String {
+        self.state.lock().unwrap().device_id.clone()
+    }
+
+    fn device_name(&self) -> String {
+        device_name(&self.config.lock().unwrap())
+    }
+
+    fn public_key(&self) -> String {
+        protocol::encode_key(PublicKey::from(&self.secret).as_bytes())
+    }
+
+    fn port(&self) -> u16 {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |running| running.port)
+    }
+}
+
+/// Adds a record from `source`, another device, or merges it into the item
+/// it matches by sync id or content. Records of items deleted since are
+/// dropped. Returns whether anything changed.
+fn apply_record(
+    db: &Database,
+    image_store: &ImageStore,
+    config: &AppConfig,
+    record: SyncRecord,
+    source: &str,
+) -> Result<bool, String> {
+    if db
+        .is_sync_deleted(&record.sync_id)
+        .map_err(|e| e.to_string())?
+    {
+        return Ok(false);
+    }
+    if let Some(id) = db
+        .find_sync_item(&record.sync_id)
+        .map_err(|e| e.to_string())?
+    {
+        return db
+            .merge_sync_record(id, &record, false, source)
+            .map_err(|e| e.to_string());
+    }
+
+    let item = match record.kind.as_str() {
+        "text" => {
+            if let Some(id) = db
+                .find_item("text", &record.content)
+                .map_err(|e| e.to_string())?
+            {
+                return db
+                    .merge_sync_record(id, &record, false, source)
+                    .map_err(|e| e.to_string());
+            }
+            let item = ClipboardItem {
+                content: record.content.clone(),
+                kind: "text".to_string(),
+                html_content: record.html_content.clone(),
+                language: record.language.clone(),
+                ..Default::default()
+            };
+            match apply_size_limits(item, config) {
+                Some(item) => item,
+                None => return Ok(false),
+            }
+        }
+        "image" => {
+            let bytes = general_purpose::STANDARD
+                .decode(record.data.as_deref().unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+            let image = image::load_from_memory(&bytes)
+                .map_err(|e| e.to_string())?
+                .to_rgba8();
+            if config.max_image_size > 0 && image.len() > config.max_image_size {
+                return Ok(false);
+            }
+            // Only the very same image is the same item, one that merely
+            // looks alike is a change of its own on the other device
+            let image_digest = digest(&image);
+            if let Some(id) = db.find_image(&image_digest).map_err(|e| e.to_string())? {
+                return db
+                    .merge_sync_record(id, &record, false, source)
+                    .map_err(|e| e.to_string());
+            }
+            let stored = image_store.save(&image, &config.image_format)?;
+            ClipboardItem {
+                content: stored.path,
+                kind: "image".to_string(),
+                thumbnail: Some(stored.thumbnail_path),
+                width: Some(stored.width),
+                height: Some(stored.height),
+                byte_size: Some(stored.byte_size),
+                image_hash: Some(dhash(&image) as i64),
+                image_digest: Some(image_digest),
+                ..Default::default()
+            }
+        }
+        _ => return Ok(false),
+    };
+
+    let item = ClipboardItem {
+        timestamp: record.timestamp.clone(),
+        source_app: record.source_app.clone(),
+        data_type: record.data_type.clone(),
+        // Pinned before pruning runs, so the item isn't pruned right away
+        is_pinned: record.is_pinned,
+        ..item
+    };
+    let (id, pruned) = db
+        .insert_item(&item, config.max_history_size)
+        .map_err(|e| e.to_string())?;
+    image_store.remove_all(&pruned);
+    if pruned.iter().any(|pruned| pruned.id == Some(id)) {
+        // Older than everything the history keeps
+        return Ok(false);
+    }
+    db.merge_sync_record(id, &record, true, source)
+        .map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// Puts the image file of an image record into the record. Returns the
+/// size the record adds to a batch.
+fn attach_image(record: &mut SyncRecord) -> io::Result<usize> {
+    if record.kind != "image" {
+        return Ok(record.content.len());
+    }
+    let bytes = fs::read(&record.content)?;
+    let len = bytes.len();
+    record.data = Some(general_purpose::STANDARD.encode(bytes));
+    record.content.clear();
+    Ok(len)
+}
+
+/// The name other devices see, the host name unless one is set.
+fn device_name(config: &AppConfig) -> String {
+    if !config.sync_device_name.is_empty() {
+        return config.sync_device_name.clone();
+    }
+    gethostname::gethostname().to_string_lossy().into_owned()
+}
+
+fn save_state(path: &Path, state: &SyncState) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(state).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| e.to_string())
+}
+
+/// Upper case without separators, with the letters Crockford's base32
+/// reads as digits replaced.
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        })
+        .collect()
+}
+
+// The address the other side listens on: the host we reached it at, with
+// the port from its hello.
+fn address_of(address: &str, port: u16) -> String {
+    match address
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+    {
+        Some(addr) => SocketAddr::new(addr.ip(), port).to_string(),
+        None => address.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Node {
+        db: Arc<Database>,
+        engine: Arc<SyncEngine>,
+        address: String,
+        device_id: String,
+    }
+
+    impl Drop for Node {
+        fn drop(&mut self) {
+            self.engine.stop();
+        }
+    }
+
+    fn node(name: &str) -> Node {
+        let dir =
+            std::env::temp_dir().join(format!("clipboard-sync-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let crypto = Arc::new(Crypto::new(dir.join("secret.key")));
+        let db = Arc::new(Database::new(dir.join("history.db"), crypto.clone()).unwrap());
+        let image_store = Arc::new(ImageStore::new(dir.join("images")));
+        let config = AppConfig {
+            sync_port: 0,
+            sync_device_name: name.to_string(),
+            ..Default::default()
+        };
+        let engine = SyncEngine::new(
+            &dir,
+            db.clone(),
+            image_store,
+            &crypto,
+            Arc::new(Mutex::new(config)),
+            || {},
+        )
+        .unwrap();
+        let port = engine.start().unwrap();
+        let device_id = engine.status().device_id;
+        Node {
+            db,
+            engine,
+            address: format!("127.0.0.1:{}", port),
+            device_id,
+        }
+    }
+
+    fn add_text(db: &Database, content: &str) -> i64 {
+        let item = ClipboardItem {
+            content: content.to_string(),
+            kind: "text".into(),
+            timestamp: "2024-01-01 00:00:00".into(),
+            data_type: "text".into(),
+            ..Default::default()
+        };
+        db.insert_item(&item, 100).unwrap().0
+    }
+
+    fn find(db: &Database, content: &str) -> Option<ClipboardItem> {
+        db.get_history(1, 100, None, false, false, None, None, None)
+            .unwrap()
+            .into_iter()
+            .find(|item| item.content == content)
+    }
+
+    #[test]
+    fn normalizes_codes() {
+        assert_eq!(normalize_code("abcde-fgh1o"), "ABCDEFGH10");
+        assert_eq!(normalize_code(" il0O "), "1100");
+    }
+
+    #[test]
+    fn pairing_code_survives_a_wrong_attempt() {
+        let a = node("pair-a");
+        let b = node("pair-b");
+
+        let code = b.engine.start_pairing().unwrap();
+        assert!(a.engine.pair(&b.address, "AAAAA-AAAAA").is_err());
+        let peer = a.engine.pair(&b.address, &code.to_lowercase()).unwrap();
+        assert_eq!(peer.device_id, b.device_id);
+
+        // Used up by the successful pairing
+        let c = node("pair-c");
+        assert!(c.engine.pair(&b.address, &code).is_err());
+    }
+
+    #[test]
+    fn syncs_changes_between_two_devices() {
+        let a = node("loop-a");
+        let b = node("loop-b");
+        let code = b.engine.start_pairing().unwrap();
+        a.engine.pair(&b.address, &code).unwrap();
+
+        // A new record, both ways
+        let id = add_text(&a.db, "from a");
+        add_text(&b.db, "from b");
+        a.engine.sync_with(&b.device_id).unwrap();
+        assert!(find(&b.db, "from a").is_some());
+        assert!(find(&a.db, "from b").is_some());
+
+        // Pin, note and collection
+        a.db.set_pinned(id, true).unwrap();
+        a.db.update_content(
+            id,
+            "from a".into(),
+            None,
+            "text".into(),
+            None,
+            false,
+            Some("a note".into()),
+        )
+        .unwrap();
+        let work = a.db.create_collection("Work".into()).unwrap();
+        a.db.set_item_collection(id, Some(work.id)).unwrap();
+        b.engine.sync_with(&a.device_id).unwrap();
+        let item = find(&b.db, "from a").unwrap();
+        assert!(item.is_pinned);
+        assert_eq!(item.note.as_deref(), Some("a note"));
+        let collection =
+            b.db.get_collections()
+                .unwrap()
+                .into_iter()
+                .find(|collection| Some(collection.id) == item.collection_id)
+                .unwrap();
+        assert_eq!(collection.name, "Work");
+
+        // Deletion, and the record coming again doesn't bring it back
+        a.db.delete_item(0).unwrap();
+        a.engine.sync_with(&b.device_id).unwrap();
+        assert!(find(&b.db, "from a").is_none());
+        assert!(b
+            .db
+            .get_trash()
+            .unwrap()
+            .iter()
+            .any(|item| item.content == "from a"));
+        a.engine.sync_with(&b.device_id).unwrap();
+        assert!(find(&b.db, "from a").is_none());
+    }
+
+    #[test]
+    fn images_are_matched_by_their_pixels() {
+        let dir =
+            std::env::temp_dir().join(format!("clipboard-sync-images-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let db = Database::new(
+            dir.join("history.db"),
+            Arc::new(Crypto::new(dir.join("key"))),
+        )
+        .unwrap();
+        let image_store = ImageStore::new(dir.join("images"));
+        let config = AppConfig::default();
+        let record = |sync_id: &str, image: &image::RgbaImage| {
+            let mut png = Vec::new();
+            image
+                .write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png)
+                .unwrap();
+            SyncRecord {
+                sync_id: sync_id.to_string(),
+                kind: "image".into(),
+                data: Some(general_purpose::STANDARD.encode(png)),
+                timestamp: "2024-01-01 00:00:00".into(),
+                data_type: "image".into(),
+                ..Default::default()
+            }
+        };
+        let white = image::RgbaImage::from_pixel(64, 64, image::Rgba([255; 4]));
+        let mut edited = white.clone();
+        edited.put_pixel(0, 0, image::Rgba([0, 0, 0, 255]));
+        assert_eq!(dhash(&white), dhash(&edited));
+
+        let images = |db: &Database| db.get_image_items().unwrap().len();
+        assert!(apply_record(
+            &db,
+            &image_store,
+            &config,
+            record(&"a".repeat(32), &white),
+            "peer"
+        )
+        .unwrap());
+        // Looks the same but isn't, so it's kept
+        assert!(apply_record(
+            &db,
+            &image_store,
+            &config,
+            record(&"b".repeat(32), &edited),
+            "peer"
+        )
+        .unwrap());
+        assert_eq!(images(&db), 2);
+        // The same image copied on both devices is one item
+        apply_record(
+            &db,
+            &image_store,
+            &config,
+            record(&"c".repeat(32), &white),
+            "peer",
+        )
+        .unwrap();
+        assert_eq!(images(&db), 2);
+    }
+}
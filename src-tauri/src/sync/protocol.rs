@@ -0,0 +1,251 @@
+//! The wire format of sync connections.
+//!
+//! A frame is a 4 byte big-endian length followed by the payload. Both sides
+//! start with a [`Hello`] in the clear that carries a fresh X25519 key.
+//! Everything after that is a JSON [`Message`] sealed with AES-256-GCM, with
+//! one key per direction derived by HKDF from the exchange of those keys and
+//! a [`Secret`] both sides have to know: the pairing code when pairing, the
+//! exchange of both devices' long-term keys once paired.
+
+use crate::models::SyncRecord;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::{engine::general_purpose, Engine as _};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+// Hellos come from anyone who can connect, so they get little room
+const MAX_HELLO_SIZE: usize = 4 * 1024;
+// Batches are cut well below this, it only guards against garbage lengths
+const MAX_FRAME_SIZE: usize = 256 * 1024 * 1024;
+// The code is only good for one attempt, this slows down guessing it from
+// a recorded handshake
+const PAIRING_ITERATIONS: u32 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Purpose {
+    Pair,
+    Sync,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Hello {
+    pub version: u32,
+    pub purpose: Purpose,
+    pub device_id: String,
+    pub name: String,
+    // Port the sender accepts sync connections on
+    pub port: u16,
+    pub ephemeral_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    // Sent by both sides when pairing
+    Identity {
+        public_key: String,
+    },
+    // Asks for the changes after `since`
+    Pull {
+        since: i64,
+    },
+    Changes {
+        records: Vec<SyncRecord>,
+        // Sync ids of items moved to the trash
+        #[serde(default)]
+        deletions: Vec<String>,
+        next: i64,
+        more: bool,
+    },
+    // The sender has no more requests
+    Done,
+}
+
+/// What besides the ephemeral keys goes into the session keys.
+pub enum Secret<'a> {
+    Code(&'a str),
+    Static {
+        ours: &'a StaticSecret,
+        theirs: PublicKey,
+    },
+}
+
+/// Sends our hello and returns the key to finish the handshake with, and
+/// the bytes that were sent.
+pub fn send_hello(
+    stream: &mut TcpStream,
+    purpose: Purpose,
+    device_id: &str,
+    name: &str,
+    port: u16,
+) -> io::Result<(EphemeralSecret, Vec<u8>)> {
+    let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+    let hello = Hello {
+        version: PROTOCOL_VERSION,
+        purpose,
+        device_id: device_id.to_string(),
+        name: name.to_string(),
+        port,
+        ephemeral_key: encode_key(PublicKey::from(&ephemeral).as_bytes()),
+    };
+    let bytes = serde_json::to_vec(&hello)?;
+    if bytes.len() > MAX_HELLO_SIZE {
+        return Err(invalid_data("The device name is too long"));
+    }
+    write_frame(stream, &bytes)?;
+    Ok((ephemeral, bytes))
+}
+
+pub fn read_hello(stream: &mut TcpStream) -> io::Result<(Hello, Vec<u8>)> {
+    let bytes = read_frame(stream, MAX_HELLO_SIZE)?;
+    let hello: Hello = serde_json::from_slice(&bytes).map_err(invalid_data)?;
+    if hello.version != PROTOCOL_VERSION {
+        return Err(invalid_data(format!(
+            "{} uses sync protocol version {}, this device {}",
+            hello.name, hello.version, PROTOCOL_VERSION
+        )));
+    }
+    Ok((hello, bytes))
+}
+
+pub fn encode_key(key: &[u8; 32]) -> String {
+    general_purpose::STANDARD.encode(key)
+}
+
+pub fn decode_key(key: &str) -> Option<[u8; 32]> {
+    general_purpose::STANDARD.decode(key).ok()?.try_into().ok()
+}
+
+/// An encrypted connection.
+pub struct Channel {
+    stream: TcpStream,
+    send_cipher: Aes256Gcm,
+    recv_cipher: Aes256Gcm,
+    sent: u64,
+    received: u64,
+}
+
+impl Channel {
+    /// Derives the session keys once both hellos went over `stream`.
+    /// `initiator` is the side that connected.
+    pub fn new(
+        stream: TcpStream,
+        ephemeral: EphemeralSecret,
+        initiator_hello: &[u8],
+        responder_hello: &[u8],
+        their_hello: &Hello,
+        secret: Secret,
+        initiator: bool,
+    ) -> io::Result<Self> {
+        let their_key = decode_key(&their_hello.ephemeral_key)
+            .map(PublicKey::from)
+            .ok_or_else(|| invalid_data("Invalid ephemeral key"))?;
+        let shared = ephemeral.diffie_hellman(&their_key);
+        if !shared.was_contributory() {
+            return Err(invalid_data("Invalid ephemeral key"));
+        }
+
+        let transcript = Sha256::new()
+            .chain_update(initiator_hello)
+            .chain_update(responder_hello)
+            .finalize();
+        let mut material = shared.as_bytes().to_vec();
+        match secret {
+            Secret::Code(code) => {
+                let mut stretched = [0u8; 32];
+                pbkdf2::pbkdf2_hmac::<Sha256>(
+                    code.as_bytes(),
+                    &transcript,
+                    PAIRING_ITERATIONS,
+                    &mut stretched,
+                );
+                material.extend_from_slice(&stretched);
+            }
+            Secret::Static { ours, theirs } => {
+                material.extend_from_slice(ours.diffie_hellman(&theirs).as_bytes());
+            }
+        }
+
+        let hkdf = Hkdf::<Sha256>::new(Some(&transcript), &material);
+        let cipher = |info: &[u8]| {
+            let mut key = Key::<Aes256Gcm>::default();
+            hkdf.expand(info, &mut key).expect("valid key length");
+            Aes256Gcm::new(&key)
+        };
+        let (initiator_cipher, responder_cipher) = (
+            cipher(b"clipboard sync initiator"),
+            cipher(b"clipboard sync responder"),
+        );
+        let (send_cipher, recv_cipher) = if initiator {
+            (initiator_cipher, responder_cipher)
+        } else {
+            (responder_cipher, initiator_cipher)
+        };
+        Ok(Self {
+            stream,
+            send_cipher,
+            recv_cipher,
+            sent: 0,
+            received: 0,
+        })
+    }
+
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        let json = serde_json::to_vec(message)?;
+        let sealed = self
+            .send_cipher
+            .encrypt(&nonce(self.sent), Payload::from(json.as_slice()))
+            .map_err(|_| io::Error::other("Encryption failed"))?;
+        self.sent += 1;
+        write_frame(&mut self.stream, &sealed)
+    }
+
+    /// Fails with `InvalidData` when the other side used different keys,
+    /// i.e. a wrong pairing code or a device we aren't paired with.
+    pub fn recv(&mut self) -> io::Result<Message> {
+        let sealed = read_frame(&mut self.stream, MAX_FRAME_SIZE)?;
+        let json = self
+            .recv_cipher
+            .decrypt(&nonce(self.received), Payload::from(sealed.as_slice()))
+            .map_err(|_| invalid_data("Can't decrypt the message"))?;
+        self.received += 1;
+        serde_json::from_slice(&json).map_err(invalid_data)
+    }
+}
+
+// Messages are numbered per direction, so none can be replayed or reordered
+fn nonce(counter: u64) -> Nonce<aes_gcm::aead::consts::U12> {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce.into()
+}
+
+fn write_frame(stream: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(data.len()).map_err(|_| invalid_data("Frame too large"))?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(data)?;
+    stream.flush()
+}
+
+fn read_frame(stream: &mut impl Read, max_size: usize) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_size {
+        return Err(invalid_data("Frame too large"));
+    }
+    let mut data = vec![0u8; len];
+    stream.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
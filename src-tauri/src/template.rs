@@ -0,0 +1,351 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::Local;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+const DEFAULT_TIME_FORMAT: &str = "%H:%M:%S";
+
+/// A problem in a snippet body, located by character offset and by
+/// 1-based line and column so the editor can point at it.
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateError {
+    pub message: String,
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Placeholder {
+    Date(String),
+    Time(String),
+    Clipboard,
+    Uuid,
+    Cursor,
+    Input(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    // Placeholder and the char offset of its opening braces
+    Placeholder(Placeholder, usize),
+}
+
+/// A parsed snippet body.
+///
+/// Supported placeholders are `{{date}}` / `{{date:<strftime>}}`,
+/// `{{time}}` / `{{time:<strftime>}}`, `{{clipboard}}`, `{{uuid}}`,
+/// `{{cursor}}` and `{{input:<name>}}`. A literal `{{` is written as `\{{`.
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+/// Values only known at paste time.
+#[derive(Default)]
+pub struct ExpandContext<'a> {
+    pub clipboard: Option<String>,
+    pub inputs: Option<&'a HashMap<String, String>>,
+}
+
+pub struct Expanded {
+    pub text: String,
+    // Where `{{cursor}}` was, counted in chars from the end of the text
+    pub cursor_from_end: Option<usize>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let chars: Vec<char> = source.chars().collect();
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut cursor_seen = false;
+        let mut i = 0;
+
+        while i < chars.len() {
+            if chars[i] == '\\' && chars.get(i + 1) == Some(&'{') && chars.get(i + 2) == Some(&'{')
+            {
+                text.push_str("{{");
+                i += 3;
+                continue;
+            }
+            if chars[i] != '{' || chars.get(i + 1) != Some(&'{') {
+                text.push(chars[i]);
+                i += 1;
+                continue;
+            }
+
+            let start = i;
+            let body_start = i + 2;
+            let mut end = body_start;
+            while end < chars.len() && !(chars[end] == '}' && chars.get(end + 1) == Some(&'}')) {
+                if chars[end] == '{' && chars.get(end + 1) == Some(&'{') {
+                    return Err(error_at(source, end, "Placeholders can't be nested"));
+                }
+                end += 1;
+            }
+            if end >= chars.len() {
+                return Err(error_at(
+                    source,
+                    start,
+                    "Unclosed placeholder, expected '}}'",
+                ));
+            }
+
+            let body: String = chars[body_start..end].iter().collect();
+            let placeholder = parse_placeholder(source, body_start, body.trim())?;
+            if placeholder == Placeholder::Cursor {
+                if cursor_seen {
+                    return Err(error_at(source, start, "Only one {{cursor}} is allowed"));
+                }
+                cursor_seen = true;
+            }
+
+            if !text.is_empty() {
+                segments.push(Segment::Text(std::mem::take(&mut text)));
+            }
+            segments.push(Segment::Placeholder(placeholder, start));
+            i = end + 2;
+        }
+
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            segments,
+        })
+    }
+
+    /// Names of the `{{input:<name>}}` values the user has to fill in, in
+    /// order of first appearance.
+    pub fn inputs(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for segment in &self.segments {
+            if let Segment::Placeholder(Placeholder::Input(name), _) = segment {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    pub fn expand(&self, ctx: &ExpandContext) -> Result<Expanded, TemplateError> {
+        let now = Local::now();
+        let mut text = String::new();
+        let mut cursor = None;
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(s) => text.push_str(s),
+                Segment::Placeholder(placeholder, offset) => match placeholder {
+                    Placeholder::Date(format) | Placeholder::Time(format) => {
+                        text.push_str(&now.format(format).to_string())
+                    }
+                    Placeholder::Clipboard => {
+                        text.push_str(ctx.clipboard.as_deref().unwrap_or_default())
+                    }
+                    Placeholder::Uuid => text.push_str(&uuid::Uuid::new_v4().to_string()),
+                    Placeholder::Cursor => cursor = Some(text.chars().count()),
+                    Placeholder::Input(name) => {
+                        let value =
+                            ctx.inputs
+                                .and_then(|inputs| inputs.get(name))
+                                .ok_or_else(|| {
+                                    error_at(
+                                        &self.source,
+                                        *offset,
+                                        &format!("No value for input '{}'", name),
+                                    )
+                                })?;
+                        text.push_str(value);
+                    }
+                },
+            }
+        }
+
+        let cursor_from_end = cursor.map(|position| text.chars().count() - position);
+        Ok(Expanded {
+            text,
+            cursor_from_end,
+        })
+    }
+}
+
+/// Parses and expands a snippet body in one go.
+pub fn expand(source: &str, ctx: &ExpandContext) -> Result<Expanded, TemplateError> {
+    Template::parse(source)?.expand(ctx)
+}
+
+fn parse_placeholder(
+    source: &str,
+    offset: usize,
+    body: &str,
+) -> Result<Placeholder, TemplateError> {
+    let (name, arg) = match body.split_once(':') {
+        Some((name, arg)) => (name.trim(), Some(arg)),
+        None => (body, None),
+    };
+
+    let placeholder = match (name, arg) {
+        ("date", arg) => Placeholder::Date(date_format(source, offset, arg, DEFAULT_DATE_FORMAT)?),
+        ("time", arg) => Placeholder::Time(date_format(source, offset, arg, DEFAULT_TIME_FORMAT)?),
+        ("clipboard", None) => Placeholder::Clipboard,
+        ("uuid", None) => Placeholder::Uuid,
+        ("cursor", None) => Placeholder::Cursor,
+        ("input", Some(input)) => {
+            let input = input.trim();
+            if input.is_empty() {
+                return Err(error_at(source, offset, "Input placeholders need a name"));
+            }
+            Placeholder::Input(input.to_string())
+        }
+        ("input", None) => {
+            return Err(error_at(source, offset, "Input placeholders need a name"));
+        }
+        ("clipboard" | "uuid" | "cursor", Some(_)) => {
+            return Err(error_at(
+                source,
+                offset,
+                &format!("'{}' doesn't take an argument", name),
+            ));
+        }
+        ("", _) => return Err(error_at(source, offset, "Empty placeholder")),
+        _ => {
+            return Err(error_at(
+                source,
+                offset,
+                &format!("Unknown placeholder '{}'", name),
+            ))
+        }
+    };
+    Ok(placeholder)
+}
+
+fn date_format(
+    source: &str,
+    offset: usize,
+    arg: Option<&str>,
+    default: &str,
+) -> Result<String, TemplateError> {
+    let format = match arg {
+        Some(arg) if !arg.is_empty() => arg,
+        _ => default,
+    };
+    // chrono panics when an invalid format is displayed, so check it up front
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(error_at(
+            source,
+            offset,
+            &format!("Invalid date format '{}'", format),
+        ));
+    }
+    Ok(format.to_string())
+}
+
+fn error_at(source: &str, offset: usize, message: &str) -> TemplateError {
+    let mut line = 1;
+    let mut column = 1;
+    for c in source.chars().take(offset) {
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    TemplateError {
+        message: message.to_string(),
+        offset,
+        line,
+        column,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(source: &str) -> TemplateError {
+        Template::parse(source).err().unwrap()
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        let inputs = HashMap::from([("name".to_string(), "Bob".to_string())]);
+        let ctx = ExpandContext {
+            clipboard: Some("copied".to_string()),
+            inputs: Some(&inputs),
+        };
+        let expanded = expand("Hi {{ input:name }}, {{clipboard}} {{cursor}}!", &ctx).unwrap();
+        assert_eq!(expanded.text, "Hi Bob, copied !");
+        assert_eq!(expanded.cursor_from_end, Some(1));
+
+        let expanded = expand("{{date:%Y}}|{{time:%H:%M}}|{{date}}|{{uuid}}", &ctx).unwrap();
+        let parts: Vec<&str> = expanded.text.split('|').collect();
+        assert_eq!(parts[0].len(), 4);
+        assert_eq!(parts[1].len(), 5);
+        assert_eq!(parts[2].len(), "2024-01-01".len());
+        assert!(uuid::Uuid::parse_str(parts[3]).is_ok());
+        assert_eq!(expanded.cursor_from_end, None);
+    }
+
+    #[test]
+    fn keeps_escaped_and_stray_braces() {
+        let expanded = expand(r"\{{x}} a}}b {c}", &ExpandContext::default()).unwrap();
+        assert_eq!(expanded.text, "{{x}} a}}b {c}");
+    }
+
+    #[test]
+    fn lists_inputs_once_in_order() {
+        let template = Template::parse("{{input:a}} {{input:b}} {{input:a}}").unwrap();
+        assert_eq!(template.inputs(), ["a", "b"]);
+
+        // A missing value points at the braces of its placeholder
+        let error = template.expand(&ExpandContext::default()).err().unwrap();
+        assert_eq!(error.offset, 0);
+        assert_eq!(error.message, "No value for input 'a'");
+    }
+
+    #[test]
+    fn locates_errors() {
+        let error = parse_error("ok\nab {{foo}}");
+        assert_eq!((error.line, error.column, error.offset), (2, 6, 8));
+        assert_eq!(
+            error.to_string(),
+            "line 2, column 6: Unknown placeholder 'foo'"
+        );
+        let error = parse_error("x {{date");
+        assert_eq!((error.line, error.column), (1, 3));
+        assert_eq!(error.message, "Unclosed placeholder, expected '}}'");
+    }
+
+    #[test]
+    fn rejects_invalid_placeholders() {
+        for source in [
+            "{{date:%Q}}",
+            "{{cursor}}{{cursor}}",
+            "{{input}}",
+            "{{input: }}",
+            "{{uuid:4}}",
+            "{{}}",
+            "{{a {{b}} }}",
+        ] {
+            assert!(Template::parse(source).is_err(), "{}", source);
+        }
+    }
+}
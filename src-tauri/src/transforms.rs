@@ -0,0 +1,393 @@
+use base64::{engine::general_purpose, Engine as _};
+use heck::{ToLowerCamelCase, ToSnakeCase};
+
+/// Text of an item together with its rich-text version, if it has one.
+#[derive(Debug, Clone)]
+pub struct Content {
+    pub text: String,
+    pub html: Option<String>,
+}
+
+enum Op {
+    // Works on the plain text; the HTML no longer matches and is dropped
+    Text(fn(&str) -> Result<String, String>),
+    // Needs to see both versions
+    Content(fn(Content) -> Result<Content, String>),
+}
+
+struct Transform {
+    name: &'static str,
+    op: Op,
+}
+
+static TRANSFORMS: &[Transform] = &[
+    Transform {
+        name: "upper",
+        op: Op::Text(upper),
+    },
+    Transform {
+        name: "lower",
+        op: Op::Text(lower),
+    },
+    Transform {
+        name: "title",
+        op: Op::Text(title),
+    },
+    Transform {
+        name: "snake",
+        op: Op::Text(snake),
+    },
+    Transform {
+        name: "camel",
+        op: Op::Text(camel),
+    },
+    Transform {
+        name: "trim",
+        op: Op::Text(trim),
+    },
+    Transform {
+        name: "collapse_whitespace",
+        op: Op::Text(collapse_whitespace),
+    },
+    Transform {
+        name: "strip_formatting",
+        op: Op::Content(strip_formatting),
+    },
+    Transform {
+        name: "json_pretty",
+        op: Op::Text(json_pretty),
+    },
+    Transform {
+        name: "json_minify",
+        op: Op::Text(json_minify),
+    },
+    Transform {
+        name: "base64_encode",
+        op: Op::Text(base64_encode),
+    },
+    Transform {
+        name: "base64_decode",
+        op: Op::Text(base64_decode),
+    },
+    Transform {
+        name: "url_encode",
+        op: Op::Text(url_encode),
+    },
+    Transform {
+        name: "url_decode",
+        op: Op::Text(url_decode),
+    },
+    Transform {
+        name: "html_to_markdown",
+        op: Op::Content(html_to_markdown),
+    },
+    Transform {
+        name: "sort_lines",
+        op: Op::Text(sort_lines),
+    },
+    Transform {
+        name: "unique_lines",
+        op: Op::Text(unique_lines),
+    },
+    Transform {
+        name: "shell_quote",
+        op: Op::Text(shell_quote),
+    },
+    Transform {
+        name: "shell_escape",
+        op: Op::Text(shell_escape),
+    },
+];
+
+/// Names of all built-in transforms, in the order the UI should list them.
+pub fn list_transforms() -> Vec<&'static str> {
+    TRANSFORMS.iter().map(|t| t.name).collect()
+}
+
+/// Runs the named transforms in order. Fails on the first unknown name or
+/// on a transform that can't handle the content (e.g. invalid JSON).
+pub fn apply_transforms(mut content: Content, names: &[String]) -> Result<Content, String> {
+    for name in names {
+        let transform = TRANSFORMS
+            .iter()
+            .find(|t| t.name == name)
+            .ok_or_else(|| format!("Unknown transform: {}", name))?;
+        content = match transform.op {
+            Op::Text(apply) => Content {
+                text: apply(&content.text).map_err(|e| format!("{}: {}", name, e))?,
+                html: None,
+            },
+            Op::Content(apply) => apply(content).map_err(|e| format!("{}: {}", name, e))?,
+        };
+    }
+    Ok(content)
+}
+
+// Applies `f` to every line, keeping the line breaks as they were.
+fn per_line(s: &str, f: impl Fn(&str) -> String) -> String {
+    s.split('\n')
+        .map(|line| match line.strip_suffix('\r') {
+            Some(line) => format!("{}\r", f(line)),
+            None => f(line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn upper(s: &str) -> Result<String, String> {
+    Ok(s.to_uppercase())
+}
+
+fn lower(s: &str) -> Result<String, String> {
+    Ok(s.to_lowercase())
+}
+
+fn title(s: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(s.len());
+    let mut word_start = true;
+    for c in s.chars() {
+        if c.is_alphanumeric() || c == '\'' {
+            if word_start {
+                result.extend(c.to_uppercase());
+            } else {
+                result.extend(c.to_lowercase());
+            }
+            word_start = false;
+        } else {
+            result.push(c);
+            word_start = true;
+        }
+    }
+    Ok(result)
+}
+
+fn snake(s: &str) -> Result<String, String> {
+    Ok(per_line(s, |line| line.to_snake_case()))
+}
+
+fn camel(s: &str) -> Result<String, String> {
+    Ok(per_line(s, |line| line.to_lower_camel_case()))
+}
+
+fn trim(s: &str) -> Result<String, String> {
+    Ok(per_line(s.trim(), |line| line.trim().to_string()))
+}
+
+fn collapse_whitespace(s: &str) -> Result<String, String> {
+    Ok(s.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+fn strip_formatting(content: Content) -> Result<Content, String> {
+    // Invisible characters that word processors and web pages leave behind
+    let text = content
+        .text
+        .chars()
+        .filter(|c| {
+            !matches!(
+                c,
+                '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}' | '\u{00AD}'
+            )
+        })
+        .map(|c| match c {
+            '\u{00A0}' | '\u{202F}' => ' ',
+            '\u{2018}' | '\u{2019}' => '\'',
+            '\u{201C}' | '\u{201D}' => '"',
+            _ => c,
+        })
+        .collect();
+    Ok(Content { text, html: None })
+}
+
+fn json_pretty(s: &str) -> Result<String, String> {
+    let value: serde_json::Value = serde_json::from_str(s).map_err(|e| e.to_string())?;
+    serde_json::to_string_pretty(&value).map_err(|e| e.to_string())
+}
+
+fn json_minify(s: &str) -> Result<String, String> {
+    let value: serde_json::Value = serde_json::from_str(s).map_err(|e| e.to_string())?;
+    serde_json::to_string(&value).map_err(|e| e.to_string())
+}
+
+fn base64_encode(s: &str) -> Result<String, String> {
+    Ok(general_purpose::STANDARD.encode(s))
+}
+
+fn base64_decode(s: &str) -> Result<String, String> {
+    let compact: String = s.split_whitespace().collect();
+    let bytes = general_purpose::STANDARD
+        .decode(&compact)
+        .or_else(|_| general_purpose::URL_SAFE.decode(&compact))
+        .or_else(|_| general_purpose::STANDARD_NO_PAD.decode(&compact))
+        .or_else(|_| general_purpose::URL_SAFE_NO_PAD.decode(&compact))
+        .map_err(|e| e.to_string())?;
+    String::from_utf8(bytes).map_err(|_| "decoded data is not text".to_string())
+}
+
+fn url_encode(s: &str) -> Result<String, String> {
+    Ok(urlencoding::encode(s).into_owned())
+}
+
+fn url_decode(s: &str) -> Result<String, String> {
+    urlencoding::decode(s)
+        .map(|s| s.into_owned())
+        .map_err(|e| e.to_string())
+}
+
+fn html_to_markdown(content: Content) -> Result<Content, String> {
+    // Plain text copied from a source view is HTML as well
+    let html = content.html.as_deref().unwrap_or(&content.text);
+    Ok(Content {
+        text: html2md::parse_html(html).trim().to_string(),
+        html: None,
+    })
+}
+
+fn sort_lines(s: &str) -> Result<String, String> {
+    let mut lines: Vec<&str> = s.lines().collect();
+    lines.sort_unstable();
+    Ok(lines.join("\n"))
+}
+
+fn unique_lines(s: &str) -> Result<String, String> {
+    let mut seen = std::collections::HashSet::new();
+    let lines: Vec<&str> = s.lines().filter(|line| seen.insert(*line)).collect();
+    Ok(lines.join("\n"))
+}
+
+fn shell_quote(s: &str) -> Result<String, String> {
+    shlex::try_quote(s)
+        .map(|s| s.into_owned())
+        .map_err(|e| e.to_string())
+}
+
+fn shell_escape(s: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '\n' {
+            // A backslash-newline would be a line continuation, not a newline
+            return Err("multi-line text can't be escaped, use shell_quote".to_string());
+        }
+        if !(c.is_alphanumeric() || "_-./=:,+@%".contains(c)) {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(text: &str, names: &[&str]) -> Result<String, String> {
+        let content = Content {
+            text: text.to_string(),
+            html: None,
+        };
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        apply_transforms(content, &names).map(|content| content.text)
+    }
+
+    #[test]
+    fn changes_case() {
+        assert_eq!(apply("hello wORLD", &["upper"]).unwrap(), "HELLO WORLD");
+        assert_eq!(apply("HeLLo", &["lower"]).unwrap(), "hello");
+        assert_eq!(
+            apply("hello wORLD it's", &["title"]).unwrap(),
+            "Hello World It's"
+        );
+        // Line by line, keeping the line breaks
+        assert_eq!(
+            apply("Hello World\r\nfooBar baz", &["snake"]).unwrap(),
+            "hello_world\r\nfoo_bar_baz"
+        );
+        assert_eq!(apply("hello world", &["camel"]).unwrap(), "helloWorld");
+    }
+
+    #[test]
+    fn cleans_up_whitespace_and_formatting() {
+        assert_eq!(apply("  a  \n  b ", &["trim"]).unwrap(), "a\nb");
+        assert_eq!(
+            apply(" a \n\t b ", &["collapse_whitespace"]).unwrap(),
+            "a b"
+        );
+        assert_eq!(
+            apply("a\u{00A0}b\u{200B}\u{201C}q\u{201D}", &["strip_formatting"]).unwrap(),
+            "a b\"q\""
+        );
+        assert_eq!(
+            apply("c\na\nb\na", &["sort_lines", "unique_lines"]).unwrap(),
+            "a\nb\nc"
+        );
+    }
+
+    #[test]
+    fn encodes_and_decodes() {
+        assert_eq!(
+            apply(r#"{"b":1,"a":[1,2]}"#, &["json_pretty", "json_minify"]).unwrap(),
+            r#"{"b":1,"a":[1,2]}"#
+        );
+        assert_eq!(
+            apply("héllo", &["base64_encode", "base64_decode"]).unwrap(),
+            "héllo"
+        );
+        // Line-wrapped and URL-safe input decodes as well
+        assert_eq!(apply("aGk_\naGk-", &["base64_decode"]).unwrap(), "hi?hi>");
+        assert_eq!(apply("a b&c", &["url_encode"]).unwrap(), "a%20b%26c");
+        assert_eq!(apply("a%20b%26c", &["url_decode"]).unwrap(), "a b&c");
+        assert_eq!(apply("it's", &["shell_quote"]).unwrap(), "\"it's\"");
+        assert_eq!(apply("a b$c", &["shell_escape"]).unwrap(), "a\\ b\\$c");
+    }
+
+    #[test]
+    fn converts_html_to_markdown() {
+        assert_eq!(
+            apply(
+                r#"<h1>Hi</h1><p>a <b>b</b> <a href="http://x">l</a></p>"#,
+                &["html_to_markdown"]
+            )
+            .unwrap(),
+            "Hi\n==========\n\na **b** [l](http://x)"
+        );
+        // The rich text wins over the plain one
+        let content = Content {
+            text: "x".to_string(),
+            html: Some("<b>x</b>".to_string()),
+        };
+        let converted = apply_transforms(content, &["html_to_markdown".to_string()]).unwrap();
+        assert_eq!(converted.text, "**x**");
+        assert_eq!(converted.html, None);
+    }
+
+    #[test]
+    fn text_transforms_drop_the_html() {
+        let content = Content {
+            text: "x".to_string(),
+            html: Some("<b>x</b>".to_string()),
+        };
+        let upper = apply_transforms(content, &["upper".to_string()]).unwrap();
+        assert_eq!(upper.text, "X");
+        assert_eq!(upper.html, None);
+    }
+
+    #[test]
+    fn reports_what_failed() {
+        assert!(apply("{x", &["json_pretty"])
+            .unwrap_err()
+            .starts_with("json_pretty:"));
+        assert!(apply("//8=", &["base64_decode"]).is_err());
+        assert!(apply("a\nb", &["shell_escape"]).is_err());
+        assert_eq!(
+            apply("x", &["upper", "nope"]).unwrap_err(),
+            "Unknown transform: nope"
+        );
+    }
+
+    #[test]
+    fn lists_every_transform_once() {
+        let names = list_transforms();
+        let unique: std::collections::HashSet<_> = names.iter().collect();
+        assert_eq!(unique.len(), names.len());
+        assert_eq!(names[0], "upper");
+    }
+}
@@ -18,6 +18,17 @@ pub fn create_tray_menu(app: &tauri::AppHandle) -> Result<Menu<Wry>, String> {
         .map_err(|e| e.to_string())?;
     menu.append(&pause_item).map_err(|e| e.to_string())?;
 
+    // Paste Stack, enabled while items are queued
+    let paste_stack_item = MenuItem::with_id(
+        app,
+        "paste_stack",
+        "Paste Stack: Empty",
+        false,
+        None::<&str>,
+    )
+    .map_err(|e| e.to_string())?;
+    menu.append(&paste_stack_item).map_err(|e| e.to_string())?;
+
     // Clear History
     let clear_item = MenuItem::with_id(app, "clear", "Clear History", true, None::<&str>)
         .map_err(|e| e.to_string())?;
@@ -74,3 +85,19 @@ pub fn update_pause_menu_item(app: &tauri::AppHandle, is_paused: bool) -> Result
     }
     Ok(())
 }
+
+pub fn update_paste_stack_menu_item(app: &tauri::AppHandle, count: usize) -> Result<(), String> {
+    let state = app.state::<crate::state::AppState>();
+    if let Ok(paste_stack_item) = state.paste_stack_item.lock() {
+        if let Some(item) = paste_stack_item.as_ref() {
+            let text = if count == 0 {
+                "Paste Stack: Empty".to_string()
+            } else {
+                format!("Clear Paste Stack ({} left)", count)
+            };
+            item.set_text(text).map_err(|e| e.to_string())?;
+            item.set_enabled(count > 0).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
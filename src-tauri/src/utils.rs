@@ -1,74 +1,109 @@
-use crate::models::ClipboardItem;
+use crate::models::{AppConfig, ClipboardItem};
+use crate::paste_mode::PasteMode;
+use crate::phash::digest;
 use crate::state::AppState;
+use crate::template::{self, ExpandContext};
 use base64::{engine::general_purpose, Engine as _};
 use clipboard_rs::{Clipboard, ClipboardContent, ClipboardContext};
-use regex::Regex;
+use std::collections::HashMap;
 use std::fs;
 use tauri::Manager;
 use tauri_plugin_clipboard_manager::ClipboardExt;
 
-pub fn classify_content(content: &str) -> String {
-    // URL
-    let url_regex = Regex::new(r"^(https?://|www\.)[^\s/$.?#].[^\s]*$").unwrap();
-    if url_regex.is_match(content) {
-        return "url".to_string();
+/// Applies the configured size limits to a new text item before it is stored.
+/// Returns `None` when the item should be skipped entirely.
+pub fn apply_size_limits(mut item: ClipboardItem, config: &AppConfig) -> Option<ClipboardItem> {
+    if item.kind != "text" {
+        return Some(item);
     }
 
-    // Email
-    let email_regex = Regex::new(r"^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$").unwrap();
-    if email_regex.is_match(content) {
-        return "email".to_string();
+    // HTML can't be cut safely, so oversized markup is always dropped
+    if let Some(html) = &item.html_content {
+        if config.max_html_size > 0 && html.len() > config.max_html_size {
+            log::info!("Dropping {} bytes of HTML over the size limit", html.len());
+            item.html_content = None;
+        }
     }
 
-    // Phone (Simple)
-    let phone_regex = Regex::new(r"^(\+\d{1,3}[- ]?)?\(?\d{3}\)?[- ]?\d{3}[- ]?\d{4}$").unwrap();
-    if phone_regex.is_match(content) {
-        return "phone".to_string();
+    if config.max_text_size == 0 || item.content.len() <= config.max_text_size {
+        return Some(item);
     }
 
-    // Code (Heuristic)
-    let code_indicators = [
-        "function", "class", "def", "import", "const", "let", "var", "public", "private", "return",
-        "if (", "for (", "while (", "=>", "->", "::", "{", "}",
-    ];
-    let mut score = 0;
-    for indicator in code_indicators {
-        if content.contains(indicator) {
-            score += 1;
+    match config.oversize_action.as_str() {
+        "skip" => {
+            log::info!(
+                "Skipping text of {} bytes over the size limit",
+                item.content.len()
+            );
+            None
+        }
+        "truncate" => {
+            let mut cut = config.max_text_size;
+            while !item.content.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            let dropped = item.content.len() - cut;
+            item.content.truncate(cut);
+            item.content
+                .push_str(&format!("\n… [truncated {} bytes]", dropped));
+            Some(item)
+        }
+        _ => {
+            item.store_as_blob = true;
+            Some(item)
         }
     }
-    // Also check for indentation or typical code structure
-    if content.contains(";\n") || content.contains("{\n") || content.contains("}\n") {
-        score += 2;
-    }
-
-    if score >= 2 {
-        return "code".to_string();
-    }
-
-    "text".to_string()
 }
 
-pub fn write_to_clipboard(app: &tauri::AppHandle, item: &ClipboardItem) -> Result<(), String> {
+/// Puts an item on the clipboard. Snippets are expanded first, using `inputs`
+/// for their named inputs; for those the position of `{{cursor}}` is returned,
+/// counted in chars from the end of the pasted text.
+pub fn write_to_clipboard(
+    app: &tauri::AppHandle,
+    item: &ClipboardItem,
+    mode: PasteMode,
+    inputs: Option<&HashMap<String, String>>,
+) -> Result<Option<usize>, String> {
+    let mut cursor_from_end = None;
     if item.kind == "text" {
+        let (text, html) = if item.data_type == "snippet" {
+            let ctx = ExpandContext {
+                clipboard: app.clipboard().read_text().ok(),
+                inputs,
+            };
+            let expanded = template::expand(&item.content, &ctx).map_err(|e| e.to_string())?;
+            cursor_from_end = expanded.cursor_from_end;
+            (Some(expanded.text), None)
+        } else {
+            mode.apply(&item.content, item.html_content.as_deref())
+        };
+
+        // Mark this content as set by the app to avoid duplication in monitor.
+        // Do this BEFORE writing to clipboard to avoid race condition
+        let state = app.state::<AppState>();
+        if let Ok(mut last_change) = state.last_app_change.lock() {
+            *last_change = Some(text.clone().unwrap_or_else(|| item.content.clone()));
+        }
+
         // Try to use clipboard-rs for dual storage (Text + HTML)
-        if let Some(html) = &item.html_content {
+        if let Some(html) = html {
             if let Ok(ctx) = ClipboardContext::new() {
-                let contents = vec![
-                    ClipboardContent::Text(item.content.clone()),
-                    ClipboardContent::Html(html.clone()),
-                ];
+                let mut contents = Vec::new();
+                if let Some(text) = &text {
+                    contents.push(ClipboardContent::Text(text.clone()));
+                }
+                contents.push(ClipboardContent::Html(html));
                 if let Err(e) = ctx.set(contents) {
                     log::error!("Failed to set rich text via clipboard-rs: {}", e);
                     // Fallback to standard text via tauri plugin if rich text fails
                 } else {
-                    return Ok(());
+                    return Ok(cursor_from_end);
                 }
             }
         }
 
         app.clipboard()
-            .write_text(item.content.clone())
+            .write_text(text.unwrap_or_else(|| item.content.clone()))
             .map_err(|e| e.to_string())?;
     } else if item.kind == "image" {
         let bytes = if item.content.starts_with('/') || item.content.chars().nth(1) == Some(':') {
@@ -85,13 +120,13 @@ pub fn write_to_clipboard(app: &tauri::AppHandle, item: &ClipboardItem) -> Resul
         let rgba = img.to_rgba8();
         let width = img.width();
         let height = img.height();
-        let rgba_bytes = rgba.into_raw();
 
         // Update last_app_image_change
         let state = app.state::<AppState>();
         if let Ok(mut last_change) = state.last_app_image_change.lock() {
-            *last_change = Some(rgba_bytes.clone());
+            *last_change = Some(digest(&rgba));
         }
+        let rgba_bytes = rgba.into_raw();
 
         let tauri_img = tauri::image::Image::new(&rgba_bytes, width, height);
         app.clipboard()
@@ -113,5 +148,78 @@ pub fn write_to_clipboard(app: &tauri::AppHandle, item: &ClipboardItem) -> Resul
             return Err("Failed to access clipboard context".to_string());
         }
     }
-    Ok(())
+    Ok(cursor_from_end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_text_size: usize, oversize_action: &str) -> AppConfig {
+        AppConfig {
+            max_text_size,
+            max_html_size: 10,
+            oversize_action: oversize_action.into(),
+            ..Default::default()
+        }
+    }
+
+    fn text(content: &str) -> ClipboardItem {
+        ClipboardItem {
+            content: content.into(),
+            kind: "text".into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn small_text_is_left_alone() {
+        let item = apply_size_limits(text("short"), &config(5, "skip")).unwrap();
+        assert_eq!(item.content, "short");
+        assert!(!item.store_as_blob);
+    }
+
+    #[test]
+    fn oversized_text_is_skipped() {
+        assert!(apply_size_limits(text("too long"), &config(5, "skip")).is_none());
+    }
+
+    #[test]
+    fn oversized_text_is_truncated_on_a_char_boundary() {
+        let item = apply_size_limits(text("ab€cd"), &config(3, "truncate")).unwrap();
+        assert_eq!(item.content, "ab\n… [truncated 5 bytes]");
+    }
+
+    #[test]
+    fn oversized_text_goes_to_a_blob() {
+        let item = apply_size_limits(text("too long"), &config(5, "blob")).unwrap();
+        assert_eq!(item.content, "too long");
+        assert!(item.store_as_blob);
+    }
+
+    #[test]
+    fn no_limit_keeps_everything() {
+        let item = apply_size_limits(text(&"x".repeat(1000)), &config(0, "skip")).unwrap();
+        assert_eq!(item.content.len(), 1000);
+    }
+
+    #[test]
+    fn oversized_html_is_dropped() {
+        let item = ClipboardItem {
+            html_content: Some("<b>far too long</b>".into()),
+            ..text("far too long")
+        };
+        let item = apply_size_limits(item, &config(0, "skip")).unwrap();
+        assert_eq!(item.html_content, None);
+        assert_eq!(item.content, "far too long");
+    }
+
+    #[test]
+    fn images_are_not_limited() {
+        let item = ClipboardItem {
+            kind: "image".into(),
+            ..text("/images/big.png")
+        };
+        assert!(apply_size_limits(item, &config(1, "skip")).is_some());
+    }
 }
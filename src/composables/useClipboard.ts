@@ -6,6 +6,7 @@ import { useI18n } from "vue-i18n";
 import { useToast } from "./useToast";
 import type { ClipboardItem, Collection } from "../types";
 import { confirm } from "@/composables/useConfirm";
+import { promptValues } from "@/composables/usePrompt";
 
 export function useClipboard() {
   const { t } = useI18n();
@@ -61,7 +62,10 @@ export function useClipboard() {
     const rest = itemsToPaste.slice(1);
 
     try {
-      await invoke("set_paste_stack", { items: rest });
+      await invoke("paste_stack_clear");
+      await invoke("paste_stack_push", {
+        ids: rest.map((i) => i.id).filter((id): id is number => id != null),
+      });
       await pasteItem(first);
       clearSelection();
       if (rest.length > 0) {
@@ -197,10 +201,6 @@ export function useClipboard() {
       // Close preview if open
       previewItem.value = null;
 
-      if (hideWindow) {
-        await getCurrentWindow().hide();
-      }
-
       // Fetch full content if it's text and might be truncated
       let content = item.content;
       if (item.kind === "text" && item.id) {
@@ -211,11 +211,27 @@ export function useClipboard() {
         }
       }
 
+      // Snippets with {{input:name}} placeholders ask for their values first
+      let inputs: Record<string, string> | undefined;
+      if (item.data_type === "snippet") {
+        const names = await invoke<string[]>("validate_snippet", { content });
+        if (names.length > 0) {
+          const values = await promptValues(t("snippet.inputsTitle"), names);
+          if (!values) return;
+          inputs = values;
+        }
+      }
+
+      if (hideWindow) {
+        await getCurrentWindow().hide();
+      }
+
       await invoke("set_clipboard_item", {
         content,
         kind: item.kind,
         id: item.id,
         htmlContent: item.html_content,
+        inputs,
       });
       await loadHistory(true);
       searchQuery.value = "";
@@ -0,0 +1,47 @@
+import { reactive } from "vue";
+
+interface PromptField {
+  name: string;
+  value: string;
+}
+
+// 内部状态，由 PromptProvider 渲染
+const state = reactive({
+  isOpen: false,
+  title: "",
+  fields: [] as PromptField[],
+});
+
+let currentResolve: ((value: Record<string, string> | null) => void) | null =
+  null;
+
+/**
+ * 依次询问每个名称的值，取消时返回 null
+ */
+export const promptValues = (
+  title: string,
+  names: string[]
+): Promise<Record<string, string> | null> => {
+  // 同一时间只有一个输入框，旧的请求视为取消
+  currentResolve?.(null);
+  state.title = title;
+  state.fields = names.map((name) => ({ name, value: "" }));
+  state.isOpen = true;
+  return new Promise((resolve) => {
+    currentResolve = resolve;
+  });
+};
+
+/**
+ * 内部方法：确定或取消
+ */
+const handlePromptResult = (confirmed: boolean) => {
+  state.isOpen = false;
+  const values = confirmed
+    ? Object.fromEntries(state.fields.map((field) => [field.name, field.value]))
+    : null;
+  currentResolve?.(values);
+  currentResolve = null;
+};
+
+export { state as promptState, handlePromptResult };
@@ -50,6 +50,9 @@ const messages = {
       placeholder: "Enter content here...",
       notePlaceholder: "E.g. GitHub Password, Work Email...",
     },
+    snippet: {
+      inputsTitle: "Fill in the snippet",
+    },
     collections: {
       all: "All Items",
       newPlaceholder: "New Collection...",
@@ -172,6 +175,9 @@ const messages = {
       placeholder: "在此输入内容...",
       notePlaceholder: "例如：GitHub 密码, 公司邮箱...",
     },
+    snippet: {
+      inputsTitle: "填写片段内容",
+    },
     collections: {
       all: "全部记录",
       newPlaceholder: "新建收藏夹...",
@@ -10,6 +10,17 @@ export interface ClipboardItem {
   collection_id?: number;
   note?: string;
   html_content?: string;
+  content_length?: number;
+  thumbnail?: string;
+  width?: number;
+  height?: number;
+  byte_size?: number;
+  file_missing?: boolean;
+  language?: string;
+  data_type_manual?: boolean;
+  source_ids?: number[];
+  deleted_at?: string;
+  no_sync?: boolean;
 }
 
 export interface Collection {
@@ -27,4 +38,170 @@ export interface AppConfig {
   compact_mode?: boolean;
   clear_pinned_on_clear?: boolean;
   clear_collected_on_clear?: boolean;
+  capture_settle_ms?: number;
+  custom_classifiers?: CustomClassifier[];
+  default_paste_mode?: PasteMode;
+  paste_mode_by_app?: Record<string, PasteMode>;
+  trash_retention_days?: number;
+  enable_ipc?: boolean;
+  enable_dbus?: boolean;
+  http_api_enabled?: boolean;
+  http_api_port?: number;
+  http_api_token?: string;
+  auto_backup_enabled?: boolean;
+  auto_backup_interval_hours?: number;
+  auto_backup_keep?: number;
+  auto_backup_dir?: string;
+  auto_backup_passphrase?: string;
+  sync_enabled?: boolean;
+  sync_port?: number;
+  sync_device_name?: string;
+  folder_sync_enabled?: boolean;
+  folder_sync_dir?: string;
+  folder_sync_passphrase?: string;
+}
+
+export type PasteMode = "rich" | "plain" | "html" | "markdown";
+
+export type PasteStackMode = "fifo" | "lifo" | "cycle";
+
+export interface PasteStack {
+  mode: PasteStackMode;
+  ids: number[];
+}
+
+export interface CustomClassifier {
+  name: string;
+  pattern: string;
+  confidence?: number;
+}
+
+export interface MergeOptions {
+  merge_html?: boolean;
+  dedupe_lines?: boolean;
+  numbered?: boolean;
+}
+
+export type SplitBy = "lines" | { delimiter: string } | { regex: string };
+
+export interface Revision {
+  id: number;
+  item_id: number;
+  content: string;
+  html_content?: string;
+  data_type: string;
+  note?: string;
+  created_at: string;
+}
+
+export interface DiffLine {
+  tag: "equal" | "insert" | "delete";
+  text: string;
+  old_line?: number;
+  new_line?: number;
+}
+
+export interface ExportOptions {
+  format?: "json" | "csv" | "markdown";
+  collection_id?: number | null;
+  data_type?: string | null;
+  query?: string | null;
+  from?: string | null;
+  to?: string | null;
+  sensitive?: "exclude" | "redact" | "decrypt";
+  confirm_decrypt?: boolean;
+  images?: "files" | "base64";
+}
+
+export interface ExportReport {
+  items: number;
+  images: number;
+  sensitive: number;
+}
+
+export type ImportSource =
+  | "copyq"
+  | "maccy"
+  | "clipy"
+  | "ditto"
+  | "gpaste"
+  | "clipman"
+  | "cliphist";
+
+export interface ImportReport {
+  dry_run: boolean;
+  found: number;
+  imported: number;
+  duplicates: number;
+  skipped: number;
+  pinned: number;
+  images: number;
+  collections: string[];
+  history_size: number;
+  history_limit: number;
+}
+
+export interface ImportProgress {
+  processed: number;
+  total: number;
+}
+
+export interface BackupReport {
+  path: string;
+  files: number;
+  size: number;
+}
+
+export interface RestoreReport {
+  created_at: string;
+  app_version: string;
+  files: number;
+}
+
+export interface SyncPeer {
+  device_id: string;
+  name: string;
+  public_key: string;
+  address?: string;
+  cursor: number;
+  last_sync?: string;
+}
+
+export interface DiscoveredDevice {
+  device_id: string;
+  name: string;
+  address: string;
+}
+
+export interface SyncStatus {
+  running: boolean;
+  device_id: string;
+  device_name: string;
+  port?: number;
+  peers: SyncPeer[];
+  discovered: DiscoveredDevice[];
+}
+
+export interface SyncReport {
+  device_id: string;
+  name: string;
+  received: number;
+  sent: number;
+}
+
+export interface FolderDevice {
+  name: string;
+  applied: number;
+}
+
+export interface FolderSyncStatus {
+  running: boolean;
+  device_id: string;
+  dir: string;
+  devices: Record<string, FolderDevice>;
+}
+
+export interface FolderSyncReport {
+  written: number;
+  applied: number;
 }